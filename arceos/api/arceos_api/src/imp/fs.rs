@@ -6,11 +6,12 @@ pub use axfs::fops::DirEntry as AxDirEntry;
 pub use axfs::fops::FileAttr as AxFileAttr;
 pub use axfs::fops::FilePerm as AxFilePerm;
 pub use axfs::fops::FileType as AxFileType;
+pub use axfs::fops::FsStat as AxFsStat;
 pub use axfs::fops::OpenOptions as AxOpenOptions;
 pub use axio::SeekFrom as AxSeekFrom;
 
 #[cfg(feature = "myfs")]
-pub use axfs::fops::{Disk as AxDisk, MyFileSystemIf};
+pub use axfs::fops::{BlockDevice as AxBlockDevice, Disk as AxDisk, MyFileSystemIf};
 
 /// A handle to an opened file.
 pub struct AxFileHandle(File);
@@ -78,6 +79,27 @@ pub fn ax_rename(old: &str, new: &str) -> AxResult {
     axfs::api::rename(old, new)
 }
 
+pub fn ax_symlink_attr(path: &str) -> AxResult<AxFileAttr> {
+    let m = axfs::api::symlink_metadata(path)?;
+    Ok(AxFileAttr::new(m.permissions(), m.file_type(), m.size(), m.blocks()).with_times(m.times()))
+}
+
+pub fn ax_symlink(original: &str, link: &str) -> AxResult {
+    axfs::api::symlink(original, link)
+}
+
+pub fn ax_read_link(path: &str) -> AxResult<String> {
+    axfs::api::read_link(path)
+}
+
+pub fn ax_hard_link(original: &str, link: &str) -> AxResult {
+    axfs::api::hard_link(original, link)
+}
+
+pub fn ax_statfs(path: &str) -> AxResult<AxFsStat> {
+    axfs::api::statfs(path)
+}
+
 pub fn ax_current_dir() -> AxResult<String> {
     axfs::api::current_dir()
 }
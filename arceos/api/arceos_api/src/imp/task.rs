@@ -20,12 +20,16 @@ pub fn ax_exit(_exit_code: i32) -> ! {
     #[cfg(feature = "multitask")]
     axtask::exit(_exit_code);
     #[cfg(not(feature = "multitask"))]
-    axhal::misc::terminate();
+    axruntime::terminate();
 }
 
 cfg_task! {
     use core::time::Duration;
 
+    pub use axtask::CpuMask as AxCpuMask;
+    pub use axtask::DeadlineParams as AxDeadlineParams;
+    pub use axtask::SchedClass as AxSchedClass;
+
     /// A handle to a task.
     pub struct AxTaskHandle {
         inner: axtask::AxTaskRef,
@@ -67,6 +71,45 @@ cfg_task! {
         }
     }
 
+    pub fn ax_spawn_with_affinity<F>(
+        f: F,
+        name: alloc::string::String,
+        stack_size: usize,
+        cpumask: AxCpuMask,
+    ) -> AxTaskHandle
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        // the default scheduling class is always supported
+        ax_spawn_with(f, name, stack_size, Some(cpumask), AxSchedClass::default()).unwrap()
+    }
+
+    pub fn ax_spawn_with<F>(
+        f: F,
+        name: alloc::string::String,
+        stack_size: usize,
+        cpumask: Option<AxCpuMask>,
+        class: AxSchedClass,
+    ) -> crate::AxResult<AxTaskHandle>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let task = axtask::TaskInner::new(f, name, stack_size);
+        if let Some(cpumask) = cpumask {
+            task.set_cpumask(cpumask);
+        }
+        let inner = axtask::spawn_task_with_class(task, class).ok_or_else(|| {
+            axerrno::ax_err_type!(
+                InvalidInput,
+                "ax_spawn_with: invalid or unsupported scheduling class"
+            )
+        })?;
+        Ok(AxTaskHandle {
+            id: inner.id().as_u64(),
+            inner,
+        })
+    }
+
     pub fn ax_wait_for_exit(task: AxTaskHandle) -> Option<i32> {
         task.inner.join()
     }
@@ -82,6 +125,28 @@ cfg_task! {
         }
     }
 
+    pub fn ax_set_current_sched_class(class: AxSchedClass) -> crate::AxResult {
+        if axtask::set_sched_class(class) {
+            Ok(())
+        } else {
+            axerrno::ax_err!(
+                InvalidInput,
+                "ax_set_current_sched_class: invalid or unsupported scheduling class"
+            )
+        }
+    }
+
+    pub fn ax_set_current_affinity(cpumask: AxCpuMask) -> crate::AxResult {
+        if axtask::set_affinity(cpumask) {
+            Ok(())
+        } else {
+            axerrno::ax_err!(
+                InvalidInput,
+                "ax_set_current_affinity: no online CPU in the CPU mask"
+            )
+        }
+    }
+
     pub fn ax_wait_queue_wait(
         wq: &AxWaitQueueHandle,
         until_condition: impl Fn() -> bool,
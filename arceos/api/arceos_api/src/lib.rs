@@ -121,6 +121,9 @@ pub mod task {
         @cfg "multitask";
         pub type AxTaskHandle;
         pub type AxWaitQueueHandle;
+        pub type AxCpuMask;
+        pub type AxSchedClass;
+        pub type AxDeadlineParams;
     }
 
     define_api! {
@@ -150,11 +153,33 @@ pub mod task {
             name: alloc::string::String,
             stack_size: usize
         ) -> AxTaskHandle;
+        /// Spawns a new task like [`ax_spawn`], which is only allowed to run
+        /// on the CPUs in `cpumask`.
+        pub fn ax_spawn_with_affinity(
+            f: impl FnOnce() + Send + 'static,
+            name: alloc::string::String,
+            stack_size: usize,
+            cpumask: AxCpuMask
+        ) -> AxTaskHandle;
+        /// Spawns a new task like [`ax_spawn`], in the scheduling class
+        /// `class`, which is only allowed to run on the CPUs in `cpumask` (if
+        /// specified).
+        pub fn ax_spawn_with(
+            f: impl FnOnce() + Send + 'static,
+            name: alloc::string::String,
+            stack_size: usize,
+            cpumask: Option<AxCpuMask>,
+            class: AxSchedClass
+        ) -> crate::AxResult<AxTaskHandle>;
         /// Waits for the given task to exit, and returns its exit code (the
         /// argument of [`ax_exit`]).
         pub fn ax_wait_for_exit(task: AxTaskHandle) -> Option<i32>;
         /// Sets the priority of the current task.
         pub fn ax_set_current_priority(prio: isize) -> crate::AxResult;
+        /// Sets the scheduling class of the current task.
+        pub fn ax_set_current_sched_class(class: AxSchedClass) -> crate::AxResult;
+        /// Sets the CPUs that the current task is allowed to run on.
+        pub fn ax_set_current_affinity(cpumask: AxCpuMask) -> crate::AxResult;
 
         /// Blocks the current task and put it into the wait queue, until the
         /// given condition becomes true, or the the given duration has elapsed
@@ -186,9 +211,12 @@ pub mod fs {
         pub type AxFilePerm;
         pub type AxDirEntry;
         pub type AxSeekFrom;
+        pub type AxFsStat;
         #[cfg(feature = "myfs")]
         pub type AxDisk;
         #[cfg(feature = "myfs")]
+        pub type AxBlockDevice;
+        #[cfg(feature = "myfs")]
         pub type MyFileSystemIf;
     }
 
@@ -245,11 +273,25 @@ pub mod fs {
         pub fn ax_remove_dir(path: &str) -> AxResult;
         /// Removes a file from the filesystem.
         pub fn ax_remove_file(path: &str) -> AxResult;
-        /// Rename a file or directory to a new name.
+        /// Rename a file or directory to a new name, possibly in another
+        /// directory of the same filesystem.
         ///
-        /// It will delete the original file if `old` already exists.
+        /// It will replace the file at `new` atomically if it already exists.
         pub fn ax_rename(old: &str, new: &str) -> AxResult;
 
+        /// Returns attributes of the file at the path, symlinks in the last
+        /// component are not followed.
+        pub fn ax_symlink_attr(path: &str) -> AxResult<AxFileAttr>;
+        /// Creates a new symbolic link at `link` pointing to `original`.
+        pub fn ax_symlink(original: &str, link: &str) -> AxResult;
+        /// Reads the target of a symbolic link.
+        pub fn ax_read_link(path: &str) -> AxResult<alloc::string::String>;
+        /// Creates a new hard link at `link` to the file at `original`.
+        pub fn ax_hard_link(original: &str, link: &str) -> AxResult;
+
+        /// Returns the usage of the filesystem containing the path.
+        pub fn ax_statfs(path: &str) -> AxResult<AxFsStat>;
+
         /// Returns the current working directory.
         pub fn ax_current_dir() -> AxResult<alloc::string::String>;
         /// Changes the current working directory to the specified path.
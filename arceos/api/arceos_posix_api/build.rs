@@ -53,6 +53,11 @@ typedef struct {{
             "clockid_t",
             "rlimit",
             "aibuf",
+            "flock",
+            "statfs",
+            "inotify_event",
+            "cpu_set_t",
+            "sched_attr",
         ];
         let allow_vars = [
             "CLOCK_.*",
@@ -62,12 +67,18 @@ typedef struct {{
             "IPPROTO_.*",
             "FD_.*",
             "F_.*",
+            "LOCK_.*",
+            "IN_.*",
             "_SC_.*",
+            "AT_.*",
+            "UTIME_.*",
+            "MS_.*",
             "EPOLL_CTL_.*",
             "EPOLL.*",
             "RLIMIT_.*",
             "EAI_.*",
             "MAXADDRS",
+            "SCHED_.*",
         ];
 
         #[derive(Debug)]
@@ -19,6 +19,11 @@ pub trait FileLike: Send + Sync {
     fn into_any(self: Arc<Self>) -> Arc<dyn core::any::Any + Send + Sync>;
     fn poll(&self) -> LinuxResult<PollState>;
     fn set_nonblocking(&self, nonblocking: bool) -> LinuxResult;
+
+    /// Performs a device-specific operation. Only devices support it.
+    fn ioctl(&self, _cmd: usize, _arg: usize) -> LinuxResult<c_int> {
+        Err(LinuxError::ENOTTY)
+    }
 }
 
 lazy_static::lazy_static! {
@@ -48,6 +53,11 @@ pub fn close_file_like(fd: c_int) -> LinuxResult {
         .write()
         .remove(fd as usize)
         .ok_or(LinuxError::EBADF)?;
+    // byte-range locks are released by closing any descriptor of the file
+    #[cfg(feature = "fs")]
+    if let Ok(file) = f.clone().into_any().downcast::<super::fs::File>() {
+        file.release_locks();
+    }
     drop(f);
     Ok(())
 }
@@ -101,6 +111,17 @@ pub fn sys_dup2(old_fd: c_int, new_fd: c_int) -> c_int {
     })
 }
 
+/// Manipulate the underlying device parameters of special files.
+///
+/// `arg` is usually a pointer, whose type depends on `request`.
+pub fn sys_ioctl(fd: c_int, request: usize, arg: usize) -> c_int {
+    debug!(
+        "sys_ioctl <= fd: {} request: {:#x} arg: {:#x}",
+        fd, request, arg
+    );
+    syscall_body!(sys_ioctl, get_file_like(fd)?.ioctl(request, arg))
+}
+
 /// Manipulate file descriptor.
 ///
 /// TODO: `SET/GET` command is ignored, hard-code stdin/stdout
@@ -120,6 +141,10 @@ pub fn sys_fcntl(fd: c_int, cmd: c_int, arg: usize) -> c_int {
                 get_file_like(fd)?.set_nonblocking(arg & (ctypes::O_NONBLOCK as usize) > 0)?;
                 Ok(0)
             }
+            #[cfg(feature = "fs")]
+            ctypes::F_GETLK | ctypes::F_SETLK | ctypes::F_SETLKW => {
+                super::fs::fcntl_lock(fd, cmd as u32, arg)
+            }
             _ => {
                 warn!("unsupported fcntl parameters: cmd {}", cmd);
                 Ok(0)
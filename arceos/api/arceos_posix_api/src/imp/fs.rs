@@ -1,8 +1,10 @@
 use alloc::sync::Arc;
-use core::ffi::{c_char, c_int};
+use core::ffi::{c_char, c_int, c_long, c_ulong, c_void};
+use core::time::Duration;
 
-use axerrno::{LinuxError, LinuxResult};
-use axfs::fops::OpenOptions;
+use axerrno::{AxError, LinuxError, LinuxResult};
+use axfs::api::MountOptions;
+use axfs::fops::{FileAttr, FileLock, FsStat, LockType, OpenOptions};
 use axio::{PollState, SeekFrom};
 use axsync::Mutex;
 
@@ -24,6 +26,12 @@ impl File {
         super::fd_ops::add_file_like(Arc::new(self))
     }
 
+    /// Releases the byte-range locks held by the current task, when any file
+    /// descriptor of the file is closed.
+    pub(crate) fn release_locks(&self) {
+        self.inner.lock().release_locks();
+    }
+
     fn from_fd(fd: c_int) -> LinuxResult<Arc<Self>> {
         let f = super::fd_ops::get_file_like(fd)?;
         f.into_any()
@@ -42,21 +50,7 @@ impl FileLike for File {
     }
 
     fn stat(&self) -> LinuxResult<ctypes::stat> {
-        let metadata = self.inner.lock().get_attr()?;
-        let ty = metadata.file_type() as u8;
-        let perm = metadata.perm().bits() as u32;
-        let st_mode = ((ty as u32) << 12) | perm;
-        Ok(ctypes::stat {
-            st_ino: 1,
-            st_nlink: 1,
-            st_mode,
-            st_uid: 1000,
-            st_gid: 1000,
-            st_size: metadata.size() as _,
-            st_blocks: metadata.blocks() as _,
-            st_blksize: 512,
-            ..Default::default()
-        })
+        Ok(attr_to_stat(&self.inner.lock().get_attr()?))
     }
 
     fn into_any(self: Arc<Self>) -> Arc<dyn core::any::Any + Send + Sync> {
@@ -73,6 +67,37 @@ impl FileLike for File {
     fn set_nonblocking(&self, _nonblocking: bool) -> LinuxResult {
         Ok(())
     }
+
+    fn ioctl(&self, cmd: usize, arg: usize) -> LinuxResult<c_int> {
+        // SAFETY: `arg` is trusted as other pointer arguments of syscalls
+        match unsafe { self.inner.lock().ioctl(cmd as u32, arg) } {
+            Ok(ret) => Ok(ret as c_int),
+            Err(AxError::Unsupported) => Err(LinuxError::ENOTTY),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// Convert file attributes to [`ctypes::stat`].
+fn attr_to_stat(attr: &FileAttr) -> ctypes::stat {
+    let ty = attr.file_type() as u8;
+    let perm = attr.perm().bits() as u32;
+    let st_mode = ((ty as u32) << 12) | perm;
+    let times = attr.times();
+    ctypes::stat {
+        st_ino: 1,
+        st_nlink: 1,
+        st_mode,
+        st_uid: 1000,
+        st_gid: 1000,
+        st_size: attr.size() as _,
+        st_blocks: attr.blocks() as _,
+        st_blksize: 512,
+        st_atim: times.accessed.into(),
+        st_mtim: times.modified.into(),
+        st_ctim: times.changed.into(),
+        ..Default::default()
+    }
 }
 
 /// Convert open flags to [`OpenOptions`].
@@ -99,9 +124,27 @@ fn flags_to_options(flags: c_int, _mode: ctypes::mode_t) -> OpenOptions {
     if flags & ctypes::O_EXEC != 0 {
         options.create_new(true);
     }
+    if flags & ctypes::O_NOFOLLOW != 0 {
+        options.no_follow(true);
+    }
     options
 }
 
+/// Modifications on a read-only filesystem fail with `PermissionDenied`, which
+/// should be `EROFS` instead of `EACCES`.
+fn is_read_only(path: &str) -> bool {
+    axfs::api::is_read_only(path).unwrap_or(false)
+}
+
+/// Convert the error of a path lookup, which fails with
+/// [`SYMLINK_LOOP`](axfs::fops::SYMLINK_LOOP) for `ELOOP`.
+fn path_err(e: AxError) -> LinuxError {
+    match e {
+        axfs::fops::SYMLINK_LOOP => LinuxError::ELOOP,
+        e => e.into(),
+    }
+}
+
 /// Open a file by `filename` and insert it into the file descriptor table.
 ///
 /// Return its index in the file table (`fd`). Return `EMFILE` if it already
@@ -110,8 +153,14 @@ pub fn sys_open(filename: *const c_char, flags: c_int, mode: ctypes::mode_t) ->
     let filename = char_ptr_to_str(filename);
     debug!("sys_open <= {:?} {:#o} {:#o}", filename, flags, mode);
     syscall_body!(sys_open, {
+        let filename = filename?;
         let options = flags_to_options(flags, mode);
-        let file = axfs::fops::File::open(filename?, &options)?;
+        let modifies = flags as u32 & 0b11 != ctypes::O_RDONLY
+            || flags as u32 & (ctypes::O_CREAT | ctypes::O_TRUNC) != 0;
+        let file = axfs::fops::File::open(filename, &options).map_err(|e| match e {
+            AxError::PermissionDenied if modifies && is_read_only(filename) => LinuxError::EROFS,
+            e => path_err(e),
+        })?;
         File::new(file).add_to_fd_table()
     })
 }
@@ -145,7 +194,7 @@ pub unsafe fn sys_stat(path: *const c_char, buf: *mut ctypes::stat) -> c_int {
         }
         let mut options = OpenOptions::new();
         options.read(true);
-        let file = axfs::fops::File::open(path?, &options)?;
+        let file = axfs::fops::File::open(path?, &options).map_err(path_err)?;
         let st = File::new(file).stat()?;
         unsafe { *buf = st };
         Ok(0)
@@ -167,6 +216,54 @@ pub unsafe fn sys_fstat(fd: c_int, buf: *mut ctypes::stat) -> c_int {
     })
 }
 
+/// Convert the usage of a filesystem to a `struct statfs`.
+fn fs_stat_to_statfs(st: &FsStat) -> ctypes::statfs {
+    ctypes::statfs {
+        f_type: st.fs_type as _,
+        f_bsize: st.block_size as _,
+        f_blocks: st.blocks,
+        f_bfree: st.blocks_free,
+        f_bavail: st.blocks_free,
+        f_files: st.files,
+        f_ffree: st.files_free,
+        f_namelen: st.name_max as _,
+        f_frsize: st.block_size as _,
+        ..Default::default()
+    }
+}
+
+/// Get the usage of the filesystem containing `path` and write into `buf`.
+///
+/// Return 0 if success.
+pub unsafe fn sys_statfs(path: *const c_char, buf: *mut ctypes::statfs) -> c_int {
+    let path = char_ptr_to_str(path);
+    debug!("sys_statfs <= {:?} {:#x}", path, buf as usize);
+    syscall_body!(sys_statfs, {
+        if buf.is_null() {
+            return Err(LinuxError::EFAULT);
+        }
+        let st = axfs::api::statfs(path?).map_err(path_err)?;
+        unsafe { *buf = fs_stat_to_statfs(&st) };
+        Ok(0)
+    })
+}
+
+/// Get the usage of the filesystem containing the file `fd` and write into
+/// `buf`.
+///
+/// Return 0 if success.
+pub unsafe fn sys_fstatfs(fd: c_int, buf: *mut ctypes::statfs) -> c_int {
+    debug!("sys_fstatfs <= {} {:#x}", fd, buf as usize);
+    syscall_body!(sys_fstatfs, {
+        if buf.is_null() {
+            return Err(LinuxError::EFAULT);
+        }
+        let st = File::from_fd(fd)?.inner.lock().fs_stat()?;
+        unsafe { *buf = fs_stat_to_statfs(&st) };
+        Ok(0)
+    })
+}
+
 /// Get the metadata of the symbolic link and write into `buf`.
 ///
 /// Return 0 if success.
@@ -177,7 +274,138 @@ pub unsafe fn sys_lstat(path: *const c_char, buf: *mut ctypes::stat) -> ctypes::
         if buf.is_null() {
             return Err(LinuxError::EFAULT);
         }
-        unsafe { *buf = Default::default() }; // TODO
+        let m = axfs::api::symlink_metadata(path?).map_err(path_err)?;
+        let attr = FileAttr::new(m.permissions(), m.file_type(), m.size(), m.blocks())
+            .with_times(m.times());
+        unsafe { *buf = attr_to_stat(&attr) };
+        Ok(0)
+    })
+}
+
+/// Convert the `times` argument of [`sys_utimensat`] to the new access and
+/// modification times, where `None` means leaving it unchanged.
+fn timespecs_to_times(
+    times: *const ctypes::timespec,
+) -> LinuxResult<(Option<Duration>, Option<Duration>)> {
+    if times.is_null() {
+        let now = axhal::time::wall_time();
+        return Ok((Some(now), Some(now)));
+    }
+    let convert = |ts: ctypes::timespec| match ts.tv_nsec {
+        n if n == ctypes::UTIME_NOW as c_long => Ok(Some(axhal::time::wall_time())),
+        n if n == ctypes::UTIME_OMIT as c_long => Ok(None),
+        n if !(0..1_000_000_000).contains(&n) || ts.tv_sec < 0 => Err(LinuxError::EINVAL),
+        _ => Ok(Some(ts.into())),
+    };
+    let times = unsafe { core::slice::from_raw_parts(times, 2) };
+    Ok((convert(times[0])?, convert(times[1])?))
+}
+
+/// Set the access and modification times of the file `path`.
+///
+/// `times` points to the new access and modification times, either can be
+/// `UTIME_NOW` or `UTIME_OMIT`. Both are set to the current time if it's null.
+/// Symlinks are not followed if `flags` has `AT_SYMLINK_NOFOLLOW`. If `path`
+/// is null, the times of the file `dirfd` are set instead.
+///
+/// Relative paths can only be resolved from the current directory, i.e.
+/// `dirfd` must be `AT_FDCWD`.
+///
+/// Return 0 if the operation succeeds.
+pub unsafe fn sys_utimensat(
+    dirfd: c_int,
+    path: *const c_char,
+    times: *const ctypes::timespec,
+    flags: c_int,
+) -> c_int {
+    debug!(
+        "sys_utimensat <= {} {:#x} {:#x} {:#x}",
+        dirfd, path as usize, times as usize, flags
+    );
+    syscall_body!(sys_utimensat, {
+        let (accessed, modified) = timespecs_to_times(times)?;
+        if path.is_null() {
+            let file = File::from_fd(dirfd)?;
+            let file = file.inner.lock();
+            file.set_times(accessed, modified).map_err(|e| match e {
+                AxError::PermissionDenied if file.is_read_only() => LinuxError::EROFS,
+                e => e.into(),
+            })?;
+            return Ok(0);
+        }
+        let path = char_ptr_to_str(path)?;
+        if dirfd != ctypes::AT_FDCWD && !path.starts_with('/') {
+            warn!("sys_utimensat: relative path with dirfd {}", dirfd);
+            return Err(LinuxError::EINVAL);
+        }
+        let res = if flags as u32 & ctypes::AT_SYMLINK_NOFOLLOW != 0 {
+            axfs::api::set_symlink_times(path, accessed, modified)
+        } else {
+            axfs::api::set_times(path, accessed, modified)
+        };
+        res.map_err(|e| match e {
+            AxError::PermissionDenied if is_read_only(path) => LinuxError::EROFS,
+            e => path_err(e),
+        })?;
+        Ok(0)
+    })
+}
+
+/// Create a symbolic link `linkpath` pointing to `target`.
+///
+/// Return 0 if the operation succeeds.
+pub fn sys_symlink(target: *const c_char, linkpath: *const c_char) -> c_int {
+    syscall_body!(sys_symlink, {
+        let target = char_ptr_to_str(target)?;
+        let linkpath = char_ptr_to_str(linkpath)?;
+        debug!(
+            "sys_symlink <= target: {:?}, linkpath: {:?}",
+            target, linkpath
+        );
+        axfs::api::symlink(target, linkpath).map_err(|e| match e {
+            AxError::PermissionDenied if is_read_only(linkpath) => LinuxError::EROFS,
+            e => path_err(e),
+        })?;
+        Ok(0)
+    })
+}
+
+/// Read the target of the symbolic link `path` into `buf`, without a
+/// terminating null byte. The target is truncated if `buf` is too small.
+///
+/// Return the number of bytes placed in `buf`.
+pub fn sys_readlink(path: *const c_char, buf: *mut c_char, bufsize: usize) -> ctypes::ssize_t {
+    let path = char_ptr_to_str(path);
+    debug!("sys_readlink <= {:?} {:#x} {}", path, buf as usize, bufsize);
+    syscall_body!(sys_readlink, {
+        if buf.is_null() {
+            return Err(LinuxError::EFAULT);
+        }
+        let target = axfs::api::read_link(path?).map_err(path_err)?;
+        let len = target.len().min(bufsize);
+        let dst = unsafe { core::slice::from_raw_parts_mut(buf as *mut u8, len) };
+        dst.copy_from_slice(&target.as_bytes()[..len]);
+        Ok(len as ctypes::ssize_t)
+    })
+}
+
+/// Create a new hard link `new` to the file `old`.
+///
+/// Return `EXDEV` if they are on different filesystems, or `EPERM` if `old`
+/// is a directory or the filesystem does not support hard links.
+pub fn sys_link(old: *const c_char, new: *const c_char) -> c_int {
+    syscall_body!(sys_link, {
+        let old_path = char_ptr_to_str(old)?;
+        let new_path = char_ptr_to_str(new)?;
+        debug!("sys_link <= old: {:?}, new: {:?}", old_path, new_path);
+        if !axfs::api::same_filesystem(old_path, new_path).map_err(path_err)? {
+            return Err(LinuxError::EXDEV);
+        }
+        axfs::api::hard_link(old_path, new_path).map_err(|e| match e {
+            AxError::PermissionDenied if is_read_only(new_path) => LinuxError::EROFS,
+            AxError::Unsupported | AxError::PermissionDenied => LinuxError::EPERM,
+            e => path_err(e),
+        })?;
         Ok(0)
     })
 }
@@ -202,16 +430,200 @@ pub fn sys_getcwd(buf: *mut c_char, size: usize) -> *mut c_char {
     })
 }
 
+/// Change the current directory of the calling thread to `path`.
+pub fn sys_chdir(path: *const c_char) -> c_int {
+    syscall_body!(sys_chdir, {
+        let path = char_ptr_to_str(path)?;
+        debug!("sys_chdir <= {:?}", path);
+        axfs::api::set_current_dir(path).map_err(path_err)?;
+        Ok(0)
+    })
+}
+
+/// Change the root directory of the calling thread to `path`.
+///
+/// The current directory is also changed to the new root.
+pub fn sys_chroot(path: *const c_char) -> c_int {
+    syscall_body!(sys_chroot, {
+        let path = char_ptr_to_str(path)?;
+        debug!("sys_chroot <= {:?}", path);
+        axfs::api::set_root_dir(path).map_err(path_err)?;
+        Ok(0)
+    })
+}
+
 /// Rename `old` to `new`
-/// If new exists, it is first removed.
+/// If new exists, it is atomically replaced.
 ///
-/// Return 0 if the operation succeeds, otherwise return -1.
+/// Return 0 if the operation succeeds, otherwise return -1. Return `EXDEV` if
+/// they are on different filesystems.
 pub fn sys_rename(old: *const c_char, new: *const c_char) -> c_int {
     syscall_body!(sys_rename, {
         let old_path = char_ptr_to_str(old)?;
         let new_path = char_ptr_to_str(new)?;
         debug!("sys_rename <= old: {:?}, new: {:?}", old_path, new_path);
-        axfs::api::rename(old_path, new_path)?;
+        if !axfs::api::same_filesystem(old_path, new_path).map_err(path_err)? {
+            return Err(LinuxError::EXDEV);
+        }
+        axfs::api::rename(old_path, new_path).map_err(|e| match e {
+            AxError::PermissionDenied if is_read_only(old_path) => LinuxError::EROFS,
+            e => path_err(e),
+        })?;
+        Ok(0)
+    })
+}
+
+/// Mount a new filesystem of type `fstype` on the directory `target`.
+///
+/// Only in-memory filesystems (`ramfs`/`tmpfs` and `devfs`) can be created,
+/// so `source` is ignored. `MS_RDONLY`, `MS_NOSUID` and `MS_NOEXEC` in
+/// `flags` are supported, and `data` is a comma-separated list of options
+/// such as `size=16m,nr_inodes=1k` if it's not null.
+///
+/// Return 0 if the operation succeeds. Return `ENODEV` if `fstype` is not
+/// supported, and `EINVAL` if the options are invalid for it.
+pub fn sys_mount(
+    source: *const c_char,
+    target: *const c_char,
+    fstype: *const c_char,
+    flags: c_ulong,
+    data: *const c_void,
+) -> c_int {
+    syscall_body!(sys_mount, {
+        let target = char_ptr_to_str(target)?;
+        let fstype = char_ptr_to_str(fstype)?;
+        let data = if data.is_null() {
+            None
+        } else {
+            Some(char_ptr_to_str(data as *const c_char)?)
+        };
+        debug!(
+            "sys_mount <= source: {:#x}, target: {:?}, fstype: {:?}, flags: {:#x}, data: {:?}",
+            source as usize, target, fstype, flags, data
+        );
+        let mut options: MountOptions = data.unwrap_or_default().parse()?;
+        options.read_only |= flags & ctypes::MS_RDONLY as c_ulong != 0;
+        options.no_suid |= flags & ctypes::MS_NOSUID as c_ulong != 0;
+        options.no_exec |= flags & ctypes::MS_NOEXEC as c_ulong != 0;
+        axfs::api::mount_fstype_with_options(target, fstype, options).map_err(|e| match e {
+            AxError::Unsupported => LinuxError::ENODEV,
+            e => path_err(e),
+        })?;
+        Ok(0)
+    })
+}
+
+/// Unmount the filesystem mounted on `target`.
+///
+/// Return `EBUSY` if the filesystem is still in use.
+pub fn sys_umount2(target: *const c_char, flags: c_int) -> c_int {
+    syscall_body!(sys_umount2, {
+        let target = char_ptr_to_str(target)?;
+        debug!("sys_umount2 <= target: {:?}, flags: {:#x}", target, flags);
+        axfs::api::umount(target).map_err(path_err)?;
+        Ok(0)
+    })
+}
+
+/// Convert a `struct flock` to a [`FileLock`], with its range relative to
+/// `l_whence` of `file`.
+fn flock_to_lock(file: &mut axfs::fops::File, fl: &ctypes::flock) -> LinuxResult<FileLock> {
+    let ty = match fl.l_type as u32 {
+        ctypes::F_RDLCK => LockType::Shared,
+        ctypes::F_WRLCK => LockType::Exclusive,
+        ctypes::F_UNLCK => LockType::Unlock,
+        _ => return Err(LinuxError::EINVAL),
+    };
+    let base = match fl.l_whence {
+        0 => 0,
+        1 => file.seek(SeekFrom::Current(0))? as i64,
+        2 => file.get_attr()?.size() as i64,
+        _ => return Err(LinuxError::EINVAL),
+    };
+    let mut start = base.checked_add(fl.l_start).ok_or(LinuxError::EOVERFLOW)?;
+    let mut len = fl.l_len;
+    if len < 0 {
+        // the range is before `l_start`
+        start = start.checked_add(len).ok_or(LinuxError::EINVAL)?;
+        len = len.checked_neg().ok_or(LinuxError::EINVAL)?;
+    }
+    if start < 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    Ok(FileLock::new(ty, start as u64, len as u64))
+}
+
+/// Converts the error of acquiring a lock, blocking if `wait` is true.
+///
+/// A blocking wait only fails with `WouldBlock` if the thread is cancelled,
+/// and it's a cancellation point.
+fn lock_err(e: AxError, wait: bool) -> LinuxError {
+    #[cfg(feature = "multitask")]
+    if wait && e == AxError::WouldBlock {
+        super::pthread::sys_pthread_testcancel();
+        return LinuxError::EINTR;
+    }
+    #[cfg(not(feature = "multitask"))]
+    let _ = wait;
+    e.into()
+}
+
+/// Handle the `F_GETLK`, `F_SETLK` and `F_SETLKW` commands of `fcntl`, with
+/// `arg` pointing to a `struct flock`.
+pub(crate) fn fcntl_lock(fd: c_int, cmd: u32, arg: usize) -> LinuxResult<c_int> {
+    let fl = arg as *mut ctypes::flock;
+    if fl.is_null() {
+        return Err(LinuxError::EFAULT);
+    }
+    // SAFETY: `arg` is trusted as other pointer arguments of syscalls
+    let fl = unsafe { &mut *fl };
+    let file = File::from_fd(fd).map_err(|_| LinuxError::EBADF)?;
+    // the file is not locked while waiting, as other tasks may unlock with it
+    let (lock, handle) = {
+        let mut file = file.inner.lock();
+        (flock_to_lock(&mut file, fl)?, file.lock_handle())
+    };
+    let res = match cmd {
+        ctypes::F_GETLK => handle.get_lock(&lock).map(|conflict| match conflict {
+            Some(conflict) => {
+                fl.l_type = match conflict.ty {
+                    LockType::Exclusive => ctypes::F_WRLCK as _,
+                    _ => ctypes::F_RDLCK as _,
+                };
+                fl.l_whence = 0; // SEEK_SET
+                fl.l_start = conflict.start as _;
+                fl.l_len = conflict.len as _;
+                fl.l_pid = conflict.owner as _;
+            }
+            None => fl.l_type = ctypes::F_UNLCK as _,
+        }),
+        _ => handle.set_lock(&lock, cmd == ctypes::F_SETLKW),
+    };
+    res.map_err(|e| match e {
+        AxError::PermissionDenied => LinuxError::EBADF,
+        e => lock_err(e, cmd == ctypes::F_SETLKW),
+    })?;
+    Ok(0)
+}
+
+/// Apply or remove an advisory lock on the whole file `fd`, as `flock`.
+///
+/// `operation` is one of `LOCK_SH`, `LOCK_EX` and `LOCK_UN`, optionally with
+/// `LOCK_NB` to return `EWOULDBLOCK` instead of blocking.
+pub fn sys_flock(fd: c_int, operation: c_int) -> c_int {
+    debug!("sys_flock <= {} {:#x}", fd, operation);
+    syscall_body!(sys_flock, {
+        let operation = operation as u32;
+        let ty = match operation & !ctypes::LOCK_NB {
+            ctypes::LOCK_SH => LockType::Shared,
+            ctypes::LOCK_EX => LockType::Exclusive,
+            ctypes::LOCK_UN => LockType::Unlock,
+            _ => return Err(LinuxError::EINVAL),
+        };
+        let file = File::from_fd(fd).map_err(|_| LinuxError::EBADF)?;
+        let handle = file.inner.lock().lock_handle();
+        let wait = operation & ctypes::LOCK_NB == 0;
+        handle.flock(ty, wait).map_err(|e| lock_err(e, wait))?;
         Ok(0)
     })
 }
@@ -0,0 +1,159 @@
+//! `inotify` implementation, on the watchers of [`axfs`].
+
+use alloc::sync::Arc;
+use core::ffi::{c_char, c_int};
+use core::mem::size_of;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use axerrno::{LinuxError, LinuxResult};
+use axfs::api::{FsEvent, WatchMask, Watcher};
+use axio::PollState;
+
+use super::fd_ops::{add_file_like, get_file_like, FileLike};
+use crate::{ctypes, utils::char_ptr_to_str};
+
+const EVENT_SIZE: usize = size_of::<ctypes::inotify_event>();
+
+pub struct Inotify {
+    watcher: Watcher,
+    nonblocking: AtomicBool,
+}
+
+impl Inotify {
+    fn new(nonblocking: bool) -> Self {
+        Self {
+            watcher: Watcher::new(),
+            nonblocking: AtomicBool::new(nonblocking),
+        }
+    }
+
+    fn from_fd(fd: c_int) -> LinuxResult<Arc<Self>> {
+        get_file_like(fd)?
+            .into_any()
+            .downcast::<Self>()
+            .map_err(|_| LinuxError::EINVAL)
+    }
+}
+
+/// Returns the length of the name of `event` in `struct inotify_event`, which
+/// is null-terminated and padded to the size of the struct.
+fn name_len(event: &FsEvent) -> usize {
+    if event.name.is_empty() {
+        0
+    } else {
+        (event.name.len() + 1).next_multiple_of(EVENT_SIZE)
+    }
+}
+
+/// Writes `event` as `struct inotify_event` followed by the name to `buf`, or
+/// returns `false` if it doesn't fit.
+fn write_event(buf: &mut [u8], event: &FsEvent) -> bool {
+    let len = name_len(event);
+    if buf.len() < EVENT_SIZE + len {
+        return false;
+    }
+    let (head, name) = buf[..EVENT_SIZE + len].split_at_mut(EVENT_SIZE);
+    // the fields `wd`, `mask`, `cookie` and `len`
+    let fields = [event.wd as u32, event.mask.bits(), event.cookie, len as u32];
+    for (bytes, field) in head.chunks_exact_mut(4).zip(fields) {
+        bytes.copy_from_slice(&field.to_ne_bytes());
+    }
+    name.fill(0);
+    name[..event.name.len()].copy_from_slice(event.name.as_bytes());
+    true
+}
+
+impl FileLike for Inotify {
+    fn read(&self, buf: &mut [u8]) -> LinuxResult<usize> {
+        let wait = !self.nonblocking.load(Ordering::Relaxed);
+        let mut read_len = 0;
+        self.watcher.read_events(wait, |event| {
+            let written = write_event(&mut buf[read_len..], event);
+            if written {
+                read_len += EVENT_SIZE + name_len(event);
+            }
+            written
+        })?;
+        if read_len == 0 {
+            // the buffer is too small for the first event
+            return Err(LinuxError::EINVAL);
+        }
+        Ok(read_len)
+    }
+
+    fn write(&self, _buf: &[u8]) -> LinuxResult<usize> {
+        Err(LinuxError::EINVAL)
+    }
+
+    fn stat(&self) -> LinuxResult<ctypes::stat> {
+        Ok(ctypes::stat {
+            st_ino: 1,
+            st_nlink: 1,
+            st_mode: 0o600, // rw-------
+            st_uid: 1000,
+            st_gid: 1000,
+            st_blksize: 4096,
+            ..Default::default()
+        })
+    }
+
+    fn into_any(self: Arc<Self>) -> Arc<dyn core::any::Any + Send + Sync> {
+        self
+    }
+
+    fn poll(&self) -> LinuxResult<PollState> {
+        Ok(PollState {
+            readable: self.watcher.has_events(),
+            writable: false,
+        })
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> LinuxResult {
+        self.nonblocking.store(nonblocking, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+/// Creates an inotify instance, and returns a file descriptor referring to
+/// it. `IN_NONBLOCK` and `IN_CLOEXEC` are supported in `flags`.
+///
+/// Events are read from the file descriptor as `struct inotify_event`, and
+/// it's readable in `select` and `epoll` while there are events queued.
+pub fn sys_inotify_init1(flags: c_int) -> c_int {
+    debug!("sys_inotify_init1 <= {:#x}", flags);
+    syscall_body!(sys_inotify_init1, {
+        // `IN_NONBLOCK` and `IN_CLOEXEC` are `O_NONBLOCK` and `O_CLOEXEC`
+        let flags = flags as u32;
+        if flags & !(ctypes::O_NONBLOCK | ctypes::O_CLOEXEC) != 0 {
+            return Err(LinuxError::EINVAL);
+        }
+        let inotify = Inotify::new(flags & ctypes::O_NONBLOCK != 0);
+        add_file_like(Arc::new(inotify))
+    })
+}
+
+/// Watches the file or directory at `path` for the events in `mask` with the
+/// inotify instance `fd`.
+///
+/// Returns the watch descriptor, which is the same one if `path` is already
+/// watched by the instance.
+pub fn sys_inotify_add_watch(fd: c_int, path: *const c_char, mask: u32) -> c_int {
+    let path = char_ptr_to_str(path);
+    debug!("sys_inotify_add_watch <= {} {:?} {:#x}", fd, path, mask);
+    syscall_body!(sys_inotify_add_watch, {
+        let inotify = Inotify::from_fd(fd)?;
+        let mask = WatchMask::from_bits_truncate(mask);
+        Ok(inotify.watcher.add_watch(path?, mask)?)
+    })
+}
+
+/// Removes the watch `wd` from the inotify instance `fd`.
+///
+/// Return 0 if success.
+pub fn sys_inotify_rm_watch(fd: c_int, wd: c_int) -> c_int {
+    debug!("sys_inotify_rm_watch <= {} {}", fd, wd);
+    syscall_body!(sys_inotify_rm_watch, {
+        Inotify::from_fd(fd)?.watcher.remove_watch(wd)?;
+        Ok(0)
+    })
+}
@@ -10,6 +10,9 @@ use crate::ctypes;
 
 pub mod mutex;
 
+/// The return value of cancelled threads.
+const PTHREAD_CANCELED: *mut c_void = -1isize as _;
+
 lazy_static::lazy_static! {
     static ref TID_TO_PTHREAD: RwLock<BTreeMap<u64, ForceSendSync<ctypes::pthread_t>>> = {
         let mut map = BTreeMap::new();
@@ -93,14 +96,34 @@ impl Pthread {
             return Err(LinuxError::EDEADLK);
         }
 
+        let thread = unsafe { &*(ptr as *const Pthread) };
+        if thread.inner.join_interruptible().is_err() {
+            Self::exit_current(PTHREAD_CANCELED);
+        }
         let thread = unsafe { Box::from_raw(ptr as *mut Pthread) };
-        thread.inner.join();
         let tid = thread.inner.id().as_u64();
         let retval = unsafe { *thread.retval.result.get() };
         TID_TO_PTHREAD.write().remove(&tid);
         drop(thread);
         Ok(retval)
     }
+
+    /// Returns the task of the thread `ptr`, or `ESRCH` if there's no such
+    /// thread.
+    fn task(ptr: ctypes::pthread_t) -> LinuxResult<AxTaskRef> {
+        // hold the table, so the thread can't be joined and freed meanwhile.
+        let threads = TID_TO_PTHREAD.read();
+        if !threads.values().any(|thread| core::ptr::eq(thread.0, ptr)) {
+            return Err(LinuxError::ESRCH);
+        }
+        let thread = unsafe { &*(ptr as *const Pthread) };
+        Ok(thread.inner.clone())
+    }
+
+    fn cancel(ptr: ctypes::pthread_t) -> LinuxResult {
+        Self::task(ptr)?.cancel();
+        Ok(())
+    }
 }
 
 /// Returns the `pthread` struct of current thread.
@@ -147,6 +170,61 @@ pub unsafe fn sys_pthread_join(thread: ctypes::pthread_t, retval: *mut *mut c_vo
     })
 }
 
+/// Requests the given thread to be cancelled.
+///
+/// The cancellation is deferred: the thread exits with `PTHREAD_CANCELED` at
+/// its next cancellation point, i.e. `pthread_testcancel`, `pthread_join`,
+/// `nanosleep`, or waiting for a file lock.
+///
+/// Return 0 if success.
+pub fn sys_pthread_cancel(thread: ctypes::pthread_t) -> c_int {
+    debug!("sys_pthread_cancel <= {:#x}", thread as usize);
+    syscall_body!(sys_pthread_cancel, {
+        Pthread::cancel(thread)?;
+        Ok(0)
+    })
+}
+
+/// Exits the current thread with `PTHREAD_CANCELED` if it's requested to be
+/// cancelled.
+pub fn sys_pthread_testcancel() {
+    if axtask::current().is_cancelled() {
+        Pthread::exit_current(PTHREAD_CANCELED);
+    }
+}
+
+/// Sets the CPUs that the given thread is allowed to run on.
+///
+/// Return 0 if success.
+pub unsafe fn sys_pthread_setaffinity_np(
+    thread: ctypes::pthread_t,
+    cpusetsize: usize,
+    cpuset: *const ctypes::cpu_set_t,
+) -> c_int {
+    debug!("sys_pthread_setaffinity_np <= {:#x}", thread as usize);
+    syscall_body!(sys_pthread_setaffinity_np, {
+        let task = Pthread::task(thread)?;
+        super::task::set_task_affinity(&task, cpusetsize, cpuset)?;
+        Ok(0)
+    })
+}
+
+/// Gets the CPUs that the given thread is allowed to run on.
+///
+/// Return 0 if success.
+pub unsafe fn sys_pthread_getaffinity_np(
+    thread: ctypes::pthread_t,
+    cpusetsize: usize,
+    cpuset: *mut ctypes::cpu_set_t,
+) -> c_int {
+    debug!("sys_pthread_getaffinity_np <= {:#x}", thread as usize);
+    syscall_body!(sys_pthread_getaffinity_np, {
+        let task = Pthread::task(thread)?;
+        super::task::get_task_affinity(&task, cpusetsize, cpuset)?;
+        Ok(0)
+    })
+}
+
 #[derive(Clone, Copy)]
 struct ForceSendSync<T>(T);
 
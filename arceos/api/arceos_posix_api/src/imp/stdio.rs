@@ -14,6 +14,24 @@ fn console_write_bytes(buf: &[u8]) -> AxResult<usize> {
     Ok(buf.len())
 }
 
+/// Performs `ioctl` on the console. Only `TIOCGWINSZ` is supported, which
+/// reports the traditional 80x24 size, as the real size is unknown.
+#[cfg(feature = "fd")]
+fn console_ioctl(cmd: usize, arg: usize) -> LinuxResult<core::ffi::c_int> {
+    const TIOCGWINSZ: usize = 0x5413;
+    if cmd != TIOCGWINSZ {
+        return Err(LinuxError::ENOTTY);
+    }
+    if arg == 0 {
+        return Err(LinuxError::EFAULT);
+    }
+    // struct winsize { ws_row, ws_col, ws_xpixel, ws_ypixel }
+    let winsize: [u16; 4] = [24, 80, 0, 0];
+    // SAFETY: `arg` is trusted as other pointer arguments of syscalls
+    unsafe { (arg as *mut [u16; 4]).write_unaligned(winsize) };
+    Ok(0)
+}
+
 struct StdinRaw;
 struct StdoutRaw;
 
@@ -131,6 +149,10 @@ impl super::fd_ops::FileLike for Stdin {
     fn set_nonblocking(&self, _nonblocking: bool) -> LinuxResult {
         Ok(())
     }
+
+    fn ioctl(&self, cmd: usize, arg: usize) -> LinuxResult<core::ffi::c_int> {
+        console_ioctl(cmd, arg)
+    }
 }
 
 #[cfg(feature = "fd")]
@@ -167,4 +189,8 @@ impl super::fd_ops::FileLike for Stdout {
     fn set_nonblocking(&self, _nonblocking: bool) -> LinuxResult {
         Ok(())
     }
+
+    fn ioctl(&self, cmd: usize, arg: usize) -> LinuxResult<core::ffi::c_int> {
+        console_ioctl(cmd, arg)
+    }
 }
@@ -1,5 +1,17 @@
 use core::ffi::c_int;
 
+#[cfg(feature = "multitask")]
+use {
+    crate::ctypes,
+    axerrno::{LinuxError, LinuxResult},
+    axtask::{AxTaskRef, CpuMask, DeadlineParams, SchedClass},
+    core::{
+        ffi::{c_uint, c_ulong},
+        mem::size_of,
+        time::Duration,
+    },
+};
+
 /// Relinquish the CPU, and switches to another task.
 ///
 /// For single-threaded configuration (`multitask` feature is disabled), we just
@@ -36,5 +48,211 @@ pub fn sys_exit(exit_code: c_int) -> ! {
     #[cfg(feature = "multitask")]
     axtask::exit(exit_code);
     #[cfg(not(feature = "multitask"))]
-    axhal::misc::terminate();
+    axruntime::terminate();
+}
+
+/// Reads the CPU mask from `cpuset` of `cpusetsize` bytes.
+#[cfg(feature = "multitask")]
+fn read_cpu_set(cpusetsize: usize, cpuset: *const ctypes::cpu_set_t) -> LinuxResult<CpuMask> {
+    if cpuset.is_null() {
+        return Err(LinuxError::EFAULT);
+    }
+    // CPUs that don't exist are ignored, they are all in the first word.
+    if cpusetsize < size_of::<c_ulong>() {
+        return Err(LinuxError::EINVAL);
+    }
+    let bits = unsafe { (*cpuset).__bits[0] };
+    Ok(CpuMask::from_bits(bits as usize))
+}
+
+/// Writes `cpumask` to `cpuset` of `cpusetsize` bytes.
+#[cfg(feature = "multitask")]
+fn write_cpu_set(
+    cpumask: CpuMask,
+    cpusetsize: usize,
+    cpuset: *mut ctypes::cpu_set_t,
+) -> LinuxResult {
+    if cpuset.is_null() {
+        return Err(LinuxError::EFAULT);
+    }
+    if cpusetsize < size_of::<c_ulong>() {
+        return Err(LinuxError::EINVAL);
+    }
+    let len = cpusetsize.min(size_of::<ctypes::cpu_set_t>());
+    unsafe {
+        core::ptr::write_bytes(cpuset as *mut u8, 0, len);
+        (*cpuset).__bits[0] = cpumask.bits() as c_ulong;
+    }
+    Ok(())
+}
+
+/// Sets the CPUs that `task` is allowed to run on to the ones in `cpuset`.
+#[cfg(feature = "multitask")]
+pub(crate) fn set_task_affinity(
+    task: &AxTaskRef,
+    cpusetsize: usize,
+    cpuset: *const ctypes::cpu_set_t,
+) -> LinuxResult {
+    let cpumask = read_cpu_set(cpusetsize, cpuset)?;
+    if axtask::online_cpus().bits() & cpumask.bits() == 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    if task.id() == axtask::current().id() {
+        // migrate the current task at once
+        axtask::set_affinity(cpumask);
+    } else {
+        task.set_cpumask(cpumask);
+    }
+    Ok(())
+}
+
+/// Stores the CPUs that `task` is allowed to run on in `cpuset`.
+#[cfg(feature = "multitask")]
+pub(crate) fn get_task_affinity(
+    task: &AxTaskRef,
+    cpusetsize: usize,
+    cpuset: *mut ctypes::cpu_set_t,
+) -> LinuxResult {
+    write_cpu_set(task.cpumask(), cpusetsize, cpuset)
+}
+
+/// Finds the task with ID `pid`, or the current task if `pid` is 0.
+#[cfg(feature = "multitask")]
+fn find_task(pid: c_int) -> LinuxResult<AxTaskRef> {
+    if pid == 0 {
+        return Ok(axtask::current().as_task_ref().clone());
+    }
+    axtask::all_tasks()
+        .into_iter()
+        .find(|task| task.id().as_u64() == pid as u64)
+        .ok_or(LinuxError::ESRCH)
+}
+
+/// Sets the CPUs that the thread `pid` is allowed to run on, or the current
+/// thread if `pid` is 0.
+///
+/// Return 0 if success.
+#[cfg(feature = "multitask")]
+pub unsafe fn sys_sched_setaffinity(
+    pid: c_int,
+    cpusetsize: usize,
+    cpuset: *const ctypes::cpu_set_t,
+) -> c_int {
+    debug!("sys_sched_setaffinity <= {} {}", pid, cpusetsize);
+    syscall_body!(sys_sched_setaffinity, {
+        set_task_affinity(&find_task(pid)?, cpusetsize, cpuset)?;
+        Ok(0)
+    })
+}
+
+/// Gets the CPUs that the thread `pid` is allowed to run on, or the current
+/// thread if `pid` is 0.
+///
+/// Return 0 if success.
+#[cfg(feature = "multitask")]
+pub unsafe fn sys_sched_getaffinity(
+    pid: c_int,
+    cpusetsize: usize,
+    cpuset: *mut ctypes::cpu_set_t,
+) -> c_int {
+    debug!("sys_sched_getaffinity <= {} {}", pid, cpusetsize);
+    syscall_body!(sys_sched_getaffinity, {
+        get_task_affinity(&find_task(pid)?, cpusetsize, cpuset)?;
+        Ok(0)
+    })
+}
+
+/// Returns `Ok` if `pid` is 0 or the ID of the current thread, the scheduling
+/// attributes of other threads can't be accessed yet.
+#[cfg(feature = "multitask")]
+fn check_current(pid: c_int) -> LinuxResult {
+    if pid == 0 || pid as u64 == axtask::current().id().as_u64() {
+        return Ok(());
+    }
+    find_task(pid)?;
+    Err(LinuxError::EPERM)
+}
+
+/// Sets the scheduling policy and attributes of the current thread.
+///
+/// `SCHED_DEADLINE` makes it a real-time thread, and `SCHED_IDLE` makes it an
+/// idle thread, which are only supported by the hierarchical scheduler.
+/// `SCHED_OTHER` and `SCHED_BATCH` make it a normal thread with the nice value
+/// `sched_nice`.
+///
+/// Return 0 if success.
+#[cfg(feature = "multitask")]
+pub unsafe fn sys_sched_setattr(
+    pid: c_int,
+    attr: *const ctypes::sched_attr,
+    flags: c_uint,
+) -> c_int {
+    debug!("sys_sched_setattr <= {} {:#x}", pid, flags);
+    syscall_body!(sys_sched_setattr, {
+        if attr.is_null() || flags != 0 {
+            return Err(LinuxError::EINVAL);
+        }
+        check_current(pid)?;
+        let attr = unsafe { &*attr };
+        let class = match attr.sched_policy {
+            ctypes::SCHED_DEADLINE => {
+                let period = if attr.sched_period == 0 {
+                    attr.sched_deadline
+                } else {
+                    attr.sched_period
+                };
+                SchedClass::RealTime(DeadlineParams {
+                    runtime: Duration::from_nanos(attr.sched_runtime),
+                    deadline: Duration::from_nanos(attr.sched_deadline),
+                    period: Duration::from_nanos(period),
+                })
+            }
+            ctypes::SCHED_OTHER | ctypes::SCHED_BATCH => SchedClass::Normal(attr.sched_nice as _),
+            ctypes::SCHED_IDLE => SchedClass::Idle,
+            _ => return Err(LinuxError::EINVAL),
+        };
+        if !axtask::set_sched_class(class) {
+            return Err(LinuxError::EINVAL);
+        }
+        Ok(0)
+    })
+}
+
+/// Gets the scheduling policy and attributes of the current thread, and
+/// stores them in `attr` of `size` bytes.
+///
+/// Return 0 if success.
+#[cfg(feature = "multitask")]
+pub unsafe fn sys_sched_getattr(
+    pid: c_int,
+    attr: *mut ctypes::sched_attr,
+    size: c_uint,
+    flags: c_uint,
+) -> c_int {
+    debug!("sys_sched_getattr <= {} {} {:#x}", pid, size, flags);
+    syscall_body!(sys_sched_getattr, {
+        if attr.is_null() || flags != 0 || (size as usize) < size_of::<ctypes::sched_attr>() {
+            return Err(LinuxError::EINVAL);
+        }
+        check_current(pid)?;
+        let mut res = ctypes::sched_attr {
+            size: size_of::<ctypes::sched_attr>() as _,
+            ..Default::default()
+        };
+        match axtask::get_sched_class() {
+            SchedClass::RealTime(params) => {
+                res.sched_policy = ctypes::SCHED_DEADLINE;
+                res.sched_runtime = params.runtime.as_nanos() as _;
+                res.sched_deadline = params.deadline.as_nanos() as _;
+                res.sched_period = params.period.as_nanos() as _;
+            }
+            SchedClass::Normal(nice) => {
+                res.sched_policy = ctypes::SCHED_OTHER;
+                res.sched_nice = nice as _;
+            }
+            SchedClass::Idle => res.sched_policy = ctypes::SCHED_IDLE,
+        }
+        unsafe { attr.write(res) };
+        Ok(0)
+    })
 }
@@ -57,6 +57,8 @@ pub unsafe fn sys_clock_gettime(clk: ctypes::clockid_t, ts: *mut ctypes::timespe
 
 /// Sleep some nanoseconds
 ///
+/// It's a cancellation point, the thread exits if it's cancelled.
+///
 /// TODO: should be woken by signals, and set errno
 pub unsafe fn sys_nanosleep(req: *const ctypes::timespec, rem: *mut ctypes::timespec) -> c_int {
     syscall_body!(sys_nanosleep, {
@@ -74,7 +76,9 @@ pub unsafe fn sys_nanosleep(req: *const ctypes::timespec, rem: *mut ctypes::time
         let now = axhal::time::monotonic_time();
 
         #[cfg(feature = "multitask")]
-        axtask::sleep(dur);
+        if axtask::sleep_interruptible(dur).is_err() {
+            super::pthread::sys_pthread_testcancel();
+        }
         #[cfg(not(feature = "multitask"))]
         axhal::time::busy_wait(dur);
 
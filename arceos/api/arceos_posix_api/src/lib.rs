@@ -37,9 +37,15 @@ pub use imp::task::{sys_exit, sys_getpid, sys_sched_yield};
 pub use imp::time::{sys_clock_gettime, sys_nanosleep};
 
 #[cfg(feature = "fd")]
-pub use imp::fd_ops::{sys_close, sys_dup, sys_dup2, sys_fcntl, get_file_like};
+pub use imp::fd_ops::{sys_close, sys_dup, sys_dup2, sys_fcntl, sys_ioctl, get_file_like};
 #[cfg(feature = "fs")]
-pub use imp::fs::{sys_fstat, sys_getcwd, sys_lseek, sys_lstat, sys_open, sys_rename, sys_stat};
+pub use imp::fs::{
+    sys_chdir, sys_chroot, sys_flock, sys_fstat, sys_fstatfs, sys_getcwd, sys_link, sys_lseek,
+    sys_lstat, sys_mount, sys_open, sys_readlink, sys_rename, sys_stat, sys_statfs, sys_symlink,
+    sys_umount2, sys_utimensat,
+};
+#[cfg(feature = "fs")]
+pub use imp::inotify::{sys_inotify_add_watch, sys_inotify_init1, sys_inotify_rm_watch};
 #[cfg(feature = "select")]
 pub use imp::io_mpx::sys_select;
 #[cfg(feature = "epoll")]
@@ -57,4 +63,11 @@ pub use imp::pthread::mutex::{
     sys_pthread_mutex_init, sys_pthread_mutex_lock, sys_pthread_mutex_unlock,
 };
 #[cfg(feature = "multitask")]
-pub use imp::pthread::{sys_pthread_create, sys_pthread_exit, sys_pthread_join, sys_pthread_self};
+pub use imp::pthread::{
+    sys_pthread_cancel, sys_pthread_create, sys_pthread_exit, sys_pthread_getaffinity_np,
+    sys_pthread_join, sys_pthread_self, sys_pthread_setaffinity_np, sys_pthread_testcancel,
+};
+#[cfg(feature = "multitask")]
+pub use imp::task::{
+    sys_sched_getaffinity, sys_sched_getattr, sys_sched_setaffinity, sys_sched_setattr,
+};
@@ -19,9 +19,12 @@
 //!     - `sched_fifo`: Use the FIFO cooperative scheduler.
 //!     - `sched_rr`: Use the Round-robin preemptive scheduler.
 //!     - `sched_cfs`: Use the Completely Fair Scheduler (CFS) preemptive scheduler.
+//!     - `sched_class`: Use the hierarchical scheduler with real-time, normal and idle classes.
+//!     - `sched_edf`: An alias of `sched_class`, for real-time tasks with deadlines.
 //! - Upperlayer stacks (fs, net, display)
 //!     - `fs`: Enable file system support.
 //!     - `myfs`: Allow users to define their custom filesystems to override the default.
+//!     - `initramfs`: Use the initramfs as the root filesystem, without requiring a block device.
 //!     - `net`: Enable networking support.
 //!     - `display`: Enable graphics support.
 //! - Device drivers
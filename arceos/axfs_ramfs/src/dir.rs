@@ -1,12 +1,17 @@
 use alloc::collections::BTreeMap;
 use alloc::sync::{Arc, Weak};
 use alloc::{string::String, vec::Vec};
+use core::ptr;
+use core::time::Duration;
 
 use axfs_vfs::{VfsDirEntry, VfsNodeAttr, VfsNodeOps, VfsNodeRef, VfsNodeType};
 use axfs_vfs::{VfsError, VfsResult};
 use spin::RwLock;
 
 use crate::file::FileNode;
+use crate::quota::Quota;
+use crate::symlink::SymlinkNode;
+use crate::time::{NodeTimes, Timestamps};
 
 /// The directory node in the RAM filesystem.
 ///
@@ -15,14 +20,19 @@ pub struct DirNode {
     this: Weak<DirNode>,
     parent: RwLock<Weak<dyn VfsNodeOps>>,
     children: RwLock<BTreeMap<String, VfsNodeRef>>,
+    times: Timestamps,
+    quota: Arc<Quota>,
 }
 
 impl DirNode {
-    pub(super) fn new(parent: Option<Weak<dyn VfsNodeOps>>) -> Arc<Self> {
+    /// Creates a new directory, which must have been charged to `quota`.
+    pub(super) fn new(parent: Option<Weak<dyn VfsNodeOps>>, quota: Arc<Quota>) -> Arc<Self> {
         Arc::new_cyclic(|this| Self {
             this: this.clone(),
             parent: RwLock::new(parent.unwrap_or_else(|| Weak::<Self>::new())),
             children: RwLock::new(BTreeMap::new()),
+            times: Timestamps::new(),
+            quota,
         })
     }
 
@@ -35,23 +45,76 @@ impl DirNode {
         self.children.read().keys().cloned().collect()
     }
 
+    /// Returns the timestamps of the node.
+    pub fn times(&self) -> NodeTimes {
+        self.times.get()
+    }
+
+    /// Sets the access and modification times of the node, the ones of `None`
+    /// are left unchanged. The change time is set to now.
+    pub fn set_times(&self, accessed: Option<Duration>, modified: Option<Duration>) {
+        self.times.set(accessed, modified)
+    }
+
+    /// Returns the usage of the filesystem the directory is on, as
+    /// [`RamFileSystem::usage`](crate::RamFileSystem::usage).
+    pub fn fs_usage(&self) -> (u64, u64) {
+        self.quota.usage()
+    }
+
+    /// Returns the limits of the filesystem the directory is on, as
+    /// [`RamFileSystem::limits`](crate::RamFileSystem::limits).
+    pub fn fs_limits(&self) -> (Option<u64>, Option<u64>) {
+        self.quota.limits()
+    }
+
     /// Checks whether a node with the given name exists in this directory.
     pub fn exist(&self, name: &str) -> bool {
         self.children.read().contains_key(name)
     }
 
     /// Creates a new node with the given name and type in this directory.
+    ///
+    /// Fails with [`VfsError::StorageFull`] if the filesystem has reached its
+    /// limit of nodes.
     pub fn create_node(&self, name: &str, ty: VfsNodeType) -> VfsResult {
         if self.exist(name) {
             log::error!("AlreadyExists {}", name);
             return Err(VfsError::AlreadyExists);
         }
+        if !matches!(
+            ty,
+            VfsNodeType::File | VfsNodeType::Dir | VfsNodeType::SymLink
+        ) {
+            return Err(VfsError::Unsupported);
+        }
+        self.quota.alloc_node()?;
+        let quota = self.quota.clone();
         let node: VfsNodeRef = match ty {
-            VfsNodeType::File => Arc::new(FileNode::new()),
-            VfsNodeType::Dir => Self::new(Some(self.this.clone())),
-            _ => return Err(VfsError::Unsupported),
+            VfsNodeType::File => Arc::new(FileNode::new(quota)),
+            VfsNodeType::Dir => Self::new(Some(self.this.clone()), quota),
+            VfsNodeType::SymLink => Arc::new(SymlinkNode::new(quota)),
+            _ => unreachable!(),
         };
         self.children.write().insert(name.into(), node);
+        self.times.modify();
+        Ok(())
+    }
+
+    /// Adds a hard link to `node` with the given name in this directory.
+    ///
+    /// Directories can't be linked, and `node` must be from the same
+    /// filesystem.
+    pub fn link_node(&self, name: &str, node: VfsNodeRef) -> VfsResult {
+        if node.as_any().is::<DirNode>() {
+            return Err(VfsError::PermissionDenied);
+        }
+        let mut children = self.children.write();
+        if children.contains_key(name) {
+            return Err(VfsError::AlreadyExists);
+        }
+        children.insert(name.into(), node);
+        self.times.modify();
         Ok(())
     }
 
@@ -65,23 +128,102 @@ impl DirNode {
             }
         }
         children.remove(name);
+        self.times.modify();
         Ok(())
     }
 
     /// Rename a node by the given old name and new name in this directory.
     pub fn rename_node(&self, old_name: &str, new_name: &str) -> VfsResult {
-        let mut children = self.children.write();
+        self.move_node(old_name, self, new_name)
+    }
+
+    /// Moves a node by the given old name in this directory to the new name
+    /// in `dst`, atomically replacing the node there if it exists.
+    ///
+    /// A directory can only replace an empty directory, and a non-directory
+    /// can't replace a directory. Moving a directory into itself fails.
+    pub fn move_node(&self, old_name: &str, dst: &DirNode, new_name: &str) -> VfsResult {
+        // lock in address order to avoid deadlocks with a move the other way
+        let same_dir = ptr::eq(self, dst);
+        let (mut children, mut dst_children) = if same_dir {
+            (self.children.write(), None)
+        } else if (self as *const Self) < (dst as *const Self) {
+            let children = self.children.write();
+            (children, Some(dst.children.write()))
+        } else {
+            let dst_children = dst.children.write();
+            (self.children.write(), Some(dst_children))
+        };
+
         let node = children.get(old_name).ok_or(VfsError::NotFound)?.clone();
-        if let Some(dir) = node.as_any().downcast_ref::<DirNode>() {
-            if !dir.children.read().is_empty() {
-                return Err(VfsError::DirectoryNotEmpty);
+        let old = match &dst_children {
+            Some(dst_children) => dst_children.get(new_name),
+            None => children.get(new_name),
+        };
+        let moved_dir = node.as_any().downcast_ref::<DirNode>();
+        if let Some(old) = old {
+            if Arc::ptr_eq(old, &node) {
+                return Ok(()); // the same node, e.g. hard links
+            }
+            match (moved_dir, old.as_any().downcast_ref::<DirNode>()) {
+                (Some(_), None) => return Err(VfsError::NotADirectory),
+                (None, Some(_)) => return Err(VfsError::IsADirectory),
+                // `self` is locked, and not empty anyway
+                (Some(_), Some(old_dir)) if ptr::eq(old_dir, self) => {
+                    return Err(VfsError::DirectoryNotEmpty)
+                }
+                (Some(_), Some(old_dir)) if !old_dir.children.read().is_empty() => {
+                    return Err(VfsError::DirectoryNotEmpty)
+                }
+                _ => {}
+            }
+        }
+        if let Some(dir) = moved_dir {
+            if !same_dir && dst.is_inside(dir) {
+                return Err(VfsError::InvalidInput);
             }
+            *dir.parent.write() = dst.this.clone();
         }
 
-        children.insert(new_name.into(), node);
         children.remove(old_name);
+        match &mut dst_children {
+            Some(dst_children) => dst_children.insert(new_name.into(), node),
+            None => children.insert(new_name.into(), node),
+        };
+        self.times.modify();
+        if !same_dir {
+            dst.times.modify();
+        }
         Ok(())
     }
+
+    /// Whether this directory is `dir` or inside it.
+    fn is_inside(&self, dir: &DirNode) -> bool {
+        let mut cur = self.this.upgrade().map(|this| this as VfsNodeRef);
+        while let Some(node) = cur {
+            if node
+                .as_any()
+                .downcast_ref::<DirNode>()
+                .is_some_and(|d| ptr::eq(d, dir))
+            {
+                return true;
+            }
+            cur = node.parent();
+        }
+        false
+    }
+
+    /// Looks up the parent directory of `path`, and returns it with the last
+    /// component of `path`.
+    fn lookup_parent<'a>(&self, path: &'a str) -> VfsResult<(VfsNodeRef, &'a str)> {
+        let path = path.trim_end_matches('/');
+        let (parent, name) = path.rsplit_once('/').unwrap_or(("", path));
+        if name.is_empty() || name == "." || name == ".." {
+            return Err(VfsError::InvalidInput);
+        }
+        let this = self.this.upgrade().ok_or(VfsError::NotFound)?;
+        Ok((this.lookup(parent)?, name))
+    }
 }
 
 impl VfsNodeOps for DirNode {
@@ -182,37 +324,25 @@ impl VfsNodeOps for DirNode {
 
     fn rename(&self, src_path: &str, dst_path: &str) -> VfsResult {
         log::debug!("rename at ramfs: {}, new name: {}", src_path, dst_path);
-        log::debug!("{:?}", self.get_entries());
-
-        let (name, rest) = split_path(src_path);
-        let (new_name, new_rest) = split_path(dst_path);
-
-        if rest.is_some() && new_rest.is_some() {
-            let rest = rest.unwrap();
-            let new_rest = new_rest.unwrap();
-            match (name, new_name) {
-                ("", "") | (".", ".") => self.rename(rest, new_rest),
-                ("..", _) | (_, "..") => return Err(VfsError::InvalidInput),
-                _ => {
-                    let subdir = self
-                        .children
-                        .read()
-                        .get(name)
-                        .ok_or(VfsError::NotFound)?
-                        .clone();
-                    subdir.rename(rest, new_rest)
-                }
-            }
-        } else if name.is_empty() || name == "." || name == ".." || new_name.is_empty() || new_name == "." || new_name == ".." {
-            Err(VfsError::InvalidInput) // remove '.' or '..
-        } else {
-            self.rename_node(name, new_name)
+        let (src_dir, name) = self.lookup_parent(src_path)?;
+        let (dst_dir, new_name) = self.lookup_parent(dst_path)?;
+        let src_dir = src_dir.as_any().downcast_ref::<DirNode>();
+        let dst_dir = dst_dir.as_any().downcast_ref::<DirNode>();
+        match (src_dir, dst_dir) {
+            (Some(src_dir), Some(dst_dir)) => src_dir.move_node(name, dst_dir, new_name),
+            _ => Err(VfsError::NotADirectory),
         }
     }
 
     axfs_vfs::impl_vfs_dir_default! {}
 }
 
+impl Drop for DirNode {
+    fn drop(&mut self) {
+        self.quota.free_node();
+    }
+}
+
 fn split_path(path: &str) -> (&str, Option<&str>) {
     let trimmed_path = path.trim_start_matches('/');
     trimmed_path.find('/').map_or((trimmed_path, None), |n| {
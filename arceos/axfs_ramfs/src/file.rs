@@ -1,20 +1,40 @@
-use alloc::vec::Vec;
+use alloc::{sync::Arc, vec::Vec};
 use axfs_vfs::{impl_vfs_non_dir_default, VfsNodeAttr, VfsNodeOps, VfsResult};
+use core::time::Duration;
 use spin::RwLock;
 
+use crate::quota::Quota;
+use crate::time::{NodeTimes, Timestamps};
+
 /// The file node in the RAM filesystem.
 ///
 /// It implements [`axfs_vfs::VfsNodeOps`].
 pub struct FileNode {
     content: RwLock<Vec<u8>>,
+    times: Timestamps,
+    quota: Arc<Quota>,
 }
 
 impl FileNode {
-    pub(super) const fn new() -> Self {
+    /// Creates a new file, which must have been charged to `quota`.
+    pub(super) fn new(quota: Arc<Quota>) -> Self {
         Self {
             content: RwLock::new(Vec::new()),
+            times: Timestamps::new(),
+            quota,
         }
     }
+
+    /// Returns the timestamps of the node.
+    pub fn times(&self) -> NodeTimes {
+        self.times.get()
+    }
+
+    /// Sets the access and modification times of the node, the ones of `None`
+    /// are left unchanged. The change time is set to now.
+    pub fn set_times(&self, accessed: Option<Duration>, modified: Option<Duration>) {
+        self.times.set(accessed, modified)
+    }
 }
 
 impl VfsNodeOps for FileNode {
@@ -24,11 +44,15 @@ impl VfsNodeOps for FileNode {
 
     fn truncate(&self, size: u64) -> VfsResult {
         let mut content = self.content.write();
-        if size < content.len() as u64 {
+        let len = content.len() as u64;
+        if size < len {
             content.truncate(size as _);
+            self.quota.free_size(len - size);
         } else {
+            self.quota.alloc_size(size - len)?;
             content.resize(size as _, 0);
         }
+        self.times.modify();
         Ok(())
     }
 
@@ -38,6 +62,7 @@ impl VfsNodeOps for FileNode {
         let end = content.len().min(offset as usize + buf.len());
         let src = &content[start..end];
         buf[..src.len()].copy_from_slice(src);
+        self.times.access();
         Ok(src.len())
     }
 
@@ -45,12 +70,22 @@ impl VfsNodeOps for FileNode {
         let offset = offset as usize;
         let mut content = self.content.write();
         if offset + buf.len() > content.len() {
+            let len = content.len();
+            self.quota.alloc_size((offset + buf.len() - len) as u64)?;
             content.resize(offset + buf.len(), 0);
         }
         let dst = &mut content[offset..offset + buf.len()];
         dst.copy_from_slice(&buf[..dst.len()]);
+        self.times.modify();
         Ok(buf.len())
     }
 
     impl_vfs_non_dir_default! {}
 }
+
+impl Drop for FileNode {
+    fn drop(&mut self) {
+        self.quota.free_size(self.content.read().len() as u64);
+        self.quota.free_node();
+    }
+}
@@ -8,32 +8,63 @@ extern crate alloc;
 
 mod dir;
 mod file;
+mod quota;
+mod symlink;
+mod time;
 
 #[cfg(test)]
 mod tests;
 
 pub use self::dir::DirNode;
 pub use self::file::FileNode;
+pub use self::symlink::SymlinkNode;
+pub use self::time::{set_clock, NodeTimes};
 
 use alloc::sync::Arc;
 use axfs_vfs::{VfsNodeRef, VfsOps, VfsResult};
 use spin::once::Once;
 
+use self::quota::Quota;
+
 /// A RAM filesystem that implements [`axfs_vfs::VfsOps`].
 pub struct RamFileSystem {
     parent: Once<VfsNodeRef>,
     root: Arc<DirNode>,
+    quota: Arc<Quota>,
 }
 
 impl RamFileSystem {
     /// Create a new instance.
     pub fn new() -> Self {
+        Self::with_limits(None, None)
+    }
+
+    /// Create a new instance whose files can take at most `max_size` bytes in
+    /// total, and which can have at most `max_nodes` nodes, including the root
+    /// directory. `None` means no limit.
+    ///
+    /// Writes and creations beyond the limits fail with
+    /// [`VfsError::StorageFull`](axfs_vfs::VfsError::StorageFull).
+    pub fn with_limits(max_size: Option<u64>, max_nodes: Option<u64>) -> Self {
+        let quota = Quota::new(max_size, max_nodes);
         Self {
             parent: Once::new(),
-            root: DirNode::new(None),
+            root: DirNode::new(None, quota.clone()),
+            quota,
         }
     }
 
+    /// Returns the total size of file contents in bytes, and the number of
+    /// nodes in the filesystem.
+    pub fn usage(&self) -> (u64, u64) {
+        self.quota.usage()
+    }
+
+    /// Returns the limits given to [`RamFileSystem::with_limits`].
+    pub fn limits(&self) -> (Option<u64>, Option<u64>) {
+        self.quota.limits()
+    }
+
     /// Returns the root directory node in [`Arc<DirNode>`](DirNode).
     pub fn root_dir_node(&self) -> Arc<DirNode> {
         self.root.clone()
@@ -0,0 +1,72 @@
+use alloc::sync::Arc;
+use axfs_vfs::{VfsError, VfsResult};
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// Space used by the nodes of a RAM filesystem, checked against its limits.
+///
+/// Nodes are charged when they're created or grow, and release what they
+/// used when they're dropped, i.e. when the last link to them is removed and
+/// they're no longer open.
+pub(crate) struct Quota {
+    max_size: u64,
+    max_nodes: u64,
+    size: AtomicU64,
+    nodes: AtomicU64,
+}
+
+impl Quota {
+    /// Creates a quota with the root directory charged.
+    pub fn new(max_size: Option<u64>, max_nodes: Option<u64>) -> Arc<Self> {
+        Arc::new(Self {
+            max_size: max_size.unwrap_or(u64::MAX),
+            max_nodes: max_nodes.unwrap_or(u64::MAX),
+            size: AtomicU64::new(0),
+            nodes: AtomicU64::new(1),
+        })
+    }
+
+    /// Returns the total size of file contents in bytes, and the number of
+    /// nodes.
+    pub fn usage(&self) -> (u64, u64) {
+        (
+            self.size.load(Ordering::Relaxed),
+            self.nodes.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Returns the limits of the size and the number of nodes, `None` if
+    /// unlimited.
+    pub fn limits(&self) -> (Option<u64>, Option<u64>) {
+        let limit = |max| (max != u64::MAX).then_some(max);
+        (limit(self.max_size), limit(self.max_nodes))
+    }
+
+    /// Charges `size` bytes of content, or fails with
+    /// [`VfsError::StorageFull`] if it exceeds the limit.
+    pub fn alloc_size(&self, size: u64) -> VfsResult {
+        charge(&self.size, size, self.max_size)
+    }
+
+    pub fn free_size(&self, size: u64) {
+        self.size.fetch_sub(size, Ordering::Relaxed);
+    }
+
+    /// Charges a new node, or fails with [`VfsError::StorageFull`] if there
+    /// are too many.
+    pub fn alloc_node(&self) -> VfsResult {
+        charge(&self.nodes, 1, self.max_nodes)
+    }
+
+    pub fn free_node(&self) {
+        self.nodes.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+fn charge(counter: &AtomicU64, n: u64, max: u64) -> VfsResult {
+    counter
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+            used.checked_add(n).filter(|&new| new <= max)
+        })
+        .map(|_| ())
+        .map_err(|_| VfsError::StorageFull)
+}
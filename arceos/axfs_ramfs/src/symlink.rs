@@ -0,0 +1,83 @@
+use alloc::{sync::Arc, vec::Vec};
+use axfs_vfs::{impl_vfs_non_dir_default, VfsNodeAttr, VfsNodeOps, VfsNodePerm, VfsNodeType};
+use axfs_vfs::{VfsError, VfsResult};
+use core::time::Duration;
+use spin::RwLock;
+
+use crate::quota::Quota;
+use crate::time::{NodeTimes, Timestamps};
+
+/// The symbolic link node in the RAM filesystem.
+///
+/// The target path is read with [`read_at`](VfsNodeOps::read_at), and set by
+/// the first [`write_at`](VfsNodeOps::write_at) after the link is created.
+/// It can't be changed afterwards.
+///
+/// It implements [`axfs_vfs::VfsNodeOps`].
+pub struct SymlinkNode {
+    target: RwLock<Vec<u8>>,
+    times: Timestamps,
+    quota: Arc<Quota>,
+}
+
+impl SymlinkNode {
+    /// Creates a new symlink, which must have been charged to `quota`.
+    pub(super) fn new(quota: Arc<Quota>) -> Self {
+        Self {
+            target: RwLock::new(Vec::new()),
+            times: Timestamps::new(),
+            quota,
+        }
+    }
+
+    /// Returns the timestamps of the node.
+    pub fn times(&self) -> NodeTimes {
+        self.times.get()
+    }
+
+    /// Sets the access and modification times of the node, the ones of `None`
+    /// are left unchanged. The change time is set to now.
+    pub fn set_times(&self, accessed: Option<Duration>, modified: Option<Duration>) {
+        self.times.set(accessed, modified)
+    }
+}
+
+impl VfsNodeOps for SymlinkNode {
+    fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
+        let perm = VfsNodePerm::from_bits_truncate(0o777);
+        let size = self.target.read().len() as u64;
+        Ok(VfsNodeAttr::new(perm, VfsNodeType::SymLink, size, 0))
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
+        let target = self.target.read();
+        let start = target.len().min(offset as usize);
+        let end = target.len().min(offset as usize + buf.len());
+        let src = &target[start..end];
+        buf[..src.len()].copy_from_slice(src);
+        Ok(src.len())
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> VfsResult<usize> {
+        let mut target = self.target.write();
+        if offset != 0 || !target.is_empty() {
+            return Err(VfsError::InvalidInput);
+        }
+        self.quota.alloc_size(buf.len() as u64)?;
+        target.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn truncate(&self, _size: u64) -> VfsResult {
+        Err(VfsError::InvalidInput)
+    }
+
+    impl_vfs_non_dir_default! {}
+}
+
+impl Drop for SymlinkNode {
+    fn drop(&mut self) {
+        self.quota.free_size(self.target.read().len() as u64);
+        self.quota.free_node();
+    }
+}
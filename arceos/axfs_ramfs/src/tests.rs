@@ -134,3 +134,200 @@ fn test_ramfs() {
     assert_eq!(root.remove("./foo"), Ok(()));
     assert!(ramfs.root_dir_node().get_entries().is_empty());
 }
+
+#[test]
+fn test_ramfs_links() {
+    let ramfs = RamFileSystem::new();
+    let root = ramfs.root_dir();
+    root.create("f1", VfsNodeType::File).unwrap();
+    root.create("foo", VfsNodeType::Dir).unwrap();
+    root.create("foo/l1", VfsNodeType::SymLink).unwrap();
+
+    // the target is set once, and is not resolved by the filesystem
+    let link = root.clone().lookup("foo/l1").unwrap();
+    assert_eq!(link.get_attr().unwrap().file_type(), VfsNodeType::SymLink);
+    assert_eq!(link.write_at(0, b"../f1").unwrap(), 5);
+    assert_eq!(link.write_at(5, b"/").err(), Some(VfsError::InvalidInput));
+    assert_eq!(link.truncate(0).err(), Some(VfsError::InvalidInput));
+    let mut buf = [0; 16];
+    assert_eq!(link.read_at(0, &mut buf).unwrap(), 5);
+    assert_eq!(&buf[..5], b"../f1");
+    assert_eq!(link.get_attr().unwrap().size(), 5);
+    assert_eq!(link.lookup("x").err(), Some(VfsError::NotADirectory));
+
+    // hard links share the node
+    let f1 = root.clone().lookup("f1").unwrap();
+    let dir_foo = root.clone().lookup("foo").unwrap();
+    let dir_foo = dir_foo.as_any().downcast_ref::<DirNode>().unwrap();
+    dir_foo.link_node("f2", f1.clone()).unwrap();
+    assert_eq!(
+        dir_foo.link_node("f2", f1.clone()).err(),
+        Some(VfsError::AlreadyExists)
+    );
+    assert_eq!(
+        dir_foo.link_node("bar", root.clone()).err(),
+        Some(VfsError::PermissionDenied)
+    );
+    f1.write_at(0, b"hello").unwrap();
+    let f2 = root.clone().lookup("foo/f2").unwrap();
+    assert!(Arc::ptr_eq(&f1, &f2));
+    assert_eq!(root.remove("f1"), Ok(()));
+    assert_eq!(f2.read_at(0, &mut buf).unwrap(), 5);
+    assert_eq!(&buf[..5], b"hello");
+}
+
+#[test]
+fn test_ramfs_rename() {
+    let ramfs = RamFileSystem::new();
+    let root = ramfs.root_dir();
+    root.create("f1", VfsNodeType::File).unwrap();
+    root.create("f2", VfsNodeType::File).unwrap();
+    root.create("foo", VfsNodeType::Dir).unwrap();
+    root.create("foo/bar", VfsNodeType::Dir).unwrap();
+    root.create("foo/bar/f3", VfsNodeType::File).unwrap();
+    root.create("empty", VfsNodeType::Dir).unwrap();
+
+    // move between directories
+    let f1 = root.clone().lookup("f1").unwrap();
+    assert_eq!(root.rename("f1", "foo/bar/f1"), Ok(()));
+    assert!(Arc::ptr_eq(
+        &root.clone().lookup("foo/bar/f1").unwrap(),
+        &f1
+    ));
+    assert_eq!(root.clone().lookup("f1").err(), Some(VfsError::NotFound));
+    assert_eq!(root.rename("f1", "f4").err(), Some(VfsError::NotFound));
+
+    // replace an existing file
+    assert_eq!(root.rename("foo/bar/f1", "f2"), Ok(()));
+    assert!(Arc::ptr_eq(&root.clone().lookup("f2").unwrap(), &f1));
+    assert_eq!(root.rename("f2", "f2"), Ok(()));
+
+    // type mismatches and non-empty targets
+    assert_eq!(
+        root.rename("f2", "empty").err(),
+        Some(VfsError::IsADirectory)
+    );
+    assert_eq!(
+        root.rename("empty", "f2").err(),
+        Some(VfsError::NotADirectory)
+    );
+    assert_eq!(
+        root.rename("empty", "foo").err(),
+        Some(VfsError::DirectoryNotEmpty)
+    );
+    assert_eq!(
+        root.rename("foo/bar/f3", "foo/bar").err(),
+        Some(VfsError::IsADirectory)
+    );
+    assert_eq!(
+        root.rename("f2", "f2/x").err(),
+        Some(VfsError::NotADirectory)
+    );
+    assert_eq!(root.rename("foo", "..").err(), Some(VfsError::InvalidInput));
+
+    // a directory can't be moved into itself
+    assert_eq!(
+        root.rename("foo", "foo/bar/foo").err(),
+        Some(VfsError::InvalidInput)
+    );
+    assert_eq!(root.rename("foo", "foo"), Ok(()));
+
+    // move a non-empty directory over an empty one
+    let bar = root.clone().lookup("foo/bar").unwrap();
+    assert_eq!(root.rename("foo/bar", "empty"), Ok(()));
+    assert!(Arc::ptr_eq(&root.clone().lookup("empty").unwrap(), &bar));
+    assert!(Arc::ptr_eq(&bar.parent().unwrap(), &root));
+    assert!(root.clone().lookup("empty/f3").is_ok());
+    assert_eq!(root.rename("empty", "foo/bar"), Ok(()));
+    assert!(Arc::ptr_eq(
+        &root.clone().lookup("foo/bar/..").unwrap(),
+        &root.clone().lookup("foo").unwrap()
+    ));
+}
+
+#[test]
+fn test_ramfs_times() {
+    use axfs_vfs::VfsNodeOps;
+    use core::sync::atomic::{AtomicU64, Ordering};
+    use core::time::Duration;
+
+    static NOW: AtomicU64 = AtomicU64::new(100);
+    set_clock(|| Duration::from_secs(NOW.load(Ordering::Relaxed)));
+
+    let ramfs = RamFileSystem::new();
+    let root = ramfs.root_dir_node();
+    root.create("foo", VfsNodeType::Dir).unwrap();
+    root.create("foo/f1", VfsNodeType::File).unwrap();
+    let foo = root.clone().lookup("foo").unwrap();
+    let foo = foo.as_any().downcast_ref::<DirNode>().unwrap();
+    let f1 = root.clone().lookup("foo/f1").unwrap();
+    let f1 = f1.as_any().downcast_ref::<FileNode>().unwrap();
+    let secs = |t: Duration| t.as_secs();
+    assert_eq!(secs(f1.times().modified), 100);
+
+    // reads and writes
+    NOW.store(200, Ordering::Relaxed);
+    f1.write_at(0, b"hello").unwrap();
+    NOW.store(300, Ordering::Relaxed);
+    f1.read_at(0, &mut [0; 8]).unwrap();
+    let times = f1.times();
+    assert_eq!(secs(times.accessed), 300);
+    assert_eq!(secs(times.modified), 200);
+    assert_eq!(secs(times.changed), 200);
+
+    // entries of directories
+    NOW.store(400, Ordering::Relaxed);
+    root.rename("foo/f1", "f2").unwrap();
+    assert_eq!(secs(foo.times().modified), 400);
+    assert_eq!(secs(root.times().modified), 400);
+
+    // set explicitly
+    NOW.store(500, Ordering::Relaxed);
+    f1.set_times(None, Some(Duration::from_secs(10)));
+    let times = f1.times();
+    assert_eq!(secs(times.accessed), 300);
+    assert_eq!(secs(times.modified), 10);
+    assert_eq!(secs(times.changed), 500);
+}
+
+#[test]
+fn test_ramfs_limits() {
+    use axfs_vfs::VfsNodeOps;
+
+    let ramfs = RamFileSystem::with_limits(Some(16), Some(4));
+    let root = ramfs.root_dir_node();
+    root.create("f1", VfsNodeType::File).unwrap();
+    root.create("d", VfsNodeType::Dir).unwrap();
+    root.create("d/l", VfsNodeType::SymLink).unwrap();
+    assert_eq!(
+        root.create("f2", VfsNodeType::File),
+        Err(VfsError::StorageFull)
+    );
+    assert_eq!(ramfs.usage(), (0, 4));
+    assert_eq!(ramfs.limits(), (Some(16), Some(4)));
+    assert_eq!(root.fs_limits(), (Some(16), Some(4)));
+    assert_eq!(RamFileSystem::new().limits(), (None, None));
+
+    let f1 = root.clone().lookup("f1").unwrap();
+    let l = root.clone().lookup("d/l").unwrap();
+    assert_eq!(l.write_at(0, b"f1"), Ok(2));
+    assert_eq!(f1.write_at(0, &[1; 10]), Ok(10));
+    assert_eq!(f1.write_at(10, &[1; 5]), Err(VfsError::StorageFull));
+    assert_eq!(f1.truncate(15), Err(VfsError::StorageFull));
+    assert_eq!(f1.write_at(4, &[1; 10]), Ok(10));
+    assert_eq!(ramfs.usage(), (16, 4));
+    assert_eq!(root.fs_usage(), (16, 4));
+    assert_eq!(f1.truncate(8), Ok(()));
+    assert_eq!(ramfs.usage(), (10, 4));
+
+    // freed when the last reference is dropped
+    root.remove("f1").unwrap();
+    assert_eq!(ramfs.usage(), (10, 4));
+    drop(f1);
+    assert_eq!(ramfs.usage(), (2, 3));
+    root.create("f2", VfsNodeType::File).unwrap();
+    drop(l);
+    root.remove("d/l").unwrap();
+    root.remove("d").unwrap();
+    assert_eq!(ramfs.usage(), (0, 2));
+}
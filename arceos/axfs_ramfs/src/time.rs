@@ -0,0 +1,73 @@
+use core::time::Duration;
+use spin::RwLock;
+
+static CLOCK: RwLock<fn() -> Duration> = RwLock::new(|| Duration::ZERO);
+
+/// Sets the clock to stamp nodes with, which returns the time elapsed since
+/// the Unix epoch.
+///
+/// Nodes are stamped with zero until it's set.
+pub fn set_clock(clock: fn() -> Duration) {
+    *CLOCK.write() = clock;
+}
+
+fn now() -> Duration {
+    (CLOCK.read())()
+}
+
+/// Timestamps of a node, as the time elapsed since the Unix epoch.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NodeTimes {
+    /// The last time the content was read.
+    pub accessed: Duration,
+    /// The last time the content was modified.
+    pub modified: Duration,
+    /// The last time the content or the timestamps were changed.
+    pub changed: Duration,
+}
+
+/// Timestamps of a node, updated by its operations.
+pub(crate) struct Timestamps(RwLock<NodeTimes>);
+
+impl Timestamps {
+    /// Creates timestamps for a node created now.
+    pub fn new() -> Self {
+        let now = now();
+        Self(RwLock::new(NodeTimes {
+            accessed: now,
+            modified: now,
+            changed: now,
+        }))
+    }
+
+    pub fn get(&self) -> NodeTimes {
+        *self.0.read()
+    }
+
+    /// Stamps a read of the content.
+    pub fn access(&self) {
+        self.0.write().accessed = now();
+    }
+
+    /// Stamps a modification of the content.
+    pub fn modify(&self) {
+        let now = now();
+        let mut times = self.0.write();
+        times.modified = now;
+        times.changed = now;
+    }
+
+    /// Sets the access and modification times, the ones of `None` are left
+    /// unchanged.
+    pub fn set(&self, accessed: Option<Duration>, modified: Option<Duration>) {
+        let now = now();
+        let mut times = self.0.write();
+        if let Some(accessed) = accessed {
+            times.accessed = accessed;
+        }
+        if let Some(modified) = modified {
+            times.modified = modified;
+        }
+        times.changed = now;
+    }
+}
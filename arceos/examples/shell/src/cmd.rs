@@ -19,6 +19,8 @@ type CmdHandler = fn(&str);
 const CMD_TABLE: &[(&str, CmdHandler)] = &[
     ("cat", do_cat),
     ("cd", do_cd),
+    #[cfg(feature = "axstd")]
+    ("df", do_df),
     ("echo", do_echo),
     ("exit", do_exit),
     ("help", do_help),
@@ -225,6 +227,42 @@ fn do_rm(args: &str) {
     }
 }
 
+#[cfg(feature = "axstd")]
+fn do_df(_args: &str) {
+    let mounts = match fs::read_to_string("/proc/mounts") {
+        Ok(mounts) => mounts,
+        Err(e) => {
+            print_err!("df", "/proc/mounts", e);
+            return;
+        }
+    };
+
+    println!(
+        "{:<12} {:>10} {:>10} {:>10} Mounted on",
+        "Filesystem", "1K-blocks", "Used", "Available"
+    );
+    for line in mounts.lines() {
+        let mut fields = line.split_whitespace();
+        let (Some(source), Some(target)) = (fields.next(), fields.next()) else {
+            continue;
+        };
+        match fs::statfs(target) {
+            Ok(st) => {
+                let kb = |blocks: u64| blocks * st.block_size / 1024;
+                println!(
+                    "{:<12} {:>10} {:>10} {:>10} {}",
+                    source,
+                    kb(st.blocks),
+                    kb(st.blocks.saturating_sub(st.blocks_free)),
+                    kb(st.blocks_free),
+                    target
+                );
+            }
+            Err(e) => print_err!("df", target, e),
+        }
+    }
+}
+
 fn do_cd(mut args: &str) {
     if args.is_empty() {
         args = "/";
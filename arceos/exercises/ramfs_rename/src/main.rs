@@ -16,7 +16,6 @@ fn create_file(fname: &str, text: &str) -> io::Result<()> {
     file.write_all(text.as_bytes())
 }
 
-// Only support rename, NOT move.
 fn rename_file(src: &str, dst: &str) -> io::Result<()> {
     println!("Rename '{}' to '{}' ...", src, dst);
     fs::rename(src, dst)
@@ -39,10 +38,14 @@ fn print_file(fname: &str) -> io::Result<()> {
 
 fn process() -> io::Result<()> {
     create_file("/tmp/f1", "hello")?;
-    // Just rename, NOT move.
-    // So this must happen in the same directory.
     rename_file("/tmp/f1", "/tmp/f2")?;
-    print_file("/tmp/f2")
+    print_file("/tmp/f2")?;
+
+    // Move it into another directory, replacing the file there.
+    fs::create_dir("/tmp/dir")?;
+    create_file("/tmp/dir/f3", "world")?;
+    rename_file("/tmp/f2", "/tmp/dir/f3")?;
+    print_file("/tmp/dir/f3")
 }
 
 #[cfg_attr(feature = "axstd", no_mangle)]
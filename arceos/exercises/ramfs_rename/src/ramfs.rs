@@ -3,13 +3,13 @@ extern crate alloc;
 use alloc::sync::Arc;
 use axfs_ramfs::RamFileSystem;
 use axfs_vfs::VfsOps;
-use std::os::arceos::api::fs::{AxDisk, MyFileSystemIf};
+use std::os::arceos::api::fs::{AxBlockDevice, MyFileSystemIf};
 
 struct MyFileSystemIfImpl;
 
 #[crate_interface::impl_interface]
 impl MyFileSystemIf for MyFileSystemIfImpl {
-    fn new_myfs(_disk: AxDisk) -> Arc<dyn VfsOps> {
+    fn new_myfs(_dev: Arc<dyn AxBlockDevice>) -> Arc<dyn VfsOps> {
         Arc::new(RamFileSystem::new())
     }
 }
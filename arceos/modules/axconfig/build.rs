@@ -102,6 +102,18 @@ fn gen_config_rs(config_path: &Path) -> Result<Vec<u8>> {
         Some("# Number of CPUs"),
     );
 
+    if let Ok(root_dev) = std::env::var("AX_ROOT_DEV") {
+        if !root_dev.is_empty() {
+            let comments = get_comments(&config, "root-dev").map(String::from);
+            add_config(
+                &mut config,
+                "root-dev",
+                toml_edit::value(root_dev),
+                comments.as_deref(),
+            );
+        }
+    }
+
     // Generate config.rs
     let mut output = Vec::new();
     writeln!(
@@ -168,5 +180,6 @@ fn main() -> Result<()> {
     println!("cargo:rerun-if-changed={}", config_path.display());
     println!("cargo:rerun-if-env-changed=AX_PLATFORM");
     println!("cargo:rerun-if-env-changed=AX_SMP");
+    println!("cargo:rerun-if-env-changed=AX_ROOT_DEV");
     Ok(())
 }
@@ -1,5 +1,5 @@
 #[allow(unused_imports)]
-use crate::{prelude::*, AllDevices};
+use crate::{prelude::*, AllDevices, BusInfo};
 
 impl AllDevices {
     pub(crate) fn probe_bus_devices(&mut self) {
@@ -14,7 +14,8 @@ impl AllDevices {
                         reg.0, reg.0 + reg.1,
                         dev.device_name(),
                     );
-                    self.add_device(dev);
+                    let bus = BusInfo::Mmio { base: reg.0, size: reg.1 };
+                    self.add_device(dev, bus, None);
                     continue; // skip to the next device
                 }
             });
@@ -1,11 +1,15 @@
-use crate::{prelude::*, AllDevices};
+use crate::{prelude::*, AllDevices, BusInfo};
 use axdriver_pci::{
     BarInfo, Cam, Command, DeviceFunction, HeaderType, MemoryBarType, PciRangeAllocator, PciRoot,
 };
-use axhal::mem::phys_to_virt;
+use axhal::mem::{phys_to_virt, VirtAddr};
 
 const PCI_BAR_NUM: u8 = 6;
 
+/// Offset of the interrupt line and interrupt pin registers in the
+/// configuration space.
+const PCI_INTERRUPT_OFFSET: usize = 0x3c;
+
 fn config_pci_device(
     root: &mut PciRoot,
     bdf: DeviceFunction,
@@ -82,6 +86,18 @@ fn config_pci_device(
     Ok(())
 }
 
+/// Returns the legacy IRQ of the device routed by the firmware, or `None` if
+/// the device does not use a legacy interrupt pin or it is not routed.
+fn pci_irq(ecam_base: VirtAddr, bdf: DeviceFunction) -> Option<usize> {
+    let offset = ((bdf.bus as usize) << 20)
+        | ((bdf.device as usize) << 15)
+        | ((bdf.function as usize) << 12)
+        | PCI_INTERRUPT_OFFSET;
+    let reg = unsafe { ((ecam_base + offset).as_ptr() as *const u32).read_volatile() };
+    let (line, pin) = (reg & 0xff, (reg >> 8) & 0xff);
+    (pin != 0 && line != 0 && line != 0xff).then_some(line as usize)
+}
+
 impl AllDevices {
     pub(crate) fn probe_bus_devices(&mut self) {
         let base_vaddr = phys_to_virt(axconfig::PCI_ECAM_BASE.into());
@@ -107,7 +123,14 @@ impl AllDevices {
                                 bdf,
                                 dev.device_name(),
                             );
-                            self.add_device(dev);
+                            let bus = BusInfo::Pci {
+                                bus: bdf.bus,
+                                device: bdf.device,
+                                function: bdf.function,
+                                vendor_id: dev_info.vendor_id,
+                                device_id: dev_info.device_id,
+                            };
+                            self.add_device(dev, bus, pci_irq(base_vaddr, bdf));
                             continue; // skip to the next device
                         }
                     }),
@@ -0,0 +1,93 @@
+//! Information of probed devices, kept after the devices are handed over to
+//! the upper layer subsystems.
+
+use alloc::{format, string::String, vec::Vec};
+
+use axdriver_base::DeviceType;
+use lazyinit::LazyInit;
+
+static DEVICE_INFOS: LazyInit<Vec<DeviceInfo>> = LazyInit::new();
+
+/// The bus that a device is found on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BusInfo {
+    /// Not on a bus, such as a RAM disk.
+    Platform,
+    /// A memory-mapped device at a fixed region.
+    Mmio {
+        /// The base physical address of the registers.
+        base: usize,
+        /// The size of the register region.
+        size: usize,
+    },
+    /// A PCI device function.
+    Pci {
+        /// The bus number.
+        bus: u8,
+        /// The device number.
+        device: u8,
+        /// The function number.
+        function: u8,
+        /// The vendor ID.
+        vendor_id: u16,
+        /// The device ID.
+        device_id: u16,
+    },
+}
+
+/// Information of a probed device.
+#[derive(Debug, Clone)]
+pub struct DeviceInfo {
+    /// The name of the driver, such as `virtio-blk`.
+    pub name: String,
+    /// The category of the device.
+    pub device_type: DeviceType,
+    /// The bus that the device is found on.
+    pub bus: BusInfo,
+    /// The IRQ number of the device, if known.
+    pub irq: Option<usize>,
+}
+
+impl BusInfo {
+    /// Returns the name of the bus, which is `platform`, `mmio`, or `pci`.
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::Platform => "platform",
+            Self::Mmio { .. } => "mmio",
+            Self::Pci { .. } => "pci",
+        }
+    }
+}
+
+impl DeviceInfo {
+    /// Returns a name that identifies the device on its bus, such as
+    /// `0000:00:01.0` for PCI devices (as on Linux) or `10001000.mmio` for
+    /// MMIO devices.
+    ///
+    /// `idx` is the index of the device in [`devices`], which is used for
+    /// devices not on a bus.
+    pub fn bus_id(&self, idx: usize) -> String {
+        match self.bus {
+            BusInfo::Platform => format!("{}.{}", self.name, idx),
+            BusInfo::Mmio { base, .. } => format!("{:x}.mmio", base),
+            BusInfo::Pci {
+                bus,
+                device,
+                function,
+                ..
+            } => format!("0000:{:02x}:{:02x}.{}", bus, device, function),
+        }
+    }
+}
+
+/// Returns the information of all probed devices, in the order they were
+/// probed.
+///
+/// It's empty before [`init_drivers`](crate::init_drivers) is called.
+pub fn devices() -> &'static [DeviceInfo] {
+    DEVICE_INFOS.get().map_or(&[], |infos| infos.as_slice())
+}
+
+pub(crate) fn init_device_infos(infos: Vec<DeviceInfo>) {
+    DEVICE_INFOS.init_once(infos);
+}
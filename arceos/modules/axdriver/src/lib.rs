@@ -7,6 +7,9 @@
 //! (e.g., the network stack) may unpack the struct to get the specified device
 //! driver they want.
 //!
+//! The information of all detected devices, such as the bus they are on, is
+//! kept after that, and can be listed by [`devices`].
+//!
 //! For each device category (i.e., net, block, display, etc.), an unified type
 //! is used to represent all devices in that category. Currently, there are 3
 //! categories: [`AxNetDevice`], [`AxBlockDevice`], and [`AxDisplayDevice`].
@@ -61,7 +64,6 @@
 #[macro_use]
 extern crate log;
 
-#[cfg(feature = "dyn")]
 extern crate alloc;
 
 #[macro_use]
@@ -70,6 +72,7 @@ mod macros;
 mod bus;
 mod drivers;
 mod dummy;
+mod info;
 mod structs;
 
 #[cfg(feature = "virtio")]
@@ -80,6 +83,7 @@ mod ixgbe;
 
 pub mod prelude;
 
+pub use self::info::{devices, BusInfo, DeviceInfo};
 #[allow(unused_imports)]
 use self::prelude::*;
 pub use self::structs::{AxDeviceContainer, AxDeviceEnum};
@@ -103,6 +107,8 @@ pub struct AllDevices {
     /// All graphics device drivers.
     #[cfg(feature = "display")]
     pub display: AxDeviceContainer<AxDisplayDevice>,
+    /// Information of all devices, moved to [`devices`] after probing.
+    infos: alloc::vec::Vec<DeviceInfo>,
 }
 
 impl AllDevices {
@@ -126,7 +132,7 @@ impl AllDevices {
                     dev.device_type(),
                     dev.device_name(),
                 );
-                self.add_device(dev);
+                self.add_device(dev, BusInfo::Platform, None);
             }
         });
 
@@ -135,7 +141,13 @@ impl AllDevices {
 
     /// Adds one device into the corresponding container, according to its device category.
     #[allow(dead_code)]
-    fn add_device(&mut self, dev: AxDeviceEnum) {
+    fn add_device(&mut self, dev: AxDeviceEnum, bus: BusInfo, irq: Option<usize>) {
+        self.infos.push(DeviceInfo {
+            name: dev.device_name().into(),
+            device_type: dev.device_type(),
+            bus,
+            irq,
+        });
         match dev {
             #[cfg(feature = "net")]
             AxDeviceEnum::Net(dev) => self.net.push(dev),
@@ -154,6 +166,7 @@ pub fn init_drivers() -> AllDevices {
 
     let mut all_devs = AllDevices::default();
     all_devs.probe();
+    info::init_device_infos(core::mem::take(&mut all_devs.infos));
 
     #[cfg(feature = "net")]
     {
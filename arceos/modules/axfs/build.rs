@@ -0,0 +1,12 @@
+fn main() {
+    // the path of the initramfs archive to be linked into the kernel image
+    println!("cargo:rerun-if-env-changed=AX_INITRAMFS");
+    println!("cargo::rustc-check-cfg=cfg(initramfs_path)");
+    if let Some(path) = std::env::var_os("AX_INITRAMFS").filter(|p| !p.is_empty()) {
+        let path = std::fs::canonicalize(&path)
+            .unwrap_or_else(|e| panic!("failed to find initramfs {:?}: {}", path, e));
+        println!("cargo:rerun-if-changed={}", path.display());
+        println!("cargo:rustc-env=AX_INITRAMFS_PATH={}", path.display());
+        println!("cargo:rustc-cfg=initramfs_path");
+    }
+}
@@ -6,6 +6,18 @@ CUR_DIR=`dirname $0`
 
 echo $OUT_DIR
 
+populate() {
+	local dir=$1
+	for i in $(seq 1 1000); do
+	  echo "Rust is cool!" >>"$dir/long.txt"
+	done
+	echo "Rust is cool!" >>"$dir/short.txt"
+	mkdir -p "$dir/very/long/path"
+	echo "Rust is cool!" >>"$dir/very/long/path/test.txt"
+	mkdir -p "$dir/very-long-dir-name"
+	echo "Rust is cool!" >>"$dir/very-long-dir-name/very-long-file-name.txt"
+}
+
 create_test_img() {
 	local name=$1
 	local blkcount=$2
@@ -14,17 +26,35 @@ create_test_img() {
 	mkfs.vfat -s 1 -F $fatSize -n "Test!" -i 12345678 "$name"
 	mkdir -p mnt
 	sudo mount -o loop "$name" mnt -o rw,uid=$USER,gid=$USER
-	for i in $(seq 1 1000); do
-	  echo "Rust is cool!" >>"mnt/long.txt"
-	done
-	echo "Rust is cool!" >>"mnt/short.txt"
-	mkdir -p "mnt/very/long/path"
-	echo "Rust is cool!" >>"mnt/very/long/path/test.txt"
-	mkdir -p "mnt/very-long-dir-name"
-	echo "Rust is cool!" >>"mnt/very-long-dir-name/very-long-file-name.txt"
-
+	populate mnt
 	sudo umount mnt
 }
 
+# ext4 images are populated from a directory, no mount needed
+create_ext4_img() {
+	local name=$1
+	local blkcount=$2
+	local src=$(mktemp -d)
+	populate "$src"
+	echo "Read only" >"$src/readonly.txt"
+	chmod 0444 "$src/readonly.txt"
+	ln -s short.txt "$src/link.txt"
+	rm -f "$name"
+	mkfs.ext4 -b 1024 -L "Test!" -d "$src" "$name" $blkcount
+	rm -rf "$src"
+}
+
+# the initramfs is packed from a directory as well
+create_initramfs() {
+	local name=$1
+	local src=$(mktemp -d)
+	populate "$src"
+	ln -s short.txt "$src/link.txt"
+	(cd "$src" && find . | cpio -o -H newc) >"$name"
+	rm -rf "$src"
+}
+
 create_test_img "$CUR_DIR/fat16.img" 2500 16
 create_test_img "$CUR_DIR/fat32.img" 34000 32
+create_ext4_img "$CUR_DIR/ext4.img" 4096
+create_initramfs "$CUR_DIR/initramfs.cpio"
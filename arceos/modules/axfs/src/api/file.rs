@@ -16,7 +16,7 @@ pub struct File {
 }
 
 /// Metadata information about a file.
-pub struct Metadata(fops::FileAttr);
+pub struct Metadata(pub(super) fops::FileAttr);
 
 /// Options and flags which can be used to configure how a file is opened.
 #[derive(Clone, Debug)]
@@ -90,6 +90,12 @@ impl Metadata {
         self.0.is_file()
     }
 
+    /// Returns `true` if this metadata is for a symbolic link. It's only
+    /// possible for metadata from [`symlink_metadata`](super::symlink_metadata).
+    pub const fn is_symlink(&self) -> bool {
+        matches!(self.0.file_type(), FileType::SymLink)
+    }
+
     /// Returns the size of the file, in bytes, this metadata is for.
     #[allow(clippy::len_without_is_empty)]
     pub const fn len(&self) -> u64 {
@@ -110,6 +116,12 @@ impl Metadata {
     pub const fn blocks(&self) -> u64 {
         self.0.blocks()
     }
+
+    /// Returns the timestamps of the file this metadata is for. They are
+    /// zero if the filesystem doesn't record them.
+    pub const fn times(&self) -> fops::FileTimes {
+        self.0.times()
+    }
 }
 
 impl fmt::Debug for Metadata {
@@ -162,6 +174,48 @@ impl File {
     pub fn metadata(&self) -> Result<Metadata> {
         self.inner.get_attr().map(Metadata)
     }
+
+    /// Queries the usage of the filesystem the file is on.
+    pub fn fs_stat(&self) -> Result<fops::FsStat> {
+        self.inner.fs_stat()
+    }
+
+    /// Acquires an exclusive lock on the file, blocking until it can be
+    /// acquired.
+    pub fn lock(&self) -> Result<()> {
+        self.inner.flock(fops::LockType::Exclusive, true)
+    }
+
+    /// Acquires a shared lock on the file, blocking until it can be
+    /// acquired.
+    pub fn lock_shared(&self) -> Result<()> {
+        self.inner.flock(fops::LockType::Shared, true)
+    }
+
+    /// Tries to acquire an exclusive lock on the file. Returns `false` if
+    /// another handle holds a lock on it.
+    pub fn try_lock(&self) -> Result<bool> {
+        try_lock(self.inner.flock(fops::LockType::Exclusive, false))
+    }
+
+    /// Tries to acquire a shared lock on the file. Returns `false` if
+    /// another handle holds an exclusive lock on it.
+    pub fn try_lock_shared(&self) -> Result<bool> {
+        try_lock(self.inner.flock(fops::LockType::Shared, false))
+    }
+
+    /// Releases the lock held on the file by this handle.
+    pub fn unlock(&self) -> Result<()> {
+        self.inner.flock(fops::LockType::Unlock, false)
+    }
+}
+
+fn try_lock(res: Result<()>) -> Result<bool> {
+    match res {
+        Ok(()) => Ok(true),
+        Err(axio::Error::WouldBlock) => Ok(false),
+        Err(e) => Err(e),
+    }
 }
 
 impl Read for File {
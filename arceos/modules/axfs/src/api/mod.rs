@@ -5,9 +5,14 @@ mod file;
 
 pub use self::dir::{DirBuilder, DirEntry, ReadDir};
 pub use self::file::{File, FileType, Metadata, OpenOptions, Permissions};
+pub use crate::fops::{FileTimes, FsStat};
+pub use crate::mounts::MountOptions;
+pub use crate::notify::{FsEvent, WatchMask, Watcher};
 
-use alloc::{string::String, vec::Vec};
+use alloc::{string::String, sync::Arc, vec::Vec};
+use axfs_vfs::VfsOps;
 use axio::{self as io, prelude::*};
+use core::time::Duration;
 
 /// Returns an iterator over the entries within a directory.
 pub fn read_dir(path: &str) -> io::Result<ReadDir> {
@@ -20,16 +25,31 @@ pub fn canonicalize(path: &str) -> io::Result<String> {
     crate::root::absolute_path(path)
 }
 
-/// Returns the current working directory as a [`String`].
+/// Returns the current working directory of the calling task as a
+/// [`String`], seen from its root directory.
 pub fn current_dir() -> io::Result<String> {
     crate::root::current_dir()
 }
 
-/// Changes the current working directory to the specified path.
+/// Changes the current working directory of the calling task to the
+/// specified path.
+///
+/// Tasks spawned by it afterwards start in the new directory, while other
+/// tasks are not affected. Without the `multitask` feature, there's only one
+/// current directory.
 pub fn set_current_dir(path: &str) -> io::Result<()> {
     crate::root::set_current_dir(path)
 }
 
+/// Changes the root directory of the calling task to the specified path, and
+/// its current working directory to the new root.
+///
+/// Absolute paths are then resolved from the new root, and `..` never leaves
+/// it. Like the current directory, it's inherited by tasks spawned later.
+pub fn set_root_dir(path: &str) -> io::Result<()> {
+    crate::root::set_root_dir(path)
+}
+
 /// Read the entire contents of a file into a bytes vector.
 pub fn read(path: &str) -> io::Result<Vec<u8>> {
     let mut file = File::open(path)?;
@@ -54,11 +74,91 @@ pub fn write<C: AsRef<[u8]>>(path: &str, contents: C) -> io::Result<()> {
 }
 
 /// Given a path, query the file system to get information about a file,
-/// directory, etc.
+/// directory, etc. Symlinks are followed.
 pub fn metadata(path: &str) -> io::Result<Metadata> {
     File::open(path)?.metadata()
 }
 
+/// Queries the metadata about a file without following symlinks.
+pub fn symlink_metadata(path: &str) -> io::Result<Metadata> {
+    let node = crate::root::lookup(None, path, false)?;
+    Ok(Metadata(crate::fops::FileAttr::of(&node)?))
+}
+
+/// Sets the access and modification times of a file, as the time elapsed
+/// since the Unix epoch. The ones of `None` are left unchanged. Symlinks are
+/// followed.
+///
+/// Fails with [`Unsupported`](io::Error::Unsupported) if the filesystem
+/// doesn't record timestamps.
+pub fn set_times(
+    path: &str,
+    accessed: Option<Duration>,
+    modified: Option<Duration>,
+) -> io::Result<()> {
+    crate::root::set_times(path, true, accessed, modified)
+}
+
+/// Sets the access and modification times like [`set_times`], but doesn't
+/// follow symlinks.
+pub fn set_symlink_times(
+    path: &str,
+    accessed: Option<Duration>,
+    modified: Option<Duration>,
+) -> io::Result<()> {
+    crate::root::set_times(path, false, accessed, modified)
+}
+
+/// Returns the usage of the filesystem that the file at `path` is on, as
+/// `statfs(2)`. Symlinks are followed.
+pub fn statfs(path: &str) -> io::Result<FsStat> {
+    crate::root::lookup(None, path, true)?;
+    let mount = crate::root::mount_point_of(path, true)?;
+    crate::root::fs_stat(mount.as_deref())
+}
+
+/// Returns `true` if the filesystem that `path` is on can't be modified, e.g.
+/// it's mounted read-only. Modifications fail with
+/// [`PermissionDenied`](io::Error::PermissionDenied) then.
+///
+/// `path` doesn't need to exist, but its parent directory does. Symlinks in
+/// the last component are not followed.
+pub fn is_read_only(path: &str) -> io::Result<bool> {
+    let mount = crate::root::mount_point_of(path, false)?;
+    Ok(crate::root::is_read_only(mount.as_deref()))
+}
+
+/// Creates a new symbolic link at `link` pointing to `original`.
+///
+/// `original` is not checked, and is resolved relative to the directory of
+/// the link when the link is followed.
+pub fn symlink(original: &str, link: &str) -> io::Result<()> {
+    crate::root::create_symlink(None, original, link)
+}
+
+/// Reads the target of a symbolic link.
+pub fn read_link(path: &str) -> io::Result<String> {
+    crate::root::read_link(None, path)
+}
+
+/// Returns `true` if `path1` and `path2` are on the same mounted filesystem,
+/// which is required by [`hard_link`] and [`rename`].
+///
+/// The paths don't need to exist, but their parent directories do. Symlinks
+/// in the last components are not followed.
+pub fn same_filesystem(path1: &str, path2: &str) -> io::Result<bool> {
+    crate::root::same_filesystem(path1, path2)
+}
+
+/// Creates a new hard link at `link` to the file at `original`.
+///
+/// Symlinks in `original` are not followed. Fails with
+/// [`Unsupported`](io::Error::Unsupported) if the paths are on different
+/// filesystems.
+pub fn hard_link(original: &str, link: &str) -> io::Result<()> {
+    crate::root::hard_link(original, link)
+}
+
 /// Creates a new, empty directory at the provided path.
 pub fn create_dir(path: &str) -> io::Result<()> {
     DirBuilder::new().create(path)
@@ -80,10 +180,82 @@ pub fn remove_file(path: &str) -> io::Result<()> {
     crate::root::remove_file(None, path)
 }
 
-/// Rename a file or directory to a new name.
-/// Delete the original file if `old` already exists.
+/// Rename a file or directory to a new name, possibly in another directory.
+/// Replace the file at `new` atomically if it already exists.
 ///
-/// This only works then the new path is in the same mounted fs.
+/// This only works then the new path is in the same mounted fs, otherwise it
+/// fails with `Unsupported`.
 pub fn rename(old: &str, new: &str) -> io::Result<()> {
     crate::root::rename(old, new)
 }
+
+/// Mounts the filesystem `fs` on the directory `path`.
+///
+/// The directory is created if it does not exist. It can be inside another
+/// mounted filesystem.
+pub fn mount(path: &str, fs: Arc<dyn VfsOps>) -> io::Result<()> {
+    crate::root::mount(path, fs, "none", "unknown", MountOptions::new())
+}
+
+/// Creates a new filesystem of type `fstype` and mounts it on `path`.
+///
+/// Supported types are `ramfs`, `tmpfs` (a size-limited ramfs) and `devfs`,
+/// if the corresponding features are enabled. See [`mount`] for details.
+pub fn mount_fstype(path: &str, fstype: &str) -> io::Result<()> {
+    mount_fstype_with_options(path, fstype, MountOptions::new())
+}
+
+/// Like [`mount_fstype`], but mounts the filesystem with `options`.
+///
+/// Size and inode limits are only supported by `ramfs` and `tmpfs`, and
+/// tmpfs defaults to half of the physical memory. Fails with
+/// [`InvalidInput`](io::Error::InvalidInput) if they're given for another
+/// type.
+pub fn mount_fstype_with_options(
+    path: &str,
+    fstype: &str,
+    mut options: MountOptions,
+) -> io::Result<()> {
+    let fs = crate::mounts::new_fs(fstype, &mut options)?;
+    crate::root::mount(path, fs, fstype, fstype, options)
+}
+
+/// Writes all cached data of block devices back to the devices.
+pub fn sync() -> io::Result<()> {
+    crate::cache::sync_all().map_err(|_| io::Error::Io)
+}
+
+/// Unmounts the filesystem mounted on `path`.
+///
+/// Fails with [`ResourceBusy`](io::Error::ResourceBusy) if the filesystem is
+/// still in use, i.e. it has open files, other filesystems mounted on it, or
+/// the current directory is on it. The filesystem is flushed before it's
+/// detached.
+pub fn umount(path: &str) -> io::Result<()> {
+    crate::root::umount(path)
+}
+
+/// Adds a kernel tunable to sysfs as `/sys/kernel/<name>`.
+///
+/// Reading the file returns the value from `get`, and writing to it calls
+/// `set` with the written value, which takes effect immediately. It's ignored
+/// if the `sysfs` feature is disabled.
+pub fn add_tunable(name: &'static str, get: fn() -> String, set: fn(&str) -> io::Result<()>) {
+    #[cfg(feature = "sysfs")]
+    crate::fs::sysfs::add_tunable(name, get, set);
+    #[cfg(not(feature = "sysfs"))]
+    let _ = (name, get, set);
+}
+
+/// Registers the device `dev` as `/dev/<name>`.
+///
+/// It appears in all devfs instances, including the one already mounted on
+/// `/dev`. Fails with [`AlreadyExists`](io::Error::AlreadyExists) if the name
+/// is taken.
+#[cfg(feature = "devfs")]
+pub fn register_device<T: crate::fops::DeviceOps + 'static>(
+    name: &str,
+    dev: Arc<T>,
+) -> io::Result<()> {
+    crate::fs::devfs::register(name, dev)
+}
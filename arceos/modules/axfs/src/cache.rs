@@ -0,0 +1,249 @@
+//! Block buffer cache.
+//!
+//! [`BlockCache`] keeps recently used blocks of a [`BlockDevice`] in memory,
+//! and is itself a [`BlockDevice`], so filesystems run on it without knowing
+//! it's there. Writes are kept in the cache until the block is evicted or the
+//! cache is synced, and sequential reads prefetch the following blocks.
+
+use alloc::{collections::BTreeMap, sync::Arc, vec, vec::Vec};
+use axdriver::prelude::*;
+use axsync::Mutex;
+
+use crate::dev::BlockDevice;
+
+/// Default number of blocks in a cache.
+pub(crate) const DEFAULT_CAPACITY: usize = 1024;
+/// Maximum number of blocks to prefetch on a sequential read miss.
+const MAX_READAHEAD: usize = 32;
+
+struct CacheEntry {
+    data: Vec<u8>,
+    dirty: bool,
+    last_used: u64,
+}
+
+struct CacheInner {
+    entries: BTreeMap<u64, CacheEntry>,
+    /// The cached blocks ordered by their last use, the least recently used
+    /// one first.
+    lru: BTreeMap<u64, u64>,
+    /// Incremented on every access, to order the blocks by their last use.
+    clock: u64,
+    /// The block right after the last read, a miss on it starts readahead.
+    next_seq_block: u64,
+}
+
+impl CacheInner {
+    /// Marks the block as the most recently used one, returns its entry if
+    /// it's cached.
+    fn touch(&mut self, block_id: u64) -> Option<&mut CacheEntry> {
+        let entry = self.entries.get_mut(&block_id)?;
+        self.clock += 1;
+        self.lru.remove(&entry.last_used);
+        self.lru.insert(self.clock, block_id);
+        entry.last_used = self.clock;
+        Some(entry)
+    }
+}
+
+/// An LRU block cache with write-back.
+pub struct BlockCache {
+    dev: Arc<dyn BlockDevice>,
+    block_size: usize,
+    num_blocks: u64,
+    capacity: usize,
+    inner: Mutex<CacheInner>,
+}
+
+impl BlockCache {
+    /// Creates a cache of at most `capacity` blocks on `dev`.
+    pub fn new(dev: Arc<dyn BlockDevice>, capacity: usize) -> Self {
+        assert!(capacity > 0);
+        Self {
+            block_size: dev.block_size(),
+            num_blocks: dev.num_blocks(),
+            dev,
+            capacity,
+            inner: Mutex::new(CacheInner {
+                entries: BTreeMap::new(),
+                lru: BTreeMap::new(),
+                clock: 0,
+                next_seq_block: u64::MAX,
+            }),
+        }
+    }
+
+    /// Writes all dirty blocks back to the device.
+    pub fn sync(&self) -> DevResult {
+        let mut inner = self.inner.lock();
+        self.write_back(&mut inner)
+    }
+
+    /// Writes dirty blocks back, consecutive blocks are written together.
+    fn write_back(&self, inner: &mut CacheInner) -> DevResult {
+        let mut run_start = 0;
+        let mut run: Vec<&[u8]> = Vec::new();
+        for (&block_id, entry) in inner.entries.iter().filter(|(_, e)| e.dirty) {
+            if run.is_empty() || run_start + run.len() as u64 != block_id {
+                if !run.is_empty() {
+                    self.dev.write_blocks_vectored(run_start, &run)?;
+                }
+                run.clear();
+                run_start = block_id;
+            }
+            run.push(&entry.data);
+        }
+        if !run.is_empty() {
+            self.dev.write_blocks_vectored(run_start, &run)?;
+        }
+        inner.entries.values_mut().for_each(|e| e.dirty = false);
+        Ok(())
+    }
+
+    /// Makes room for a new block, writing the evicted one back if dirty.
+    fn evict_one(&self, inner: &mut CacheInner) -> DevResult {
+        if inner.entries.len() < self.capacity {
+            return Ok(());
+        }
+        let (&last_used, &block_id) = inner.lru.first_key_value().unwrap();
+        let entry = &inner.entries[&block_id];
+        // keep it on failure so that the data is not lost
+        if entry.dirty {
+            self.dev.write_blocks(block_id, &entry.data)?;
+        }
+        inner.entries.remove(&block_id);
+        inner.lru.remove(&last_used);
+        Ok(())
+    }
+
+    fn insert(
+        &self,
+        inner: &mut CacheInner,
+        block_id: u64,
+        data: Vec<u8>,
+        dirty: bool,
+    ) -> DevResult {
+        self.evict_one(inner)?;
+        inner.clock += 1;
+        let last_used = inner.clock;
+        inner.lru.insert(last_used, block_id);
+        inner.entries.insert(
+            block_id,
+            CacheEntry {
+                data,
+                dirty,
+                last_used,
+            },
+        );
+        Ok(())
+    }
+
+    /// Reads `count` missing blocks starting from `block_id` from the device
+    /// and caches them, with readahead if the access is sequential.
+    fn fill(&self, inner: &mut CacheInner, block_id: u64, count: usize) -> DevResult {
+        let bs = self.block_size;
+        let mut total = count;
+        if block_id == inner.next_seq_block {
+            let readahead = MAX_READAHEAD.min(self.capacity / 2);
+            let remaining = (self.num_blocks - block_id) as usize;
+            total = (count + readahead).min(remaining).max(count);
+        }
+        let mut buf = vec![0u8; total * bs];
+        self.dev.read_blocks(block_id, &mut buf)?;
+        for (i, data) in buf.chunks_exact(bs).enumerate() {
+            let id = block_id + i as u64;
+            // prefetched blocks may be cached and dirty already
+            if i >= count && inner.entries.contains_key(&id) {
+                continue;
+            }
+            self.insert(inner, id, data.to_vec(), false)?;
+        }
+        Ok(())
+    }
+
+    fn check_range(&self, block_id: u64, len: usize) -> DevResult<usize> {
+        if len % self.block_size != 0 {
+            return Err(DevError::InvalidParam);
+        }
+        let count = len / self.block_size;
+        match block_id.checked_add(count as u64) {
+            Some(end) if end <= self.num_blocks => Ok(count),
+            _ => Err(DevError::InvalidParam),
+        }
+    }
+}
+
+impl BlockDevice for BlockCache {
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn num_blocks(&self) -> u64 {
+        self.num_blocks
+    }
+
+    fn read_blocks(&self, block_id: u64, buf: &mut [u8]) -> DevResult {
+        let count = self.check_range(block_id, buf.len())?;
+        let bs = self.block_size;
+        let mut inner = self.inner.lock();
+        let mut i = 0;
+        while i < count {
+            let id = block_id + i as u64;
+            if !inner.entries.contains_key(&id) {
+                // fetch the whole run of missing blocks at once
+                let missing = (i..count)
+                    .take_while(|&j| !inner.entries.contains_key(&(block_id + j as u64)))
+                    .count();
+                self.fill(&mut inner, id, missing)?;
+            }
+            match inner.touch(id) {
+                Some(entry) => {
+                    buf[i * bs..(i + 1) * bs].copy_from_slice(&entry.data);
+                }
+                // evicted by readahead of a small cache
+                None => self.dev.read_blocks(id, &mut buf[i * bs..(i + 1) * bs])?,
+            }
+            i += 1;
+        }
+        inner.next_seq_block = block_id + count as u64;
+        Ok(())
+    }
+
+    fn write_blocks(&self, block_id: u64, buf: &[u8]) -> DevResult {
+        self.check_range(block_id, buf.len())?;
+        let bs = self.block_size;
+        let mut inner = self.inner.lock();
+        for (i, data) in buf.chunks_exact(bs).enumerate() {
+            let id = block_id + i as u64;
+            match inner.touch(id) {
+                Some(entry) => {
+                    entry.data.copy_from_slice(data);
+                    entry.dirty = true;
+                }
+                None => self.insert(&mut inner, id, data.to_vec(), true)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> DevResult {
+        self.sync()?;
+        self.dev.flush()
+    }
+}
+
+/// Caches of all block devices, in the order they were probed.
+static BLOCK_CACHES: Mutex<Vec<Arc<BlockCache>>> = Mutex::new(Vec::new());
+
+/// Registers a cache so that [`sync_all`] writes it back.
+pub(crate) fn register(cache: Arc<BlockCache>) {
+    BLOCK_CACHES.lock().push(cache);
+}
+
+/// Writes back and flushes all block caches.
+pub(crate) fn sync_all() -> DevResult {
+    for cache in BLOCK_CACHES.lock().iter() {
+        cache.flush()?;
+    }
+    Ok(())
+}
@@ -1,92 +1,398 @@
+use alloc::{string::String, sync::Arc, vec, vec::Vec};
 use axdriver::prelude::*;
+#[cfg(any(feature = "devfs", feature = "ext4fs"))]
+use axfs_vfs::VfsError;
+#[cfg(feature = "devfs")]
+use axfs_vfs::{VfsNodeAttr, VfsNodeOps, VfsNodePerm, VfsNodeType, VfsResult};
+use axsync::Mutex;
 
-const BLOCK_SIZE: usize = 512;
+/// A block device that filesystems are built on.
+///
+/// It can be a device from the driver, a range of blocks on another device
+/// (e.g. a partition), or anything that forwards to another device. All
+/// methods take `&self`, so a device can be shared by multiple users, each of
+/// which keeps its own [`Disk`] cursor.
+pub trait BlockDevice: Send + Sync {
+    /// The size of a block in bytes.
+    fn block_size(&self) -> usize;
 
-/// A disk device with a cursor.
+    /// The number of blocks of the device.
+    fn num_blocks(&self) -> u64;
+
+    /// Reads consecutive blocks starting from `block_id` into `buf`, whose
+    /// length must be a multiple of the block size.
+    fn read_blocks(&self, block_id: u64, buf: &mut [u8]) -> DevResult;
+
+    /// Writes `buf` to consecutive blocks starting from `block_id`. The
+    /// length of `buf` must be a multiple of the block size.
+    fn write_blocks(&self, block_id: u64, buf: &[u8]) -> DevResult;
+
+    /// Reads consecutive blocks starting from `block_id` into `bufs` in
+    /// order. The length of each buffer must be a multiple of the block size.
+    fn read_blocks_vectored(&self, mut block_id: u64, bufs: &mut [&mut [u8]]) -> DevResult {
+        for buf in bufs.iter_mut() {
+            self.read_blocks(block_id, buf)?;
+            block_id += (buf.len() / self.block_size()) as u64;
+        }
+        Ok(())
+    }
+
+    /// Writes `bufs` in order to consecutive blocks starting from `block_id`.
+    /// The length of each buffer must be a multiple of the block size.
+    fn write_blocks_vectored(&self, mut block_id: u64, bufs: &[&[u8]]) -> DevResult {
+        for buf in bufs {
+            self.write_blocks(block_id, buf)?;
+            block_id += (buf.len() / self.block_size()) as u64;
+        }
+        Ok(())
+    }
+
+    /// Flushes data written to the device.
+    fn flush(&self) -> DevResult;
+}
+
+impl BlockDevice for Mutex<AxBlockDevice> {
+    fn block_size(&self) -> usize {
+        self.lock().block_size()
+    }
+
+    fn num_blocks(&self) -> u64 {
+        self.lock().num_blocks()
+    }
+
+    fn read_blocks(&self, block_id: u64, buf: &mut [u8]) -> DevResult {
+        self.lock().read_block(block_id, buf)
+    }
+
+    fn write_blocks(&self, block_id: u64, buf: &[u8]) -> DevResult {
+        self.lock().write_block(block_id, buf)
+    }
+
+    fn flush(&self) -> DevResult {
+        self.lock().flush()
+    }
+}
+
+/// A range of blocks on another block device, e.g. a partition.
+pub struct BlockRange {
+    dev: Arc<dyn BlockDevice>,
+    start_block: u64,
+    num_blocks: u64,
+}
+
+impl BlockRange {
+    /// Creates a device that covers `num_blocks` blocks of `dev` starting
+    /// from `start_block`.
+    pub fn new(dev: Arc<dyn BlockDevice>, start_block: u64, num_blocks: u64) -> Self {
+        assert!(start_block + num_blocks <= dev.num_blocks());
+        Self {
+            dev,
+            start_block,
+            num_blocks,
+        }
+    }
+
+    fn check_range(&self, block_id: u64, len: usize) -> DevResult {
+        let count = (len / self.block_size()) as u64;
+        match block_id.checked_add(count) {
+            Some(end) if end <= self.num_blocks => Ok(()),
+            _ => Err(DevError::InvalidParam),
+        }
+    }
+}
+
+impl BlockDevice for BlockRange {
+    fn block_size(&self) -> usize {
+        self.dev.block_size()
+    }
+
+    fn num_blocks(&self) -> u64 {
+        self.num_blocks
+    }
+
+    fn read_blocks(&self, block_id: u64, buf: &mut [u8]) -> DevResult {
+        self.check_range(block_id, buf.len())?;
+        self.dev.read_blocks(self.start_block + block_id, buf)
+    }
+
+    fn write_blocks(&self, block_id: u64, buf: &[u8]) -> DevResult {
+        self.check_range(block_id, buf.len())?;
+        self.dev.write_blocks(self.start_block + block_id, buf)
+    }
+
+    fn read_blocks_vectored(&self, block_id: u64, bufs: &mut [&mut [u8]]) -> DevResult {
+        self.check_range(block_id, bufs.iter().map(|b| b.len()).sum())?;
+        self.dev
+            .read_blocks_vectored(self.start_block + block_id, bufs)
+    }
+
+    fn write_blocks_vectored(&self, block_id: u64, bufs: &[&[u8]]) -> DevResult {
+        self.check_range(block_id, bufs.iter().map(|b| b.len()).sum())?;
+        self.dev
+            .write_blocks_vectored(self.start_block + block_id, bufs)
+    }
+
+    fn flush(&self) -> DevResult {
+        self.dev.flush()
+    }
+}
+
+/// A block device with a cursor.
+///
+/// Clones of a disk share the same underlying device, but each has its own
+/// cursor.
+#[derive(Clone)]
 pub struct Disk {
     block_id: u64,
     offset: usize,
-    dev: AxBlockDevice,
+    block_size: usize,
+    num_blocks: u64,
+    dev: Arc<dyn BlockDevice>,
+    /// Scratch buffer for partial block accesses.
+    block_buf: Vec<u8>,
 }
 
 impl Disk {
     /// Create a new disk.
-    pub fn new(dev: AxBlockDevice) -> Self {
-        assert_eq!(BLOCK_SIZE, dev.block_size());
+    pub fn new(dev: Arc<dyn BlockDevice>) -> Self {
+        let block_size = dev.block_size();
+        assert!(block_size > 0);
         Self {
             block_id: 0,
             offset: 0,
+            block_size,
+            num_blocks: dev.num_blocks(),
             dev,
+            block_buf: vec![0; block_size],
         }
     }
 
+    /// Create a disk that covers `num_blocks` blocks starting from
+    /// `start_block` of this disk, e.g. a partition.
+    pub fn sub_disk(&self, start_block: u64, num_blocks: u64) -> Self {
+        Self::new(Arc::new(BlockRange::new(
+            self.dev.clone(),
+            start_block,
+            num_blocks,
+        )))
+    }
+
+    /// The underlying block device.
+    pub fn device(&self) -> &Arc<dyn BlockDevice> {
+        &self.dev
+    }
+
+    /// Get the size of a block of the disk.
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
     /// Get the size of the disk.
     pub fn size(&self) -> u64 {
-        self.dev.num_blocks() * BLOCK_SIZE as u64
+        self.num_blocks * self.block_size as u64
     }
 
     /// Get the position of the cursor.
     pub fn position(&self) -> u64 {
-        self.block_id * BLOCK_SIZE as u64 + self.offset as u64
+        self.block_id * self.block_size as u64 + self.offset as u64
     }
 
     /// Set the position of the cursor.
     pub fn set_position(&mut self, pos: u64) {
-        self.block_id = pos / BLOCK_SIZE as u64;
-        self.offset = pos as usize % BLOCK_SIZE;
+        self.block_id = pos / self.block_size as u64;
+        self.offset = (pos % self.block_size as u64) as usize;
     }
 
-    /// Read within one block, returns the number of bytes read.
+    /// Read whole blocks if the cursor is at the start of a block and `buf`
+    /// holds at least one block, or within one block otherwise. Returns the
+    /// number of bytes read.
     pub fn read_one(&mut self, buf: &mut [u8]) -> DevResult<usize> {
-        let read_size = if self.offset == 0 && buf.len() >= BLOCK_SIZE {
-            // whole block
+        let bs = self.block_size;
+        if self.block_id >= self.num_blocks {
+            return Ok(0); // end of disk
+        }
+        let read_size = if self.offset == 0 && buf.len() >= bs {
+            // whole blocks
+            let count = (buf.len() / bs).min((self.num_blocks - self.block_id) as usize);
             self.dev
-                .read_block(self.block_id, &mut buf[0..BLOCK_SIZE])?;
-            self.block_id += 1;
-            BLOCK_SIZE
+                .read_blocks(self.block_id, &mut buf[..count * bs])?;
+            self.block_id += count as u64;
+            count * bs
         } else {
             // partial block
-            let mut data = [0u8; BLOCK_SIZE];
             let start = self.offset;
-            let count = buf.len().min(BLOCK_SIZE - self.offset);
+            let count = buf.len().min(bs - self.offset);
 
-            self.dev.read_block(self.block_id, &mut data)?;
-            buf[..count].copy_from_slice(&data[start..start + count]);
+            self.dev.read_blocks(self.block_id, &mut self.block_buf)?;
+            buf[..count].copy_from_slice(&self.block_buf[start..start + count]);
 
             self.offset += count;
-            if self.offset >= BLOCK_SIZE {
+            if self.offset >= bs {
                 self.block_id += 1;
-                self.offset -= BLOCK_SIZE;
+                self.offset -= bs;
             }
             count
         };
         Ok(read_size)
     }
 
-    /// Write within one block, returns the number of bytes written.
+    /// Write whole blocks if the cursor is at the start of a block and `buf`
+    /// holds at least one block, or within one block otherwise. Returns the
+    /// number of bytes written.
     pub fn write_one(&mut self, buf: &[u8]) -> DevResult<usize> {
-        let write_size = if self.offset == 0 && buf.len() >= BLOCK_SIZE {
-            // whole block
-            self.dev.write_block(self.block_id, &buf[0..BLOCK_SIZE])?;
-            self.block_id += 1;
-            BLOCK_SIZE
+        let bs = self.block_size;
+        if self.block_id >= self.num_blocks {
+            return Ok(0); // end of disk
+        }
+        let write_size = if self.offset == 0 && buf.len() >= bs {
+            // whole blocks
+            let count = (buf.len() / bs).min((self.num_blocks - self.block_id) as usize);
+            self.dev.write_blocks(self.block_id, &buf[..count * bs])?;
+            self.block_id += count as u64;
+            count * bs
         } else {
             // partial block
-            let mut data = [0u8; BLOCK_SIZE];
             let start = self.offset;
-            let count = buf.len().min(BLOCK_SIZE - self.offset);
+            let count = buf.len().min(bs - self.offset);
 
-            self.dev.read_block(self.block_id, &mut data)?;
-            data[start..start + count].copy_from_slice(&buf[..count]);
-            self.dev.write_block(self.block_id, &data)?;
+            self.dev.read_blocks(self.block_id, &mut self.block_buf)?;
+            self.block_buf[start..start + count].copy_from_slice(&buf[..count]);
+            self.dev.write_blocks(self.block_id, &self.block_buf)?;
 
             self.offset += count;
-            if self.offset >= BLOCK_SIZE {
+            if self.offset >= bs {
                 self.block_id += 1;
-                self.offset -= BLOCK_SIZE;
+                self.offset -= bs;
             }
             count
         };
         Ok(write_size)
     }
+
+    /// Flushes the underlying device.
+    pub fn flush(&mut self) -> DevResult {
+        self.dev.flush()
+    }
+}
+
+/// The unit of the sizes Linux reports in sectors, whatever the block size
+/// of the device is.
+#[cfg(feature = "devfs")]
+const SECTOR_SIZE: u64 = 512;
+
+/// A block device file in devfs, e.g. `/dev/vda`.
+///
+/// It gives raw access to the whole disk.
+#[cfg(feature = "devfs")]
+pub(crate) struct BlockDevNode {
+    disk: Mutex<Disk>,
+}
+
+#[cfg(feature = "devfs")]
+impl BlockDevNode {
+    pub fn new(disk: Disk) -> Self {
+        Self {
+            disk: Mutex::new(disk),
+        }
+    }
+}
+
+#[cfg(feature = "devfs")]
+impl VfsNodeOps for BlockDevNode {
+    fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
+        let size = self.disk.lock().size();
+        Ok(VfsNodeAttr::new(
+            VfsNodePerm::from_bits_truncate(0o660),
+            VfsNodeType::BlockDevice,
+            size,
+            size / SECTOR_SIZE,
+        ))
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
+        let mut disk = self.disk.lock();
+        let len = disk.size().saturating_sub(offset).min(buf.len() as u64) as usize;
+        disk.set_position(offset);
+        let mut read_len = 0;
+        while read_len < len {
+            read_len += disk.read_one(&mut buf[read_len..len]).map_err(as_vfs_err)?;
+        }
+        Ok(read_len)
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> VfsResult<usize> {
+        let mut disk = self.disk.lock();
+        let len = disk.size().saturating_sub(offset).min(buf.len() as u64) as usize;
+        disk.set_position(offset);
+        let mut write_len = 0;
+        while write_len < len {
+            write_len += disk.write_one(&buf[write_len..len]).map_err(as_vfs_err)?;
+        }
+        Ok(write_len)
+    }
+
+    fn fsync(&self) -> VfsResult {
+        self.disk.lock().flush().map_err(as_vfs_err)
+    }
+
+    axfs_vfs::impl_vfs_non_dir_default! {}
+}
+
+#[cfg(feature = "devfs")]
+impl crate::fops::DeviceOps for BlockDevNode {
+    unsafe fn ioctl(&self, cmd: u32, arg: usize) -> VfsResult<usize> {
+        const BLKGETSIZE: u32 = 0x1260;
+        const BLKFLSBUF: u32 = 0x1261;
+        const BLKSSZGET: u32 = 0x1268;
+        const BLKGETSIZE64: u32 = 0x8008_1272;
+
+        use crate::fs::devfs::write_arg;
+
+        let disk = self.disk.lock();
+        let (size, block_size) = (disk.size(), disk.block_size());
+        drop(disk);
+        match cmd {
+            BLKGETSIZE => write_arg(arg, (size / SECTOR_SIZE) as usize),
+            BLKGETSIZE64 => write_arg(arg, size),
+            BLKSSZGET => write_arg(arg, block_size as core::ffi::c_int),
+            BLKFLSBUF => self.fsync().map(|_| 0),
+            _ => Err(VfsError::Unsupported),
+        }
+    }
+}
+
+#[cfg(any(feature = "devfs", feature = "ext4fs"))]
+pub(crate) const fn as_vfs_err(err: DevError) -> VfsError {
+    match err {
+        DevError::AlreadyExists => VfsError::AlreadyExists,
+        DevError::Again => VfsError::WouldBlock,
+        DevError::InvalidParam => VfsError::InvalidInput,
+        DevError::NoMemory => VfsError::NoMemory,
+        DevError::ResourceBusy => VfsError::ResourceBusy,
+        DevError::Unsupported => VfsError::Unsupported,
+        _ => VfsError::Io,
+    }
+}
+
+/// Returns the name of the `idx`-th disk, i.e. `vda`, `vdb`, ..., `vdz`,
+/// `vdaa`, etc.
+pub(crate) fn disk_name(mut idx: usize) -> String {
+    let mut suffix = Vec::new();
+    loop {
+        suffix.push(b'a' + (idx % 26) as u8);
+        if idx < 26 {
+            break;
+        }
+        idx = idx / 26 - 1;
+    }
+    suffix.reverse();
+    String::from("vd") + core::str::from_utf8(&suffix).unwrap()
+}
+
+/// A whole disk or a partition, with its name in `/dev`.
+pub(crate) struct NamedDisk {
+    pub name: String,
+    pub disk: Disk,
+    /// Whether this is a whole disk that has a partition table.
+    pub partitioned: bool,
 }
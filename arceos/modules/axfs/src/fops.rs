@@ -1,30 +1,182 @@
 //! Low-level filesystem operations.
 
+use alloc::{string::String, sync::Arc};
 use axerrno::{ax_err, ax_err_type, AxError, AxResult};
-use axfs_vfs::{VfsError, VfsNodeRef};
+use axfs_vfs::{VfsError, VfsNodeAttr, VfsNodeOps, VfsNodeRef, VfsResult};
 use axio::SeekFrom;
 use cap_access::{Cap, WithCap};
-use core::fmt;
+use core::{fmt, time::Duration};
+
+use crate::notify::{self, WatchMask};
+use crate::root::MountPoint;
+
+pub use crate::locks::{FileLock, LockHandle, LockType};
 
-#[cfg(feature = "myfs")]
-pub use crate::dev::Disk;
 #[cfg(feature = "myfs")]
 pub use crate::fs::myfs::MyFileSystemIf;
+#[cfg(feature = "myfs")]
+pub use crate::{
+    cache::BlockCache,
+    dev::{BlockDevice, BlockRange, Disk},
+};
+
+#[cfg(feature = "devfs")]
+use crate::fs::devfs::ioctl as device_ioctl;
+
+/// Devices are only registered in devfs.
+#[cfg(not(feature = "devfs"))]
+unsafe fn device_ioctl(_node: &VfsNodeRef, _cmd: u32, _arg: usize) -> VfsResult<usize> {
+    Err(VfsError::Unsupported)
+}
+
+/// The error of following too many symlinks in a path, or opening a symlink
+/// with [`OpenOptions::no_follow`].
+///
+/// There's no error kind for `ELOOP`, and this one is not returned otherwise
+/// by path lookups or opens.
+pub const SYMLINK_LOOP: AxError = AxError::BadState;
 
 /// Alias of [`axfs_vfs::VfsNodeType`].
 pub type FileType = axfs_vfs::VfsNodeType;
 /// Alias of [`axfs_vfs::VfsDirEntry`].
 pub type DirEntry = axfs_vfs::VfsDirEntry;
-/// Alias of [`axfs_vfs::VfsNodeAttr`].
-pub type FileAttr = axfs_vfs::VfsNodeAttr;
 /// Alias of [`axfs_vfs::VfsNodePerm`].
 pub type FilePerm = axfs_vfs::VfsNodePerm;
 
+/// File attributes, i.e. [`axfs_vfs::VfsNodeAttr`] with the timestamps.
+#[derive(Debug, Clone, Copy)]
+pub struct FileAttr {
+    attr: VfsNodeAttr,
+    times: FileTimes,
+}
+
+/// Timestamps of a file, as the time elapsed since the Unix epoch.
+///
+/// They are zero if the filesystem doesn't record them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FileTimes {
+    /// The last time the content was read.
+    pub accessed: Duration,
+    /// The last time the content was modified.
+    pub modified: Duration,
+    /// The last time the content or the attributes were changed.
+    pub changed: Duration,
+}
+
+/// Usage of a filesystem, as `struct statfs`.
+///
+/// The numbers are zero if the filesystem has no storage, e.g. procfs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FsStat {
+    /// The type of the filesystem, as the magic number of Linux.
+    pub fs_type: u64,
+    /// The size of a block in bytes.
+    pub block_size: u64,
+    /// The total number of blocks.
+    pub blocks: u64,
+    /// The number of free blocks.
+    pub blocks_free: u64,
+    /// The total number of files (inodes), or 0 if they are not limited.
+    pub files: u64,
+    /// The number of files that can be created.
+    pub files_free: u64,
+    /// The maximum length of file names.
+    pub name_max: u64,
+}
+
+/// Operations of device files, in addition to the ones of [`VfsNodeOps`].
+///
+/// Devices implementing it can be registered in devfs, by
+/// [`api::register_device`](crate::api::register_device).
+pub trait DeviceOps: VfsNodeOps {
+    /// Performs a device-specific operation, as `ioctl(2)`. Returns a
+    /// non-negative value on success, whose meaning depends on `cmd`.
+    ///
+    /// The default implementation returns [`Unsupported`](AxError::Unsupported)
+    /// for all commands.
+    ///
+    /// # Safety
+    ///
+    /// If `cmd` takes a pointer, `arg` must be valid for the access of the
+    /// type that `cmd` specifies.
+    unsafe fn ioctl(&self, cmd: u32, arg: usize) -> VfsResult<usize> {
+        let _ = (cmd, arg);
+        Err(VfsError::Unsupported)
+    }
+}
+
+impl FileAttr {
+    /// Creates file attributes with zero timestamps.
+    pub const fn new(perm: FilePerm, ty: FileType, size: u64, blocks: u64) -> Self {
+        Self {
+            attr: VfsNodeAttr::new(perm, ty, size, blocks),
+            times: FileTimes {
+                accessed: Duration::ZERO,
+                modified: Duration::ZERO,
+                changed: Duration::ZERO,
+            },
+        }
+    }
+
+    /// Gets the attributes of `node`.
+    pub(crate) fn of(node: &VfsNodeRef) -> AxResult<Self> {
+        Ok(Self {
+            attr: node.get_attr()?,
+            times: crate::root::node_times(node)?,
+        })
+    }
+
+    /// Replaces the timestamps.
+    pub const fn with_times(self, times: FileTimes) -> Self {
+        Self { times, ..self }
+    }
+
+    /// Returns the permissions of the file.
+    pub const fn perm(&self) -> FilePerm {
+        self.attr.perm()
+    }
+
+    /// Returns the type of the file.
+    pub const fn file_type(&self) -> FileType {
+        self.attr.file_type()
+    }
+
+    /// Returns the size of the file in bytes.
+    pub const fn size(&self) -> u64 {
+        self.attr.size()
+    }
+
+    /// Returns the number of blocks allocated to the file, in 512-byte units.
+    pub const fn blocks(&self) -> u64 {
+        self.attr.blocks()
+    }
+
+    /// Whether the file is a directory.
+    pub const fn is_dir(&self) -> bool {
+        self.attr.is_dir()
+    }
+
+    /// Whether the file is a regular file.
+    pub const fn is_file(&self) -> bool {
+        self.attr.is_file()
+    }
+
+    /// Returns the timestamps of the file.
+    pub const fn times(&self) -> FileTimes {
+        self.times
+    }
+}
+
 /// An opened file object, with open permissions and a cursor.
 pub struct File {
     node: WithCap<VfsNodeRef>,
     is_append: bool,
     offset: u64,
+    mount: Option<Arc<MountPoint>>,
+    locks: LockHandle,
+    /// The absolute path, for reporting changes to watchers, or `None` if
+    /// it's opened relative to a directory.
+    path: Option<String>,
 }
 
 /// An opened directory object, with open permissions and a cursor for
@@ -32,6 +184,10 @@ pub struct File {
 pub struct Directory {
     node: WithCap<VfsNodeRef>,
     entry_idx: usize,
+    mount: Option<Arc<MountPoint>>,
+    /// The absolute path, for resolving the filesystems of relative paths, or
+    /// `None` if it's not known.
+    path: Option<String>,
 }
 
 /// Options and flags which can be used to configure how a file is opened.
@@ -45,6 +201,8 @@ pub struct OpenOptions {
     create: bool,
     create_new: bool,
     // system-specific
+    no_follow: bool,
+    execute: bool,
     _custom_flags: i32,
     _mode: u32,
 }
@@ -61,6 +219,8 @@ impl OpenOptions {
             create: false,
             create_new: false,
             // system-specific
+            no_follow: false,
+            execute: false,
             _custom_flags: 0,
             _mode: 0o666,
         }
@@ -89,6 +249,17 @@ impl OpenOptions {
     pub fn create_new(&mut self, create_new: bool) {
         self.create_new = create_new;
     }
+    /// Sets the option to fail with [`SYMLINK_LOOP`] if the last component of
+    /// the path is a symlink, instead of following it (`O_NOFOLLOW`).
+    pub fn no_follow(&mut self, no_follow: bool) {
+        self.no_follow = no_follow;
+    }
+    /// Sets the option to open a regular file for execution, which fails on
+    /// filesystems mounted `noexec`. Execute bits are not checked, as none of
+    /// the filesystems keeps them per file.
+    pub fn execute(&mut self, execute: bool) {
+        self.execute = execute;
+    }
 
     const fn is_valid(&self) -> bool {
         if !self.read && !self.write && !self.append {
@@ -116,21 +287,33 @@ impl File {
         self.node.access_or_err(cap, AxError::PermissionDenied)
     }
 
-    fn _open_at(dir: Option<&VfsNodeRef>, path: &str, opts: &OpenOptions) -> AxResult<Self> {
+    fn _open_at(dir: Option<&Directory>, path: &str, opts: &OpenOptions) -> AxResult<Self> {
         debug!("open file: {} {:?}", path, opts);
         if !opts.is_valid() {
             return ax_err!(InvalidInput);
         }
+        let follow = !opts.no_follow;
+        let (dir, mount) = match dir {
+            Some(dir) => (dir.access_at(path)?, dir.mount_at(path, follow)?),
+            None => (None, crate::root::mount_point_of(path, follow)?),
+        };
+        // `create` and `truncate` also require write access
+        if opts.write || opts.append {
+            crate::root::check_writable(mount.as_deref())?;
+        }
+        if opts.execute && crate::root::mount_options(mount.as_deref()).no_exec {
+            return ax_err!(PermissionDenied, "filesystem is mounted noexec");
+        }
 
-        let node_option = crate::root::lookup(dir, path);
-        let node = if opts.create || opts.create_new {
+        let node_option = crate::root::lookup_with_path(dir, path, follow);
+        let (node, real_path) = if opts.create || opts.create_new {
             match node_option {
-                Ok(node) => {
+                Ok(res) => {
                     // already exists
                     if opts.create_new {
                         return ax_err!(AlreadyExists);
                     }
-                    node
+                    res
                 }
                 // not exists, create new
                 Err(VfsError::NotFound) => crate::root::create_file(dir, path)?,
@@ -142,11 +325,18 @@ impl File {
         };
 
         let attr = node.get_attr()?;
+        if attr.file_type() == FileType::SymLink {
+            // only if `no_follow` is set, see `SYMLINK_LOOP`
+            return ax_err!(BadState, "last component is a symlink");
+        }
         if attr.is_dir()
             && (opts.create || opts.create_new || opts.write || opts.append || opts.truncate)
         {
             return ax_err!(IsADirectory);
         }
+        if opts.execute && !attr.is_file() {
+            return ax_err!(PermissionDenied);
+        }
         let access_cap = opts.into();
         if !perm_to_cap(attr.perm()).contains(access_cap) {
             return ax_err!(PermissionDenied);
@@ -156,11 +346,27 @@ impl File {
         if opts.truncate {
             node.truncate(0)?;
         }
-        Ok(Self {
+        let locks = LockHandle::new(&node, access_cap, || real_path.clone());
+        let file = Self {
             node: WithCap::new(node, access_cap),
             is_append: opts.append,
             offset: 0,
-        })
+            mount,
+            locks,
+            path: real_path,
+        };
+        if opts.truncate {
+            file.notify(WatchMask::MODIFY);
+        }
+        file.notify(WatchMask::OPEN);
+        Ok(file)
+    }
+
+    /// Reports an event of `mask` on the file to the watchers.
+    fn notify(&self, mask: WatchMask) {
+        if let Some(path) = &self.path {
+            notify::notify(path, mask);
+        }
     }
 
     /// Opens a file at the path relative to the current directory. Returns a
@@ -172,6 +378,7 @@ impl File {
     /// Truncates the file to the specified size.
     pub fn truncate(&self, size: u64) -> AxResult {
         self.access_node(Cap::WRITE)?.truncate(size)?;
+        self.notify(WatchMask::MODIFY);
         Ok(())
     }
 
@@ -183,6 +390,7 @@ impl File {
         let node = self.access_node(Cap::READ)?;
         let read_len = node.read_at(self.offset, buf)?;
         self.offset += read_len as u64;
+        self.notify(WatchMask::ACCESS);
         Ok(read_len)
     }
 
@@ -192,6 +400,7 @@ impl File {
     pub fn read_at(&self, offset: u64, buf: &mut [u8]) -> AxResult<usize> {
         let node = self.access_node(Cap::READ)?;
         let read_len = node.read_at(offset, buf)?;
+        self.notify(WatchMask::ACCESS);
         Ok(read_len)
     }
 
@@ -209,6 +418,7 @@ impl File {
         let node = self.access_node(Cap::WRITE)?;
         let write_len = node.write_at(offset, buf)?;
         self.offset = offset + write_len as u64;
+        self.notify(WatchMask::MODIFY);
         Ok(write_len)
     }
 
@@ -219,6 +429,7 @@ impl File {
     pub fn write_at(&self, offset: u64, buf: &[u8]) -> AxResult<usize> {
         let node = self.access_node(Cap::WRITE)?;
         let write_len = node.write_at(offset, buf)?;
+        self.notify(WatchMask::MODIFY);
         Ok(write_len)
     }
 
@@ -244,7 +455,85 @@ impl File {
 
     /// Gets the file attributes.
     pub fn get_attr(&self) -> AxResult<FileAttr> {
-        self.access_node(Cap::empty())?.get_attr()
+        FileAttr::of(self.access_node(Cap::empty())?)
+    }
+
+    /// Sets the access and modification times of the file, the ones of
+    /// `None` are left unchanged. The change time is set to now.
+    ///
+    /// Returns [`Unsupported`](AxError::Unsupported) if the filesystem
+    /// doesn't record timestamps.
+    pub fn set_times(&self, accessed: Option<Duration>, modified: Option<Duration>) -> AxResult {
+        let node = self.access_node(Cap::empty())?;
+        crate::root::check_writable(self.mount.as_deref())?;
+        crate::root::set_node_times(node, accessed, modified)?;
+        self.notify(WatchMask::ATTRIB);
+        Ok(())
+    }
+
+    /// Returns `true` if the filesystem the file is on can't be modified, see
+    /// [`api::is_read_only`](crate::api::is_read_only).
+    pub fn is_read_only(&self) -> bool {
+        crate::root::is_read_only(self.mount.as_deref())
+    }
+
+    /// Returns the usage of the filesystem the file is on, as `fstatfs(2)`.
+    pub fn fs_stat(&self) -> AxResult<FsStat> {
+        crate::root::fs_stat(self.mount.as_deref())
+    }
+
+    /// Performs a device-specific operation on the file, as `ioctl(2)`.
+    ///
+    /// Returns [`Unsupported`](AxError::Unsupported) if the file is not a
+    /// device, or the device doesn't support `cmd`.
+    ///
+    /// # Safety
+    ///
+    /// See [`DeviceOps::ioctl`].
+    pub unsafe fn ioctl(&self, cmd: u32, arg: usize) -> AxResult<usize> {
+        device_ioctl(self.access_node(Cap::empty())?, cmd, arg)
+    }
+
+    /// Acquires, converts or releases the whole-file lock of this opened
+    /// file, as `flock(2)`.
+    ///
+    /// If the lock is held by another opened file, blocks until it's released
+    /// if `wait` is true, otherwise returns
+    /// [`WouldBlock`](AxError::WouldBlock). It's also returned if the task is
+    /// cancelled while blocked.
+    pub fn flock(&self, ty: LockType, wait: bool) -> AxResult {
+        self.locks.flock(ty, wait)
+    }
+
+    /// Acquires or releases a byte-range lock for the current task, as the
+    /// `F_SETLK` and `F_SETLKW` commands of `fcntl(2)`.
+    ///
+    /// A shared lock requires the file to be opened for reading, and an
+    /// exclusive lock requires it to be opened for writing. If the range is
+    /// locked by another task, blocks until it's released if `wait` is true,
+    /// otherwise returns [`WouldBlock`](AxError::WouldBlock). It's also
+    /// returned if the task is cancelled while blocked.
+    pub fn set_lock(&self, lock: &FileLock, wait: bool) -> AxResult {
+        self.locks.set_lock(lock, wait)
+    }
+
+    /// Returns the byte-range lock of another task that prevents the current
+    /// task from acquiring `lock`, or `None` if there is none, as the
+    /// `F_GETLK` command of `fcntl(2)`.
+    pub fn get_lock(&self, lock: &FileLock) -> AxResult<Option<FileLock>> {
+        self.locks.get_lock(lock)
+    }
+
+    /// Releases the byte-range locks held by the current task on the file,
+    /// as closing any file descriptor of it does.
+    pub fn release_locks(&self) {
+        self.locks.release_locks()
+    }
+
+    /// Returns the handle to the locks of the file, which can wait for a
+    /// lock while the file is used elsewhere.
+    pub fn lock_handle(&self) -> LockHandle {
+        self.locks.clone()
     }
 }
 
@@ -253,7 +542,7 @@ impl Directory {
         self.node.access_or_err(cap, AxError::PermissionDenied)
     }
 
-    fn _open_dir_at(dir: Option<&VfsNodeRef>, path: &str, opts: &OpenOptions) -> AxResult<Self> {
+    fn _open_dir_at(dir: Option<&Directory>, path: &str, opts: &OpenOptions) -> AxResult<Self> {
         debug!("open dir: {}", path);
         if !opts.read {
             return ax_err!(InvalidInput);
@@ -261,8 +550,17 @@ impl Directory {
         if opts.create || opts.create_new || opts.write || opts.append || opts.truncate {
             return ax_err!(InvalidInput);
         }
+        let (node, real_path, mount) = match dir {
+            Some(dir) => {
+                let (node, real_path) = dir.lookup_at(path, true)?;
+                (node, real_path, dir.mount_at(path, true)?)
+            }
+            None => {
+                let (node, real_path) = crate::root::lookup_with_path(None, path, true)?;
+                (node, real_path, crate::root::mount_point_of(path, true)?)
+            }
+        };
 
-        let node = crate::root::lookup(dir, path)?;
         let attr = node.get_attr()?;
         if !attr.is_dir() {
             return ax_err!(NotADirectory);
@@ -276,6 +574,8 @@ impl Directory {
         Ok(Self {
             node: WithCap::new(node, access_cap),
             entry_idx: 0,
+            mount,
+            path: real_path,
         })
     }
 
@@ -287,6 +587,46 @@ impl Directory {
         }
     }
 
+    /// Fails if `path` relative to this directory is on a read-only
+    /// filesystem. Absolute paths are checked when they're resolved.
+    fn check_writable_at(&self, path: &str) -> AxResult {
+        if path.starts_with('/') {
+            Ok(())
+        } else {
+            crate::root::check_writable(self.mount_at(path, false)?.as_deref())
+        }
+    }
+
+    /// Looks up `path` relative to this directory, also returning its
+    /// absolute path if it's known.
+    fn lookup_at(&self, path: &str, follow: bool) -> AxResult<(VfsNodeRef, Option<String>)> {
+        match (self.access_at(path)?, &self.path) {
+            (Some(dir), Some(dir_path)) => {
+                crate::root::lookup_with_path_at(dir, dir_path, path, follow)
+            }
+            (dir, _) => crate::root::lookup_with_path(dir, path, follow),
+        }
+    }
+
+    /// Returns the mount point of the filesystem that `path` relative to this
+    /// directory is on.
+    ///
+    /// Relative paths are resolved from the absolute path of this directory,
+    /// as they may cross mount points. If it's not known, they're taken to be
+    /// on the filesystem of this directory.
+    fn mount_at(&self, path: &str, follow: bool) -> AxResult<Option<Arc<MountPoint>>> {
+        if path.starts_with('/') {
+            return crate::root::mount_point_of(path, follow);
+        }
+        match &self.path {
+            Some(dir_path) => {
+                let dir = self.access_node(Cap::EXECUTE)?;
+                crate::root::mount_point_at(dir, dir_path, path, follow)
+            }
+            None => Ok(self.mount.clone()),
+        }
+    }
+
     /// Opens a directory at the path relative to the current directory.
     /// Returns a [`Directory`] object.
     pub fn open_dir(path: &str, opts: &OpenOptions) -> AxResult<Self> {
@@ -296,32 +636,36 @@ impl Directory {
     /// Opens a directory at the path relative to this directory. Returns a
     /// [`Directory`] object.
     pub fn open_dir_at(&self, path: &str, opts: &OpenOptions) -> AxResult<Self> {
-        Self::_open_dir_at(self.access_at(path)?, path, opts)
+        Self::_open_dir_at(Some(self), path, opts)
     }
 
     /// Opens a file at the path relative to this directory. Returns a [`File`]
     /// object.
     pub fn open_file_at(&self, path: &str, opts: &OpenOptions) -> AxResult<File> {
-        File::_open_at(self.access_at(path)?, path, opts)
+        File::_open_at(Some(self), path, opts)
     }
 
     /// Creates an empty file at the path relative to this directory.
     pub fn create_file(&self, path: &str) -> AxResult<VfsNodeRef> {
-        crate::root::create_file(self.access_at(path)?, path)
+        self.check_writable_at(path)?;
+        crate::root::create_file(self.access_at(path)?, path).map(|(node, _)| node)
     }
 
     /// Creates an empty directory at the path relative to this directory.
     pub fn create_dir(&self, path: &str) -> AxResult {
+        self.check_writable_at(path)?;
         crate::root::create_dir(self.access_at(path)?, path)
     }
 
     /// Removes a file at the path relative to this directory.
     pub fn remove_file(&self, path: &str) -> AxResult {
+        self.check_writable_at(path)?;
         crate::root::remove_file(self.access_at(path)?, path)
     }
 
     /// Removes a directory at the path relative to this directory.
     pub fn remove_dir(&self, path: &str) -> AxResult {
+        self.check_writable_at(path)?;
         crate::root::remove_dir(self.access_at(path)?, path)
     }
 
@@ -338,10 +682,11 @@ impl Directory {
         Ok(n)
     }
 
-    /// Rename a file or directory to a new name.
-    /// Delete the original file if `old` already exists.
+    /// Rename a file or directory to a new name, possibly in another directory.
+    /// Replace the file at `new` atomically if it already exists.
     ///
-    /// This only works then the new path is in the same mounted fs.
+    /// This only works then the new path is in the same mounted fs, otherwise it
+    /// fails with [`Unsupported`](AxError::Unsupported).
     pub fn rename(&self, old: &str, new: &str) -> AxResult {
         crate::root::rename(old, new)
     }
@@ -349,6 +694,12 @@ impl Directory {
 
 impl Drop for File {
     fn drop(&mut self) {
+        self.locks.release_all();
+        if self.access_node(Cap::WRITE).is_ok() {
+            self.notify(WatchMask::CLOSE_WRITE);
+        } else {
+            self.notify(WatchMask::CLOSE_NOWRITE);
+        }
         unsafe { self.node.access_unchecked().release().ok() };
     }
 }
@@ -380,6 +731,8 @@ impl fmt::Debug for OpenOptions {
         fmt_opt!(truncate, "TRUNC");
         fmt_opt!(create, "CREATE");
         fmt_opt!(create_new, "CREATE_NEW");
+        fmt_opt!(no_follow, "NOFOLLOW");
+        fmt_opt!(execute, "EXECUTE");
         Ok(())
     }
 }
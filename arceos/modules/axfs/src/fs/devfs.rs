@@ -0,0 +1,377 @@
+//! Device files in devfs, and the registry of them.
+//!
+//! Devices are registered by name, and appear in all mounted devfs instances,
+//! including the ones mounted before the registration.
+
+pub use axfs_devfs::*;
+
+use alloc::collections::BTreeMap;
+use alloc::sync::{Arc, Weak};
+use alloc::{string::String, vec::Vec};
+
+use axdriver::prelude::DeviceType;
+use axfs_vfs::{VfsError, VfsResult};
+use axfs_vfs::{VfsNodeAttr, VfsNodeOps, VfsNodePerm, VfsNodeRef, VfsNodeType};
+use axsync::Mutex;
+
+use crate::fops::DeviceOps;
+
+/// A registered device, as a node and as a device.
+struct Device {
+    node: VfsNodeRef,
+    ops: Arc<dyn DeviceOps>,
+}
+
+/// All registered devices, by their names.
+static DEVICES: Mutex<BTreeMap<&'static str, Device>> = Mutex::new(BTreeMap::new());
+
+/// All created devfs instances, to which new devices are added.
+static INSTANCES: Mutex<Vec<Weak<DeviceFileSystem>>> = Mutex::new(Vec::new());
+
+const TIOCGWINSZ: u32 = 0x5413;
+#[cfg(feature = "display")]
+const FBIOGET_VSCREENINFO: u32 = 0x4600;
+#[cfg(feature = "display")]
+const FBIOGET_FSCREENINFO: u32 = 0x4602;
+
+/// Registers a device as `/dev/<name>`.
+///
+/// Returns [`AlreadyExists`](VfsError::AlreadyExists) if the name is taken,
+/// or [`InvalidInput`](VfsError::InvalidInput) if it's not a valid file name.
+pub fn register<T: DeviceOps + 'static>(name: &str, dev: Arc<T>) -> VfsResult {
+    if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+        return Err(VfsError::InvalidInput);
+    }
+    let mut devices = DEVICES.lock();
+    if devices.contains_key(name) {
+        return Err(VfsError::AlreadyExists);
+    }
+    // device names live as long as devfs
+    let name: &'static str = String::leak(name.into());
+    let node = dev.clone() as VfsNodeRef;
+    let mut instances = INSTANCES.lock();
+    instances.retain(|devfs| devfs.strong_count() > 0);
+    for devfs in instances.iter().filter_map(Weak::upgrade) {
+        devfs.add(name, node.clone());
+    }
+    devices.insert(name, Device { node, ops: dev });
+    Ok(())
+}
+
+/// Adds all registered devices to `devfs`, and the ones registered later.
+pub fn attach(devfs: &Arc<DeviceFileSystem>) {
+    let devices = DEVICES.lock();
+    for (&name, dev) in devices.iter() {
+        devfs.add(name, dev.node.clone());
+    }
+    INSTANCES.lock().push(Arc::downgrade(devfs));
+}
+
+/// Performs a device-specific operation on `node`.
+///
+/// Returns [`Unsupported`](VfsError::Unsupported) if `node` is not a
+/// registered device.
+///
+/// # Safety
+///
+/// See [`DeviceOps::ioctl`].
+pub unsafe fn ioctl(node: &VfsNodeRef, cmd: u32, arg: usize) -> VfsResult<usize> {
+    let ops = DEVICES
+        .lock()
+        .values()
+        .find(|dev| Arc::ptr_eq(&dev.node, node))
+        .map(|dev| dev.ops.clone())
+        .ok_or(VfsError::Unsupported)?;
+    ops.ioctl(cmd, arg)
+}
+
+/// Registers the devices that are always present: `null`, `zero`, `console`,
+/// `urandom` (and `random`), a node for each network device, and `fb0` if
+/// there is a display device.
+pub fn register_builtin() {
+    register("null", Arc::new(NullDev)).ok();
+    register("zero", Arc::new(ZeroDev)).ok();
+    register("console", Arc::new(ConsoleDev)).ok();
+    register("urandom", Arc::new(RandomDev)).ok();
+    register("random", Arc::new(RandomDev)).ok();
+
+    let devices = axdriver::devices();
+    let net_devs = devices.iter().filter(|d| d.device_type == DeviceType::Net);
+    for idx in 0..net_devs.count() {
+        register(&alloc::format!("eth{}", idx), Arc::new(NetDev)).ok();
+    }
+    #[cfg(feature = "display")]
+    if devices.iter().any(|d| d.device_type == DeviceType::Display) {
+        register("fb0", Arc::new(FramebufferDev)).ok();
+    }
+}
+
+/// Writes `val` to the ioctl argument `arg`, which is a pointer to `T`.
+///
+/// # Safety
+///
+/// `arg` must be null or valid for writes of `T`.
+pub(crate) unsafe fn write_arg<T>(arg: usize, val: T) -> VfsResult<usize> {
+    if arg == 0 {
+        return Err(VfsError::BadAddress);
+    }
+    (arg as *mut T).write_unaligned(val);
+    Ok(0)
+}
+
+fn char_dev_attr(mode: u16) -> VfsNodeAttr {
+    VfsNodeAttr::new(
+        VfsNodePerm::from_bits_truncate(mode),
+        VfsNodeType::CharDevice,
+        0,
+        0,
+    )
+}
+
+impl DeviceOps for NullDev {}
+
+impl DeviceOps for ZeroDev {}
+
+/// The console, i.e. `/dev/console`. Reads do not block.
+pub struct ConsoleDev;
+
+/// The size of a terminal, i.e. `struct winsize`.
+#[repr(C)]
+#[allow(dead_code)] // read by the caller of ioctl
+struct WinSize {
+    ws_row: u16,
+    ws_col: u16,
+    ws_xpixel: u16,
+    ws_ypixel: u16,
+}
+
+impl VfsNodeOps for ConsoleDev {
+    fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
+        Ok(char_dev_attr(0o620))
+    }
+
+    fn read_at(&self, _offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
+        let mut read_len = 0;
+        while read_len < buf.len() {
+            match axhal::console::getchar() {
+                Some(c) => buf[read_len] = if c == b'\r' { b'\n' } else { c },
+                None => break,
+            }
+            read_len += 1;
+        }
+        Ok(read_len)
+    }
+
+    fn write_at(&self, _offset: u64, buf: &[u8]) -> VfsResult<usize> {
+        axhal::console::write_bytes(buf);
+        Ok(buf.len())
+    }
+
+    fn truncate(&self, _size: u64) -> VfsResult {
+        Ok(())
+    }
+
+    axfs_vfs::impl_vfs_non_dir_default! {}
+}
+
+impl DeviceOps for ConsoleDev {
+    unsafe fn ioctl(&self, cmd: u32, arg: usize) -> VfsResult<usize> {
+        match cmd {
+            // the size is unknown, report the traditional one
+            TIOCGWINSZ => write_arg(
+                arg,
+                WinSize {
+                    ws_row: 24,
+                    ws_col: 80,
+                    ws_xpixel: 0,
+                    ws_ypixel: 0,
+                },
+            ),
+            _ => Err(VfsError::Unsupported),
+        }
+    }
+}
+
+/// A source of random bytes, i.e. `/dev/urandom`. Writes are discarded.
+pub struct RandomDev;
+
+impl VfsNodeOps for RandomDev {
+    fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
+        Ok(char_dev_attr(0o666))
+    }
+
+    fn read_at(&self, _offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
+        for chunk in buf.chunks_mut(16) {
+            let bytes = axhal::misc::random().to_ne_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+        Ok(buf.len())
+    }
+
+    fn write_at(&self, _offset: u64, buf: &[u8]) -> VfsResult<usize> {
+        Ok(buf.len())
+    }
+
+    fn truncate(&self, _size: u64) -> VfsResult {
+        Ok(())
+    }
+
+    axfs_vfs::impl_vfs_non_dir_default! {}
+}
+
+impl DeviceOps for RandomDev {}
+
+/// A network device, e.g. `/dev/eth0`.
+///
+/// It can't be read or written, since packets go through sockets. It only
+/// shows that the device exists.
+pub struct NetDev;
+
+impl VfsNodeOps for NetDev {
+    fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
+        Ok(char_dev_attr(0o600))
+    }
+
+    axfs_vfs::impl_vfs_non_dir_default! {}
+}
+
+impl DeviceOps for NetDev {}
+
+/// The framebuffer of the main display, i.e. `/dev/fb0`.
+///
+/// Writes are shown on the screen immediately.
+#[cfg(feature = "display")]
+pub struct FramebufferDev;
+
+/// The variable screen information, i.e. `struct fb_var_screeninfo`.
+#[cfg(feature = "display")]
+#[repr(C)]
+#[allow(dead_code)] // read by the caller of ioctl
+#[derive(Default)]
+struct FbVarScreenInfo {
+    xres: u32,
+    yres: u32,
+    xres_virtual: u32,
+    yres_virtual: u32,
+    xoffset: u32,
+    yoffset: u32,
+    bits_per_pixel: u32,
+    grayscale: u32,
+    /// `offset`, `length` and `msb_right` of red, green, blue and alpha.
+    bitfields: [[u32; 3]; 4],
+    nonstd: u32,
+    activate: u32,
+    height: u32,
+    width: u32,
+    accel_flags: u32,
+    timings: [u32; 11],
+    reserved: [u32; 4],
+}
+
+/// The fixed screen information, i.e. `struct fb_fix_screeninfo`.
+#[cfg(feature = "display")]
+#[repr(C)]
+#[allow(dead_code)] // read by the caller of ioctl
+#[derive(Default)]
+struct FbFixScreenInfo {
+    id: [u8; 16],
+    smem_start: usize,
+    smem_len: u32,
+    type_: u32,
+    type_aux: u32,
+    visual: u32,
+    xpanstep: u16,
+    ypanstep: u16,
+    ywrapstep: u16,
+    line_length: u32,
+    mmio_start: usize,
+    mmio_len: u32,
+    accel: u32,
+    capabilities: u16,
+    reserved: [u16; 2],
+}
+
+/// Returns the framebuffer memory.
+#[cfg(feature = "display")]
+fn framebuffer() -> &'static mut [u8] {
+    let info = axdisplay::framebuffer_info();
+    // SAFETY: the framebuffer is mapped for the whole lifetime of the system
+    unsafe { core::slice::from_raw_parts_mut(info.fb_base_vaddr as *mut u8, info.fb_size) }
+}
+
+#[cfg(feature = "display")]
+impl VfsNodeOps for FramebufferDev {
+    fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
+        let size = axdisplay::framebuffer_info().fb_size as u64;
+        let perm = VfsNodePerm::from_bits_truncate(0o660);
+        Ok(VfsNodeAttr::new(perm, VfsNodeType::CharDevice, size, 0))
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
+        let fb = framebuffer();
+        let start = fb.len().min(offset as usize);
+        let len = buf.len().min(fb.len() - start);
+        buf[..len].copy_from_slice(&fb[start..start + len]);
+        Ok(len)
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> VfsResult<usize> {
+        let fb = framebuffer();
+        let start = fb.len().min(offset as usize);
+        let len = buf.len().min(fb.len() - start);
+        fb[start..start + len].copy_from_slice(&buf[..len]);
+        axdisplay::framebuffer_flush();
+        Ok(len)
+    }
+
+    fn fsync(&self) -> VfsResult {
+        axdisplay::framebuffer_flush();
+        Ok(())
+    }
+
+    fn truncate(&self, _size: u64) -> VfsResult {
+        Ok(())
+    }
+
+    axfs_vfs::impl_vfs_non_dir_default! {}
+}
+
+#[cfg(feature = "display")]
+impl DeviceOps for FramebufferDev {
+    unsafe fn ioctl(&self, cmd: u32, arg: usize) -> VfsResult<usize> {
+        // the pixels are always 32-bit BGRA
+        let info = axdisplay::framebuffer_info();
+        match cmd {
+            FBIOGET_VSCREENINFO => write_arg(
+                arg,
+                FbVarScreenInfo {
+                    xres: info.width,
+                    yres: info.height,
+                    xres_virtual: info.width,
+                    yres_virtual: info.height,
+                    bits_per_pixel: 32,
+                    bitfields: [[16, 8, 0], [8, 8, 0], [0, 8, 0], [24, 8, 0]],
+                    height: u32::MAX, // unknown physical size
+                    width: u32::MAX,
+                    ..Default::default()
+                },
+            ),
+            FBIOGET_FSCREENINFO => {
+                let mut id = [0; 16];
+                id[..6].copy_from_slice(b"axfb-0");
+                write_arg(
+                    arg,
+                    FbFixScreenInfo {
+                        id,
+                        smem_start: info.fb_base_vaddr,
+                        smem_len: info.fb_size as u32,
+                        visual: 2, // FB_VISUAL_TRUECOLOR
+                        line_length: info.width * 4,
+                        ..Default::default()
+                    },
+                )
+            }
+            _ => Err(VfsError::Unsupported),
+        }
+    }
+}
@@ -0,0 +1,492 @@
+//! Mapping logical blocks of inodes to physical blocks, by extent trees or
+//! (for ext2/3) indirect blocks.
+//!
+//! Functions here update the inode in memory, the caller should write it
+//! back.
+
+use alloc::{vec, vec::Vec};
+use axerrno::{ax_err, AxError};
+use axfs_vfs::VfsResult;
+
+use super::crc::crc32c;
+use super::layout::*;
+use super::volume::Volume;
+
+/// Maximum number of extents in the root node in the inode.
+const ROOT_EXTENTS: usize = (INODE_BLOCK_SIZE - EXTENT_HEADER_SIZE) / EXTENT_ENTRY_SIZE;
+
+impl Volume {
+    /// Initializes the block map of a new inode, uses an extent tree if the
+    /// volume supports it.
+    pub fn init_block_map(&self, inode: &mut Inode) {
+        if self.sb.has_incompat(INCOMPAT_EXTENTS) {
+            inode.set_flags(inode.flags() | INODE_FLAG_EXTENTS);
+            let header = ExtentHeader {
+                entries: 0,
+                max: ROOT_EXTENTS as u16,
+                depth: 0,
+            };
+            header.write(inode.block_area_mut());
+        }
+    }
+
+    /// Returns the physical block that logical block `lblk` of the inode is
+    /// mapped to, or `None` if it's a hole.
+    pub fn map_block(&self, inode: &Inode, lblk: u32) -> VfsResult<Option<u64>> {
+        if inode.has_flag(INODE_FLAG_EXTENTS) {
+            Ok(self
+                .find_extent(inode, lblk)?
+                .filter(|e| !e.uninit)
+                .map(|e| e.pblk + (lblk - e.lblk) as u64))
+        } else {
+            self.map_indirect(inode, lblk)
+        }
+    }
+
+    /// Returns the physical block that logical block `lblk` of the inode is
+    /// mapped to, allocates one if it's not mapped. The second value is
+    /// `true` if the block is newly allocated (or was uninitialized), so its
+    /// content should be treated as zeros.
+    pub fn map_or_alloc_block(
+        &mut self,
+        ino: u32,
+        inode: &mut Inode,
+        lblk: u32,
+    ) -> VfsResult<(u64, bool)> {
+        if inode.has_flag(INODE_FLAG_EXTENTS) {
+            if let Some(e) = self.find_extent(inode, lblk)? {
+                if !e.uninit {
+                    return Ok((e.pblk + (lblk - e.lblk) as u64, false));
+                }
+            }
+            self.alloc_extent_block(ino, inode, lblk)
+        } else {
+            self.alloc_indirect(ino, inode, lblk)
+        }
+    }
+
+    /// Frees all blocks of the inode from logical block `from`.
+    pub fn truncate_blocks(&mut self, ino: u32, inode: &mut Inode, from: u32) -> VfsResult {
+        if inode.has_flag(INODE_FLAG_EXTENTS) {
+            self.truncate_extents(ino, inode, from)
+        } else {
+            self.truncate_indirect(inode, from)
+        }
+    }
+
+    // ---- extent trees ----
+
+    fn find_extent(&self, inode: &Inode, lblk: u32) -> VfsResult<Option<Extent>> {
+        let mut node = inode.block_area().to_vec();
+        loop {
+            let header = ExtentHeader::parse(&node).ok_or(AxError::InvalidData)?;
+            let entries = node[EXTENT_HEADER_SIZE..]
+                .chunks_exact(EXTENT_ENTRY_SIZE)
+                .take(header.entries as usize);
+            if header.depth == 0 {
+                return Ok(entries
+                    .map(Extent::parse)
+                    .find(|e| e.lblk <= lblk && lblk < e.end()));
+            }
+            let Some((_, child)) = entries
+                .map(parse_extent_index)
+                .take_while(|&(first, _)| first <= lblk)
+                .last()
+            else {
+                return Ok(None);
+            };
+            node = vec![0; self.block_size];
+            self.read_block(child, &mut node)?;
+        }
+    }
+
+    /// Loads all extents of the inode, and the blocks of the tree nodes.
+    fn load_extents(&self, inode: &Inode) -> VfsResult<(Vec<Extent>, Vec<u64>)> {
+        let mut extents = Vec::new();
+        let mut nodes = Vec::new();
+        self.load_extent_node(inode.block_area(), &mut extents, &mut nodes, 0)?;
+        Ok((extents, nodes))
+    }
+
+    fn load_extent_node(
+        &self,
+        node: &[u8],
+        extents: &mut Vec<Extent>,
+        nodes: &mut Vec<u64>,
+        level: usize,
+    ) -> VfsResult {
+        let header = ExtentHeader::parse(node).ok_or(AxError::InvalidData)?;
+        if level > 5 {
+            return ax_err!(InvalidData, "ext4: extent tree is too deep");
+        }
+        let entries = node[EXTENT_HEADER_SIZE..]
+            .chunks_exact(EXTENT_ENTRY_SIZE)
+            .take(header.entries as usize);
+        if header.depth == 0 {
+            extents.extend(entries.map(Extent::parse).filter(|e| e.len > 0));
+            return Ok(());
+        }
+        let mut child = vec![0; self.block_size];
+        for (_, block) in entries.map(parse_extent_index) {
+            nodes.push(block);
+            self.read_block(block, &mut child)?;
+            self.load_extent_node(&child, extents, nodes, level + 1)?;
+        }
+        Ok(())
+    }
+
+    /// Writes `extents` as the extent tree of the inode, reusing the blocks
+    /// of the old tree nodes in `old_nodes`.
+    fn store_extents(
+        &mut self,
+        ino: u32,
+        inode: &mut Inode,
+        extents: &[Extent],
+        mut old_nodes: Vec<u64>,
+    ) -> VfsResult {
+        let mut root = [0u8; INODE_BLOCK_SIZE];
+        if extents.len() <= ROOT_EXTENTS {
+            write_extent_node(&mut root, 0, extents.len(), |i, raw| extents[i].write(raw));
+        } else {
+            let cap = (self.block_size - EXTENT_HEADER_SIZE) / EXTENT_ENTRY_SIZE;
+            let goal = extents[0].pblk;
+            let mut block = vec![0; self.block_size];
+
+            // leaves, then index nodes until the top level fits in the inode
+            let mut level = Vec::new();
+            for chunk in extents.chunks(cap) {
+                let node = self.take_node_block(inode, &mut old_nodes, goal)?;
+                write_extent_node(&mut block, 0, chunk.len(), |i, raw| chunk[i].write(raw));
+                self.write_extent_block(ino, inode, node, &mut block)?;
+                level.push((chunk[0].lblk, node));
+            }
+            let mut depth = 1;
+            while level.len() > ROOT_EXTENTS {
+                let mut upper = Vec::new();
+                for chunk in level.chunks(cap) {
+                    let node = self.take_node_block(inode, &mut old_nodes, goal)?;
+                    write_extent_node(&mut block, depth, chunk.len(), |i, raw| {
+                        write_extent_index(raw, chunk[i].0, chunk[i].1)
+                    });
+                    self.write_extent_block(ino, inode, node, &mut block)?;
+                    upper.push((chunk[0].0, node));
+                }
+                level = upper;
+                depth += 1;
+            }
+            write_extent_node(&mut root, depth, level.len(), |i, raw| {
+                write_extent_index(raw, level[i].0, level[i].1)
+            });
+        }
+        inode.block_area_mut().copy_from_slice(&root);
+        for node in old_nodes {
+            self.free_block(node)?;
+            self.add_inode_blocks(inode, -1);
+        }
+        Ok(())
+    }
+
+    fn take_node_block(
+        &mut self,
+        inode: &mut Inode,
+        old: &mut Vec<u64>,
+        goal: u64,
+    ) -> VfsResult<u64> {
+        if let Some(block) = old.pop() {
+            return Ok(block);
+        }
+        let block = self.alloc_block(goal)?;
+        self.add_inode_blocks(inode, 1);
+        Ok(block)
+    }
+
+    fn write_extent_block(
+        &mut self,
+        ino: u32,
+        inode: &Inode,
+        block: u64,
+        data: &mut [u8],
+    ) -> VfsResult {
+        if self.sb.has_metadata_csum() {
+            let cap = (self.block_size - EXTENT_HEADER_SIZE) / EXTENT_ENTRY_SIZE;
+            let tail = EXTENT_HEADER_SIZE + cap * EXTENT_ENTRY_SIZE;
+            let csum = crc32c(self.inode_csum_seed(ino, inode), &data[..tail]);
+            write_u32(data, tail, csum);
+        }
+        self.write_block(block, data)
+    }
+
+    fn alloc_extent_block(
+        &mut self,
+        ino: u32,
+        inode: &mut Inode,
+        lblk: u32,
+    ) -> VfsResult<(u64, bool)> {
+        let (mut extents, nodes) = self.load_extents(inode)?;
+        let idx = extents.partition_point(|e| e.end() <= lblk);
+        let pblk = match extents.get(idx) {
+            Some(e) if e.lblk <= lblk => {
+                // uninitialized, split out the block as initialized
+                let e = extents.remove(idx);
+                let off = lblk - e.lblk;
+                let pblk = e.pblk + off as u64;
+                let before = Extent { len: off, ..e };
+                let after = Extent {
+                    lblk: lblk + 1,
+                    len: e.len - off - 1,
+                    pblk: pblk + 1,
+                    uninit: true,
+                };
+                for piece in [before, after] {
+                    if piece.len > 0 {
+                        insert_extent(&mut extents, piece);
+                    }
+                }
+                insert_extent(
+                    &mut extents,
+                    Extent {
+                        lblk,
+                        len: 1,
+                        pblk,
+                        uninit: false,
+                    },
+                );
+                pblk
+            }
+            _ => {
+                let goal = match idx.checked_sub(1).map(|i| extents[i]) {
+                    Some(prev) => prev.pblk + (lblk - prev.lblk) as u64,
+                    None => self.inode_goal_block(ino),
+                };
+                let pblk = self.alloc_block(goal)?;
+                self.add_inode_blocks(inode, 1);
+                insert_extent(
+                    &mut extents,
+                    Extent {
+                        lblk,
+                        len: 1,
+                        pblk,
+                        uninit: false,
+                    },
+                );
+                pblk
+            }
+        };
+        self.store_extents(ino, inode, &extents, nodes)?;
+        Ok((pblk, true))
+    }
+
+    fn truncate_extents(&mut self, ino: u32, inode: &mut Inode, from: u32) -> VfsResult {
+        let (mut extents, nodes) = self.load_extents(inode)?;
+        let mut freed = 0;
+        for e in extents.iter_mut().filter(|e| e.end() > from) {
+            let keep = from.saturating_sub(e.lblk);
+            for i in keep..e.len {
+                self.free_block(e.pblk + i as u64)?;
+                freed += 1;
+            }
+            e.len = keep;
+        }
+        if freed == 0 {
+            return Ok(());
+        }
+        self.add_inode_blocks(inode, -freed);
+        extents.retain(|e| e.len > 0);
+        self.store_extents(ino, inode, &extents, nodes)
+    }
+
+    // ---- indirect blocks ----
+
+    fn ptrs_per_block(&self) -> u64 {
+        (self.block_size / 4) as u64
+    }
+
+    /// Returns the slot in `i_block` and the indices in the indirect blocks
+    /// to reach logical block `lblk`.
+    fn indirect_path(&self, lblk: u32) -> VfsResult<(usize, Vec<usize>)> {
+        let p = self.ptrs_per_block();
+        let mut l = lblk as u64;
+        if l < DIRECT_BLOCKS as u64 {
+            return Ok((l as usize, Vec::new()));
+        }
+        l -= DIRECT_BLOCKS as u64;
+        if l < p {
+            return Ok((DIRECT_BLOCKS, vec![l as usize]));
+        }
+        l -= p;
+        if l < p * p {
+            return Ok((DIRECT_BLOCKS + 1, vec![(l / p) as usize, (l % p) as usize]));
+        }
+        l -= p * p;
+        if l < p * p * p {
+            let path = vec![(l / p / p) as usize, (l / p % p) as usize, (l % p) as usize];
+            return Ok((DIRECT_BLOCKS + 2, path));
+        }
+        ax_err!(InvalidInput, "ext4: file too large")
+    }
+
+    fn map_indirect(&self, inode: &Inode, lblk: u32) -> VfsResult<Option<u64>> {
+        let (slot, path) = self.indirect_path(lblk)?;
+        let mut block = read_u32(inode.block_area(), slot * 4) as u64;
+        let mut buf = vec![0; self.block_size];
+        for idx in path {
+            if block == 0 {
+                return Ok(None);
+            }
+            self.read_block(block, &mut buf)?;
+            block = read_u32(&buf, idx * 4) as u64;
+        }
+        Ok((block != 0).then_some(block))
+    }
+
+    fn alloc_indirect(&mut self, ino: u32, inode: &mut Inode, lblk: u32) -> VfsResult<(u64, bool)> {
+        let (slot, path) = self.indirect_path(lblk)?;
+        let goal = self.inode_goal_block(ino);
+        let mut block = read_u32(inode.block_area(), slot * 4) as u64;
+        let mut new = false;
+        if block == 0 {
+            block = self.alloc_block(goal)?;
+            self.add_inode_blocks(inode, 1);
+            write_u32(inode.block_area_mut(), slot * 4, block as u32);
+            new = true;
+            if !path.is_empty() {
+                self.write_block(block, &vec![0; self.block_size])?;
+            }
+        }
+        let mut buf = vec![0; self.block_size];
+        for (level, &idx) in path.iter().enumerate() {
+            self.read_block(block, &mut buf)?;
+            let mut child = read_u32(&buf, idx * 4) as u64;
+            new = child == 0;
+            if new {
+                child = self.alloc_block(block)?;
+                self.add_inode_blocks(inode, 1);
+                if level + 1 < path.len() {
+                    self.write_block(child, &vec![0; self.block_size])?;
+                }
+                write_u32(&mut buf, idx * 4, child as u32);
+                self.write_block(block, &buf)?;
+            }
+            block = child;
+        }
+        Ok((block, new))
+    }
+
+    fn truncate_indirect(&mut self, inode: &mut Inode, from: u32) -> VfsResult {
+        let p = self.ptrs_per_block();
+        let mut freed = 0;
+        for slot in (from as usize).min(DIRECT_BLOCKS)..DIRECT_BLOCKS {
+            let block = read_u32(inode.block_area(), slot * 4) as u64;
+            if block != 0 {
+                self.free_block(block)?;
+                write_u32(inode.block_area_mut(), slot * 4, 0);
+                freed += 1;
+            }
+        }
+        let mut base = DIRECT_BLOCKS as u64;
+        let mut span = p;
+        for (depth, slot) in (DIRECT_BLOCKS..DIRECT_BLOCKS + 3).enumerate() {
+            let block = read_u32(inode.block_area(), slot * 4) as u64;
+            if block != 0
+                && (from as u64) < base + span
+                && self.free_indirect_tree(block, depth + 1, base, from as u64, &mut freed)?
+            {
+                self.free_block(block)?;
+                write_u32(inode.block_area_mut(), slot * 4, 0);
+                freed += 1;
+            }
+            base += span;
+            span *= p;
+        }
+        self.add_inode_blocks(inode, -freed);
+        Ok(())
+    }
+
+    /// Frees blocks from logical block `from` in the indirect block `block`
+    /// of `depth` levels that maps blocks from `base`. Returns `true` if the
+    /// indirect block becomes empty.
+    fn free_indirect_tree(
+        &mut self,
+        block: u64,
+        depth: usize,
+        base: u64,
+        from: u64,
+        freed: &mut i64,
+    ) -> VfsResult<bool> {
+        let p = self.ptrs_per_block();
+        let child_span = p.pow(depth as u32 - 1);
+        let mut buf = vec![0; self.block_size];
+        self.read_block(block, &mut buf)?;
+        let mut changed = false;
+        let mut empty = true;
+        for i in 0..p as usize {
+            let child = read_u32(&buf, i * 4) as u64;
+            if child == 0 {
+                continue;
+            }
+            let child_base = base + i as u64 * child_span;
+            let free = if child_base + child_span <= from {
+                false
+            } else if depth == 1 {
+                child_base >= from
+            } else {
+                self.free_indirect_tree(child, depth - 1, child_base, from, freed)?
+            };
+            if free {
+                self.free_block(child)?;
+                write_u32(&mut buf, i * 4, 0);
+                *freed += 1;
+                changed = true;
+            } else {
+                empty = false;
+            }
+        }
+        if changed && !empty {
+            self.write_block(block, &buf)?;
+        }
+        Ok(empty)
+    }
+}
+
+/// Writes an extent tree node with `count` entries written by `write_entry`.
+fn write_extent_node(
+    node: &mut [u8],
+    depth: u16,
+    count: usize,
+    mut write_entry: impl FnMut(usize, &mut [u8]),
+) {
+    node.fill(0);
+    let max = (node.len() - EXTENT_HEADER_SIZE) / EXTENT_ENTRY_SIZE;
+    let header = ExtentHeader {
+        entries: count as u16,
+        max: max as u16,
+        depth,
+    };
+    header.write(node);
+    for i in 0..count {
+        let offset = EXTENT_HEADER_SIZE + i * EXTENT_ENTRY_SIZE;
+        write_entry(i, &mut node[offset..offset + EXTENT_ENTRY_SIZE]);
+    }
+}
+
+fn can_merge(a: &Extent, b: &Extent) -> bool {
+    a.uninit == b.uninit
+        && a.end() == b.lblk
+        && a.pblk + a.len as u64 == b.pblk
+        && a.len + b.len <= a.max_len()
+}
+
+/// Inserts an extent into the sorted list, merging it with its neighbors if
+/// possible.
+fn insert_extent(extents: &mut Vec<Extent>, new: Extent) {
+    let idx = extents.partition_point(|e| e.lblk < new.lblk);
+    extents.insert(idx, new);
+    if idx + 1 < extents.len() && can_merge(&extents[idx], &extents[idx + 1]) {
+        extents[idx].len += extents[idx + 1].len;
+        extents.remove(idx + 1);
+    }
+    if idx > 0 && can_merge(&extents[idx - 1], &extents[idx]) {
+        extents[idx - 1].len += extents[idx].len;
+        extents.remove(idx);
+    }
+}
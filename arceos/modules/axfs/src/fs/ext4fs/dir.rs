@@ -0,0 +1,434 @@
+//! Directories, and creating and removing inodes.
+//!
+//! Directories are searched linearly. Hashed (indexed) directories can be
+//! read and have entries removed, since their leaf blocks are ordinary
+//! directory blocks, but adding entries to them is not supported.
+
+use alloc::vec;
+use axerrno::{ax_err, AxError};
+use axfs_vfs::VfsResult;
+
+use super::crc::crc32c;
+use super::layout::*;
+use super::volume::Volume;
+
+/// Maximum length of a file name.
+const NAME_MAX: usize = 255;
+/// Maximum links count of an inode.
+const LINK_MAX: u16 = 65000;
+
+impl Volume {
+    fn has_file_type(&self) -> bool {
+        self.sb.has_incompat(INCOMPAT_FILETYPE)
+    }
+
+    /// The end of directory entries in a directory block, a checksum tail
+    /// follows if `metadata_csum` is enabled.
+    fn dir_block_end(&self) -> usize {
+        if self.sb.has_metadata_csum() {
+            self.block_size - DIRENT_TAIL_SIZE
+        } else {
+            self.block_size
+        }
+    }
+
+    fn dir_blocks(&self, dir: &Inode) -> u32 {
+        dir.size().div_ceil(self.block_size as u64) as u32
+    }
+
+    fn write_dir_block(&self, ino: u32, dir: &Inode, pblk: u64, block: &mut [u8]) -> VfsResult {
+        if self.sb.has_metadata_csum() {
+            let end = self.dir_block_end();
+            write_u32(block, end, 0);
+            write_u16(block, end + 4, DIRENT_TAIL_SIZE as u16);
+            block[end + 6] = 0;
+            block[end + 7] = DIRENT_TAIL_FILE_TYPE;
+            let csum = crc32c(self.inode_csum_seed(ino, dir), &block[..end]);
+            write_u32(block, end + 8, csum);
+        }
+        self.write_block(pblk, block)
+    }
+
+    fn parse_dirent<'a>(&self, block: &'a [u8], off: usize) -> VfsResult<DirEntry<'a>> {
+        DirEntry::parse(block, off, self.has_file_type()).ok_or_else(|| {
+            warn!("ext4: corrupted directory entry");
+            AxError::InvalidData
+        })
+    }
+
+    /// Calls `f` on every entry in use of the directory, until it returns
+    /// `Some`.
+    pub fn scan_dir<T>(
+        &self,
+        dir: &Inode,
+        mut f: impl FnMut(&DirEntry) -> Option<T>,
+    ) -> VfsResult<Option<T>> {
+        let end = self.dir_block_end();
+        let mut block = vec![0; self.block_size];
+        for lblk in 0..self.dir_blocks(dir) {
+            let Some(pblk) = self.map_block(dir, lblk)? else {
+                continue;
+            };
+            self.read_block(pblk, &mut block)?;
+            let mut off = 0;
+            while off < end {
+                let entry = self.parse_dirent(&block, off)?;
+                if entry.inode != 0 {
+                    if let Some(res) = f(&entry) {
+                        return Ok(Some(res));
+                    }
+                }
+                off += entry.rec_len;
+            }
+        }
+        Ok(None)
+    }
+
+    /// Finds the entry `name` in the directory, returns its inode number.
+    pub fn dir_find(&self, dir: &Inode, name: &[u8]) -> VfsResult<Option<u32>> {
+        self.scan_dir(dir, |e| (e.name == name).then_some(e.inode))
+    }
+
+    fn dir_is_empty(&self, dir: &Inode) -> VfsResult<bool> {
+        let other = self.scan_dir(dir, |e| (e.name != b"." && e.name != b"..").then_some(()))?;
+        Ok(other.is_none())
+    }
+
+    /// Adds an entry to the directory.
+    fn dir_add(&mut self, dir_ino: u32, name: &[u8], ino: u32, mode: u16) -> VfsResult {
+        let mut dir = self.read_inode(dir_ino)?;
+        if dir.has_flag(INODE_FLAG_INDEX) {
+            warn!("ext4: adding entries to hashed directories is not supported");
+            return ax_err!(Unsupported);
+        }
+        let file_type = if self.has_file_type() {
+            mode_to_file_type(mode)
+        } else {
+            FT_UNKNOWN
+        };
+        let end = self.dir_block_end();
+        let need = dirent_len(name.len());
+        let mut block = vec![0; self.block_size];
+        for lblk in 0..self.dir_blocks(&dir) {
+            let Some(pblk) = self.map_block(&dir, lblk)? else {
+                continue;
+            };
+            self.read_block(pblk, &mut block)?;
+            let mut off = 0;
+            while off < end {
+                let entry = self.parse_dirent(&block, off)?;
+                let (rec_len, used) = (entry.rec_len, entry.used_len());
+                if entry.inode == 0 && rec_len >= need {
+                    write_dirent(&mut block, off, ino, rec_len, name, file_type);
+                    return self.write_dir_block(dir_ino, &dir, pblk, &mut block);
+                } else if entry.inode != 0 && rec_len >= used + need {
+                    write_u16(&mut block, off + 4, used as u16);
+                    write_dirent(&mut block, off + used, ino, rec_len - used, name, file_type);
+                    return self.write_dir_block(dir_ino, &dir, pblk, &mut block);
+                }
+                off += rec_len;
+            }
+        }
+
+        // no room, append a new block
+        let lblk = self.dir_blocks(&dir);
+        let (pblk, _) = self.map_or_alloc_block(dir_ino, &mut dir, lblk)?;
+        block.fill(0);
+        write_dirent(&mut block, 0, ino, end, name, file_type);
+        self.write_dir_block(dir_ino, &dir, pblk, &mut block)?;
+        dir.set_size((lblk as u64 + 1) * self.block_size as u64);
+        self.write_inode(dir_ino, &mut dir)
+    }
+
+    /// Removes the entry `name` from the directory, returns its inode
+    /// number.
+    fn dir_remove(&mut self, dir_ino: u32, name: &[u8]) -> VfsResult<u32> {
+        let dir = self.read_inode(dir_ino)?;
+        let end = self.dir_block_end();
+        let mut block = vec![0; self.block_size];
+        for lblk in 0..self.dir_blocks(&dir) {
+            let Some(pblk) = self.map_block(&dir, lblk)? else {
+                continue;
+            };
+            self.read_block(pblk, &mut block)?;
+            let mut prev = None;
+            let mut off = 0;
+            while off < end {
+                let entry = self.parse_dirent(&block, off)?;
+                let rec_len = entry.rec_len;
+                if entry.inode != 0 && entry.name == name {
+                    let ino = entry.inode;
+                    match prev {
+                        // merge into the previous entry
+                        Some(prev) => {
+                            let prev_len = read_u16(&block, prev + 4) as usize;
+                            write_u16(&mut block, prev + 4, (prev_len + rec_len) as u16);
+                        }
+                        None => write_u32(&mut block, off, 0),
+                    }
+                    self.write_dir_block(dir_ino, &dir, pblk, &mut block)?;
+                    return Ok(ino);
+                }
+                prev = Some(off);
+                off += rec_len;
+            }
+        }
+        ax_err!(NotFound)
+    }
+
+    /// Points the `..` entry of the directory to `parent`.
+    fn dir_set_parent(&mut self, dir_ino: u32, parent: u32) -> VfsResult {
+        let dir = self.read_inode(dir_ino)?;
+        let pblk = self.map_block(&dir, 0)?.ok_or(AxError::InvalidData)?;
+        let mut block = vec![0; self.block_size];
+        self.read_block(pblk, &mut block)?;
+        let dot = self.parse_dirent(&block, 0)?;
+        let off = dot.rec_len;
+        let dotdot = self.parse_dirent(&block, off)?;
+        if dot.name != b"." || dotdot.name != b".." {
+            return ax_err!(InvalidData, "ext4: corrupted directory");
+        }
+        write_u32(&mut block, off, parent);
+        self.write_dir_block(dir_ino, &dir, pblk, &mut block)
+    }
+
+    /// Adds `delta` to the links count of the directory, for subdirectories
+    /// created or removed in it.
+    fn add_dir_links(&mut self, dir_ino: u32, delta: i32) -> VfsResult {
+        let mut dir = self.read_inode(dir_ino)?;
+        let links = dir.links_count();
+        // with `dir_nlink`, a count of 1 means too many subdirectories
+        if links == 1 && self.sb.has_ro_compat(RO_COMPAT_DIR_NLINK) {
+            return Ok(());
+        }
+        let links = links as i32 + delta;
+        if links >= LINK_MAX as i32 {
+            if !self.sb.has_ro_compat(RO_COMPAT_DIR_NLINK) {
+                return ax_err!(StorageFull, "ext4: too many links");
+            }
+            dir.set_links_count(1);
+        } else {
+            dir.set_links_count(links.max(2) as u16);
+        }
+        self.write_inode(dir_ino, &mut dir)
+    }
+
+    /// Finds the inode of the path relative to the directory `dir_ino`.
+    /// Symbolic links are not followed.
+    pub fn lookup_path(&self, mut ino: u32, path: &str) -> VfsResult<u32> {
+        for name in path.split('/').filter(|s| !s.is_empty() && *s != ".") {
+            let dir = self.read_inode(ino)?;
+            if !dir.is_dir() {
+                return ax_err!(NotADirectory);
+            }
+            ino = self
+                .dir_find(&dir, name.as_bytes())?
+                .ok_or(AxError::NotFound)?;
+        }
+        Ok(ino)
+    }
+
+    /// Creates an inode with `mode` named `name` in the directory, returns
+    /// its inode number.
+    pub fn create_node(&mut self, dir_ino: u32, name: &str, mode: u16) -> VfsResult<u32> {
+        check_name(name)?;
+        let dir = self.read_inode(dir_ino)?;
+        if !dir.is_dir() {
+            return ax_err!(NotADirectory);
+        }
+        if self.dir_find(&dir, name.as_bytes())?.is_some() {
+            return ax_err!(AlreadyExists);
+        }
+
+        let is_dir = mode & S_IFMT == S_IFDIR;
+        let ino = self.alloc_inode(self.group_of_inode(dir_ino), is_dir)?;
+        let mut inode = self.new_inode(mode);
+        // fast symlinks keep the target in place of the block map
+        if mode & S_IFMT != S_IFLNK {
+            self.init_block_map(&mut inode);
+        }
+        inode.set_links_count(if is_dir { 2 } else { 1 });
+        let res = self.init_node(dir_ino, ino, &mut inode, is_dir);
+        let res = res.and_then(|_| self.dir_add(dir_ino, name.as_bytes(), ino, mode));
+        if let Err(e) = res {
+            self.release_inode(ino)?;
+            return Err(e);
+        }
+        if is_dir {
+            self.add_dir_links(dir_ino, 1)?;
+        }
+        Ok(ino)
+    }
+
+    fn init_node(&mut self, dir_ino: u32, ino: u32, inode: &mut Inode, is_dir: bool) -> VfsResult {
+        self.write_inode(ino, inode)?;
+        if is_dir {
+            let (pblk, _) = self.map_or_alloc_block(ino, inode, 0)?;
+            let mut block = vec![0; self.block_size];
+            let dot_len = dirent_len(1);
+            let file_type = if self.has_file_type() {
+                FT_DIR
+            } else {
+                FT_UNKNOWN
+            };
+            write_dirent(&mut block, 0, ino, dot_len, b".", file_type);
+            let rest = self.dir_block_end() - dot_len;
+            write_dirent(&mut block, dot_len, dir_ino, rest, b"..", file_type);
+            self.write_dir_block(ino, inode, pblk, &mut block)?;
+            inode.set_size(self.block_size as u64);
+            self.write_inode(ino, inode)?;
+        }
+        Ok(())
+    }
+
+    /// Adds an entry `name` for the existing inode `ino` to the directory.
+    pub fn link(&mut self, dir_ino: u32, name: &str, ino: u32) -> VfsResult {
+        check_name(name)?;
+        let dir = self.read_inode(dir_ino)?;
+        if !dir.is_dir() {
+            return ax_err!(NotADirectory);
+        }
+        if self.dir_find(&dir, name.as_bytes())?.is_some() {
+            return ax_err!(AlreadyExists);
+        }
+        let mut inode = self.read_inode(ino)?;
+        if inode.is_dir() {
+            return ax_err!(PermissionDenied);
+        }
+        // removed while it's still open
+        if inode.links_count() == 0 {
+            return ax_err!(NotFound);
+        }
+        if inode.links_count() >= LINK_MAX {
+            return ax_err!(StorageFull, "ext4: too many links");
+        }
+        self.dir_add(dir_ino, name.as_bytes(), ino, inode.mode())?;
+        inode.set_links_count(inode.links_count() + 1);
+        self.write_inode(ino, &mut inode)
+    }
+
+    /// Removes the entry `name` from the directory. Returns the inode
+    /// number, and whether it has no links left and should be released
+    /// once it's not in use.
+    pub fn unlink(&mut self, dir_ino: u32, name: &str) -> VfsResult<(u32, bool)> {
+        if name == "." || name == ".." {
+            return ax_err!(InvalidInput);
+        }
+        let dir = self.read_inode(dir_ino)?;
+        if !dir.is_dir() {
+            return ax_err!(NotADirectory);
+        }
+        let ino = self
+            .dir_find(&dir, name.as_bytes())?
+            .ok_or(AxError::NotFound)?;
+        let mut inode = self.read_inode(ino)?;
+        if inode.is_dir() && !self.dir_is_empty(&inode)? {
+            return ax_err!(DirectoryNotEmpty);
+        }
+        self.dir_remove(dir_ino, name.as_bytes())?;
+        if inode.is_dir() {
+            inode.set_links_count(0);
+            self.add_dir_links(dir_ino, -1)?;
+        } else {
+            inode.set_links_count(inode.links_count().saturating_sub(1));
+        }
+        self.write_inode(ino, &mut inode)?;
+        Ok((ino, inode.links_count() == 0))
+    }
+
+    /// Moves the entry `src_name` in `src_dir` to `dst_name` in `dst_dir`,
+    /// replacing the destination if it exists. Returns the replaced inode as
+    /// [`Volume::unlink`] does.
+    pub fn rename(
+        &mut self,
+        src_dir: u32,
+        src_name: &str,
+        dst_dir: u32,
+        dst_name: &str,
+    ) -> VfsResult<Option<(u32, bool)>> {
+        check_name(dst_name)?;
+        if src_name == "." || src_name == ".." {
+            return ax_err!(InvalidInput);
+        }
+        let src_dir_inode = self.read_inode(src_dir)?;
+        let dst_dir_inode = self.read_inode(dst_dir)?;
+        if !src_dir_inode.is_dir() || !dst_dir_inode.is_dir() {
+            return ax_err!(NotADirectory);
+        }
+        let ino = self
+            .dir_find(&src_dir_inode, src_name.as_bytes())?
+            .ok_or(AxError::NotFound)?;
+        let inode = self.read_inode(ino)?;
+        let is_dir = inode.is_dir();
+
+        // a directory can't be moved into itself
+        if is_dir && src_dir != dst_dir {
+            let mut cur = dst_dir;
+            while cur != ROOT_INO {
+                if cur == ino {
+                    return ax_err!(InvalidInput, "ext4: move a directory into itself");
+                }
+                cur = self.lookup_path(cur, "..")?;
+            }
+        }
+
+        let mut replaced = None;
+        if let Some(old) = self.dir_find(&dst_dir_inode, dst_name.as_bytes())? {
+            if old == ino {
+                return Ok(None);
+            }
+            let old_inode = self.read_inode(old)?;
+            match (is_dir, old_inode.is_dir()) {
+                (true, false) => return ax_err!(NotADirectory),
+                (false, true) => return ax_err!(IsADirectory),
+                _ => {}
+            }
+            replaced = Some(self.unlink(dst_dir, dst_name)?);
+        }
+
+        self.dir_remove(src_dir, src_name.as_bytes())?;
+        self.dir_add(dst_dir, dst_name.as_bytes(), ino, inode.mode())?;
+        if is_dir && src_dir != dst_dir {
+            self.dir_set_parent(ino, dst_dir)?;
+            self.add_dir_links(src_dir, -1)?;
+            self.add_dir_links(dst_dir, 1)?;
+        }
+        Ok(replaced)
+    }
+
+    /// Frees the blocks and the inode itself of an inode without links.
+    pub fn release_inode(&mut self, ino: u32) -> VfsResult {
+        let mut inode = self.read_inode(ino)?;
+        if !is_fast_symlink(&inode, self.block_size) {
+            self.truncate_blocks(ino, &mut inode, 0)?;
+        }
+        // keep the generation, so that stale references can be detected
+        let mut freed = Inode::new(inode.raw.len(), inode.extra_isize() as u16);
+        freed.raw[0x64..0x68].copy_from_slice(&inode.raw[0x64..0x68]);
+        self.write_inode(ino, &mut freed)?;
+        self.free_inode(ino, inode.is_dir())
+    }
+}
+
+/// Whether the target of the symlink is stored in the inode itself.
+pub fn is_fast_symlink(inode: &Inode, block_size: usize) -> bool {
+    let acl_sectors = if inode.file_acl() != 0 {
+        (block_size / 512) as u64
+    } else {
+        0
+    };
+    inode.file_type() == S_IFLNK
+        && inode.size() < INODE_BLOCK_SIZE as u64
+        && !inode.has_flag(INODE_FLAG_EXTENTS)
+        && inode.blocks_raw() <= acl_sectors
+}
+
+fn check_name(name: &str) -> VfsResult {
+    if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+        ax_err!(InvalidInput)
+    } else if name.len() > NAME_MAX {
+        ax_err!(InvalidInput, "ext4: file name too long")
+    } else {
+        Ok(())
+    }
+}
@@ -0,0 +1,132 @@
+//! Reading and writing file data.
+
+use alloc::vec;
+use axerrno::ax_err;
+use axfs_vfs::VfsResult;
+
+use super::layout::*;
+use super::volume::Volume;
+
+impl Volume {
+    /// Reads data of the inode at `offset`, holes are read as zeros.
+    pub fn read_data(&self, inode: &Inode, offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
+        let size = inode.size();
+        if offset >= size {
+            return Ok(0);
+        }
+        let len = buf.len().min((size - offset) as usize);
+        let bs = self.block_size as u64;
+        let mut block = vec![0; self.block_size];
+        let mut done = 0;
+        while done < len {
+            let pos = offset + done as u64;
+            let in_block = (pos % bs) as usize;
+            let n = (self.block_size - in_block).min(len - done);
+            let out = &mut buf[done..done + n];
+            match self.map_block(inode, (pos / bs) as u32)? {
+                Some(pblk) => {
+                    self.read_block(pblk, &mut block)?;
+                    out.copy_from_slice(&block[in_block..in_block + n]);
+                }
+                None => out.fill(0),
+            }
+            done += n;
+        }
+        Ok(len)
+    }
+
+    /// Writes data to the inode at `offset`, allocating blocks and extending
+    /// the size as needed.
+    pub fn write_data(
+        &mut self,
+        ino: u32,
+        inode: &mut Inode,
+        offset: u64,
+        buf: &[u8],
+    ) -> VfsResult<usize> {
+        let bs = self.block_size as u64;
+        let end = offset + buf.len() as u64;
+        if end.div_ceil(bs) > u32::MAX as u64 {
+            return ax_err!(InvalidInput, "ext4: file too large");
+        }
+        let mut block = vec![0; self.block_size];
+        let mut done = 0;
+        let result = loop {
+            if done == buf.len() {
+                break Ok(());
+            }
+            let pos = offset + done as u64;
+            let in_block = (pos % bs) as usize;
+            let n = (self.block_size - in_block).min(buf.len() - done);
+            let (pblk, new) = match self.map_or_alloc_block(ino, inode, (pos / bs) as u32) {
+                Ok(res) => res,
+                Err(e) => break Err(e),
+            };
+            if n < self.block_size {
+                if new {
+                    block.fill(0);
+                } else if let Err(e) = self.read_block(pblk, &mut block) {
+                    break Err(e);
+                }
+            }
+            block[in_block..in_block + n].copy_from_slice(&buf[done..done + n]);
+            if let Err(e) = self.write_block(pblk, &block) {
+                break Err(e);
+            }
+            done += n;
+        };
+        // blocks may have been allocated even if it failed
+        let written_end = offset + done as u64;
+        if written_end > inode.size() {
+            inode.set_size(written_end);
+        }
+        self.write_inode(ino, inode)?;
+        match result {
+            Err(e) if done == 0 => Err(e),
+            _ => Ok(done),
+        }
+    }
+
+    /// Changes the size of the inode, freeing blocks past the end.
+    pub fn truncate(&mut self, ino: u32, inode: &mut Inode, size: u64) -> VfsResult {
+        let bs = self.block_size as u64;
+        if size.div_ceil(bs) > u32::MAX as u64 {
+            return ax_err!(InvalidInput, "ext4: file too large");
+        }
+        if size < inode.size() {
+            self.truncate_blocks(ino, inode, size.div_ceil(bs) as u32)?;
+            // zero the tail of the last block, it would be visible if the
+            // file is extended again
+            let in_block = (size % bs) as usize;
+            if in_block != 0 {
+                if let Some(pblk) = self.map_block(inode, (size / bs) as u32)? {
+                    let mut block = vec![0; self.block_size];
+                    self.read_block(pblk, &mut block)?;
+                    block[in_block..].fill(0);
+                    self.write_block(pblk, &block)?;
+                }
+            }
+        }
+        inode.set_size(size);
+        self.write_inode(ino, inode)
+    }
+
+    /// Sets the target of a new symlink. Short targets are stored in the
+    /// inode itself.
+    pub fn write_symlink(&mut self, ino: u32, inode: &mut Inode, target: &[u8]) -> VfsResult {
+        if inode.size() != 0 || target.is_empty() {
+            return ax_err!(InvalidInput);
+        }
+        if target.len() >= self.block_size {
+            return ax_err!(InvalidInput, "ext4: symlink target too long");
+        }
+        if target.len() < INODE_BLOCK_SIZE {
+            inode.block_area_mut()[..target.len()].copy_from_slice(target);
+            inode.set_size(target.len() as u64);
+            return self.write_inode(ino, inode);
+        }
+        self.init_block_map(inode);
+        self.write_data(ino, inode, 0, target)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,670 @@
+//! On-disk structures of ext2/3/4.
+//!
+//! Structures are kept as raw bytes and accessed by offsets, so fields we
+//! don't know about are preserved when they are written back.
+
+use alloc::{vec, vec::Vec};
+use core::time::Duration;
+
+pub const EXT4_MAGIC: u16 = 0xef53;
+pub const SUPERBLOCK_OFFSET: u64 = 1024;
+pub const SUPERBLOCK_SIZE: usize = 1024;
+pub const ROOT_INO: u32 = 2;
+
+pub const INCOMPAT_FILETYPE: u32 = 0x2;
+pub const INCOMPAT_RECOVER: u32 = 0x4;
+pub const INCOMPAT_EXTENTS: u32 = 0x40;
+pub const INCOMPAT_64BIT: u32 = 0x80;
+pub const INCOMPAT_FLEX_BG: u32 = 0x200;
+pub const INCOMPAT_CSUM_SEED: u32 = 0x2000;
+pub const INCOMPAT_LARGEDIR: u32 = 0x4000;
+/// Incompatible features we can read and write.
+pub const INCOMPAT_SUPPORTED: u32 = INCOMPAT_FILETYPE
+    | INCOMPAT_RECOVER
+    | INCOMPAT_EXTENTS
+    | INCOMPAT_64BIT
+    | INCOMPAT_FLEX_BG
+    | INCOMPAT_CSUM_SEED
+    | INCOMPAT_LARGEDIR;
+
+pub const RO_COMPAT_SPARSE_SUPER: u32 = 0x1;
+pub const RO_COMPAT_LARGE_FILE: u32 = 0x2;
+pub const RO_COMPAT_HUGE_FILE: u32 = 0x8;
+pub const RO_COMPAT_GDT_CSUM: u32 = 0x10;
+pub const RO_COMPAT_DIR_NLINK: u32 = 0x20;
+pub const RO_COMPAT_EXTRA_ISIZE: u32 = 0x40;
+pub const RO_COMPAT_METADATA_CSUM: u32 = 0x400;
+/// Read-only compatible features we can write with.
+pub const RO_COMPAT_SUPPORTED: u32 = RO_COMPAT_SPARSE_SUPER
+    | RO_COMPAT_LARGE_FILE
+    | RO_COMPAT_HUGE_FILE
+    | RO_COMPAT_GDT_CSUM
+    | RO_COMPAT_DIR_NLINK
+    | RO_COMPAT_EXTRA_ISIZE
+    | RO_COMPAT_METADATA_CSUM;
+
+pub const BG_INODE_UNINIT: u16 = 0x1;
+pub const BG_BLOCK_UNINIT: u16 = 0x2;
+
+pub const S_IFMT: u16 = 0o170000;
+pub const S_IFIFO: u16 = 0o010000;
+pub const S_IFCHR: u16 = 0o020000;
+pub const S_IFDIR: u16 = 0o040000;
+pub const S_IFBLK: u16 = 0o060000;
+pub const S_IFREG: u16 = 0o100000;
+pub const S_IFLNK: u16 = 0o120000;
+pub const S_IFSOCK: u16 = 0o140000;
+
+pub const INODE_FLAG_INDEX: u32 = 0x1000;
+pub const INODE_FLAG_HUGE_FILE: u32 = 0x40000;
+pub const INODE_FLAG_EXTENTS: u32 = 0x80000;
+
+/// Size of `i_block` in the inode.
+pub const INODE_BLOCK_SIZE: usize = 60;
+/// Number of direct block pointers in `i_block`.
+pub const DIRECT_BLOCKS: usize = 12;
+
+pub const EXTENT_MAGIC: u16 = 0xf30a;
+pub const EXTENT_HEADER_SIZE: usize = 12;
+pub const EXTENT_ENTRY_SIZE: usize = 12;
+/// Maximum length of an initialized extent.
+pub const EXTENT_MAX_LEN: u32 = 32768;
+/// Maximum length of an uninitialized extent.
+pub const EXTENT_MAX_UNINIT_LEN: u32 = 32767;
+
+pub const DIRENT_HEADER_SIZE: usize = 8;
+/// Size of the fake directory entry holding the checksum of a directory
+/// block.
+pub const DIRENT_TAIL_SIZE: usize = 12;
+pub const DIRENT_TAIL_FILE_TYPE: u8 = 0xde;
+
+pub const FT_UNKNOWN: u8 = 0;
+pub const FT_REG_FILE: u8 = 1;
+pub const FT_DIR: u8 = 2;
+pub const FT_CHRDEV: u8 = 3;
+pub const FT_BLKDEV: u8 = 4;
+pub const FT_FIFO: u8 = 5;
+pub const FT_SOCK: u8 = 6;
+pub const FT_SYMLINK: u8 = 7;
+
+pub fn read_u16(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(buf[offset..offset + 2].try_into().unwrap())
+}
+
+pub fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+pub fn write_u16(buf: &mut [u8], offset: usize, val: u16) {
+    buf[offset..offset + 2].copy_from_slice(&val.to_le_bytes());
+}
+
+pub fn write_u32(buf: &mut [u8], offset: usize, val: u32) {
+    buf[offset..offset + 4].copy_from_slice(&val.to_le_bytes());
+}
+
+/// The superblock.
+pub struct Superblock {
+    pub raw: Vec<u8>,
+}
+
+impl Superblock {
+    pub fn magic(&self) -> u16 {
+        read_u16(&self.raw, 0x38)
+    }
+
+    pub fn inodes_count(&self) -> u32 {
+        read_u32(&self.raw, 0x0)
+    }
+
+    pub fn blocks_count(&self) -> u64 {
+        let hi = if self.is_64bit() {
+            read_u32(&self.raw, 0x150)
+        } else {
+            0
+        };
+        read_u32(&self.raw, 0x4) as u64 | (hi as u64) << 32
+    }
+
+    pub fn free_blocks_count(&self) -> u64 {
+        let hi = if self.is_64bit() {
+            read_u32(&self.raw, 0x158)
+        } else {
+            0
+        };
+        read_u32(&self.raw, 0xc) as u64 | (hi as u64) << 32
+    }
+
+    pub fn set_free_blocks_count(&mut self, count: u64) {
+        write_u32(&mut self.raw, 0xc, count as u32);
+        if self.is_64bit() {
+            write_u32(&mut self.raw, 0x158, (count >> 32) as u32);
+        }
+    }
+
+    pub fn free_inodes_count(&self) -> u32 {
+        read_u32(&self.raw, 0x10)
+    }
+
+    pub fn set_free_inodes_count(&mut self, count: u32) {
+        write_u32(&mut self.raw, 0x10, count);
+    }
+
+    pub fn first_data_block(&self) -> u32 {
+        read_u32(&self.raw, 0x14)
+    }
+
+    pub fn log_block_size(&self) -> u32 {
+        read_u32(&self.raw, 0x18)
+    }
+
+    pub fn blocks_per_group(&self) -> u32 {
+        read_u32(&self.raw, 0x20)
+    }
+
+    pub fn inodes_per_group(&self) -> u32 {
+        read_u32(&self.raw, 0x28)
+    }
+
+    pub fn rev_level(&self) -> u32 {
+        read_u32(&self.raw, 0x4c)
+    }
+
+    pub fn first_ino(&self) -> u32 {
+        if self.rev_level() == 0 {
+            11
+        } else {
+            read_u32(&self.raw, 0x54)
+        }
+    }
+
+    pub fn inode_size(&self) -> usize {
+        if self.rev_level() == 0 {
+            128
+        } else {
+            read_u16(&self.raw, 0x58) as usize
+        }
+    }
+
+    pub fn feature_incompat(&self) -> u32 {
+        read_u32(&self.raw, 0x60)
+    }
+
+    pub fn feature_ro_compat(&self) -> u32 {
+        read_u32(&self.raw, 0x64)
+    }
+
+    pub fn has_incompat(&self, feature: u32) -> bool {
+        self.feature_incompat() & feature != 0
+    }
+
+    pub fn has_ro_compat(&self, feature: u32) -> bool {
+        self.feature_ro_compat() & feature != 0
+    }
+
+    pub fn is_64bit(&self) -> bool {
+        self.has_incompat(INCOMPAT_64BIT)
+    }
+
+    pub fn uuid(&self) -> &[u8] {
+        &self.raw[0x68..0x78]
+    }
+
+    pub fn volume_name(&self) -> &[u8] {
+        let name = &self.raw[0x78..0x88];
+        let len = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+        &name[..len]
+    }
+
+    pub fn reserved_gdt_blocks(&self) -> u32 {
+        read_u16(&self.raw, 0xce) as u32
+    }
+
+    pub fn desc_size(&self) -> usize {
+        if self.is_64bit() {
+            (read_u16(&self.raw, 0xfe) as usize).max(32)
+        } else {
+            32
+        }
+    }
+
+    pub fn want_extra_isize(&self) -> u16 {
+        read_u16(&self.raw, 0x15e)
+    }
+
+    pub fn checksum_seed(&self) -> u32 {
+        read_u32(&self.raw, 0x270)
+    }
+
+    pub fn set_checksum(&mut self, csum: u32) {
+        write_u32(&mut self.raw, 0x3fc, csum);
+    }
+
+    /// Whether group descriptors are checksummed and may be uninitialized.
+    pub fn has_group_csum(&self) -> bool {
+        self.has_ro_compat(RO_COMPAT_GDT_CSUM | RO_COMPAT_METADATA_CSUM)
+    }
+
+    pub fn has_metadata_csum(&self) -> bool {
+        self.has_ro_compat(RO_COMPAT_METADATA_CSUM)
+    }
+}
+
+/// A block group descriptor.
+pub struct GroupDesc {
+    pub raw: Vec<u8>,
+}
+
+impl GroupDesc {
+    fn get_lo_hi(&self, lo: usize, hi: usize) -> u64 {
+        let hi = if self.raw.len() >= 64 {
+            read_u32(&self.raw, hi) as u64
+        } else {
+            0
+        };
+        read_u32(&self.raw, lo) as u64 | hi << 32
+    }
+
+    fn get_lo_hi16(&self, lo: usize, hi: usize) -> u32 {
+        let hi = if self.raw.len() >= 64 {
+            read_u16(&self.raw, hi) as u32
+        } else {
+            0
+        };
+        read_u16(&self.raw, lo) as u32 | hi << 16
+    }
+
+    fn set_lo_hi16(&mut self, lo: usize, hi: usize, val: u32) {
+        write_u16(&mut self.raw, lo, val as u16);
+        if self.raw.len() >= 64 {
+            write_u16(&mut self.raw, hi, (val >> 16) as u16);
+        }
+    }
+
+    pub fn block_bitmap(&self) -> u64 {
+        self.get_lo_hi(0x0, 0x20)
+    }
+
+    pub fn inode_bitmap(&self) -> u64 {
+        self.get_lo_hi(0x4, 0x24)
+    }
+
+    pub fn inode_table(&self) -> u64 {
+        self.get_lo_hi(0x8, 0x28)
+    }
+
+    pub fn free_blocks_count(&self) -> u32 {
+        self.get_lo_hi16(0xc, 0x2c)
+    }
+
+    pub fn set_free_blocks_count(&mut self, count: u32) {
+        self.set_lo_hi16(0xc, 0x2c, count)
+    }
+
+    pub fn free_inodes_count(&self) -> u32 {
+        self.get_lo_hi16(0xe, 0x2e)
+    }
+
+    pub fn set_free_inodes_count(&mut self, count: u32) {
+        self.set_lo_hi16(0xe, 0x2e, count)
+    }
+
+    pub fn used_dirs_count(&self) -> u32 {
+        self.get_lo_hi16(0x10, 0x30)
+    }
+
+    pub fn set_used_dirs_count(&mut self, count: u32) {
+        self.set_lo_hi16(0x10, 0x30, count)
+    }
+
+    pub fn flags(&self) -> u16 {
+        read_u16(&self.raw, 0x12)
+    }
+
+    pub fn set_flags(&mut self, flags: u16) {
+        write_u16(&mut self.raw, 0x12, flags)
+    }
+
+    pub fn itable_unused(&self) -> u32 {
+        self.get_lo_hi16(0x1c, 0x32)
+    }
+
+    pub fn set_itable_unused(&mut self, count: u32) {
+        self.set_lo_hi16(0x1c, 0x32, count)
+    }
+
+    pub fn set_block_bitmap_csum(&mut self, csum: u32) {
+        self.set_lo_hi16(0x18, 0x38, csum)
+    }
+
+    pub fn set_inode_bitmap_csum(&mut self, csum: u32) {
+        self.set_lo_hi16(0x1a, 0x3a, csum)
+    }
+
+    pub fn set_checksum(&mut self, csum: u16) {
+        write_u16(&mut self.raw, 0x1e, csum)
+    }
+}
+
+/// Offset of the checksum in a group descriptor.
+pub const GROUP_DESC_CSUM_OFFSET: usize = 0x1e;
+
+/// An inode.
+#[derive(Clone)]
+pub struct Inode {
+    pub raw: Vec<u8>,
+}
+
+/// Offsets of the low and high 16 bits of the checksum in an inode.
+pub const INODE_CSUM_LO_OFFSET: usize = 0x7c;
+pub const INODE_CSUM_HI_OFFSET: usize = 0x82;
+
+/// Offsets of the seconds and the extra bits (nanoseconds and the epoch) of
+/// the timestamps in an inode.
+pub const INODE_ATIME: (usize, usize) = (0x08, 0x8c);
+pub const INODE_CTIME: (usize, usize) = (0x0c, 0x84);
+pub const INODE_MTIME: (usize, usize) = (0x10, 0x88);
+
+impl Inode {
+    /// Creates a zeroed inode of `size` bytes.
+    pub fn new(size: usize, extra_isize: u16) -> Self {
+        let mut inode = Self { raw: vec![0; size] };
+        if size > 128 {
+            write_u16(&mut inode.raw, 0x80, extra_isize);
+        }
+        inode
+    }
+
+    pub fn mode(&self) -> u16 {
+        read_u16(&self.raw, 0x0)
+    }
+
+    pub fn set_mode(&mut self, mode: u16) {
+        write_u16(&mut self.raw, 0x0, mode)
+    }
+
+    pub fn file_type(&self) -> u16 {
+        self.mode() & S_IFMT
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.file_type() == S_IFDIR
+    }
+
+    pub fn size(&self) -> u64 {
+        read_u32(&self.raw, 0x4) as u64 | (read_u32(&self.raw, 0x6c) as u64) << 32
+    }
+
+    pub fn set_size(&mut self, size: u64) {
+        write_u32(&mut self.raw, 0x4, size as u32);
+        write_u32(&mut self.raw, 0x6c, (size >> 32) as u32);
+    }
+
+    pub fn links_count(&self) -> u16 {
+        read_u16(&self.raw, 0x1a)
+    }
+
+    pub fn set_links_count(&mut self, count: u16) {
+        write_u16(&mut self.raw, 0x1a, count)
+    }
+
+    /// Number of 512-byte sectors (or blocks, if the inode is huge) used.
+    pub fn blocks_raw(&self) -> u64 {
+        read_u32(&self.raw, 0x1c) as u64 | (read_u16(&self.raw, 0x74) as u64) << 32
+    }
+
+    pub fn set_blocks_raw(&mut self, blocks: u64) {
+        write_u32(&mut self.raw, 0x1c, blocks as u32);
+        write_u16(&mut self.raw, 0x74, (blocks >> 32) as u16);
+    }
+
+    pub fn flags(&self) -> u32 {
+        read_u32(&self.raw, 0x20)
+    }
+
+    pub fn set_flags(&mut self, flags: u32) {
+        write_u32(&mut self.raw, 0x20, flags)
+    }
+
+    pub fn has_flag(&self, flag: u32) -> bool {
+        self.flags() & flag != 0
+    }
+
+    /// The `i_block` area holding block pointers, the extent tree root, or
+    /// the target of a fast symlink.
+    pub fn block_area(&self) -> &[u8] {
+        &self.raw[0x28..0x28 + INODE_BLOCK_SIZE]
+    }
+
+    pub fn block_area_mut(&mut self) -> &mut [u8] {
+        &mut self.raw[0x28..0x28 + INODE_BLOCK_SIZE]
+    }
+
+    pub fn generation(&self) -> u32 {
+        read_u32(&self.raw, 0x64)
+    }
+
+    pub fn file_acl(&self) -> u64 {
+        read_u32(&self.raw, 0x68) as u64 | (read_u16(&self.raw, 0x76) as u64) << 32
+    }
+
+    pub fn extra_isize(&self) -> usize {
+        if self.raw.len() > 128 {
+            read_u16(&self.raw, 0x80) as usize
+        } else {
+            0
+        }
+    }
+
+    /// Whether the inode has room for the high 16 bits of the checksum.
+    pub fn has_csum_hi(&self) -> bool {
+        self.extra_isize() >= INODE_CSUM_HI_OFFSET + 2 - 128
+    }
+
+    /// Reads a timestamp, whose offsets are given by `INODE_*TIME`, as the
+    /// time elapsed since the Unix epoch. Times before it are read as zero.
+    pub fn time(&self, (secs_offset, extra_offset): (usize, usize)) -> Duration {
+        // seconds are signed, and extended by the low 2 bits of the extra
+        let mut secs = read_u32(&self.raw, secs_offset) as i32 as i64;
+        let mut nanos = 0;
+        if self.extra_isize() >= extra_offset + 4 - 128 {
+            let extra = read_u32(&self.raw, extra_offset);
+            secs += ((extra & 3) as i64) << 32;
+            nanos = (extra >> 2).min(999_999_999);
+        }
+        Duration::new(secs.max(0) as u64, nanos)
+    }
+
+    /// Writes a timestamp, whose offsets are given by `INODE_*TIME`.
+    pub fn set_time(&mut self, (secs_offset, extra_offset): (usize, usize), time: Duration) {
+        let secs = time.as_secs() as i64;
+        write_u32(&mut self.raw, secs_offset, secs as u32);
+        if self.extra_isize() >= extra_offset + 4 - 128 {
+            let epoch = ((secs - secs as u32 as i32 as i64) >> 32) as u32 & 3;
+            write_u32(
+                &mut self.raw,
+                extra_offset,
+                time.subsec_nanos() << 2 | epoch,
+            );
+        }
+    }
+
+    /// Sets the modification and change times to `now`.
+    pub fn touch_modified(&mut self, now: Duration) {
+        self.set_time(INODE_MTIME, now);
+        self.set_time(INODE_CTIME, now);
+    }
+}
+
+/// An extent, mapping `len` logical blocks from `lblk` to physical blocks
+/// from `pblk`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Extent {
+    pub lblk: u32,
+    pub len: u32,
+    pub pblk: u64,
+    /// Uninitialized extents are allocated but read as zeros.
+    pub uninit: bool,
+}
+
+impl Extent {
+    pub fn parse(raw: &[u8]) -> Self {
+        let len = read_u16(raw, 4) as u32;
+        let (len, uninit) = if len > EXTENT_MAX_LEN {
+            (len - EXTENT_MAX_LEN, true)
+        } else {
+            (len, false)
+        };
+        Self {
+            lblk: read_u32(raw, 0),
+            len,
+            pblk: read_u32(raw, 8) as u64 | (read_u16(raw, 6) as u64) << 32,
+            uninit,
+        }
+    }
+
+    pub fn write(&self, raw: &mut [u8]) {
+        let len = if self.uninit {
+            self.len + EXTENT_MAX_LEN
+        } else {
+            self.len
+        };
+        write_u32(raw, 0, self.lblk);
+        write_u16(raw, 4, len as u16);
+        write_u16(raw, 6, (self.pblk >> 32) as u16);
+        write_u32(raw, 8, self.pblk as u32);
+    }
+
+    pub fn end(&self) -> u32 {
+        self.lblk + self.len
+    }
+
+    pub fn max_len(&self) -> u32 {
+        if self.uninit {
+            EXTENT_MAX_UNINIT_LEN
+        } else {
+            EXTENT_MAX_LEN
+        }
+    }
+}
+
+/// The header of an extent tree node.
+pub struct ExtentHeader {
+    pub entries: u16,
+    pub max: u16,
+    pub depth: u16,
+}
+
+impl ExtentHeader {
+    pub fn parse(raw: &[u8]) -> Option<Self> {
+        if read_u16(raw, 0) != EXTENT_MAGIC {
+            return None;
+        }
+        let header = Self {
+            entries: read_u16(raw, 2),
+            max: read_u16(raw, 4),
+            depth: read_u16(raw, 6),
+        };
+        let capacity = (raw.len() - EXTENT_HEADER_SIZE) / EXTENT_ENTRY_SIZE;
+        (header.entries <= header.max && header.max as usize <= capacity).then_some(header)
+    }
+
+    pub fn write(&self, raw: &mut [u8]) {
+        write_u16(raw, 0, EXTENT_MAGIC);
+        write_u16(raw, 2, self.entries);
+        write_u16(raw, 4, self.max);
+        write_u16(raw, 6, self.depth);
+        write_u32(raw, 8, 0); // generation
+    }
+}
+
+/// Parses an index entry of an extent tree, returns the first logical block
+/// it covers and the physical block of the child node.
+pub fn parse_extent_index(raw: &[u8]) -> (u32, u64) {
+    let leaf = read_u32(raw, 4) as u64 | (read_u16(raw, 8) as u64) << 32;
+    (read_u32(raw, 0), leaf)
+}
+
+pub fn write_extent_index(raw: &mut [u8], lblk: u32, leaf: u64) {
+    write_u32(raw, 0, lblk);
+    write_u32(raw, 4, leaf as u32);
+    write_u16(raw, 8, (leaf >> 32) as u16);
+    write_u16(raw, 10, 0);
+}
+
+/// A directory entry.
+pub struct DirEntry<'a> {
+    pub inode: u32,
+    pub rec_len: usize,
+    pub name: &'a [u8],
+    pub file_type: u8,
+}
+
+impl<'a> DirEntry<'a> {
+    /// Parses the entry at `offset` of a directory block, returns `None` if
+    /// it's corrupted.
+    pub fn parse(block: &'a [u8], offset: usize, has_file_type: bool) -> Option<Self> {
+        if offset + DIRENT_HEADER_SIZE > block.len() {
+            return None;
+        }
+        let rec_len = read_u16(block, offset + 4) as usize;
+        let name_len = if has_file_type {
+            block[offset + 6] as usize
+        } else {
+            read_u16(block, offset + 6) as usize
+        };
+        if rec_len < DIRENT_HEADER_SIZE
+            || rec_len % 4 != 0
+            || offset + rec_len > block.len()
+            || DIRENT_HEADER_SIZE + name_len > rec_len
+        {
+            return None;
+        }
+        Some(Self {
+            inode: read_u32(block, offset),
+            rec_len,
+            name: &block[offset + 8..offset + 8 + name_len],
+            file_type: if has_file_type { block[offset + 7] } else { 0 },
+        })
+    }
+
+    /// The space the entry actually needs.
+    pub fn used_len(&self) -> usize {
+        dirent_len(self.name.len())
+    }
+}
+
+/// Size of a directory entry with a name of `name_len` bytes.
+pub const fn dirent_len(name_len: usize) -> usize {
+    (DIRENT_HEADER_SIZE + name_len + 3) & !3
+}
+
+/// Writes a directory entry at `offset` of a directory block.
+pub fn write_dirent(
+    block: &mut [u8],
+    offset: usize,
+    inode: u32,
+    rec_len: usize,
+    name: &[u8],
+    file_type: u8,
+) {
+    write_u32(block, offset, inode);
+    write_u16(block, offset + 4, rec_len as u16);
+    block[offset + 6] = name.len() as u8;
+    block[offset + 7] = file_type;
+    block[offset + 8..offset + 8 + name.len()].copy_from_slice(name);
+}
+
+/// Converts the file type in an inode mode to the one in directory entries.
+pub fn mode_to_file_type(mode: u16) -> u8 {
+    match mode & S_IFMT {
+        S_IFREG => FT_REG_FILE,
+        S_IFDIR => FT_DIR,
+        S_IFCHR => FT_CHRDEV,
+        S_IFBLK => FT_BLKDEV,
+        S_IFIFO => FT_FIFO,
+        S_IFSOCK => FT_SOCK,
+        S_IFLNK => FT_SYMLINK,
+        _ => FT_UNKNOWN,
+    }
+}
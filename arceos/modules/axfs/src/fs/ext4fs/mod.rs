@@ -0,0 +1,400 @@
+//! ext2/3/4 filesystem.
+//!
+//! Supports the features `mkfs.ext4` enables by default, including extents,
+//! 64-bit block numbers, flexible block groups and metadata checksums, as
+//! well as ext2/3 volumes with indirect blocks. The journal is not used:
+//! metadata is written in place, and volumes whose journal needs recovery
+//! are mounted read-only.
+
+mod bmap;
+mod crc;
+mod dir;
+mod file;
+mod layout;
+mod volume;
+
+use alloc::{string::String, sync::Arc, sync::Weak};
+use core::{any::Any, time::Duration};
+
+use axerrno::ax_err;
+use axfs_vfs::{VfsDirEntry, VfsNodeAttr, VfsNodeOps, VfsNodePerm, VfsNodeRef, VfsNodeType};
+use axfs_vfs::{VfsOps, VfsResult};
+use axsync::Mutex;
+
+use self::dir::is_fast_symlink;
+use self::layout::*;
+use self::volume::Volume;
+use crate::dev::BlockDevice;
+use crate::fops::{FileTimes, FsStat};
+
+/// An ext2/3/4 filesystem.
+pub struct Ext4FileSystem {
+    vol: Mutex<Volume>,
+    this: Weak<Ext4FileSystem>,
+}
+
+/// An inode of an ext2/3/4 filesystem.
+///
+/// There's at most one node for an inode at a time, inodes that are removed
+/// while they are open are released when their node is dropped.
+pub struct Ext4Node {
+    fs: Arc<Ext4FileSystem>,
+    ino: u32,
+}
+
+impl Ext4FileSystem {
+    /// Whether `dev` holds an ext2/3/4 filesystem.
+    pub fn probe(dev: &dyn BlockDevice) -> bool {
+        Volume::read_superblock(dev).is_some()
+    }
+
+    /// Opens the ext2/3/4 filesystem on `dev`.
+    pub fn new(dev: Arc<dyn BlockDevice>) -> VfsResult<Arc<Self>> {
+        let vol = Volume::open(dev)?;
+        Ok(Arc::new_cyclic(|this| Self {
+            vol: Mutex::new(vol),
+            this: this.clone(),
+        }))
+    }
+
+    /// Returns the volume label, or `None` if the volume is not labeled.
+    pub fn volume_label(&self) -> Option<String> {
+        let vol = self.vol.lock();
+        let name = vol.sb.volume_name();
+        (!name.is_empty()).then(|| String::from_utf8_lossy(name).into_owned())
+    }
+
+    /// Returns the node of inode `ino`.
+    ///
+    /// The returned node must not be dropped while the volume is locked.
+    fn node(&self, vol: &mut Volume, ino: u32) -> Arc<Ext4Node> {
+        if let Some(node) = vol.nodes.get(&ino).and_then(Weak::upgrade) {
+            return node;
+        }
+        let node = Arc::new(Ext4Node {
+            fs: self.this.upgrade().unwrap(),
+            ino,
+        });
+        vol.nodes.insert(ino, Arc::downgrade(&node));
+        node
+    }
+
+    /// Releases an unlinked inode now if it's not in use, or leaves it to
+    /// the drop of its node otherwise.
+    fn release_unlinked(vol: &mut Volume, ino: u32) -> VfsResult {
+        if vol.nodes.get(&ino).is_some_and(|n| n.strong_count() > 0) {
+            return Ok(());
+        }
+        // a node being dropped must not release it again
+        vol.nodes.remove(&ino);
+        vol.release_inode(ino)
+    }
+}
+
+impl VfsOps for Ext4FileSystem {
+    fn umount(&self) -> VfsResult {
+        self.vol.lock().flush()
+    }
+
+    fn root_dir(&self) -> VfsNodeRef {
+        let mut vol = self.vol.lock();
+        let root = self.node(&mut vol, ROOT_INO);
+        drop(vol);
+        root
+    }
+}
+
+/// Splits a path into the parent directory and the last component.
+fn split_parent(path: &str) -> (&str, &str) {
+    let path = path.trim_end_matches('/');
+    match path.rfind('/') {
+        Some(idx) => (&path[..idx], &path[idx + 1..]),
+        None => ("", path),
+    }
+}
+
+impl Ext4Node {
+    /// Resolves `path` relative to this node to a parent directory and a
+    /// name in it.
+    fn lookup_parent<'a>(&self, vol: &Volume, path: &'a str) -> VfsResult<(u32, &'a str)> {
+        let (parent, name) = split_parent(path);
+        let dir = vol.lookup_path(self.ino, parent)?;
+        if !vol.read_inode(dir)?.is_dir() {
+            return ax_err!(NotADirectory);
+        }
+        Ok((dir, name))
+    }
+
+    /// Adds a hard link at `path` relative to this node to `node`, which
+    /// must be on the same filesystem.
+    pub fn link(&self, path: &str, node: &VfsNodeRef) -> VfsResult {
+        debug!("link at ext4fs: {}", path);
+        let target = match node.as_any().downcast_ref::<Ext4Node>() {
+            Some(target) if Arc::ptr_eq(&target.fs, &self.fs) => target.ino,
+            _ => return ax_err!(InvalidInput),
+        };
+        let mut vol = self.fs.vol.lock();
+        let (dir, name) = self.lookup_parent(&vol, path)?;
+        vol.check_writable()?;
+        vol.link(dir, name, target)
+    }
+
+    /// Returns `true` if the filesystem can't be modified, as it has features
+    /// that are not supported for writing, or its journal needs recovery.
+    pub fn is_read_only(&self) -> bool {
+        self.fs.vol.lock().read_only
+    }
+
+    /// Returns the usage of the filesystem, from its superblock.
+    pub fn fs_stat(&self) -> VfsResult<FsStat> {
+        let vol = self.fs.vol.lock();
+        Ok(FsStat {
+            block_size: vol.block_size as u64,
+            blocks: vol.sb.blocks_count(),
+            blocks_free: vol.sb.free_blocks_count(),
+            files: vol.sb.inodes_count() as u64,
+            files_free: vol.sb.free_inodes_count() as u64,
+            name_max: 255,
+            ..Default::default()
+        })
+    }
+
+    /// Returns the timestamps of the inode.
+    pub fn times(&self) -> VfsResult<FileTimes> {
+        let inode = self.fs.vol.lock().read_inode(self.ino)?;
+        Ok(FileTimes {
+            accessed: inode.time(INODE_ATIME),
+            modified: inode.time(INODE_MTIME),
+            changed: inode.time(INODE_CTIME),
+        })
+    }
+
+    /// Sets the access and modification times of the inode, the ones of
+    /// `None` are left unchanged. The change time is set to now.
+    pub fn set_times(&self, accessed: Option<Duration>, modified: Option<Duration>) -> VfsResult {
+        let mut vol = self.fs.vol.lock();
+        vol.check_writable()?;
+        let mut inode = vol.read_inode(self.ino)?;
+        if let Some(accessed) = accessed {
+            inode.set_time(INODE_ATIME, accessed);
+        }
+        if let Some(modified) = modified {
+            inode.set_time(INODE_MTIME, modified);
+        }
+        inode.set_time(INODE_CTIME, axhal::time::wall_time());
+        vol.write_inode(self.ino, &mut inode)
+    }
+}
+
+impl VfsNodeOps for Ext4Node {
+    fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
+        let vol = self.fs.vol.lock();
+        let inode = vol.read_inode(self.ino)?;
+        let ty = mode_to_node_type(inode.mode());
+        let perm = VfsNodePerm::from_bits_truncate(inode.mode() & 0o777);
+        let blocks = vol.inode_sectors(&inode);
+        Ok(VfsNodeAttr::new(perm, ty, inode.size(), blocks))
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
+        let vol = self.fs.vol.lock();
+        let inode = vol.read_inode(self.ino)?;
+        if inode.is_dir() {
+            return ax_err!(IsADirectory);
+        }
+        if is_fast_symlink(&inode, vol.block_size) {
+            let target = &inode.block_area()[..inode.size() as usize];
+            let start = target.len().min(offset as usize);
+            let n = buf.len().min(target.len() - start);
+            buf[..n].copy_from_slice(&target[start..start + n]);
+            return Ok(n);
+        }
+        vol.read_data(&inode, offset, buf)
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> VfsResult<usize> {
+        let mut vol = self.fs.vol.lock();
+        vol.check_writable()?;
+        let mut inode = vol.read_inode(self.ino)?;
+        match inode.file_type() {
+            S_IFDIR => ax_err!(IsADirectory),
+            S_IFREG => {
+                inode.touch_modified(axhal::time::wall_time());
+                vol.write_data(self.ino, &mut inode, offset, buf)
+            }
+            S_IFLNK if offset == 0 => {
+                vol.write_symlink(self.ino, &mut inode, buf)?;
+                Ok(buf.len())
+            }
+            _ => ax_err!(InvalidInput),
+        }
+    }
+
+    fn fsync(&self) -> VfsResult {
+        self.fs.vol.lock().flush()
+    }
+
+    fn truncate(&self, size: u64) -> VfsResult {
+        let mut vol = self.fs.vol.lock();
+        vol.check_writable()?;
+        let mut inode = vol.read_inode(self.ino)?;
+        match inode.file_type() {
+            S_IFDIR => ax_err!(IsADirectory),
+            S_IFREG => {
+                inode.touch_modified(axhal::time::wall_time());
+                vol.truncate(self.ino, &mut inode, size)
+            }
+            _ => ax_err!(InvalidInput),
+        }
+    }
+
+    fn parent(&self) -> Option<VfsNodeRef> {
+        if self.ino == ROOT_INO {
+            return None;
+        }
+        let mut vol = self.fs.vol.lock();
+        let parent = vol.lookup_path(self.ino, "..").ok()?;
+        let node = self.fs.node(&mut vol, parent);
+        drop(vol);
+        Some(node)
+    }
+
+    fn lookup(self: Arc<Self>, path: &str) -> VfsResult<VfsNodeRef> {
+        debug!("lookup at ext4fs: {}", path);
+        let mut vol = self.fs.vol.lock();
+        let ino = vol.lookup_path(self.ino, path)?;
+        if ino == self.ino {
+            drop(vol);
+            return Ok(self);
+        }
+        let node = self.fs.node(&mut vol, ino);
+        drop(vol);
+        Ok(node)
+    }
+
+    fn create(&self, path: &str, ty: VfsNodeType) -> VfsResult {
+        debug!("create {:?} at ext4fs: {}", ty, path);
+        let mode = match ty {
+            VfsNodeType::File => S_IFREG | 0o644,
+            VfsNodeType::Dir => S_IFDIR | 0o755,
+            VfsNodeType::SymLink => S_IFLNK | 0o777,
+            _ => return ax_err!(Unsupported),
+        };
+        let mut vol = self.fs.vol.lock();
+        let (dir, name) = self.lookup_parent(&vol, path)?;
+        if name.is_empty() || name == "." || name == ".." {
+            return Ok(()); // already exists
+        }
+        vol.check_writable()?;
+        vol.create_node(dir, name, mode)?;
+        Ok(())
+    }
+
+    fn remove(&self, path: &str) -> VfsResult {
+        debug!("remove at ext4fs: {}", path);
+        let mut vol = self.fs.vol.lock();
+        let (dir, name) = self.lookup_parent(&vol, path)?;
+        vol.check_writable()?;
+        let (ino, unlinked) = vol.unlink(dir, name)?;
+        if unlinked {
+            Ext4FileSystem::release_unlinked(&mut vol, ino)?;
+        }
+        Ok(())
+    }
+
+    fn read_dir(&self, start_idx: usize, dirents: &mut [VfsDirEntry]) -> VfsResult<usize> {
+        let vol = self.fs.vol.lock();
+        let inode = vol.read_inode(self.ino)?;
+        if !inode.is_dir() {
+            return ax_err!(NotADirectory);
+        }
+        let mut idx = 0;
+        let mut count = 0;
+        let mut error = None;
+        vol.scan_dir(&inode, |entry| {
+            if idx >= start_idx {
+                let ty = match entry.file_type {
+                    FT_UNKNOWN => match vol.read_inode(entry.inode) {
+                        Ok(inode) => inode.mode(),
+                        Err(e) => {
+                            error = Some(e);
+                            return Some(());
+                        }
+                    },
+                    ft => file_type_to_mode(ft),
+                };
+                let name = String::from_utf8_lossy(entry.name);
+                dirents[count] = VfsDirEntry::new(&name, mode_to_node_type(ty));
+                count += 1;
+            }
+            idx += 1;
+            (count == dirents.len()).then_some(())
+        })?;
+        match error {
+            Some(e) => Err(e),
+            None => Ok(count),
+        }
+    }
+
+    fn rename(&self, src_path: &str, dst_path: &str) -> VfsResult {
+        debug!("rename at ext4fs: {} -> {}", src_path, dst_path);
+        let mut vol = self.fs.vol.lock();
+        let (src_dir, src_name) = self.lookup_parent(&vol, src_path)?;
+        let (dst_dir, dst_name) = self.lookup_parent(&vol, dst_path)?;
+        vol.check_writable()?;
+        if let Some((ino, true)) = vol.rename(src_dir, src_name, dst_dir, dst_name)? {
+            Ext4FileSystem::release_unlinked(&mut vol, ino)?;
+        }
+        Ok(())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl Drop for Ext4Node {
+    fn drop(&mut self) {
+        let mut vol = self.fs.vol.lock();
+        // a new node may have been created for the inode after the last
+        // reference to this one was gone
+        let this: *const Self = self;
+        if vol.nodes.get(&self.ino).map(Weak::as_ptr) != Some(this) {
+            return;
+        }
+        vol.nodes.remove(&self.ino);
+        let unlinked = match vol.read_inode(self.ino) {
+            Ok(inode) => inode.links_count() == 0 && inode.mode() != 0,
+            Err(_) => false,
+        };
+        if unlinked && !vol.read_only {
+            if let Err(e) = vol.release_inode(self.ino) {
+                warn!("ext4: failed to release inode {}: {:?}", self.ino, e);
+            }
+        }
+    }
+}
+
+fn file_type_to_mode(file_type: u8) -> u16 {
+    match file_type {
+        FT_DIR => S_IFDIR,
+        FT_CHRDEV => S_IFCHR,
+        FT_BLKDEV => S_IFBLK,
+        FT_FIFO => S_IFIFO,
+        FT_SOCK => S_IFSOCK,
+        FT_SYMLINK => S_IFLNK,
+        _ => S_IFREG,
+    }
+}
+
+fn mode_to_node_type(mode: u16) -> VfsNodeType {
+    match mode & S_IFMT {
+        S_IFDIR => VfsNodeType::Dir,
+        S_IFLNK => VfsNodeType::SymLink,
+        S_IFCHR => VfsNodeType::CharDevice,
+        S_IFBLK => VfsNodeType::BlockDevice,
+        S_IFIFO => VfsNodeType::Fifo,
+        S_IFSOCK => VfsNodeType::Socket,
+        _ => VfsNodeType::File,
+    }
+}
@@ -0,0 +1,530 @@
+//! Block I/O, metadata and allocation of an ext2/3/4 volume.
+
+use alloc::{collections::BTreeMap, sync::Arc, sync::Weak, vec, vec::Vec};
+use axerrno::{ax_err, AxError};
+use axfs_vfs::VfsResult;
+
+use super::crc::{crc16, crc32c};
+use super::layout::*;
+use super::Ext4Node;
+use crate::dev::{as_vfs_err, BlockDevice};
+
+/// A mounted ext2/3/4 volume. All accesses are serialized by the lock in
+/// [`Ext4FileSystem`](super::Ext4FileSystem).
+pub struct Volume {
+    dev: Arc<dyn BlockDevice>,
+    pub sb: Superblock,
+    groups: Vec<GroupDesc>,
+    pub block_size: usize,
+    /// Number of device blocks in a filesystem block.
+    dev_blocks_per_block: u64,
+    gdt_blocks: u64,
+    csum_seed: u32,
+    next_generation: u32,
+    pub read_only: bool,
+    /// Nodes in use, to share them between lookups and to defer freeing
+    /// unlinked inodes until they are closed.
+    pub nodes: BTreeMap<u32, Weak<Ext4Node>>,
+}
+
+impl Volume {
+    /// Reads the superblock, returns `None` if `dev` doesn't hold an ext2/3/4
+    /// filesystem.
+    pub fn read_superblock(dev: &dyn BlockDevice) -> Option<Superblock> {
+        let mut raw = vec![0; SUPERBLOCK_SIZE];
+        read_dev_bytes(dev, SUPERBLOCK_OFFSET, &mut raw).ok()?;
+        let sb = Superblock { raw };
+        (sb.magic() == EXT4_MAGIC).then_some(sb)
+    }
+
+    pub fn open(dev: Arc<dyn BlockDevice>) -> VfsResult<Self> {
+        let sb = Self::read_superblock(dev.as_ref()).ok_or(AxError::InvalidData)?;
+        if sb.log_block_size() > 6 {
+            return ax_err!(InvalidData, "ext4: invalid block size");
+        }
+        let block_size = 1024 << sb.log_block_size();
+        if block_size % dev.block_size() != 0 {
+            return ax_err!(Unsupported, "ext4: block size smaller than the device's");
+        }
+        let unsupported = sb.feature_incompat() & !INCOMPAT_SUPPORTED;
+        if unsupported != 0 {
+            warn!("ext4: unsupported incompatible features {:#x}", unsupported);
+            return Err(AxError::Unsupported);
+        }
+        let mut read_only = false;
+        let unsupported = sb.feature_ro_compat() & !RO_COMPAT_SUPPORTED;
+        if unsupported != 0 {
+            warn!(
+                "ext4: unsupported features {:#x}, mount read-only",
+                unsupported
+            );
+            read_only = true;
+        }
+        if sb.has_incompat(INCOMPAT_RECOVER) {
+            warn!("ext4: journal needs recovery, mount read-only");
+            read_only = true;
+        }
+        if sb.blocks_per_group() == 0
+            || sb.inodes_per_group() == 0
+            || sb.blocks_per_group() as usize > block_size * 8
+            || sb.inodes_per_group() as usize > block_size * 8
+            || sb.inode_size() < 128
+        {
+            return ax_err!(InvalidData, "ext4: corrupted superblock");
+        }
+
+        let num_groups = (sb.blocks_count() - sb.first_data_block() as u64)
+            .div_ceil(sb.blocks_per_group() as u64);
+        let desc_size = sb.desc_size();
+        let gdt_blocks = (num_groups * desc_size as u64).div_ceil(block_size as u64);
+        let mut gdt = vec![0; (gdt_blocks as usize) * block_size];
+        let gdt_offset = (sb.first_data_block() as u64 + 1) * block_size as u64;
+        read_dev_bytes(dev.as_ref(), gdt_offset, &mut gdt).map_err(as_vfs_err)?;
+        let groups = gdt
+            .chunks_exact(desc_size)
+            .take(num_groups as usize)
+            .map(|raw| GroupDesc { raw: raw.to_vec() })
+            .collect();
+
+        let csum_seed = if sb.has_incompat(INCOMPAT_CSUM_SEED) {
+            sb.checksum_seed()
+        } else {
+            crc32c(!0, sb.uuid())
+        };
+        Ok(Self {
+            dev_blocks_per_block: (block_size / dev.block_size()) as u64,
+            dev,
+            sb,
+            groups,
+            block_size,
+            gdt_blocks,
+            csum_seed,
+            next_generation: 1,
+            read_only,
+            nodes: BTreeMap::new(),
+        })
+    }
+
+    pub fn check_writable(&self) -> VfsResult {
+        if self.read_only {
+            ax_err!(PermissionDenied, "ext4: read-only filesystem")
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn flush(&self) -> VfsResult {
+        self.dev.flush().map_err(as_vfs_err)
+    }
+
+    pub fn read_block(&self, block: u64, buf: &mut [u8]) -> VfsResult {
+        self.dev
+            .read_blocks(block * self.dev_blocks_per_block, buf)
+            .map_err(as_vfs_err)
+    }
+
+    pub fn write_block(&self, block: u64, buf: &[u8]) -> VfsResult {
+        self.dev
+            .write_blocks(block * self.dev_blocks_per_block, buf)
+            .map_err(as_vfs_err)
+    }
+
+    fn write_bytes(&self, offset: u64, data: &[u8]) -> VfsResult {
+        write_dev_bytes(self.dev.as_ref(), offset, data).map_err(as_vfs_err)
+    }
+
+    pub fn write_superblock(&mut self) -> VfsResult {
+        if self.sb.has_metadata_csum() {
+            let csum = crc32c(!0, &self.sb.raw[..0x3fc]);
+            self.sb.set_checksum(csum);
+        }
+        write_dev_bytes(self.dev.as_ref(), SUPERBLOCK_OFFSET, &self.sb.raw).map_err(as_vfs_err)
+    }
+
+    // ---- block groups ----
+
+    fn num_groups(&self) -> usize {
+        self.groups.len()
+    }
+
+    fn group_start(&self, group: usize) -> u64 {
+        self.sb.first_data_block() as u64 + group as u64 * self.sb.blocks_per_group() as u64
+    }
+
+    fn blocks_in_group(&self, group: usize) -> usize {
+        let left = self.sb.blocks_count() - self.group_start(group);
+        left.min(self.sb.blocks_per_group() as u64) as usize
+    }
+
+    fn group_of_block(&self, block: u64) -> usize {
+        let block = block.max(self.sb.first_data_block() as u64);
+        (((block - self.sb.first_data_block() as u64) / self.sb.blocks_per_group() as u64) as usize)
+            .min(self.num_groups() - 1)
+    }
+
+    pub fn group_of_inode(&self, ino: u32) -> usize {
+        ((ino - 1) / self.sb.inodes_per_group()) as usize
+    }
+
+    /// Whether the group holds a backup of the superblock.
+    fn group_has_super(&self, group: usize) -> bool {
+        fn is_power_of(mut n: usize, base: usize) -> bool {
+            while n % base == 0 {
+                n /= base;
+            }
+            n == 1
+        }
+        group <= 1
+            || !self.sb.has_ro_compat(RO_COMPAT_SPARSE_SUPER)
+            || is_power_of(group, 3)
+            || is_power_of(group, 5)
+            || is_power_of(group, 7)
+    }
+
+    fn inode_table_blocks(&self) -> u64 {
+        (self.sb.inodes_per_group() as u64 * self.sb.inode_size() as u64)
+            .div_ceil(self.block_size as u64)
+    }
+
+    fn write_group_desc(&mut self, group: usize) -> VfsResult {
+        let sb = &self.sb;
+        let desc = &mut self.groups[group];
+        if sb.has_metadata_csum() {
+            let mut csum = crc32c(self.csum_seed, &(group as u32).to_le_bytes());
+            csum = crc32c(csum, &desc.raw[..GROUP_DESC_CSUM_OFFSET]);
+            csum = crc32c(csum, &[0, 0]);
+            csum = crc32c(csum, &desc.raw[GROUP_DESC_CSUM_OFFSET + 2..]);
+            desc.set_checksum(csum as u16);
+        } else if sb.has_group_csum() {
+            let mut csum = crc16(!0, sb.uuid());
+            csum = crc16(csum, &(group as u32).to_le_bytes());
+            csum = crc16(csum, &desc.raw[..GROUP_DESC_CSUM_OFFSET]);
+            csum = crc16(csum, &desc.raw[GROUP_DESC_CSUM_OFFSET + 2..]);
+            desc.set_checksum(csum);
+        }
+        let desc_size = desc.raw.len();
+        let offset = (sb.first_data_block() as u64 + 1) * self.block_size as u64
+            + (group * desc_size) as u64;
+        write_dev_bytes(self.dev.as_ref(), offset, &self.groups[group].raw).map_err(as_vfs_err)
+    }
+
+    /// Builds the block bitmap of a group whose bitmap is not initialized on
+    /// disk, where only the group's metadata blocks are in use.
+    fn init_block_bitmap(&self, group: usize) -> Vec<u8> {
+        let mut bitmap = vec![0; self.block_size];
+        let start = self.group_start(group);
+        let end = start + self.sb.blocks_per_group() as u64;
+        if self.group_has_super(group) {
+            let n = 1 + self.gdt_blocks + self.sb.reserved_gdt_blocks() as u64;
+            (0..n as usize).for_each(|i| set_bit(&mut bitmap, i));
+        }
+        let itb = self.inode_table_blocks();
+        for desc in self.groups.iter() {
+            let table = desc.inode_table();
+            let metadata = [desc.block_bitmap(), desc.inode_bitmap()]
+                .into_iter()
+                .chain(table..table + itb);
+            for block in metadata.filter(|b| (start..end).contains(b)) {
+                set_bit(&mut bitmap, (block - start) as usize);
+            }
+        }
+        (self.blocks_in_group(group)..self.block_size * 8).for_each(|i| set_bit(&mut bitmap, i));
+        bitmap
+    }
+
+    fn read_block_bitmap(&self, group: usize) -> VfsResult<Vec<u8>> {
+        let desc = &self.groups[group];
+        if self.sb.has_group_csum() && desc.flags() & BG_BLOCK_UNINIT != 0 {
+            return Ok(self.init_block_bitmap(group));
+        }
+        let mut bitmap = vec![0; self.block_size];
+        self.read_block(desc.block_bitmap(), &mut bitmap)?;
+        Ok(bitmap)
+    }
+
+    fn write_block_bitmap(&mut self, group: usize, bitmap: &[u8]) -> VfsResult {
+        let desc = &self.groups[group];
+        self.write_block(desc.block_bitmap(), bitmap)?;
+        let len = self.sb.blocks_per_group() as usize / 8;
+        let csum = crc32c(self.csum_seed, &bitmap[..len]);
+        let desc = &mut self.groups[group];
+        if self.sb.has_metadata_csum() {
+            desc.set_block_bitmap_csum(csum);
+        }
+        desc.set_flags(desc.flags() & !BG_BLOCK_UNINIT);
+        Ok(())
+    }
+
+    fn read_inode_bitmap(&self, group: usize) -> VfsResult<Vec<u8>> {
+        let desc = &self.groups[group];
+        let mut bitmap = vec![0; self.block_size];
+        if self.sb.has_group_csum() && desc.flags() & BG_INODE_UNINIT != 0 {
+            let ipg = self.sb.inodes_per_group() as usize;
+            (ipg..self.block_size * 8).for_each(|i| set_bit(&mut bitmap, i));
+        } else {
+            self.read_block(desc.inode_bitmap(), &mut bitmap)?;
+        }
+        Ok(bitmap)
+    }
+
+    fn write_inode_bitmap(&mut self, group: usize, bitmap: &[u8]) -> VfsResult {
+        let desc = &self.groups[group];
+        self.write_block(desc.inode_bitmap(), bitmap)?;
+        let len = self.sb.inodes_per_group() as usize / 8;
+        let csum = crc32c(self.csum_seed, &bitmap[..len]);
+        let desc = &mut self.groups[group];
+        if self.sb.has_metadata_csum() {
+            desc.set_inode_bitmap_csum(csum);
+        }
+        desc.set_flags(desc.flags() & !BG_INODE_UNINIT);
+        Ok(())
+    }
+
+    // ---- allocation ----
+
+    /// Allocates a block, preferably `goal` or one after it.
+    pub fn alloc_block(&mut self, goal: u64) -> VfsResult<u64> {
+        let goal = goal.clamp(
+            self.sb.first_data_block() as u64,
+            self.sb.blocks_count() - 1,
+        );
+        let goal_group = self.group_of_block(goal);
+        for i in 0..self.num_groups() {
+            let group = (goal_group + i) % self.num_groups();
+            if self.groups[group].free_blocks_count() == 0 {
+                continue;
+            }
+            let mut bitmap = self.read_block_bitmap(group)?;
+            let nbits = self.blocks_in_group(group);
+            let start = if i == 0 {
+                (goal - self.group_start(group)) as usize
+            } else {
+                0
+            };
+            let Some(bit) = find_zero_bit(&bitmap, nbits, start) else {
+                warn!("ext4: free blocks count of group {} is wrong", group);
+                continue;
+            };
+            set_bit(&mut bitmap, bit);
+            self.write_block_bitmap(group, &bitmap)?;
+            let desc = &mut self.groups[group];
+            desc.set_free_blocks_count(desc.free_blocks_count() - 1);
+            self.write_group_desc(group)?;
+            let free = self.sb.free_blocks_count();
+            self.sb.set_free_blocks_count(free.saturating_sub(1));
+            self.write_superblock()?;
+            return Ok(self.group_start(group) + bit as u64);
+        }
+        ax_err!(StorageFull)
+    }
+
+    pub fn free_block(&mut self, block: u64) -> VfsResult {
+        if block < self.sb.first_data_block() as u64 || block >= self.sb.blocks_count() {
+            return ax_err!(InvalidData, "ext4: freeing an invalid block");
+        }
+        let group = self.group_of_block(block);
+        let mut bitmap = self.read_block_bitmap(group)?;
+        let bit = (block - self.group_start(group)) as usize;
+        if !test_bit(&bitmap, bit) {
+            warn!("ext4: block {} is already free", block);
+            return Ok(());
+        }
+        clear_bit(&mut bitmap, bit);
+        self.write_block_bitmap(group, &bitmap)?;
+        let desc = &mut self.groups[group];
+        desc.set_free_blocks_count(desc.free_blocks_count() + 1);
+        self.write_group_desc(group)?;
+        let free = self.sb.free_blocks_count();
+        self.sb.set_free_blocks_count(free + 1);
+        self.write_superblock()
+    }
+
+    /// Allocates an inode, preferably in the group `goal_group`.
+    pub fn alloc_inode(&mut self, goal_group: usize, is_dir: bool) -> VfsResult<u32> {
+        let ipg = self.sb.inodes_per_group();
+        for i in 0..self.num_groups() {
+            let group = (goal_group + i) % self.num_groups();
+            if self.groups[group].free_inodes_count() == 0 {
+                continue;
+            }
+            let mut bitmap = self.read_inode_bitmap(group)?;
+            // skip reserved inodes
+            let first = (self.sb.first_ino() as usize).saturating_sub(group * ipg as usize + 1);
+            let Some(bit) = find_zero_bit(&bitmap, ipg as usize, first.min(ipg as usize)) else {
+                continue;
+            };
+            if bit < first {
+                continue; // wrapped around to reserved inodes
+            }
+            set_bit(&mut bitmap, bit);
+            self.write_inode_bitmap(group, &bitmap)?;
+            let has_group_csum = self.sb.has_group_csum();
+            let desc = &mut self.groups[group];
+            desc.set_free_inodes_count(desc.free_inodes_count() - 1);
+            if is_dir {
+                desc.set_used_dirs_count(desc.used_dirs_count() + 1);
+            }
+            if has_group_csum {
+                let used = ipg - desc.itable_unused();
+                if bit as u32 >= used {
+                    desc.set_itable_unused(ipg - bit as u32 - 1);
+                }
+            }
+            self.write_group_desc(group)?;
+            let free = self.sb.free_inodes_count();
+            self.sb.set_free_inodes_count(free.saturating_sub(1));
+            self.write_superblock()?;
+            return Ok(group as u32 * ipg + bit as u32 + 1);
+        }
+        ax_err!(StorageFull)
+    }
+
+    pub fn free_inode(&mut self, ino: u32, is_dir: bool) -> VfsResult {
+        let group = self.group_of_inode(ino);
+        let mut bitmap = self.read_inode_bitmap(group)?;
+        let bit = ((ino - 1) % self.sb.inodes_per_group()) as usize;
+        clear_bit(&mut bitmap, bit);
+        self.write_inode_bitmap(group, &bitmap)?;
+        let desc = &mut self.groups[group];
+        desc.set_free_inodes_count(desc.free_inodes_count() + 1);
+        if is_dir {
+            desc.set_used_dirs_count(desc.used_dirs_count().saturating_sub(1));
+        }
+        self.write_group_desc(group)?;
+        let free = self.sb.free_inodes_count();
+        self.sb.set_free_inodes_count(free + 1);
+        self.write_superblock()
+    }
+
+    // ---- inodes ----
+
+    fn inode_offset(&self, ino: u32) -> VfsResult<u64> {
+        if ino == 0 || ino > self.sb.inodes_count() {
+            return ax_err!(InvalidData, "ext4: invalid inode number");
+        }
+        let group = self.group_of_inode(ino);
+        let index = (ino - 1) % self.sb.inodes_per_group();
+        Ok(self.groups[group].inode_table() * self.block_size as u64
+            + index as u64 * self.sb.inode_size() as u64)
+    }
+
+    pub fn read_inode(&self, ino: u32) -> VfsResult<Inode> {
+        let mut raw = vec![0; self.sb.inode_size()];
+        read_dev_bytes(self.dev.as_ref(), self.inode_offset(ino)?, &mut raw).map_err(as_vfs_err)?;
+        Ok(Inode { raw })
+    }
+
+    pub fn write_inode(&mut self, ino: u32, inode: &mut Inode) -> VfsResult {
+        if self.sb.has_metadata_csum() {
+            let mut raw = inode.raw.clone();
+            write_u16(&mut raw, INODE_CSUM_LO_OFFSET, 0);
+            if inode.has_csum_hi() {
+                write_u16(&mut raw, INODE_CSUM_HI_OFFSET, 0);
+            }
+            let csum = crc32c(self.inode_csum_seed(ino, inode), &raw);
+            write_u16(&mut inode.raw, INODE_CSUM_LO_OFFSET, csum as u16);
+            if inode.has_csum_hi() {
+                write_u16(&mut inode.raw, INODE_CSUM_HI_OFFSET, (csum >> 16) as u16);
+            }
+        }
+        self.write_bytes(self.inode_offset(ino)?, &inode.raw)
+    }
+
+    /// Creates an in-memory inode with `mode`, the caller should write it.
+    pub fn new_inode(&mut self, mode: u16) -> Inode {
+        let extra_isize = self.sb.want_extra_isize().max(32);
+        let extra_isize = extra_isize.min((self.sb.inode_size() - 128) as u16);
+        let mut inode = Inode::new(self.sb.inode_size(), extra_isize);
+        inode.set_mode(mode);
+        let now = axhal::time::wall_time();
+        inode.set_time(INODE_ATIME, now);
+        inode.touch_modified(now);
+        write_u32(&mut inode.raw, 0x64, self.next_generation);
+        self.next_generation = self.next_generation.wrapping_add(1);
+        inode
+    }
+
+    /// The seed of checksums of an inode and the metadata blocks it owns.
+    pub fn inode_csum_seed(&self, ino: u32, inode: &Inode) -> u32 {
+        let csum = crc32c(self.csum_seed, &ino.to_le_bytes());
+        crc32c(csum, &inode.generation().to_le_bytes())
+    }
+
+    /// Adds `count` (can be negative) blocks to the number of blocks used by
+    /// the inode.
+    pub fn add_inode_blocks(&self, inode: &mut Inode, count: i64) {
+        let unit = if inode.has_flag(INODE_FLAG_HUGE_FILE) {
+            1
+        } else {
+            (self.block_size / 512) as i64
+        };
+        let blocks = inode.blocks_raw() as i64 + count * unit;
+        inode.set_blocks_raw(blocks.max(0) as u64);
+    }
+
+    /// Number of 512-byte sectors used by the inode.
+    pub fn inode_sectors(&self, inode: &Inode) -> u64 {
+        if inode.has_flag(INODE_FLAG_HUGE_FILE) {
+            inode.blocks_raw() * (self.block_size / 512) as u64
+        } else {
+            inode.blocks_raw()
+        }
+    }
+
+    /// The first block of the group the inode belongs to, as the goal to
+    /// allocate data blocks.
+    pub fn inode_goal_block(&self, ino: u32) -> u64 {
+        self.group_start(self.group_of_inode(ino))
+    }
+}
+
+fn test_bit(bitmap: &[u8], bit: usize) -> bool {
+    bitmap[bit / 8] & (1 << (bit % 8)) != 0
+}
+
+fn set_bit(bitmap: &mut [u8], bit: usize) {
+    bitmap[bit / 8] |= 1 << (bit % 8);
+}
+
+fn clear_bit(bitmap: &mut [u8], bit: usize) {
+    bitmap[bit / 8] &= !(1 << (bit % 8));
+}
+
+/// Finds a zero bit in the first `nbits` bits, starting from `start` and
+/// wrapping around.
+fn find_zero_bit(bitmap: &[u8], nbits: usize, start: usize) -> Option<usize> {
+    let start = if start < nbits { start } else { 0 };
+    (start..nbits)
+        .chain(0..start)
+        .find(|&bit| !test_bit(bitmap, bit))
+}
+
+/// Reads bytes at any offset of the device.
+fn read_dev_bytes(
+    dev: &dyn BlockDevice,
+    offset: u64,
+    buf: &mut [u8],
+) -> axdriver::prelude::DevResult {
+    let bs = dev.block_size() as u64;
+    let first = offset / bs;
+    let last = (offset + buf.len() as u64).div_ceil(bs);
+    let mut data = vec![0; ((last - first) * bs) as usize];
+    dev.read_blocks(first, &mut data)?;
+    let start = (offset % bs) as usize;
+    buf.copy_from_slice(&data[start..start + buf.len()]);
+    Ok(())
+}
+
+/// Writes bytes at any offset of the device.
+fn write_dev_bytes(dev: &dyn BlockDevice, offset: u64, buf: &[u8]) -> axdriver::prelude::DevResult {
+    let bs = dev.block_size() as u64;
+    let first = offset / bs;
+    let last = (offset + buf.len() as u64).div_ceil(bs);
+    let mut data = vec![0; ((last - first) * bs) as usize];
+    let start = (offset % bs) as usize;
+    if start != 0 || buf.len() % bs as usize != 0 {
+        dev.read_blocks(first, &mut data)?;
+    }
+    data[start..start + buf.len()].copy_from_slice(buf);
+    dev.write_blocks(first, &data)
+}
@@ -1,22 +1,55 @@
-use alloc::sync::Arc;
-use core::cell::UnsafeCell;
+#[cfg(feature = "automount")]
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use core::{mem::ManuallyDrop, time::Duration};
 
 use axfs_vfs::{VfsDirEntry, VfsError, VfsNodePerm, VfsResult};
 use axfs_vfs::{VfsNodeAttr, VfsNodeOps, VfsNodeRef, VfsNodeType, VfsOps};
 use axsync::Mutex;
-use fatfs::{Dir, File, LossyOemCpConverter, NullTimeProvider, Read, Seek, SeekFrom, Write};
+use fatfs::{Date, DateTime, Dir, DirEntry, File, LossyOemCpConverter, Time, TimeProvider};
+use fatfs::{Read, Seek, SeekFrom, Write};
 
-use crate::dev::Disk;
+use crate::dev::{BlockDevice, Disk};
+use crate::fops::{FileTimes, FsStat};
 
 const BLOCK_SIZE: usize = 512;
-
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Provides the time to stamp files with, by [`axhal::time::wall_time`].
+///
+/// It's the real time only if `axhal` has the `rtc` feature, otherwise the
+/// time since boot is used, which is stamped as 1980-01-01, the earliest time
+/// FAT can record.
+#[derive(Debug)]
+pub struct WallClock;
+
+/// A FAT filesystem, which is unmounted when the last reference to it,
+/// including those held by its nodes, is dropped.
 pub struct FatFileSystem {
-    inner: fatfs::FileSystem<Disk, NullTimeProvider, LossyOemCpConverter>,
-    root_dir: UnsafeCell<Option<VfsNodeRef>>,
+    /// Dropped before the device is flushed, which writes FSInfo and clears
+    /// the dirty flag of the volume.
+    inner: ManuallyDrop<fatfs::FileSystem<Disk, WallClock, LossyOemCpConverter>>,
+    dev: Arc<dyn BlockDevice>,
+    this: Weak<FatFileSystem>,
 }
 
-pub struct FileWrapper<'a>(Mutex<File<'a, Disk, NullTimeProvider, LossyOemCpConverter>>);
-pub struct DirWrapper<'a>(Dir<'a, Disk, NullTimeProvider, LossyOemCpConverter>);
+/// A file, with the timestamps read from its directory entry when it's looked
+/// up, and updated by the operations through it. It holds the filesystem it
+/// borrows from.
+pub struct FileWrapper<'a>(
+    Mutex<File<'a, Disk, WallClock, LossyOemCpConverter>>,
+    Mutex<FileTimes>,
+    Arc<FatFileSystem>,
+);
+/// A directory, with the timestamps read from its directory entry when it's
+/// looked up, and whether it's the root directory, for [`fs_stat`]. The root
+/// directory has no entry, so its timestamps are zero.
+pub struct DirWrapper<'a>(
+    Dir<'a, Disk, WallClock, LossyOemCpConverter>,
+    FileTimes,
+    Arc<FatFileSystem>,
+    bool,
+);
 
 unsafe impl Sync for FatFileSystem {}
 unsafe impl Send for FatFileSystem {}
@@ -27,38 +60,99 @@ unsafe impl<'a> Sync for DirWrapper<'a> {}
 
 impl FatFileSystem {
     #[cfg(feature = "use-ramdisk")]
-    pub fn new(mut disk: Disk) -> Self {
+    pub fn new(mut disk: Disk) -> Arc<Self> {
         let opts = fatfs::FormatVolumeOptions::new();
         fatfs::format_volume(&mut disk, opts).expect("failed to format volume");
-        let inner = fatfs::FileSystem::new(disk, fatfs::FsOptions::new())
-            .expect("failed to initialize FAT filesystem");
-        Self {
-            inner,
-            root_dir: UnsafeCell::new(None),
-        }
+        Self::try_new(disk).expect("failed to initialize FAT filesystem")
     }
 
     #[cfg(not(feature = "use-ramdisk"))]
-    pub fn new(disk: Disk) -> Self {
-        let inner = fatfs::FileSystem::new(disk, fatfs::FsOptions::new())
-            .expect("failed to initialize FAT filesystem");
-        Self {
-            inner,
-            root_dir: UnsafeCell::new(None),
+    pub fn new(disk: Disk) -> Arc<Self> {
+        Self::try_new(disk).expect("failed to initialize FAT filesystem")
+    }
+
+    /// Opens the existing FAT volume on `disk`.
+    pub fn try_new(disk: Disk) -> VfsResult<Arc<Self>> {
+        let dev = disk.device().clone();
+        let opts = fatfs::FsOptions::new().time_provider(WallClock);
+        let inner = fatfs::FileSystem::new(disk, opts).map_err(as_vfs_err)?;
+        Ok(Arc::new_cyclic(|this| Self {
+            inner: ManuallyDrop::new(inner),
+            dev,
+            this: this.clone(),
+        }))
+    }
+
+    /// Returns the volume label, or `None` if the volume is not labeled.
+    #[cfg(feature = "automount")]
+    pub fn volume_label(&self) -> Option<String> {
+        let label = self.inner.volume_label();
+        let label = label.trim_end();
+        if label.is_empty() || label == "NO NAME" {
+            None
+        } else {
+            Some(label.into())
         }
     }
 
-    pub fn init(&'static self) {
-        // must be called before later operations
-        unsafe { *self.root_dir.get() = Some(Self::new_dir(self.inner.root_dir())) }
+    fn new_file(
+        self: &Arc<Self>,
+        file: File<'static, Disk, WallClock, LossyOemCpConverter>,
+        times: FileTimes,
+    ) -> Arc<FileWrapper<'static>> {
+        Arc::new(FileWrapper(
+            Mutex::new(file),
+            Mutex::new(times),
+            self.clone(),
+        ))
     }
 
-    fn new_file(file: File<'_, Disk, NullTimeProvider, LossyOemCpConverter>) -> Arc<FileWrapper> {
-        Arc::new(FileWrapper(Mutex::new(file)))
+    fn new_dir(
+        self: &Arc<Self>,
+        dir: Dir<'static, Disk, WallClock, LossyOemCpConverter>,
+        times: FileTimes,
+    ) -> Arc<DirWrapper<'static>> {
+        Arc::new(DirWrapper(dir, times, self.clone(), false))
+    }
+
+    fn new_node(
+        self: &Arc<Self>,
+        entry: DirEntry<'static, Disk, WallClock, LossyOemCpConverter>,
+    ) -> VfsNodeRef {
+        // FAT has no change time, the modification time is the closest
+        let modified = from_fat_time(entry.modified());
+        let times = FileTimes {
+            accessed: from_fat_time(DateTime::new(entry.accessed(), Time::new(0, 0, 0, 0))),
+            modified,
+            changed: modified,
+        };
+        if entry.is_dir() {
+            self.new_dir(entry.to_dir(), times)
+        } else {
+            self.new_file(entry.to_file(), times)
+        }
+    }
+}
+
+impl Drop for FatFileSystem {
+    fn drop(&mut self) {
+        // SAFETY: no node borrows it anymore, since each one holds a reference
+        // to `self`, and it's not used afterwards.
+        unsafe { ManuallyDrop::drop(&mut self.inner) };
+        if self.dev.flush().is_err() {
+            warn!("failed to flush the FAT volume");
+        }
     }
+}
 
-    fn new_dir(dir: Dir<'_, Disk, NullTimeProvider, LossyOemCpConverter>) -> Arc<DirWrapper> {
-        Arc::new(DirWrapper(dir))
+impl FileWrapper<'_> {
+    /// Stamps a modification of the content.
+    fn touch_modified(&self, file: &mut File<'_, Disk, WallClock, LossyOemCpConverter>) {
+        let now = axhal::time::wall_time();
+        file.set_modified(to_fat_time(now));
+        let mut times = self.1.lock();
+        times.modified = now;
+        times.changed = now;
     }
 }
 
@@ -82,13 +176,22 @@ impl VfsNodeOps for FileWrapper<'static> {
     fn write_at(&self, offset: u64, buf: &[u8]) -> VfsResult<usize> {
         let mut file = self.0.lock();
         file.seek(SeekFrom::Start(offset)).map_err(as_vfs_err)?; // TODO: more efficient
-        file.write(buf).map_err(as_vfs_err)
+        let n = file.write(buf).map_err(as_vfs_err)?;
+        self.touch_modified(&mut file);
+        Ok(n)
     }
 
     fn truncate(&self, size: u64) -> VfsResult {
         let mut file = self.0.lock();
         file.seek(SeekFrom::Start(size)).map_err(as_vfs_err)?; // TODO: more efficient
-        file.truncate().map_err(as_vfs_err)
+        file.truncate().map_err(as_vfs_err)?;
+        self.touch_modified(&mut file);
+        Ok(())
+    }
+
+    fn fsync(&self) -> VfsResult {
+        // writes the directory entry, and the cached blocks of the device
+        self.0.lock().flush().map_err(as_vfs_err)
     }
 }
 
@@ -106,9 +209,9 @@ impl VfsNodeOps for DirWrapper<'static> {
     }
 
     fn parent(&self) -> Option<VfsNodeRef> {
-        self.0
-            .open_dir("..")
-            .map_or(None, |dir| Some(FatFileSystem::new_dir(dir)))
+        find_entry(&self.0, "..")
+            .ok()
+            .map(|entry| self.2.new_node(entry))
     }
 
     fn lookup(self: Arc<Self>, path: &str) -> VfsResult<VfsNodeRef> {
@@ -121,14 +224,11 @@ impl VfsNodeOps for DirWrapper<'static> {
             return self.lookup(rest);
         }
 
-        // TODO: use `fatfs::Dir::find_entry`, but it's not public.
-        if let Ok(file) = self.0.open_file(path) {
-            Ok(FatFileSystem::new_file(file))
-        } else if let Ok(dir) = self.0.open_dir(path) {
-            Ok(FatFileSystem::new_dir(dir))
-        } else {
-            Err(VfsError::NotFound)
-        }
+        let (dir, name) = match path.rsplit_once('/') {
+            Some((parent, name)) => (self.0.open_dir(parent).map_err(as_vfs_err)?, name),
+            None => (self.0.clone(), path),
+        };
+        Ok(self.2.new_node(find_entry(&dir, name)?))
     }
 
     fn create(&self, path: &str, ty: VfsNodeType) -> VfsResult {
@@ -192,6 +292,32 @@ impl VfsNodeOps for DirWrapper<'static> {
             src_path, dst_path
         );
 
+        let src_path = src_path.trim_matches('/');
+        let dst_path = dst_path.trim_matches('/');
+        let src_is_dir = match self.0.open_dir(src_path) {
+            Ok(_) => true,
+            Err(_) => self
+                .0
+                .open_file(src_path)
+                .map(|_| false)
+                .map_err(as_vfs_err)?,
+        };
+
+        // FAT can't replace an entry atomically, so remove the destination
+        // first. Names are case-insensitive, so it may be the source itself.
+        if !src_path.eq_ignore_ascii_case(dst_path) {
+            if self.0.open_dir(dst_path).is_ok() {
+                if !src_is_dir {
+                    return Err(VfsError::IsADirectory);
+                }
+                self.0.remove(dst_path).map_err(as_vfs_err)?; // fails if not empty
+            } else if self.0.open_file(dst_path).is_ok() {
+                if src_is_dir {
+                    return Err(VfsError::NotADirectory);
+                }
+                self.0.remove(dst_path).map_err(as_vfs_err)?;
+            }
+        }
         self.0
             .rename(src_path, &self.0, dst_path)
             .map_err(as_vfs_err)
@@ -199,12 +325,166 @@ impl VfsNodeOps for DirWrapper<'static> {
 }
 
 impl VfsOps for FatFileSystem {
+    fn umount(&self) -> VfsResult {
+        // the volume itself is unmounted when the filesystem is dropped
+        self.dev.flush().map_err(|_| VfsError::Io)
+    }
+
     fn root_dir(&self) -> VfsNodeRef {
-        let root_dir = unsafe { (*self.root_dir.get()).as_ref().unwrap() };
-        root_dir.clone()
+        let fs = self.this.upgrade().unwrap();
+        // SAFETY: the directory borrows the filesystem, which is kept alive,
+        // and at the same address, by the reference the directory holds.
+        let inner: &'static fatfs::FileSystem<_, _, _> =
+            unsafe { &*(&*fs.inner as *const fatfs::FileSystem<_, _, _>) };
+        Arc::new(DirWrapper(inner.root_dir(), FileTimes::default(), fs, true))
     }
 }
 
+impl TimeProvider for WallClock {
+    fn get_current_date(&self) -> Date {
+        self.get_current_date_time().date
+    }
+
+    fn get_current_date_time(&self) -> DateTime {
+        to_fat_time(axhal::time::wall_time())
+    }
+}
+
+/// Returns the timestamps of `node` if it's on a FAT filesystem.
+///
+/// They are the ones when it was looked up, modified by operations through
+/// `node` afterwards.
+pub fn node_times(node: &VfsNodeRef) -> Option<FileTimes> {
+    let any = node.as_any();
+    if let Some(file) = any.downcast_ref::<FileWrapper<'static>>() {
+        Some(*file.1.lock())
+    } else {
+        any.downcast_ref::<DirWrapper<'static>>().map(|dir| dir.1)
+    }
+}
+
+/// Returns the usage of the FAT filesystem if `root` is its root directory.
+/// FAT has no inodes, so the numbers of files are zero.
+pub fn fs_stat(root: &VfsNodeRef) -> Option<VfsResult<FsStat>> {
+    let dir = root.as_any().downcast_ref::<DirWrapper<'static>>()?;
+    if !dir.3 {
+        return None;
+    }
+    let stats = match dir.2.inner.stats() {
+        Ok(stats) => stats,
+        Err(e) => return Some(Err(as_vfs_err(e))),
+    };
+    Some(Ok(FsStat {
+        block_size: stats.cluster_size() as u64,
+        blocks: stats.total_clusters() as u64,
+        blocks_free: stats.free_clusters() as u64,
+        name_max: 255, // with long file names
+        ..Default::default()
+    }))
+}
+
+/// Sets the access and modification times of `node` if it's on a FAT
+/// filesystem. Only the date of the access time is recorded.
+///
+/// The directory entries of directories can't be changed by [`fatfs`], so
+/// it fails with [`VfsError::Unsupported`] for them.
+pub fn set_node_times(
+    node: &VfsNodeRef,
+    accessed: Option<Duration>,
+    modified: Option<Duration>,
+) -> Option<VfsResult> {
+    let any = node.as_any();
+    if let Some(wrapper) = any.downcast_ref::<FileWrapper<'static>>() {
+        let mut file = wrapper.0.lock();
+        let mut times = wrapper.1.lock();
+        if let Some(accessed) = accessed {
+            file.set_accessed(to_fat_time(accessed).date);
+            times.accessed = accessed;
+        }
+        if let Some(modified) = modified {
+            file.set_modified(to_fat_time(modified));
+            times.modified = modified;
+        }
+        times.changed = axhal::time::wall_time();
+        Some(Ok(()))
+    } else if any.is::<DirWrapper<'static>>() {
+        Some(Err(VfsError::Unsupported))
+    } else {
+        None
+    }
+}
+
+/// Finds the entry named `name` in `dir`, by its long or short name.
+fn find_entry<'a>(
+    dir: &Dir<'a, Disk, WallClock, LossyOemCpConverter>,
+    name: &str,
+) -> VfsResult<DirEntry<'a, Disk, WallClock, LossyOemCpConverter>> {
+    for entry in dir.iter() {
+        let entry = entry.map_err(as_vfs_err)?;
+        if entry.file_name().eq_ignore_ascii_case(name)
+            || entry.short_file_name().eq_ignore_ascii_case(name)
+        {
+            return Ok(entry);
+        }
+    }
+    Err(VfsError::NotFound)
+}
+
+/// Converts the time elapsed since the Unix epoch to the FAT date and time,
+/// clamped to the range FAT can record, from 1980 to 2107.
+fn to_fat_time(time: Duration) -> DateTime {
+    let secs = time.as_secs();
+    let (year, month, day) = civil_from_days(secs / SECS_PER_DAY);
+    if year < 1980 {
+        return DateTime::new(Date::new(1980, 1, 1), Time::new(0, 0, 0, 0));
+    } else if year > 2107 {
+        return DateTime::new(Date::new(2107, 12, 31), Time::new(23, 59, 59, 999));
+    }
+    let secs_of_day = secs % SECS_PER_DAY;
+    DateTime::new(
+        Date::new(year as u16, month as u16, day as u16),
+        Time::new(
+            (secs_of_day / 3600) as u16,
+            (secs_of_day / 60 % 60) as u16,
+            (secs_of_day % 60) as u16,
+            time.subsec_millis() as u16,
+        ),
+    )
+}
+
+/// Converts the FAT date and time to the time elapsed since the Unix epoch.
+fn from_fat_time(time: DateTime) -> Duration {
+    let (date, time) = (time.date, time.time);
+    let days = days_from_civil(date.year as u64, date.month as u64, date.day as u64);
+    let secs = time.hour as u64 * 3600 + time.min as u64 * 60 + time.sec as u64;
+    Duration::from_secs(days * SECS_PER_DAY + secs) + Duration::from_millis(time.millis as u64)
+}
+
+/// Returns the number of days since 1970-01-01 of a date after it.
+fn days_from_civil(year: u64, month: u64, day: u64) -> u64 {
+    // years start from March, so that the leap day is the last day
+    let year = if month <= 2 { year - 1 } else { year };
+    let (era, year_of_era) = (year / 400, year % 400);
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Returns the date of the day `days` days after 1970-01-01, as the year,
+/// month and day.
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let days = days + 719468;
+    let (era, day_of_era) = (days / 146097, days % 146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = era * 400 + year_of_era;
+    (if month <= 2 { year + 1 } else { year }, month, day)
+}
+
 impl fatfs::IoBase for Disk {
     type Error = ();
 }
@@ -243,7 +523,7 @@ impl Write for Disk {
         Ok(write_len)
     }
     fn flush(&mut self) -> Result<(), Self::Error> {
-        Ok(())
+        Disk::flush(self).map_err(|_| ())
     }
 }
 
@@ -1,13 +1,26 @@
 cfg_if::cfg_if! {
     if #[cfg(feature = "myfs")] {
         pub mod myfs;
-    } else if #[cfg(feature = "fatfs")] {
+    } else {
+        #[cfg(feature = "fatfs")]
         pub mod fatfs;
+        #[cfg(feature = "ext4fs")]
+        pub mod ext4fs;
     }
 }
 
 #[cfg(feature = "devfs")]
-pub use axfs_devfs as devfs;
+pub mod devfs;
 
 #[cfg(feature = "ramfs")]
 pub use axfs_ramfs as ramfs;
+
+#[cfg(feature = "overlayfs")]
+pub mod overlayfs;
+
+#[cfg(feature = "procfs")]
+pub mod procfs;
+#[cfg(any(feature = "procfs", feature = "sysfs"))]
+pub mod pseudo;
+#[cfg(feature = "sysfs")]
+pub mod sysfs;
@@ -1,4 +1,4 @@
-use crate::dev::Disk;
+use crate::dev::BlockDevice;
 use alloc::sync::Arc;
 use axfs_vfs::VfsOps;
 
@@ -7,10 +7,11 @@ use axfs_vfs::VfsOps;
 pub trait MyFileSystemIf {
     /// Creates a new instance of the filesystem with initialization.
     ///
-    /// TODO: use generic disk type
-    fn new_myfs(disk: Disk) -> Arc<dyn VfsOps>;
+    /// `dev` is the block device (or partition) of the root filesystem. Wrap
+    /// it in a [`Disk`](crate::fops::Disk) for cursor-based access.
+    fn new_myfs(dev: Arc<dyn BlockDevice>) -> Arc<dyn VfsOps>;
 }
 
-pub(crate) fn new_myfs(disk: Disk) -> Arc<dyn VfsOps> {
-    crate_interface::call_interface!(MyFileSystemIf::new_myfs(disk))
+pub(crate) fn new_myfs(dev: Arc<dyn BlockDevice>) -> Arc<dyn VfsOps> {
+    crate_interface::call_interface!(MyFileSystemIf::new_myfs(dev))
 }
@@ -0,0 +1,508 @@
+//! An overlay filesystem, which merges a writable RAM filesystem on top of
+//! another filesystem that is never modified.
+//!
+//! All changes go to the upper filesystem. A file or symlink of the lower
+//! filesystem is copied up the first time it's modified, along with its
+//! parent directories. Removed lower entries are hidden by whiteouts, i.e.
+//! empty files named `.wh.<name>` in the upper directory, which are never
+//! listed. A whiteout is kept while an upper entry of the same name replaces
+//! the lower one, so that a directory created there doesn't merge with the
+//! removed one.
+//!
+//! Directories that are in both filesystems are merged, with the upper
+//! entries taking precedence. As on Linux without `redirect_dir`, they can't
+//! be renamed.
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::{vec, vec::Vec};
+use core::any::Any;
+
+use axfs_vfs::{VfsDirEntry, VfsNodeAttr, VfsNodeOps, VfsNodeRef, VfsNodeType};
+use axfs_vfs::{VfsError, VfsOps, VfsResult};
+use axsync::Mutex;
+
+use super::ramfs::{DirNode, RamFileSystem};
+
+const WHITEOUT_PREFIX: &str = ".wh.";
+
+/// A filesystem that merges a RAM filesystem on top of a read-only one.
+pub struct OverlayFileSystem {
+    lower: Arc<dyn VfsOps>,
+    upper: Arc<RamFileSystem>,
+    root: Arc<OverlayNode>,
+}
+
+/// A node of an overlay filesystem, backed by the node at the same path in
+/// the upper filesystem, the lower one, or both.
+pub struct OverlayNode {
+    this: Weak<OverlayNode>,
+    /// The parent directory, or the parent of the mount point for the root.
+    /// It's `None` once the node is removed.
+    parent: Mutex<Option<VfsNodeRef>>,
+    name: Mutex<String>,
+    upper: Mutex<Option<VfsNodeRef>>,
+    /// The lower node, unless it's hidden by a whiteout.
+    lower: Mutex<Option<VfsNodeRef>>,
+    /// Children that have been looked up, so that there's only one node to
+    /// copy up for each path.
+    children: Mutex<BTreeMap<String, Weak<OverlayNode>>>,
+}
+
+impl OverlayFileSystem {
+    /// Creates an overlay of `upper` on top of `lower`.
+    pub fn new(lower: Arc<dyn VfsOps>, upper: Arc<RamFileSystem>) -> Self {
+        let root = OverlayNode::new(
+            None,
+            String::new(),
+            Some(upper.root_dir()),
+            Some(lower.root_dir()),
+        );
+        Self { lower, upper, root }
+    }
+}
+
+impl VfsOps for OverlayFileSystem {
+    fn mount(&self, _path: &str, mount_point: VfsNodeRef) -> VfsResult {
+        *self.root.parent.lock() = mount_point.parent();
+        Ok(())
+    }
+
+    fn umount(&self) -> VfsResult {
+        self.upper.umount()?;
+        self.lower.umount()
+    }
+
+    fn root_dir(&self) -> VfsNodeRef {
+        self.root.clone()
+    }
+}
+
+impl OverlayNode {
+    fn new(
+        parent: Option<VfsNodeRef>,
+        name: String,
+        upper: Option<VfsNodeRef>,
+        lower: Option<VfsNodeRef>,
+    ) -> Arc<Self> {
+        Arc::new_cyclic(|this| Self {
+            this: this.clone(),
+            parent: Mutex::new(parent),
+            name: Mutex::new(name),
+            upper: Mutex::new(upper),
+            lower: Mutex::new(lower),
+            children: Mutex::new(BTreeMap::new()),
+        })
+    }
+
+    /// Returns the node that holds the content, i.e. the upper one if it has
+    /// been copied up.
+    pub fn real_node(&self) -> VfsNodeRef {
+        let upper = self.upper.lock().clone();
+        upper
+            .or_else(|| self.lower.lock().clone())
+            .expect("overlay node without any layer")
+    }
+
+    /// Copies the node to the upper filesystem if it's only in the lower
+    /// one, along with its parent directories, and returns the upper node.
+    ///
+    /// The entries of a directory are not copied, as they're merged anyway.
+    pub fn copy_up(&self) -> VfsResult<VfsNodeRef> {
+        let mut upper = self.upper.lock();
+        if let Some(upper) = upper.as_ref() {
+            return Ok(upper.clone());
+        }
+        let parent = self.parent.lock().clone().ok_or(VfsError::NotFound)?;
+        let parent_upper = as_overlay(&parent)?.copy_up()?;
+        let lower = self.lower.lock().clone().ok_or(VfsError::NotFound)?;
+        let name = self.name.lock().clone();
+
+        let ty = lower.get_attr()?.file_type();
+        parent_upper.create(&name, ty)?;
+        let node = parent_upper.clone().lookup(&name)?;
+        if ty != VfsNodeType::Dir {
+            if let Err(e) = copy_content(&lower, &node) {
+                parent_upper.remove(&name).ok();
+                return Err(e);
+            }
+        }
+        if let Ok(times) = crate::root::node_times(&lower) {
+            let (accessed, modified) = (Some(times.accessed), Some(times.modified));
+            crate::root::set_node_times(&node, accessed, modified).ok();
+        }
+        *upper = Some(node.clone());
+        Ok(node)
+    }
+
+    fn is_dir(&self) -> VfsResult<bool> {
+        Ok(self.get_attr()?.is_dir())
+    }
+
+    fn check_dir(&self) -> VfsResult {
+        match self.is_dir()? {
+            true => Ok(()),
+            false => Err(VfsError::NotADirectory),
+        }
+    }
+
+    /// Looks up the entry `name` in this directory.
+    fn child(&self, name: &str) -> VfsResult<Arc<OverlayNode>> {
+        if name.starts_with(WHITEOUT_PREFIX) {
+            return Err(VfsError::NotFound);
+        }
+        let mut children = self.children.lock();
+        if let Some(node) = children.get(name).and_then(Weak::upgrade) {
+            return Ok(node);
+        }
+
+        let (mut upper, mut whiteout) = (None, false);
+        if let Some(dir) = self.upper.lock().clone() {
+            upper = lookup_entry(&dir, name)?;
+            whiteout = lookup_entry(&dir, &whiteout_name(name))?.is_some();
+        }
+        let lower = match self.lower.lock().clone() {
+            Some(dir) if !whiteout => lookup_entry(&dir, name)?,
+            _ => None,
+        };
+        if upper.is_none() && lower.is_none() {
+            return Err(VfsError::NotFound);
+        }
+
+        let this = self.this.upgrade().map(|this| this as VfsNodeRef);
+        let node = Self::new(this, name.into(), upper, lower);
+        children.retain(|_, child| child.strong_count() > 0);
+        children.insert(name.into(), Arc::downgrade(&node));
+        Ok(node)
+    }
+
+    /// Returns the merged entries of this directory, except `.` and `..`.
+    fn entries(&self) -> VfsResult<Vec<(String, VfsNodeType)>> {
+        let mut entries = Vec::new();
+        let mut hidden = BTreeSet::new();
+        if let Some(upper) = self.upper.lock().clone() {
+            for (name, ty) in dir_entries(&upper)? {
+                if let Some(name) = name.strip_prefix(WHITEOUT_PREFIX) {
+                    hidden.insert(String::from(name));
+                } else {
+                    hidden.insert(name.clone());
+                    entries.push((name, ty));
+                }
+            }
+        }
+        if let Some(lower) = self.lower.lock().clone() {
+            for (name, ty) in dir_entries(&lower)? {
+                if !hidden.contains(&name) && !name.starts_with(WHITEOUT_PREFIX) {
+                    entries.push((name, ty));
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    fn create_child(&self, name: &str, ty: VfsNodeType) -> VfsResult {
+        if name.starts_with(WHITEOUT_PREFIX) {
+            return Err(VfsError::InvalidInput);
+        }
+        match self.child(name) {
+            Ok(_) => Err(VfsError::AlreadyExists),
+            Err(VfsError::NotFound) => self.copy_up()?.create(name, ty),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn remove_child(&self, name: &str) -> VfsResult {
+        let child = self.child(name)?;
+        let is_dir = child.is_dir()?;
+        if is_dir && !child.entries()?.is_empty() {
+            return Err(VfsError::DirectoryNotEmpty);
+        }
+        let upper_dir = self.copy_up()?;
+        if child.lower.lock().is_some() {
+            create_whiteout(&upper_dir, name)?;
+        }
+        if let Some(upper) = child.upper.lock().clone() {
+            if is_dir {
+                remove_whiteouts(&upper)?;
+            }
+            upper_dir.remove(name)?;
+        }
+        *child.parent.lock() = None;
+        self.children.lock().remove(name);
+        Ok(())
+    }
+
+    /// Looks up the parent directory of `path`, and returns it with the last
+    /// component of `path`.
+    fn lookup_parent<'a>(&self, path: &'a str) -> VfsResult<(VfsNodeRef, &'a str)> {
+        let path = path.trim_end_matches('/');
+        let (parent, name) = path.rsplit_once('/').unwrap_or(("", path));
+        if name.is_empty() || name == "." || name == ".." {
+            return Err(VfsError::InvalidInput);
+        }
+        let this = self.this.upgrade().ok_or(VfsError::NotFound)?;
+        Ok((this.lookup(parent)?, name))
+    }
+}
+
+impl VfsNodeOps for OverlayNode {
+    fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
+        self.real_node().get_attr()
+    }
+
+    fn parent(&self) -> Option<VfsNodeRef> {
+        self.parent.lock().clone()
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
+        self.real_node().read_at(offset, buf)
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> VfsResult<usize> {
+        self.copy_up()?.write_at(offset, buf)
+    }
+
+    fn fsync(&self) -> VfsResult {
+        self.real_node().fsync()
+    }
+
+    fn truncate(&self, size: u64) -> VfsResult {
+        self.copy_up()?.truncate(size)
+    }
+
+    fn lookup(self: Arc<Self>, path: &str) -> VfsResult<VfsNodeRef> {
+        self.check_dir()?;
+        let (name, rest) = split_path(path);
+        let node = match name {
+            "" | "." => self.clone() as VfsNodeRef,
+            ".." => self.parent().ok_or(VfsError::NotFound)?,
+            _ => self.child(name)?,
+        };
+
+        if let Some(rest) = rest {
+            node.lookup(rest)
+        } else {
+            Ok(node)
+        }
+    }
+
+    fn read_dir(&self, start_idx: usize, dirents: &mut [VfsDirEntry]) -> VfsResult<usize> {
+        self.check_dir()?;
+        let mut entries: Vec<(String, VfsNodeType)> = vec![
+            (".".into(), VfsNodeType::Dir),
+            ("..".into(), VfsNodeType::Dir),
+        ];
+        entries.extend(self.entries()?);
+
+        let mut entries = entries.into_iter().skip(start_idx);
+        for (i, ent) in dirents.iter_mut().enumerate() {
+            match entries.next() {
+                Some((name, ty)) => *ent = VfsDirEntry::new(&name, ty),
+                None => return Ok(i),
+            }
+        }
+        Ok(dirents.len())
+    }
+
+    fn create(&self, path: &str, ty: VfsNodeType) -> VfsResult {
+        self.check_dir()?;
+        let (name, rest) = split_path(path);
+        if let Some(rest) = rest {
+            match name {
+                "" | "." => self.create(rest, ty),
+                ".." => self.parent().ok_or(VfsError::NotFound)?.create(rest, ty),
+                _ => self.child(name)?.create(rest, ty),
+            }
+        } else if name.is_empty() || name == "." || name == ".." {
+            Ok(()) // already exists
+        } else {
+            self.create_child(name, ty)
+        }
+    }
+
+    fn remove(&self, path: &str) -> VfsResult {
+        self.check_dir()?;
+        let (name, rest) = split_path(path);
+        if let Some(rest) = rest {
+            match name {
+                "" | "." => self.remove(rest),
+                ".." => self.parent().ok_or(VfsError::NotFound)?.remove(rest),
+                _ => self.child(name)?.remove(rest),
+            }
+        } else if name.is_empty() || name == "." || name == ".." {
+            Err(VfsError::InvalidInput) // remove '.' or '..
+        } else {
+            self.remove_child(name)
+        }
+    }
+
+    fn rename(&self, src_path: &str, dst_path: &str) -> VfsResult {
+        let (src_dir, src_name) = self.lookup_parent(src_path)?;
+        let (dst_dir_ref, dst_name) = self.lookup_parent(dst_path)?;
+        let (src_dir, dst_dir) = (as_overlay(&src_dir)?, as_overlay(&dst_dir_ref)?);
+        if dst_name.starts_with(WHITEOUT_PREFIX) {
+            return Err(VfsError::InvalidInput);
+        }
+
+        let node = src_dir.child(src_name)?;
+        let is_dir = node.is_dir()?;
+        let replaced = match dst_dir.child(dst_name) {
+            Ok(old) if Arc::ptr_eq(&old, &node) => return Ok(()),
+            Ok(old) => Some(old),
+            Err(VfsError::NotFound) => None,
+            Err(e) => return Err(e),
+        };
+        if let Some(old) = &replaced {
+            match (is_dir, old.is_dir()?) {
+                (true, false) => return Err(VfsError::NotADirectory),
+                (false, true) => return Err(VfsError::IsADirectory),
+                (true, true) if !old.entries()?.is_empty() => {
+                    return Err(VfsError::DirectoryNotEmpty)
+                }
+                _ => {}
+            }
+        }
+        if is_dir {
+            if node.lower.lock().is_some() {
+                return Err(VfsError::Unsupported); // merged directories stay in place
+            }
+            // moving a directory into itself
+            let mut cur = Some(dst_dir_ref.clone());
+            while let Some(dir) = cur {
+                if Arc::as_ptr(&dir) as *const () == Arc::as_ptr(&node) as *const () {
+                    return Err(VfsError::InvalidInput);
+                }
+                cur = dir.parent();
+            }
+        }
+
+        let src_upper_dir = src_dir.copy_up()?;
+        let dst_upper_dir = dst_dir.copy_up()?;
+        node.copy_up()?;
+        if let Some(old_upper) = replaced.as_ref().and_then(|old| old.upper.lock().clone()) {
+            if is_dir {
+                remove_whiteouts(&old_upper)?;
+            }
+        }
+        as_ramfs_dir(&src_upper_dir)?.move_node(
+            src_name,
+            as_ramfs_dir(&dst_upper_dir)?,
+            dst_name,
+        )?;
+
+        // the whiteouts are created after the move, so that nothing is hidden
+        // if it fails
+        if replaced
+            .as_ref()
+            .is_some_and(|old| old.lower.lock().is_some())
+        {
+            create_whiteout(&dst_upper_dir, dst_name)?;
+        }
+        if node.lower.lock().take().is_some() {
+            create_whiteout(&src_upper_dir, src_name)?;
+        }
+        if let Some(old) = replaced {
+            *old.parent.lock() = None;
+        }
+        src_dir.children.lock().remove(src_name);
+        *node.parent.lock() = Some(dst_dir_ref.clone());
+        *node.name.lock() = dst_name.into();
+        let node = Arc::downgrade(&node);
+        dst_dir.children.lock().insert(dst_name.into(), node);
+        Ok(())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+fn as_overlay(node: &VfsNodeRef) -> VfsResult<&OverlayNode> {
+    node.as_any()
+        .downcast_ref::<OverlayNode>()
+        .ok_or(VfsError::InvalidInput)
+}
+
+fn as_ramfs_dir(node: &VfsNodeRef) -> VfsResult<&DirNode> {
+    node.as_any()
+        .downcast_ref::<DirNode>()
+        .ok_or(VfsError::NotADirectory)
+}
+
+fn whiteout_name(name: &str) -> String {
+    String::from(WHITEOUT_PREFIX) + name
+}
+
+fn create_whiteout(dir: &VfsNodeRef, name: &str) -> VfsResult {
+    match dir.create(&whiteout_name(name), VfsNodeType::File) {
+        Err(VfsError::AlreadyExists) => Ok(()),
+        res => res,
+    }
+}
+
+/// Removes the whiteouts in the upper directory `dir`, before it's removed
+/// or replaced.
+fn remove_whiteouts(dir: &VfsNodeRef) -> VfsResult {
+    for (name, _) in dir_entries(dir)? {
+        if name.starts_with(WHITEOUT_PREFIX) {
+            dir.remove(&name)?;
+        }
+    }
+    Ok(())
+}
+
+/// Looks up `name` in `dir`, returns `None` if it does not exist.
+fn lookup_entry(dir: &VfsNodeRef, name: &str) -> VfsResult<Option<VfsNodeRef>> {
+    match dir.clone().lookup(name) {
+        Ok(node) => Ok(Some(node)),
+        Err(VfsError::NotFound) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Reads all entries of `dir`, except `.` and `..`.
+fn dir_entries(dir: &VfsNodeRef) -> VfsResult<Vec<(String, VfsNodeType)>> {
+    const EMPTY: VfsDirEntry = VfsDirEntry::default();
+    let mut buf = [EMPTY; 16];
+    let mut entries = Vec::new();
+    let mut idx = 0;
+    loop {
+        let n = dir.read_dir(idx, &mut buf)?;
+        if n == 0 {
+            return Ok(entries);
+        }
+        idx += n;
+        for ent in &buf[..n] {
+            let name = ent.name_as_bytes();
+            if name != b"." && name != b".." {
+                let name = String::from_utf8_lossy(name).into_owned();
+                entries.push((name, ent.entry_type()));
+            }
+        }
+    }
+}
+
+/// Copies the content of the file or symlink `src` to `dst`.
+fn copy_content(src: &VfsNodeRef, dst: &VfsNodeRef) -> VfsResult {
+    let mut buf = vec![0; 4096];
+    let mut offset = 0;
+    loop {
+        let n = src.read_at(offset, &mut buf)?;
+        if n == 0 {
+            return Ok(());
+        }
+        let mut written = 0;
+        while written < n {
+            written += dst.write_at(offset + written as u64, &buf[written..n])?;
+        }
+        offset += n as u64;
+    }
+}
+
+fn split_path(path: &str) -> (&str, Option<&str>) {
+    let trimmed_path = path.trim_start_matches('/');
+    trimmed_path.find('/').map_or((trimmed_path, None), |n| {
+        (&trimmed_path[..n], Some(&trimmed_path[n + 1..]))
+    })
+}
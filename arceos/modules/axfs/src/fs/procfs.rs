@@ -0,0 +1,122 @@
+//! Files of procfs, which are generated from the live kernel state.
+
+use alloc::sync::Arc;
+use alloc::{format, string::String};
+use core::fmt::Write;
+
+use axfs_vfs::{VfsNodeRef, VfsResult};
+
+use super::pseudo::DynDir;
+
+/// Creates the root directory of procfs, with only the per-task directories.
+/// Other entries are added by the caller.
+#[cfg(feature = "multitask")]
+pub fn new_root() -> Arc<DynDir> {
+    DynDir::new_dynamic(None, task_dirs)
+}
+
+/// Creates an empty root directory of procfs. The entries are added by the
+/// caller.
+#[cfg(not(feature = "multitask"))]
+pub fn new_root() -> Arc<DynDir> {
+    DynDir::new(None)
+}
+
+/// Generates `/proc/meminfo`, with the page allocator and the byte allocator
+/// reported separately.
+pub fn meminfo() -> VfsResult<String> {
+    let alloc = axalloc::global_allocator();
+    let page_kb = axhal::mem::PAGE_SIZE_4K / 1024;
+    let free_pages = alloc.available_pages();
+    let total_pages = free_pages + alloc.used_pages();
+    let heap_free = alloc.available_bytes();
+    let heap_total = heap_free + alloc.used_bytes();
+
+    let mut s = String::new();
+    writeln!(s, "MemTotal:  {:>10} kB", total_pages * page_kb).unwrap();
+    writeln!(s, "MemFree:   {:>10} kB", free_pages * page_kb).unwrap();
+    writeln!(s, "HeapTotal: {:>10} kB", heap_total / 1024).unwrap();
+    writeln!(s, "HeapFree:  {:>10} kB", heap_free / 1024).unwrap();
+    Ok(s)
+}
+
+/// Generates `/proc/uptime`. Idle time is not tracked, so it's always 0.
+pub fn uptime() -> VfsResult<String> {
+    let now = axhal::time::monotonic_time();
+    Ok(format!(
+        "{}.{:02} 0.00\n",
+        now.as_secs(),
+        now.subsec_millis() / 10
+    ))
+}
+
+/// Generates `/proc/mounts`, in the format of `fstab(5)`.
+pub fn mounts() -> VfsResult<String> {
+    let mut s = String::new();
+    for mp in crate::root::mounts() {
+        let (source, path, fstype) = (mp.source, mp.path, mp.fstype);
+        writeln!(s, "{} {} {} {} 0 0", source, path, fstype, mp.options).unwrap();
+    }
+    Ok(s)
+}
+
+/// Generates `/proc/interrupts`, with the number of occurrences of each IRQ.
+#[cfg(feature = "irq")]
+pub fn interrupts() -> VfsResult<String> {
+    let mut s = String::new();
+    writeln!(s, "{:>4} {:>10}", "", "CPU0").unwrap();
+    for (irq_num, count) in axhal::irq::irq_counts() {
+        writeln!(s, "{:>3}: {:>10}", irq_num, count).unwrap();
+    }
+    Ok(s)
+}
+
+/// Generates the target of `/proc/self`, i.e. the ID of the current task.
+#[cfg(feature = "multitask")]
+pub fn self_link() -> VfsResult<String> {
+    Ok(format!("{}", axtask::current().id().as_u64()))
+}
+
+/// Generates the `/proc/<tid>` directories for all tasks.
+#[cfg(feature = "multitask")]
+fn task_dirs(root: &VfsNodeRef) -> alloc::vec::Vec<(String, VfsNodeRef)> {
+    use super::pseudo::DynFile;
+    use alloc::sync::Weak;
+    use axfs_vfs::VfsError;
+
+    axtask::all_tasks()
+        .iter()
+        .map(|task| {
+            let dir = DynDir::new(Some(root));
+            // do not keep the task alive, as the node may be held by open files
+            let weak = Arc::downgrade(task);
+            dir.add(
+                "status",
+                DynFile::new(move || {
+                    let task = Weak::upgrade(&weak).ok_or(VfsError::NotFound)?;
+                    Ok(task_status(&task))
+                }),
+            );
+            (format!("{}", task.id().as_u64()), dir as VfsNodeRef)
+        })
+        .collect()
+}
+
+/// Generates `/proc/<tid>/status` for `task`.
+#[cfg(feature = "multitask")]
+fn task_status(task: &axtask::AxTaskRef) -> String {
+    use axtask::TaskState;
+
+    let state = match task.state() {
+        TaskState::Running => "R (running)",
+        TaskState::Ready => "R (ready)",
+        TaskState::Blocked => "S (sleeping)",
+        TaskState::Exited => "Z (zombie)",
+    };
+    let mut s = String::new();
+    writeln!(s, "Name:\t{}", task.name()).unwrap();
+    writeln!(s, "State:\t{}", state).unwrap();
+    writeln!(s, "Pid:\t{}", task.id().as_u64()).unwrap();
+    writeln!(s, "Cpus_allowed:\t{:x}", task.cpumask().bits()).unwrap();
+    s
+}
@@ -0,0 +1,310 @@
+//! Nodes of pseudo filesystems, whose contents are generated from the kernel
+//! state each time they are accessed.
+
+use alloc::collections::BTreeMap;
+use alloc::sync::{Arc, Weak};
+use alloc::{boxed::Box, string::String, vec, vec::Vec};
+
+use axfs_vfs::{VfsDirEntry, VfsNodeAttr, VfsNodeOps, VfsNodePerm, VfsNodeRef, VfsNodeType};
+use axfs_vfs::{VfsError, VfsOps, VfsResult};
+use axsync::Mutex;
+
+type Generator = Box<dyn Fn() -> VfsResult<String> + Send + Sync>;
+type Setter = Box<dyn Fn(&str) -> VfsResult + Send + Sync>;
+type EntriesGenerator = Box<dyn Fn(&VfsNodeRef) -> Vec<(String, VfsNodeRef)> + Send + Sync>;
+
+/// A filesystem made of the nodes in this module, such as procfs and sysfs.
+pub struct PseudoFileSystem {
+    parent: Mutex<Option<VfsNodeRef>>,
+    root: Arc<DynDir>,
+}
+
+/// A file whose content is generated each time it's read.
+///
+/// Its size is reported as 0, as on Linux, since the content is unknown
+/// until it's generated. If it's writable, each write sets the whole value.
+pub struct DynFile {
+    generate: Generator,
+    set: Option<Setter>,
+}
+
+/// A symbolic link whose target is generated each time it's read.
+pub struct DynSymlink {
+    generate: Generator,
+}
+
+/// A read-only directory with fixed entries, and optionally entries
+/// generated each time it's looked up or read.
+pub struct DynDir {
+    this: Weak<DynDir>,
+    parent: Mutex<Weak<dyn VfsNodeOps>>,
+    children: Mutex<BTreeMap<String, VfsNodeRef>>,
+    dynamic: Option<EntriesGenerator>,
+}
+
+impl PseudoFileSystem {
+    /// Creates a filesystem with the given root directory.
+    pub fn new(root: Arc<DynDir>) -> Self {
+        Self {
+            parent: Mutex::new(None),
+            root,
+        }
+    }
+
+    /// Returns the root directory node.
+    pub fn root_dir_node(&self) -> Arc<DynDir> {
+        self.root.clone()
+    }
+}
+
+impl VfsOps for PseudoFileSystem {
+    fn mount(&self, _path: &str, mount_point: VfsNodeRef) -> VfsResult {
+        // the root only holds a weak reference to its parent
+        let mut parent = self.parent.lock();
+        *parent = mount_point.parent();
+        self.root.set_parent(parent.as_ref());
+        Ok(())
+    }
+
+    fn root_dir(&self) -> VfsNodeRef {
+        self.root.clone()
+    }
+}
+
+impl DynFile {
+    /// Creates a read-only file whose content is generated by `generate`.
+    pub fn new(generate: impl Fn() -> VfsResult<String> + Send + Sync + 'static) -> Arc<Self> {
+        Arc::new(Self {
+            generate: Box::new(generate),
+            set: None,
+        })
+    }
+
+    /// Creates a writable file whose content is generated by `generate`,
+    /// and written by `set` with the written string, without the trailing
+    /// newline.
+    pub fn new_writable(
+        generate: impl Fn() -> VfsResult<String> + Send + Sync + 'static,
+        set: impl Fn(&str) -> VfsResult + Send + Sync + 'static,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            generate: Box::new(generate),
+            set: Some(Box::new(set)),
+        })
+    }
+}
+
+impl VfsNodeOps for DynFile {
+    fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
+        let mode = if self.set.is_some() { 0o644 } else { 0o444 };
+        let perm = VfsNodePerm::from_bits_truncate(mode);
+        Ok(VfsNodeAttr::new(perm, VfsNodeType::File, 0, 0))
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
+        read_generated(&self.generate, offset, buf)
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> VfsResult<usize> {
+        let set = self.set.as_ref().ok_or(VfsError::PermissionDenied)?;
+        // the value can't be written in pieces
+        if offset != 0 {
+            return Err(VfsError::InvalidInput);
+        }
+        let value = core::str::from_utf8(buf).map_err(|_| VfsError::InvalidInput)?;
+        set(value.strip_suffix('\n').unwrap_or(value))?;
+        Ok(buf.len())
+    }
+
+    fn truncate(&self, _size: u64) -> VfsResult {
+        // opening with `O_TRUNC` before writing is fine
+        match self.set {
+            Some(_) => Ok(()),
+            None => Err(VfsError::PermissionDenied),
+        }
+    }
+
+    axfs_vfs::impl_vfs_non_dir_default! {}
+}
+
+impl DynSymlink {
+    /// Creates a symbolic link whose target is generated by `generate`.
+    pub fn new(generate: impl Fn() -> VfsResult<String> + Send + Sync + 'static) -> Arc<Self> {
+        Arc::new(Self {
+            generate: Box::new(generate),
+        })
+    }
+}
+
+impl VfsNodeOps for DynSymlink {
+    fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
+        // the size is the length of the target, which readers rely on
+        let perm = VfsNodePerm::from_bits_truncate(0o777);
+        let size = (self.generate)()?.len() as u64;
+        Ok(VfsNodeAttr::new(perm, VfsNodeType::SymLink, size, 0))
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
+        read_generated(&self.generate, offset, buf)
+    }
+
+    axfs_vfs::impl_vfs_non_dir_default! {}
+}
+
+impl DynDir {
+    /// Creates an empty directory.
+    pub fn new(parent: Option<&VfsNodeRef>) -> Arc<Self> {
+        Self::new_inner(parent, None)
+    }
+
+    /// Creates a directory whose entries are generated by `generate`, in
+    /// addition to the ones added by [`DynDir::add`].
+    ///
+    /// `generate` is called with the directory itself, which should be the
+    /// parent of the generated directories.
+    pub fn new_dynamic(
+        parent: Option<&VfsNodeRef>,
+        generate: impl Fn(&VfsNodeRef) -> Vec<(String, VfsNodeRef)> + Send + Sync + 'static,
+    ) -> Arc<Self> {
+        Self::new_inner(parent, Some(Box::new(generate)))
+    }
+
+    fn new_inner(parent: Option<&VfsNodeRef>, dynamic: Option<EntriesGenerator>) -> Arc<Self> {
+        Arc::new_cyclic(|this| Self {
+            this: this.clone(),
+            parent: Mutex::new(parent.map_or(Weak::<Self>::new() as _, Arc::downgrade)),
+            children: Mutex::new(BTreeMap::new()),
+            dynamic,
+        })
+    }
+
+    pub(crate) fn set_parent(&self, parent: Option<&VfsNodeRef>) {
+        *self.parent.lock() = parent.map_or(Weak::<Self>::new() as _, Arc::downgrade);
+    }
+
+    /// Creates a subdirectory with the given name.
+    pub fn mkdir(&self, name: &str) -> Arc<Self> {
+        let this = self.this.upgrade().map(|this| this as VfsNodeRef);
+        let dir = Self::new(this.as_ref());
+        self.add(name, dir.clone());
+        dir
+    }
+
+    /// Adds a node with the given name.
+    pub fn add(&self, name: &str, node: VfsNodeRef) {
+        self.children.lock().insert(name.into(), node);
+    }
+
+    /// Returns the generated entries, or an empty list if there are none.
+    fn dynamic_entries(&self) -> Vec<(String, VfsNodeRef)> {
+        match (&self.dynamic, self.this.upgrade()) {
+            (Some(generate), Some(this)) => generate(&(this as VfsNodeRef)),
+            _ => Vec::new(),
+        }
+    }
+
+    fn find(&self, name: &str) -> Option<VfsNodeRef> {
+        if let Some(node) = self.children.lock().get(name) {
+            return Some(node.clone());
+        }
+        self.dynamic_entries()
+            .into_iter()
+            .find_map(|(n, node)| (n == name).then_some(node))
+    }
+}
+
+impl VfsNodeOps for DynDir {
+    fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
+        let perm = VfsNodePerm::from_bits_truncate(0o555);
+        Ok(VfsNodeAttr::new(perm, VfsNodeType::Dir, 0, 0))
+    }
+
+    fn parent(&self) -> Option<VfsNodeRef> {
+        self.parent.lock().upgrade()
+    }
+
+    fn lookup(self: Arc<Self>, path: &str) -> VfsResult<VfsNodeRef> {
+        let (name, rest) = split_path(path);
+        let node = match name {
+            "" | "." => Ok(self.clone() as VfsNodeRef),
+            ".." => self.parent().ok_or(VfsError::NotFound),
+            _ => self.find(name).ok_or(VfsError::NotFound),
+        }?;
+
+        if let Some(rest) = rest {
+            node.lookup(rest)
+        } else {
+            Ok(node)
+        }
+    }
+
+    fn read_dir(&self, start_idx: usize, dirents: &mut [VfsDirEntry]) -> VfsResult<usize> {
+        let mut entries: Vec<(String, VfsNodeType)> = vec![
+            (".".into(), VfsNodeType::Dir),
+            ("..".into(), VfsNodeType::Dir),
+        ];
+        for (name, node) in self.children.lock().iter() {
+            entries.push((name.clone(), node.get_attr()?.file_type()));
+        }
+        for (name, node) in self.dynamic_entries() {
+            entries.push((name, node.get_attr()?.file_type()));
+        }
+
+        let mut entries = entries.into_iter().skip(start_idx);
+        for (i, ent) in dirents.iter_mut().enumerate() {
+            match entries.next() {
+                Some((name, ty)) => *ent = VfsDirEntry::new(&name, ty),
+                None => return Ok(i),
+            }
+        }
+        Ok(dirents.len())
+    }
+
+    fn create(&self, path: &str, ty: VfsNodeType) -> VfsResult {
+        let (name, rest) = split_path(path);
+        if let Some(rest) = rest {
+            match name {
+                "" | "." => self.create(rest, ty),
+                ".." => self.parent().ok_or(VfsError::NotFound)?.create(rest, ty),
+                _ => self.find(name).ok_or(VfsError::NotFound)?.create(rest, ty),
+            }
+        } else if name.is_empty() || name == "." || name == ".." {
+            Ok(()) // already exists
+        } else {
+            Err(VfsError::PermissionDenied) // the entries are fixed or generated
+        }
+    }
+
+    fn remove(&self, path: &str) -> VfsResult {
+        let (name, rest) = split_path(path);
+        if let Some(rest) = rest {
+            match name {
+                "" | "." => self.remove(rest),
+                ".." => self.parent().ok_or(VfsError::NotFound)?.remove(rest),
+                _ => self.find(name).ok_or(VfsError::NotFound)?.remove(rest),
+            }
+        } else {
+            Err(VfsError::PermissionDenied)
+        }
+    }
+
+    axfs_vfs::impl_vfs_dir_default! {}
+}
+
+/// Generates the content and copies the part starting at `offset` to `buf`.
+fn read_generated(generate: &Generator, offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
+    let content = generate()?;
+    let content = content.as_bytes();
+    let start = content.len().min(offset as usize);
+    let end = content.len().min(start + buf.len());
+    let src = &content[start..end];
+    buf[..src.len()].copy_from_slice(src);
+    Ok(src.len())
+}
+
+fn split_path(path: &str) -> (&str, Option<&str>) {
+    let trimmed_path = path.trim_start_matches('/');
+    trimmed_path.find('/').map_or((trimmed_path, None), |n| {
+        (&trimmed_path[..n], Some(&trimmed_path[n + 1..]))
+    })
+}
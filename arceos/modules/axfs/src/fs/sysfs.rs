@@ -0,0 +1,125 @@
+//! Files of sysfs, which expose the probed devices and kernel tunables.
+
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::{format, string::String, vec::Vec};
+
+use axdriver::{prelude::DeviceType, BusInfo, DeviceInfo};
+use axfs_vfs::{VfsError, VfsNodeRef, VfsResult};
+use axsync::Mutex;
+
+use super::pseudo::{DynDir, DynFile, DynSymlink};
+
+type Getter = fn() -> String;
+type Setter = fn(&str) -> VfsResult;
+
+/// Kernel tunables in `/sys/kernel`, by their names.
+static TUNABLES: Mutex<BTreeMap<&'static str, (Getter, Setter)>> = Mutex::new(BTreeMap::new());
+
+/// Adds a kernel tunable as `/sys/kernel/<name>`, replacing the one with the
+/// same name if any.
+pub fn add_tunable(name: &'static str, get: Getter, set: Setter) {
+    TUNABLES.lock().insert(name, (get, set));
+}
+
+/// Generates the files of the kernel tunables.
+pub fn tunable_files(_dir: &VfsNodeRef) -> Vec<(String, VfsNodeRef)> {
+    TUNABLES
+        .lock()
+        .iter()
+        .map(|(&name, &(get, set))| {
+            let file = DynFile::new_writable(move || Ok(get() + "\n"), set);
+            (String::from(name), file as VfsNodeRef)
+        })
+        .collect()
+}
+
+/// The `log_level` tunable, i.e. the maximum log level.
+pub fn log_level() -> String {
+    format!("{}", log::max_level()).to_lowercase()
+}
+
+/// Sets the `log_level` tunable, which is one of `off`, `error`, `warn`,
+/// `info`, `debug`, and `trace`.
+pub fn set_log_level(level: &str) -> VfsResult {
+    let level = level.trim().parse().map_err(|_| VfsError::InvalidInput)?;
+    log::set_max_level(level);
+    Ok(())
+}
+
+/// Returns the name of the clock source, as on Linux.
+pub const fn clocksource() -> &'static str {
+    if cfg!(target_arch = "x86_64") {
+        "tsc"
+    } else if cfg!(target_arch = "riscv64") {
+        "riscv_clocksource"
+    } else if cfg!(target_arch = "aarch64") {
+        "arch_sys_counter"
+    } else {
+        "unknown"
+    }
+}
+
+/// Returns the class of devices of the type, as on Linux.
+const fn class_name(ty: DeviceType) -> &'static str {
+    match ty {
+        DeviceType::Block => "block",
+        DeviceType::Net => "net",
+        DeviceType::Display => "graphics",
+        DeviceType::Char => "tty",
+        #[allow(unreachable_patterns)]
+        _ => "misc",
+    }
+}
+
+/// Adds a directory for each probed device to `devices`, and links to it in
+/// `bus/<bus>/devices` and `class/<class>`.
+pub fn add_devices(devices: &Arc<DynDir>, bus: &Arc<DynDir>, class: &Arc<DynDir>) {
+    let mut bus_dirs: BTreeMap<&str, Arc<DynDir>> = BTreeMap::new();
+    let mut class_dirs: BTreeMap<&str, Arc<DynDir>> = BTreeMap::new();
+    for (idx, info) in axdriver::devices().iter().enumerate() {
+        let id = info.bus_id(idx);
+        add_attrs(&devices.mkdir(&id), info);
+
+        let bus_name = info.bus.name();
+        let bus_devs = bus_dirs
+            .entry(bus_name)
+            .or_insert_with(|| bus.mkdir(bus_name).mkdir("devices"));
+        let target = format!("../../../devices/{}", id);
+        bus_devs.add(&id, DynSymlink::new(move || Ok(target.clone())));
+
+        let class_name = class_name(info.device_type);
+        let class_devs = class_dirs
+            .entry(class_name)
+            .or_insert_with(|| class.mkdir(class_name));
+        let target = format!("../../devices/{}", id);
+        class_devs.add(&id, DynSymlink::new(move || Ok(target.clone())));
+    }
+}
+
+/// Adds a file for each attribute of the device to its directory.
+fn add_attrs(dir: &DynDir, info: &DeviceInfo) {
+    let attr = |name: &str, value: String| {
+        dir.add(name, DynFile::new(move || Ok(format!("{}\n", value))));
+    };
+    attr("driver", info.name.clone());
+    attr("bus", info.bus.name().into());
+    attr("class", class_name(info.device_type).into());
+    match info.bus {
+        BusInfo::Platform => {}
+        BusInfo::Mmio { base, size } => {
+            attr("resource", format!("{:#x} {:#x}", base, base + size - 1));
+        }
+        BusInfo::Pci {
+            vendor_id,
+            device_id,
+            ..
+        } => {
+            attr("vendor", format!("{:#06x}", vendor_id));
+            attr("device", format!("{:#06x}", device_id));
+        }
+    }
+    if let Some(irq) = info.irq {
+        attr("irq", format!("{}", irq));
+    }
+}
@@ -0,0 +1,318 @@
+//! Unpacks the initramfs, a cpio (`newc`) or tar archive that is linked into
+//! the kernel image or loaded by the bootloader, into a ramfs.
+//!
+//! Only directories, regular files, symlinks and hard links are unpacked,
+//! other entries (e.g. device files) are skipped. Permissions and owners are
+//! not kept, as ramfs doesn't record them.
+
+use alloc::{collections::BTreeMap, format, string::String, sync::Arc, vec::Vec};
+use axerrno::{ax_err, AxError, AxResult};
+use axfs_vfs::{VfsNodeOps, VfsNodeRef, VfsNodeType};
+use core::{str, time::Duration};
+
+use crate::fs::ramfs::{DirNode, RamFileSystem};
+use crate::root::{link_node, set_node_times};
+
+const CPIO_MAGIC: &[u8] = b"070701";
+const CPIO_CRC_MAGIC: &[u8] = b"070702";
+const CPIO_TRAILER: &str = "TRAILER!!!";
+const TAR_BLOCK_SIZE: usize = 512;
+
+/// Loads the initramfs, or returns `None` if there is no archive or it's
+/// malformed.
+///
+/// The archive linked into the kernel image (given by the `AX_INITRAMFS`
+/// environment variable at build time) is unpacked first, then the one
+/// loaded by the bootloader, which can replace files in the former.
+pub(crate) fn load() -> Option<Arc<RamFileSystem>> {
+    let archives = [builtin_archive(), axhal::mem::initrd()];
+    if archives.iter().all(Option::is_none) {
+        return None;
+    }
+    let fs = Arc::new(RamFileSystem::new());
+    let root = fs.root_dir_node();
+    for archive in archives.into_iter().flatten() {
+        info!("  unpack initramfs ({} bytes)", archive.len());
+        if let Err(e) = unpack(&root, archive) {
+            warn!("failed to unpack initramfs: {:?}", e);
+            return None;
+        }
+    }
+    Some(fs)
+}
+
+/// Returns the archive linked into the kernel image.
+fn builtin_archive() -> Option<&'static [u8]> {
+    #[cfg(initramfs_path)]
+    return Some(include_bytes!(env!("AX_INITRAMFS_PATH")));
+    #[cfg(not(initramfs_path))]
+    None
+}
+
+/// Unpacks the cpio or tar archive `data` into the directory `root`. Several
+/// cpio archives can be concatenated, optionally with zeros between them.
+fn unpack(root: &Arc<DirNode>, data: &[u8]) -> AxResult {
+    if data.starts_with(CPIO_MAGIC) || data.starts_with(CPIO_CRC_MAGIC) {
+        unpack_cpio(root, data)
+    } else if data.get(257..262) == Some(&b"ustar"[..]) {
+        unpack_tar(root, data)
+    } else {
+        ax_err!(InvalidData, "unknown initramfs format")
+    }
+}
+
+/// The type of an archive entry.
+enum Entry<'a> {
+    Dir,
+    File(&'a [u8]),
+    Symlink(&'a str),
+    /// A hard link to the entry with the given path.
+    HardLink(&'a str),
+}
+
+fn unpack_cpio(root: &Arc<DirNode>, mut data: &[u8]) -> AxResult {
+    // hard links share an inode number, and the last of them has the data
+    let mut inodes = BTreeMap::<_, String>::new();
+    loop {
+        // skip the padding between archives
+        while data.len() >= 4 && data[..4] == [0; 4] {
+            data = &data[4..];
+        }
+        if data.iter().all(|&b| b == 0) {
+            return Ok(());
+        }
+        if data.len() < 110 || !(data.starts_with(CPIO_MAGIC) || data.starts_with(CPIO_CRC_MAGIC)) {
+            return ax_err!(InvalidData, "bad cpio header");
+        }
+        let field = |idx: usize| -> AxResult<u32> {
+            let hex = str::from_utf8(&data[6 + idx * 8..14 + idx * 8]);
+            hex.ok()
+                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                .ok_or(AxError::InvalidData)
+        };
+        let (ino, mode, nlink, mtime) = (field(0)?, field(1)?, field(4)?, field(5)? as u64);
+        let (file_size, dev) = (field(6)? as usize, (field(7)?, field(8)?));
+        let name_size = field(11)? as usize;
+
+        let name_end = 110 + name_size;
+        let data_start = align_up(name_end, 4);
+        let data_end = data_start + file_size;
+        if name_size == 0 || data_end > data.len() {
+            return ax_err!(InvalidData, "truncated cpio archive");
+        }
+        let name = str::from_utf8(&data[110..name_end - 1]).map_err(|_| AxError::InvalidData)?;
+        let content = &data[data_start..data_end];
+        data = &data[align_up(data_end, 4).min(data.len())..];
+        if name == CPIO_TRAILER {
+            inodes.clear();
+            continue;
+        }
+
+        let entry = match mode & 0o170000 {
+            0o040000 => Entry::Dir,
+            0o120000 => Entry::Symlink(str::from_utf8(content).map_err(|_| AxError::InvalidData)?),
+            0o100000 if nlink > 1 => match inodes.get(&(dev, ino)) {
+                Some(first) => {
+                    add_entry(root, name, Entry::HardLink(first), mtime)?;
+                    if !content.is_empty() {
+                        write_file(&lookup(root, name)?, content)?;
+                    }
+                    continue;
+                }
+                None => {
+                    inodes.insert((dev, ino), String::from(name));
+                    Entry::File(content)
+                }
+            },
+            0o100000 => Entry::File(content),
+            _ => {
+                warn!("initramfs: skip special file {:?}", name);
+                continue;
+            }
+        };
+        add_entry(root, name, entry, mtime)?;
+    }
+}
+
+fn unpack_tar(root: &Arc<DirNode>, mut data: &[u8]) -> AxResult {
+    // names given by GNU or pax extended headers for the next entry
+    let mut long_name = None;
+    let mut long_link = None;
+    while data.len() >= TAR_BLOCK_SIZE {
+        let header = &data[..TAR_BLOCK_SIZE];
+        if header.iter().all(|&b| b == 0) {
+            return Ok(()); // end of archive
+        }
+        let size = parse_octal(&header[124..136])? as usize;
+        let mtime = parse_octal(&header[136..148])?;
+        let data_end = TAR_BLOCK_SIZE + size;
+        if data_end > data.len() {
+            return ax_err!(InvalidData, "truncated tar archive");
+        }
+        let content = &data[TAR_BLOCK_SIZE..data_end];
+        data = &data[align_up(data_end, TAR_BLOCK_SIZE).min(data.len())..];
+
+        let typeflag = header[156];
+        match typeflag {
+            b'L' => {
+                long_name = Some(cstr(content)?);
+                continue;
+            }
+            b'K' => {
+                long_link = Some(cstr(content)?);
+                continue;
+            }
+            b'x' => {
+                for (key, value) in pax_records(content)? {
+                    match key {
+                        "path" => long_name = Some(value),
+                        "linkpath" => long_link = Some(value),
+                        _ => {}
+                    }
+                }
+                continue;
+            }
+            b'g' => continue,
+            _ => {}
+        }
+
+        let name = match long_name.take() {
+            Some(name) => String::from(name),
+            None => {
+                let (prefix, name) = (cstr(&header[345..500])?, cstr(&header[..100])?);
+                if prefix.is_empty() {
+                    String::from(name)
+                } else {
+                    format!("{}/{}", prefix, name)
+                }
+            }
+        };
+        let link = match long_link.take() {
+            Some(link) => link,
+            None => cstr(&header[157..257])?,
+        };
+        let entry = match typeflag {
+            b'0' | b'\0' | b'7' => Entry::File(content),
+            b'1' => Entry::HardLink(link),
+            b'2' => Entry::Symlink(link),
+            b'5' => Entry::Dir,
+            _ => {
+                warn!("initramfs: skip special file {:?}", name);
+                continue;
+            }
+        };
+        add_entry(root, &name, entry, mtime)?;
+    }
+    Ok(())
+}
+
+/// Creates the entry at `path` in `root`, with any missing parent
+/// directories. An existing file at `path` is replaced, while an existing
+/// directory is kept.
+fn add_entry(root: &Arc<DirNode>, path: &str, entry: Entry, mtime: u64) -> AxResult {
+    let path = path.trim_start_matches("./").trim_matches('/');
+    if path.is_empty() || path == "." {
+        return Ok(()); // the root directory
+    }
+    if path.split('/').any(|name| name == "..") {
+        return ax_err!(InvalidData, "initramfs path goes out of the root");
+    }
+    for (idx, _) in path.match_indices('/') {
+        create_dir(root, &path[..idx])?;
+    }
+
+    let exists = match lookup(root, path) {
+        Ok(node) => {
+            if matches!(entry, Entry::Dir) && node.get_attr()?.is_dir() {
+                true
+            } else {
+                root.remove(path)?;
+                false
+            }
+        }
+        Err(AxError::NotFound) => false,
+        Err(e) => return Err(e),
+    };
+    match entry {
+        Entry::Dir if exists => {}
+        Entry::Dir => root.create(path, VfsNodeType::Dir)?,
+        Entry::File(content) => {
+            root.create(path, VfsNodeType::File)?;
+            write_file(&lookup(root, path)?, content)?;
+        }
+        Entry::Symlink(target) => {
+            root.create(path, VfsNodeType::SymLink)?;
+            write_file(&lookup(root, path)?, target.as_bytes())?;
+        }
+        Entry::HardLink(target) => {
+            let node = lookup(root, target.trim_start_matches("./").trim_matches('/'))?;
+            let (parent, name) = match path.rsplit_once('/') {
+                Some((parent, name)) => (lookup(root, parent)?, name),
+                None => (root.clone() as VfsNodeRef, path),
+            };
+            link_node(&parent, name, &node)?;
+        }
+    }
+    let mtime = Some(Duration::from_secs(mtime));
+    set_node_times(&lookup(root, path)?, mtime, mtime)
+}
+
+fn create_dir(root: &Arc<DirNode>, path: &str) -> AxResult {
+    match lookup(root, path) {
+        Ok(node) if node.get_attr()?.is_dir() => Ok(()),
+        Ok(_) => ax_err!(NotADirectory),
+        Err(AxError::NotFound) => root.create(path, VfsNodeType::Dir),
+        Err(e) => Err(e),
+    }
+}
+
+fn lookup(root: &Arc<DirNode>, path: &str) -> AxResult<VfsNodeRef> {
+    root.clone().lookup(path)
+}
+
+fn write_file(node: &VfsNodeRef, content: &[u8]) -> AxResult {
+    if node.write_at(0, content)? != content.len() {
+        return ax_err!(StorageFull);
+    }
+    Ok(())
+}
+
+/// Parses the records (`<length> <key>=<value>\n`) of a pax extended header.
+fn pax_records(mut data: &[u8]) -> AxResult<Vec<(&str, &str)>> {
+    let mut records = Vec::new();
+    while !data.is_empty() && data[0] != 0 {
+        let space = data.iter().position(|&b| b == b' ');
+        let len = space
+            .and_then(|pos| str::from_utf8(&data[..pos]).ok())
+            .and_then(|len| len.parse::<usize>().ok())
+            .filter(|&len| len <= data.len() && len > space.unwrap() + 1)
+            .ok_or(AxError::InvalidData)?;
+        let record =
+            str::from_utf8(&data[space.unwrap() + 1..len - 1]).map_err(|_| AxError::InvalidData)?;
+        if let Some((key, value)) = record.split_once('=') {
+            records.push((key, value));
+        }
+        data = &data[len..];
+    }
+    Ok(records)
+}
+
+/// Parses a NUL or space terminated octal number in a tar header.
+fn parse_octal(field: &[u8]) -> AxResult<u64> {
+    let digits = str::from_utf8(field).map_err(|_| AxError::InvalidData)?;
+    let digits = digits.trim_matches(|c| c == '\0' || c == ' ');
+    if digits.is_empty() {
+        return Ok(0);
+    }
+    u64::from_str_radix(digits, 8).map_err(|_| AxError::InvalidData)
+}
+
+/// Returns the string before the first NUL in `field`.
+fn cstr(field: &[u8]) -> AxResult<&str> {
+    let len = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    str::from_utf8(&field[..len]).map_err(|_| AxError::InvalidData)
+}
+
+const fn align_up(pos: usize, align: usize) -> usize {
+    (pos + align - 1) & !(align - 1)
+}
@@ -6,10 +6,45 @@
 //!
 //! - `fatfs`: Use [FAT] as the main filesystem and mount it on `/`. This feature
 //!    is **enabled** by default.
-//! - `devfs`: Mount [`axfs_devfs::DeviceFileSystem`] on `/dev`. This feature is
-//!    **enabled** by default.
-//! - `ramfs`: Mount [`axfs_ramfs::RamFileSystem`] on `/tmp`. This feature is
+//! - `ext4fs`: Use [ext2/3/4][ext4] as the main filesystem and mount it on `/`.
+//!    If `fatfs` is also enabled, the root device is mounted as FAT unless it
+//!    holds an ext2/3/4 filesystem. This feature is **disabled** by default.
+//! - `devfs`: Mount [`axfs_devfs::DeviceFileSystem`] on `/dev`, with block
+//!    devices, network devices, `console`, `urandom`, and devices registered by
+//!    [`api::register_device`]. This feature is **enabled** by default.
+//! - `display`: Add the framebuffer of the main display as `/dev/fb0` to
+//!    devfs.
+//! - `ramfs`: Mount [`axfs_ramfs::RamFileSystem`] on `/tmp` as a tmpfs, which
+//!    can take up to half of the physical memory. Also allow mounting ramfs
+//!    and tmpfs at runtime, with limits given in [`api::MountOptions`]. This
+//!    feature is **enabled** by default.
+//! - `overlayfs`: Mount the main filesystem on `/` under an overlay with a
+//!    tmpfs on top, so that changes are kept in memory and the root device is
+//!    never modified. Files are copied to the tmpfs when they're modified, and
+//!    removed files are hidden by whiteouts. This feature is **disabled** by
+//!    default.
+//! - `initramfs`: Unpack the initramfs, a cpio (`newc`) or tar archive, into
+//!    a ramfs and use it as `/`, so that no block device is needed. The
+//!    archive is linked into the kernel image if the `AX_INITRAMFS`
+//!    environment variable gives its path at build time, or is loaded by the
+//!    bootloader (see [`axhal::mem::initrd`]). The root device is used if
+//!    there is no archive. This feature is **disabled** by default.
+//! - `procfs`: Mount a procfs on `/proc`, whose files (`meminfo`, `mounts`,
+//!    `uptime`, etc.) are generated from the live kernel state. This feature
+//!    is **enabled** by default.
+//! - `sysfs`: Mount a sysfs on `/sys`, which has a directory for each probed
+//!    device in `/sys/devices`, and kernel tunables in `/sys/kernel` that can
+//!    be written to change them (see [`api::add_tunable`]). This feature is
 //!    **enabled** by default.
+//! - `multitask`: Keep the current and root directories per task, instead of
+//!    sharing them among all tasks. Also add the `/proc/<tid>` directory for
+//!    each task, and `/proc/self` for the current task to procfs.
+//! - `irq`: Add `/proc/interrupts` to procfs, with the number of occurrences
+//!    of each IRQ.
+//! - `automount`: Mount FAT (and ext2/3/4 if `ext4fs` is enabled) filesystems
+//!    on block devices other than the root device on `/mnt/<label>` (or
+//!    `/mnt/<device name>` if not labeled). This feature is **disabled** by
+//!    default.
 //! - `myfs`: Allow users to define their custom filesystems to override the
 //!    default. In this case, [`MyFileSystemIf`] is required to be implemented
 //!    to create and initialize other filesystems. This feature is **disabled** by
@@ -17,6 +52,7 @@
 //!    both are enabled.
 //!
 //! [FAT]: https://en.wikipedia.org/wiki/File_Allocation_Table
+//! [ext4]: https://en.wikipedia.org/wiki/Ext4
 //! [`MyFileSystemIf`]: fops::MyFileSystemIf
 
 #![cfg_attr(all(not(test), not(doc)), no_std)]
@@ -26,21 +62,75 @@
 extern crate log;
 extern crate alloc;
 
+mod cache;
 mod dev;
 mod fs;
+#[cfg(feature = "initramfs")]
+mod initramfs;
+mod locks;
 mod mounts;
+mod notify;
+mod partition;
 mod root;
 
 pub mod api;
 pub mod fops;
 
+use alloc::{format, sync::Arc, vec::Vec};
 use axdriver::{prelude::*, AxDeviceContainer};
+use axsync::Mutex;
+use cache::BlockCache;
+use dev::{Disk, NamedDisk};
 
 /// Initializes filesystems by block devices.
+///
+/// All block devices are named `vda`, `vdb`, etc. in the order they were
+/// probed, and partitions on them are named `vda1`, `vda2`, etc. The disk or
+/// partition named by [`axconfig::ROOT_DEV`] is used for the root filesystem.
+/// If it names a partitioned disk, its first partition is used.
+///
+/// With the `initramfs` feature, the initramfs is used for the root filesystem
+/// instead if there is one, and `blk_devs` can be empty.
 pub fn init_filesystems(mut blk_devs: AxDeviceContainer<AxBlockDevice>) {
     info!("Initialize filesystems...");
 
-    let dev = blk_devs.take_one().expect("No block device found!");
-    info!("  use block device 0: {:?}", dev.device_name());
-    self::root::init_rootfs(self::dev::Disk::new(dev));
+    let mut disks = Vec::new();
+    let mut idx = 0;
+    while let Some(dev) = blk_devs.take_one() {
+        let name = self::dev::disk_name(idx);
+        info!(
+            "  use block device {}: {:?} as {}",
+            idx,
+            dev.device_name(),
+            name
+        );
+        idx += 1;
+
+        let cache = Arc::new(BlockCache::new(
+            Arc::new(Mutex::new(dev)),
+            self::cache::DEFAULT_CAPACITY,
+        ));
+        self::cache::register(cache.clone());
+        let disk = Disk::new(cache);
+        let parts = self::partition::parse_partitions(disk.device().as_ref());
+        for part in parts.iter() {
+            info!(
+                "    partition {}{}: start {}, {} blocks",
+                name, part.number, part.start_block, part.num_blocks
+            );
+        }
+        let partitioned = !parts.is_empty();
+        let part_disks = parts.into_iter().map(|part| NamedDisk {
+            name: format!("{}{}", name, part.number),
+            disk: disk.sub_disk(part.start_block, part.num_blocks),
+            partitioned: false,
+        });
+        let whole = NamedDisk {
+            name,
+            disk: disk.clone(),
+            partitioned,
+        };
+        disks.extend(core::iter::once(whole).chain(part_disks));
+    }
+    self::root::init_rootfs(disks);
 }
@@ -0,0 +1,397 @@
+//! Advisory file locks: whole-file locks as `flock`, and byte-range locks as
+//! the `F_SETLK`, `F_SETLKW` and `F_GETLK` commands of `fcntl`.
+//!
+//! The two kinds of locks don't affect each other, as on Linux. A whole-file
+//! lock is owned by an opened [`File`](crate::fops::File), and is released
+//! when it's closed. Byte-range locks are owned by a task, and are released
+//! when the task closes any file that refers to the same file, or exits.
+//!
+//! The locks of an opened file are used through a [`LockHandle`], which can
+//! wait for a lock without borrowing the file.
+
+use alloc::{string::String, sync::Arc, vec::Vec};
+use axerrno::{ax_err, AxError, AxResult};
+use axfs_vfs::VfsNodeRef;
+use axsync::Mutex;
+use cap_access::Cap;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// The type of an advisory lock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockType {
+    /// A shared (read) lock, which can be held by several owners.
+    Shared,
+    /// An exclusive (write) lock, which can be held by only one owner.
+    Exclusive,
+    /// No lock, to release the lock held.
+    Unlock,
+}
+
+/// A byte-range lock, as `struct flock`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileLock {
+    /// The type of the lock.
+    pub ty: LockType,
+    /// The offset of the first byte locked.
+    pub start: u64,
+    /// The number of bytes locked, or 0 for all bytes from `start`, even
+    /// those appended later.
+    pub len: u64,
+    /// The ID of the task that holds the lock, only set by
+    /// [`File::get_lock`](crate::fops::File::get_lock).
+    pub owner: u64,
+}
+
+impl FileLock {
+    /// Creates a lock of the given type on `len` bytes from `start`.
+    pub const fn new(ty: LockType, start: u64, len: u64) -> Self {
+        Self {
+            ty,
+            start,
+            len,
+            owner: 0,
+        }
+    }
+
+    /// Returns the end offset (exclusive) of the range.
+    fn end(&self) -> u64 {
+        match self.len {
+            0 => u64::MAX,
+            len => self.start.saturating_add(len),
+        }
+    }
+}
+
+/// Identifies the file that a lock is on.
+///
+/// Most filesystems keep one node for each file while it's opened, so the
+/// node is used. FAT creates a new node for each lookup, so its files are
+/// identified by their paths instead, if known.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum LockKey {
+    Node(usize),
+    #[cfg(all(feature = "fatfs", not(feature = "myfs")))]
+    Path(String),
+}
+
+impl LockKey {
+    /// Returns the key of the opened file `node`. `path` returns its absolute
+    /// path if known, and is only called for FAT files.
+    fn new(node: &VfsNodeRef, path: impl FnOnce() -> Option<String>) -> Self {
+        #[cfg(all(feature = "fatfs", not(feature = "myfs")))]
+        if node.as_any().is::<crate::fs::fatfs::FileWrapper<'static>>() {
+            if let Some(path) = path() {
+                return Self::Path(path);
+            }
+        }
+        let _ = path;
+        Self::Node(Arc::as_ptr(node) as *const () as usize)
+    }
+}
+
+/// The handle to the locks of an opened file, given by
+/// [`File::lock_handle`](crate::fops::File::lock_handle).
+///
+/// The whole-file lock is released when the file is closed, so the handle
+/// should not be used after that.
+#[derive(Debug, Clone)]
+pub struct LockHandle {
+    key: LockKey,
+    open_id: u64,
+    cap: Cap,
+}
+
+impl LockHandle {
+    /// Creates the handle of a file opened on `node` with `cap`. `path`
+    /// returns its absolute path if known.
+    pub(crate) fn new(node: &VfsNodeRef, cap: Cap, path: impl FnOnce() -> Option<String>) -> Self {
+        static NEXT_OPEN_ID: AtomicU64 = AtomicU64::new(1);
+        Self {
+            key: LockKey::new(node, path),
+            open_id: NEXT_OPEN_ID.fetch_add(1, Ordering::Relaxed),
+            cap,
+        }
+    }
+
+    /// See [`File::flock`](crate::fops::File::flock).
+    pub fn flock(&self, ty: LockType, wait: bool) -> AxResult {
+        let request = Lock {
+            key: self.key.clone(),
+            owner: Owner::File(self.open_id),
+            exclusive: ty == LockType::Exclusive,
+            start: 0,
+            end: u64::MAX,
+        };
+        set_lock(request, ty, wait)
+    }
+
+    /// See [`File::set_lock`](crate::fops::File::set_lock).
+    pub fn set_lock(&self, lock: &FileLock, wait: bool) -> AxResult {
+        let cap = match lock.ty {
+            LockType::Shared => Cap::READ,
+            LockType::Exclusive => Cap::WRITE,
+            LockType::Unlock => Cap::empty(),
+        };
+        if !self.cap.contains(cap) {
+            return ax_err!(PermissionDenied);
+        }
+        let owner = current_task_id();
+        #[cfg(feature = "multitask")]
+        if lock.ty != LockType::Unlock {
+            TaskLocks::attach(owner);
+        }
+        let request = Lock {
+            key: self.key.clone(),
+            owner: Owner::Task(owner),
+            exclusive: lock.ty == LockType::Exclusive,
+            start: lock.start,
+            end: lock.end(),
+        };
+        set_lock(request, lock.ty, wait)
+    }
+
+    /// See [`File::get_lock`](crate::fops::File::get_lock).
+    pub fn get_lock(&self, lock: &FileLock) -> AxResult<Option<FileLock>> {
+        get_posix_lock(&self.key, lock)
+    }
+
+    /// See [`File::release_locks`](crate::fops::File::release_locks).
+    pub fn release_locks(&self) {
+        let owner = Owner::Task(current_task_id());
+        release_if(|l| l.owner == owner && l.key == self.key);
+    }
+
+    /// Releases the whole-file lock and the byte-range locks of the current
+    /// task, when the file is closed.
+    pub(crate) fn release_all(&self) {
+        let (file, task) = (Owner::File(self.open_id), Owner::Task(current_task_id()));
+        release_if(|l| l.owner == file || (l.owner == task && l.key == self.key));
+    }
+}
+
+/// The owner of a lock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Owner {
+    /// An opened file, with its ID given by [`new_open_id`].
+    File(u64),
+    /// A task, with its ID.
+    Task(u64),
+}
+
+impl Owner {
+    fn same_kind(&self, other: &Owner) -> bool {
+        matches!(
+            (self, other),
+            (Owner::File(_), Owner::File(_)) | (Owner::Task(_), Owner::Task(_))
+        )
+    }
+}
+
+/// A lock held, or requested by [`set_lock`], on `start..end` of a file.
+struct Lock {
+    key: LockKey,
+    owner: Owner,
+    exclusive: bool,
+    start: u64,
+    end: u64,
+}
+
+impl Lock {
+    fn overlaps(&self, other: &Lock) -> bool {
+        self.key == other.key && self.start < other.end && other.start < self.end
+    }
+
+    /// Whether this lock prevents `other` from being acquired.
+    fn conflicts(&self, other: &Lock) -> bool {
+        self.overlaps(other)
+            && self.owner.same_kind(&other.owner)
+            && self.owner != other.owner
+            && (self.exclusive || other.exclusive)
+    }
+}
+
+static LOCKS: Mutex<Vec<Lock>> = Mutex::new(Vec::new());
+
+/// Increased whenever locks are released, so that waiters check again.
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+#[cfg(feature = "multitask")]
+static WAIT_QUEUE: axtask::WaitQueue = axtask::WaitQueue::new();
+
+/// Returns the ID of the current task, which owns its byte-range locks.
+fn current_task_id() -> u64 {
+    #[cfg(feature = "multitask")]
+    return axtask::current().id().as_u64();
+    #[cfg(not(feature = "multitask"))]
+    2 // `main` task ID
+}
+
+/// Returns the first byte-range lock that prevents the current task from
+/// acquiring `lock`, or `None` if there is none.
+fn get_posix_lock(key: &LockKey, lock: &FileLock) -> AxResult<Option<FileLock>> {
+    if lock.ty == LockType::Unlock {
+        return ax_err!(InvalidInput);
+    }
+    let request = Lock {
+        key: key.clone(),
+        owner: Owner::Task(current_task_id()),
+        exclusive: lock.ty == LockType::Exclusive,
+        start: lock.start,
+        end: lock.end(),
+    };
+    let locks = LOCKS.lock();
+    let Some(conflict) = locks.iter().find(|l| l.conflicts(&request)) else {
+        return Ok(None);
+    };
+    let Owner::Task(id) = conflict.owner else {
+        unreachable!()
+    };
+    let len = match conflict.end {
+        u64::MAX => 0,
+        end => end - conflict.start,
+    };
+    let ty = match conflict.exclusive {
+        true => LockType::Exclusive,
+        false => LockType::Shared,
+    };
+    Ok(Some(FileLock {
+        owner: id,
+        ..FileLock::new(ty, conflict.start, len)
+    }))
+}
+
+/// Acquires `request`, replacing the locks of its owner in the range, or
+/// only releases them if `ty` is [`LockType::Unlock`].
+fn set_lock(request: Lock, ty: LockType, wait: bool) -> AxResult {
+    if request.start >= request.end {
+        return ax_err!(InvalidInput);
+    }
+    loop {
+        let generation = GENERATION.load(Ordering::Acquire);
+        let mut locks = LOCKS.lock();
+        if ty != LockType::Unlock {
+            if locks.iter().any(|l| l.conflicts(&request)) {
+                if !wait {
+                    return ax_err!(WouldBlock);
+                }
+                drop(locks);
+                wait_for_release(generation)?;
+                continue;
+            }
+        }
+
+        let released = remove_range(&mut locks, &request);
+        if ty != LockType::Unlock {
+            locks.push(request);
+        }
+        drop(locks);
+        if released {
+            notify_released();
+        }
+        return Ok(());
+    }
+}
+
+/// Removes the range of `request` from the locks of its owner on the file,
+/// splitting the locks partly in the range. Returns whether any lock is
+/// changed.
+fn remove_range(locks: &mut Vec<Lock>, request: &Lock) -> bool {
+    let (start, end) = (request.start, request.end);
+    let mut changed = false;
+    let mut idx = 0;
+    while idx < locks.len() {
+        let l = &mut locks[idx];
+        if l.owner != request.owner || !l.overlaps(request) {
+            idx += 1;
+            continue;
+        }
+        changed = true;
+        if l.start < start && end < l.end {
+            // split into two
+            let tail = Lock {
+                key: l.key.clone(),
+                start: end,
+                ..*l
+            };
+            l.end = start;
+            locks.insert(idx + 1, tail);
+            idx += 2;
+        } else if l.start < start {
+            l.end = start;
+            idx += 1;
+        } else if end < l.end {
+            l.start = end;
+            idx += 1;
+        } else {
+            locks.remove(idx);
+        }
+    }
+    changed
+}
+
+/// Releases the locks that match `f`.
+fn release_if(f: impl Fn(&Lock) -> bool) {
+    let mut locks = LOCKS.lock();
+    let len = locks.len();
+    locks.retain(|l| !f(l));
+    let released = locks.len() != len;
+    drop(locks);
+    if released {
+        notify_released();
+    }
+}
+
+/// Releases all byte-range locks held by the task `id`.
+fn release_task(id: u64) {
+    release_if(|l| l.owner == Owner::Task(id));
+}
+
+fn notify_released() {
+    GENERATION.fetch_add(1, Ordering::Release);
+    #[cfg(feature = "multitask")]
+    WAIT_QUEUE.notify_all(false);
+}
+
+/// Blocks the current task until some locks are released after
+/// `generation`.
+///
+/// Fails with [`AxError::WouldBlock`] if the task is cancelled while waiting,
+/// as there's no error kind for `EINTR`.
+fn wait_for_release(generation: u64) -> AxResult {
+    #[cfg(feature = "multitask")]
+    {
+        WAIT_QUEUE
+            .wait_until_interruptible(|| GENERATION.load(Ordering::Acquire) != generation)
+            .map_err(|_| AxError::WouldBlock)
+    }
+    #[cfg(not(feature = "multitask"))]
+    {
+        // no other task can release the lock
+        let _ = generation;
+        ax_err!(WouldBlock)
+    }
+}
+
+/// Attached to a task that holds byte-range locks, to release them when the
+/// task is dropped. They're also released when it exits, as it may not be
+/// dropped until it's joined.
+#[cfg(feature = "multitask")]
+struct TaskLocks(u64);
+
+#[cfg(feature = "multitask")]
+impl TaskLocks {
+    fn attach(id: u64) {
+        let curr = axtask::current();
+        if curr.module_ext::<Self>().map_or(true, |ext| ext.0 != id) {
+            curr.set_module_ext(Arc::new(Self(id)));
+            curr.on_exit(move |_| release_task(id));
+        }
+    }
+}
+
+#[cfg(feature = "multitask")]
+impl Drop for TaskLocks {
+    fn drop(&mut self) {
+        release_task(self.0);
+    }
+}
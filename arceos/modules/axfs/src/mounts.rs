@@ -1,80 +1,261 @@
 use alloc::sync::Arc;
-use axfs_vfs::{VfsNodeType, VfsOps, VfsResult};
+use axerrno::{ax_err, AxError, AxResult};
+use axfs_vfs::VfsOps;
+use core::{fmt, str::FromStr};
 
+#[cfg(feature = "ramfs")]
+use crate::fops::FsStat;
 use crate::fs;
 
+/// The block size of RAM filesystems, which is also the memory for a node of
+/// tmpfs by default.
+#[cfg(feature = "ramfs")]
+const PAGE_SIZE: u64 = 4096;
+
+/// Options of a mounted filesystem.
+///
+/// They are parsed from and displayed as a comma-separated list, as in the
+/// options column of `/proc/mounts`, e.g. `ro,noexec,size=64m`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MountOptions {
+    /// Nothing on the filesystem can be created, removed, renamed or
+    /// modified (`ro`).
+    pub read_only: bool,
+    /// Files on the filesystem can't be opened for execution (`noexec`).
+    pub no_exec: bool,
+    /// Set-user-ID and set-group-ID bits are ignored (`nosuid`). There are no
+    /// such bits in [`FilePerm`](crate::fops::FilePerm), so it's only
+    /// recorded.
+    pub no_suid: bool,
+    /// The maximum total size of files in bytes (`size=`), only for
+    /// ramfs and tmpfs.
+    pub size: Option<u64>,
+    /// The maximum number of files, directories and symlinks (`nr_inodes=`),
+    /// only for ramfs and tmpfs.
+    pub nr_inodes: Option<u64>,
+}
+
+impl MountOptions {
+    /// Options of pseudo filesystems like devfs and procfs, which don't hold
+    /// programs.
+    pub(crate) const PSEUDO: Self = Self {
+        no_exec: true,
+        no_suid: true,
+        ..Self::new()
+    };
+
+    /// Creates the default options, i.e. read-write without limits.
+    pub const fn new() -> Self {
+        Self {
+            read_only: false,
+            no_exec: false,
+            no_suid: false,
+            size: None,
+            nr_inodes: None,
+        }
+    }
+}
+
+impl FromStr for MountOptions {
+    type Err = AxError;
+
+    /// Parses a comma-separated list of `ro`, `rw`, `noexec`, `exec`,
+    /// `nosuid`, `suid`, `size=<bytes>` and `nr_inodes=<number>`. Numbers can
+    /// have a `k`, `m` or `g` suffix.
+    fn from_str(s: &str) -> AxResult<Self> {
+        let mut opts = Self::default();
+        for opt in s.split(',').filter(|opt| !opt.is_empty()) {
+            match opt.split_once('=') {
+                None => match opt {
+                    "ro" => opts.read_only = true,
+                    "rw" => opts.read_only = false,
+                    "noexec" => opts.no_exec = true,
+                    "exec" => opts.no_exec = false,
+                    "nosuid" => opts.no_suid = true,
+                    "suid" => opts.no_suid = false,
+                    "defaults" => {}
+                    _ => return ax_err!(InvalidInput, "unknown mount option"),
+                },
+                Some(("size", size)) => opts.size = Some(parse_size(size)?),
+                Some(("nr_inodes", n)) => opts.nr_inodes = Some(parse_size(n)?),
+                Some(_) => return ax_err!(InvalidInput, "unknown mount option"),
+            }
+        }
+        Ok(opts)
+    }
+}
+
+impl fmt::Display for MountOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(if self.read_only { "ro" } else { "rw" })?;
+        if self.no_suid {
+            f.write_str(",nosuid")?;
+        }
+        if self.no_exec {
+            f.write_str(",noexec")?;
+        }
+        if let Some(size) = self.size {
+            write!(f, ",size={}k", size.div_ceil(1024))?;
+        }
+        if let Some(n) = self.nr_inodes {
+            write!(f, ",nr_inodes={}", n)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses a number with an optional `k`, `m` or `g` suffix.
+fn parse_size(s: &str) -> AxResult<u64> {
+    let (num, shift) = match s.as_bytes().last() {
+        Some(b'k' | b'K') => (&s[..s.len() - 1], 10),
+        Some(b'm' | b'M') => (&s[..s.len() - 1], 20),
+        Some(b'g' | b'G') => (&s[..s.len() - 1], 30),
+        _ => (s, 0),
+    };
+    num.parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(1 << shift))
+        .ok_or(AxError::InvalidInput)
+}
+
+/// Creates a new filesystem by its type name, for mounting at runtime.
+///
+/// Supported types are `ramfs`, `tmpfs` and `devfs`, if the corresponding
+/// features are enabled. Limits of tmpfs that are not given in `options` are
+/// set to the defaults, while ramfs is unlimited unless they're given.
+pub(crate) fn new_fs(fstype: &str, options: &mut MountOptions) -> AxResult<Arc<dyn VfsOps>> {
+    let limited = options.size.is_some() || options.nr_inodes.is_some();
+    if limited && !matches!(fstype, "ramfs" | "tmpfs") {
+        return ax_err!(InvalidInput, "only ramfs and tmpfs can be limited");
+    }
+    match fstype {
+        #[cfg(feature = "ramfs")]
+        "ramfs" => Ok(ramfs(options)),
+        #[cfg(feature = "ramfs")]
+        "tmpfs" => Ok(tmpfs(options)),
+        #[cfg(feature = "devfs")]
+        "devfs" => Ok(devfs()),
+        _ => ax_err!(Unsupported, "unknown filesystem type"),
+    }
+}
+
 #[cfg(feature = "devfs")]
 pub(crate) fn devfs() -> Arc<fs::devfs::DeviceFileSystem> {
-    let null = fs::devfs::NullDev;
-    let zero = fs::devfs::ZeroDev;
     let bar = fs::devfs::ZeroDev;
-    let devfs = fs::devfs::DeviceFileSystem::new();
+    let devfs = Arc::new(fs::devfs::DeviceFileSystem::new());
     let foo_dir = devfs.mkdir("foo");
-    devfs.add("null", Arc::new(null));
-    devfs.add("zero", Arc::new(zero));
     foo_dir.add("bar", Arc::new(bar));
-    Arc::new(devfs)
+    // other devices are from the registry
+    fs::devfs::attach(&devfs);
+    devfs
+}
+
+/// Creates a RAM filesystem limited by `options`.
+#[cfg(feature = "ramfs")]
+pub(crate) fn ramfs(options: &MountOptions) -> Arc<fs::ramfs::RamFileSystem> {
+    let ramfs = fs::ramfs::RamFileSystem::with_limits(options.size, options.nr_inodes);
+    Arc::new(ramfs)
 }
 
+/// Creates a tmpfs limited by `options`. By default, it can take half of the
+/// physical memory, and have a node for every two pages of it, as on Linux.
 #[cfg(feature = "ramfs")]
-pub(crate) fn ramfs() -> Arc<fs::ramfs::RamFileSystem> {
-    Arc::new(fs::ramfs::RamFileSystem::new())
+pub(crate) fn tmpfs(options: &mut MountOptions) -> Arc<fs::ramfs::RamFileSystem> {
+    let half_mem = axconfig::PHYS_MEMORY_SIZE as u64 / 2;
+    options.size.get_or_insert(half_mem);
+    options.nr_inodes.get_or_insert(half_mem / PAGE_SIZE);
+    ramfs(options)
+}
+
+/// Returns the usage of the RAM filesystem whose root directory is `root`.
+///
+/// Contents are allocated from the heap, so the free space is limited by the
+/// free memory as well as the limits of the filesystem. Without a limit of
+/// nodes, a node can be created for each free page.
+#[cfg(feature = "ramfs")]
+pub(crate) fn ramfs_stat(root: &fs::ramfs::DirNode) -> FsStat {
+    let alloc = axalloc::global_allocator();
+    let mem_free = (alloc.available_bytes() + alloc.available_pages() * PAGE_SIZE as usize) as u64;
+    let (size, nodes) = root.fs_usage();
+    let (max_size, max_nodes) = root.fs_limits();
+
+    let size_free = max_size.map_or(mem_free, |max| max.saturating_sub(size).min(mem_free));
+    let blocks_free = size_free / PAGE_SIZE;
+    let blocks = match max_size {
+        Some(max) => max.div_ceil(PAGE_SIZE),
+        None => size.div_ceil(PAGE_SIZE) + blocks_free,
+    };
+    let files_free = match max_nodes {
+        Some(max) => max.saturating_sub(nodes),
+        None => mem_free / PAGE_SIZE,
+    };
+    FsStat {
+        block_size: PAGE_SIZE,
+        blocks,
+        blocks_free,
+        files: max_nodes.unwrap_or(nodes + files_free),
+        files_free,
+        name_max: 255,
+        ..Default::default()
+    }
+}
+
+/// Creates an overlay of a tmpfs on top of `lower`, which keeps `lower`
+/// unmodified.
+#[cfg(feature = "overlayfs")]
+pub(crate) fn overlayfs(lower: Arc<dyn VfsOps>) -> Arc<fs::overlayfs::OverlayFileSystem> {
+    let upper = tmpfs(&mut MountOptions::new());
+    Arc::new(fs::overlayfs::OverlayFileSystem::new(lower, upper))
 }
 
 #[cfg(feature = "procfs")]
-pub(crate) fn procfs() -> VfsResult<Arc<fs::ramfs::RamFileSystem>> {
-    let procfs = fs::ramfs::RamFileSystem::new();
-    let proc_root = procfs.root_dir();
-
-    // Create /proc/sys/net/core/somaxconn
-    proc_root.create("sys", VfsNodeType::Dir)?;
-    proc_root.create("sys/net", VfsNodeType::Dir)?;
-    proc_root.create("sys/net/core", VfsNodeType::Dir)?;
-    proc_root.create("sys/net/core/somaxconn", VfsNodeType::File)?;
-    let file_somaxconn = proc_root.clone().lookup("./sys/net/core/somaxconn")?;
-    file_somaxconn.write_at(0, b"4096\n")?;
-
-    // Create /proc/sys/vm/overcommit_memory
-    proc_root.create("sys/vm", VfsNodeType::Dir)?;
-    proc_root.create("sys/vm/overcommit_memory", VfsNodeType::File)?;
-    let file_over = proc_root.clone().lookup("./sys/vm/overcommit_memory")?;
-    file_over.write_at(0, b"0\n")?;
-
-    // Create /proc/self/stat
-    proc_root.create("self", VfsNodeType::Dir)?;
-    proc_root.create("self/stat", VfsNodeType::File)?;
-
-    Ok(Arc::new(procfs))
+pub(crate) fn procfs() -> Arc<fs::pseudo::PseudoFileSystem> {
+    use fs::procfs;
+    use fs::pseudo::DynFile;
+
+    let proc_root = procfs::new_root();
+    proc_root.add("meminfo", DynFile::new(procfs::meminfo));
+    proc_root.add("mounts", DynFile::new(procfs::mounts));
+    proc_root.add("uptime", DynFile::new(procfs::uptime));
+    #[cfg(feature = "irq")]
+    proc_root.add("interrupts", DynFile::new(procfs::interrupts));
+    #[cfg(feature = "multitask")]
+    proc_root.add("self", fs::pseudo::DynSymlink::new(procfs::self_link));
+
+    // Create /proc/sys/net/core/somaxconn and /proc/sys/vm/overcommit_memory
+    let sys = proc_root.mkdir("sys");
+    let somaxconn = DynFile::new(|| Ok("4096\n".into()));
+    sys.mkdir("net").mkdir("core").add("somaxconn", somaxconn);
+    let overcommit = DynFile::new(|| Ok("0\n".into()));
+    sys.mkdir("vm").add("overcommit_memory", overcommit);
+
+    Arc::new(fs::pseudo::PseudoFileSystem::new(proc_root))
 }
 
 #[cfg(feature = "sysfs")]
-pub(crate) fn sysfs() -> VfsResult<Arc<fs::ramfs::RamFileSystem>> {
-    let sysfs = fs::ramfs::RamFileSystem::new();
-    let sys_root = sysfs.root_dir();
-
-    // Create /sys/kernel/mm/transparent_hugepage/enabled
-    sys_root.create("kernel", VfsNodeType::Dir)?;
-    sys_root.create("kernel/mm", VfsNodeType::Dir)?;
-    sys_root.create("kernel/mm/transparent_hugepage", VfsNodeType::Dir)?;
-    sys_root.create("kernel/mm/transparent_hugepage/enabled", VfsNodeType::File)?;
-    let file_hp = sys_root
-        .clone()
-        .lookup("./kernel/mm/transparent_hugepage/enabled")?;
-    file_hp.write_at(0, b"always [madvise] never\n")?;
+pub(crate) fn sysfs() -> Arc<fs::pseudo::PseudoFileSystem> {
+    use fs::pseudo::{DynDir, DynFile};
+    use fs::sysfs;
+
+    let sys_root = DynDir::new(None);
+    let devices = sys_root.mkdir("devices");
+    sysfs::add_devices(&devices, &sys_root.mkdir("bus"), &sys_root.mkdir("class"));
+
+    // Create /sys/kernel with the tunables
+    sysfs::add_tunable("log_level", sysfs::log_level, sysfs::set_log_level);
+    let parent = sys_root.clone() as axfs_vfs::VfsNodeRef;
+    let kernel = DynDir::new_dynamic(Some(&parent), sysfs::tunable_files);
+    sys_root.add("kernel", kernel.clone());
+    let hugepage = DynFile::new(|| Ok("always [madvise] never\n".into()));
+    let mm = kernel.mkdir("mm");
+    mm.mkdir("transparent_hugepage").add("enabled", hugepage);
 
     // Create /sys/devices/system/clocksource/clocksource0/current_clocksource
-    sys_root.create("devices", VfsNodeType::Dir)?;
-    sys_root.create("devices/system", VfsNodeType::Dir)?;
-    sys_root.create("devices/system/clocksource", VfsNodeType::Dir)?;
-    sys_root.create("devices/system/clocksource/clocksource0", VfsNodeType::Dir)?;
-    sys_root.create(
-        "devices/system/clocksource/clocksource0/current_clocksource",
-        VfsNodeType::File,
-    )?;
-    let file_cc = sys_root
-        .clone()
-        .lookup("devices/system/clocksource/clocksource0/current_clocksource")?;
-    file_cc.write_at(0, b"tsc\n")?;
-
-    Ok(Arc::new(sysfs))
+    let clocksource = DynFile::new(|| Ok(alloc::format!("{}\n", sysfs::clocksource())));
+    let clocksource_dir = devices.mkdir("system").mkdir("clocksource");
+    clocksource_dir
+        .mkdir("clocksource0")
+        .add("current_clocksource", clocksource);
+
+    Arc::new(fs::pseudo::PseudoFileSystem::new(sys_root))
 }
@@ -0,0 +1,409 @@
+//! Filesystem change notifications, as `inotify(7)`.
+//!
+//! A [`Watcher`] watches files and directories by their paths, and queues an
+//! event when a watched file is changed, or when an entry is created, removed
+//! or renamed in a watched directory. Renaming a watched file or any of its
+//! parents moves the watch along with it.
+//!
+//! The events are reported by the operations on paths and on opened files.
+//! Changes made through paths relative to an opened directory, whose absolute
+//! paths are unknown, or through other hard links of a watched file are not
+//! reported.
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::{string::String, vec::Vec};
+use axerrno::{ax_err, AxResult};
+use axsync::Mutex;
+use core::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
+
+bitflags::bitflags! {
+    /// Kinds of filesystem events and options of watches, with the values of
+    /// `inotify(7)`.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct WatchMask: u32 {
+        /// The file was read.
+        const ACCESS = 0x1;
+        /// The file was modified.
+        const MODIFY = 0x2;
+        /// The attributes of the file, such as timestamps, were changed.
+        const ATTRIB = 0x4;
+        /// The file opened for writing was closed.
+        const CLOSE_WRITE = 0x8;
+        /// The file not opened for writing was closed.
+        const CLOSE_NOWRITE = 0x10;
+        /// The file was opened.
+        const OPEN = 0x20;
+        /// An entry was renamed out of the directory.
+        const MOVED_FROM = 0x40;
+        /// An entry was renamed into the directory.
+        const MOVED_TO = 0x80;
+        /// An entry was created in the directory.
+        const CREATE = 0x100;
+        /// An entry was removed from the directory.
+        const DELETE = 0x200;
+        /// The watched file or directory itself was removed.
+        const DELETE_SELF = 0x400;
+        /// The watched file or directory itself was renamed.
+        const MOVE_SELF = 0x800;
+        /// All events above.
+        const ALL_EVENTS = 0xfff;
+
+        /// Events were dropped as the queue is full. Only set in events.
+        const Q_OVERFLOW = 0x4000;
+        /// The watch was removed. Only set in events.
+        const IGNORED = 0x8000;
+        /// The subject of the event is a directory. Only set in events.
+        const ISDIR = 0x4000_0000;
+
+        /// Only watch the path if it's a directory.
+        const ONLYDIR = 0x0100_0000;
+        /// Don't follow the path if it's a symlink.
+        const DONT_FOLLOW = 0x0200_0000;
+        /// Add the events to the watch of the same path, instead of replacing
+        /// them.
+        const MASK_ADD = 0x2000_0000;
+        /// Remove the watch after its first event.
+        const ONESHOT = 0x8000_0000;
+    }
+}
+
+/// A filesystem event, as `struct inotify_event`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FsEvent {
+    /// The watch descriptor of the watch, or -1 for
+    /// [`Q_OVERFLOW`](WatchMask::Q_OVERFLOW).
+    pub wd: i32,
+    /// The kind of the event.
+    pub mask: WatchMask,
+    /// The same nonzero value for the [`MOVED_FROM`](WatchMask::MOVED_FROM)
+    /// and [`MOVED_TO`](WatchMask::MOVED_TO) events of a rename, otherwise 0.
+    pub cookie: u32,
+    /// The name of the entry in the watched directory, or empty if the event
+    /// is on the watched file or directory itself.
+    pub name: String,
+}
+
+impl FsEvent {
+    fn new(wd: i32, mask: WatchMask, cookie: u32, name: &str) -> Self {
+        Self {
+            wd,
+            mask,
+            cookie,
+            name: name.into(),
+        }
+    }
+}
+
+/// The maximum number of events queued for a watcher, as the default
+/// `max_queued_events` of Linux.
+const MAX_QUEUED_EVENTS: usize = 16384;
+
+struct Watch {
+    wd: i32,
+    path: String,
+    mask: WatchMask,
+}
+
+#[derive(Default)]
+struct WatcherState {
+    watches: Vec<Watch>,
+    events: VecDeque<FsEvent>,
+    last_wd: i32,
+}
+
+impl WatcherState {
+    /// Queues `event`, unless it's the same as the last one.
+    fn push(&mut self, event: FsEvent) {
+        if self.events.back() == Some(&event) {
+            return;
+        }
+        if self.events.len() >= MAX_QUEUED_EVENTS {
+            if self.events.back().unwrap().mask != WatchMask::Q_OVERFLOW {
+                let overflow = FsEvent::new(-1, WatchMask::Q_OVERFLOW, 0, "");
+                self.events.push_back(overflow);
+            }
+            return;
+        }
+        self.events.push_back(event);
+    }
+
+    /// Queues an event of `mask` for each watch on `path` that watches it.
+    fn report(&mut self, path: &str, mask: WatchMask, cookie: u32, name: &str) {
+        let kind = mask - WatchMask::ISDIR;
+        let watches: Vec<_> = self
+            .watches
+            .iter()
+            .filter(|w| w.path == path && w.mask.intersects(kind))
+            .map(|w| (w.wd, w.mask.contains(WatchMask::ONESHOT)))
+            .collect();
+        for (wd, oneshot) in watches {
+            self.push(FsEvent::new(wd, mask, cookie, name));
+            if oneshot {
+                self.remove(wd);
+            }
+        }
+    }
+
+    /// Reports an event of `mask` on the entry at `path`, to the watches on
+    /// its parent directory and on itself.
+    fn report_entry(&mut self, path: &str, mask: WatchMask, cookie: u32) {
+        if let Some((parent, name)) = split_path(path) {
+            self.report(parent, mask, cookie, name);
+        }
+        let entry_events =
+            WatchMask::CREATE | WatchMask::DELETE | WatchMask::MOVED_FROM | WatchMask::MOVED_TO;
+        if !mask.intersects(entry_events) {
+            self.report(path, mask, 0, "");
+        }
+    }
+
+    /// Removes the watches on `path` as it's removed.
+    fn remove_path(&mut self, path: &str) {
+        self.report(path, WatchMask::DELETE_SELF, 0, "");
+        let watches: Vec<_> = self
+            .watches
+            .iter()
+            .filter(|w| w.path == path)
+            .map(|w| w.wd)
+            .collect();
+        for wd in watches {
+            self.remove(wd);
+        }
+    }
+
+    /// Moves the watches on `old` and on the paths under it to `new`.
+    fn move_path(&mut self, old: &str, new: &str) {
+        self.report(old, WatchMask::MOVE_SELF, 0, "");
+        for watch in &mut self.watches {
+            if let Some(rest) = watch.path.strip_prefix(old) {
+                if rest.is_empty() || rest.starts_with('/') {
+                    watch.path = String::from(new) + rest;
+                }
+            }
+        }
+    }
+
+    /// Removes the watch `wd` and queues [`WatchMask::IGNORED`] for it.
+    /// Returns `false` if there's no such watch.
+    fn remove(&mut self, wd: i32) -> bool {
+        match self.watches.iter().position(|w| w.wd == wd) {
+            Some(idx) => {
+                self.watches.remove(idx);
+                self.push(FsEvent::new(wd, WatchMask::IGNORED, 0, ""));
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+static WATCHERS: Mutex<BTreeMap<u64, WatcherState>> = Mutex::new(BTreeMap::new());
+
+/// The number of watchers, to skip reporting events when there's none.
+static NUM_WATCHERS: AtomicUsize = AtomicUsize::new(0);
+
+/// Increased each time events are queued.
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+#[cfg(feature = "multitask")]
+static WAIT_QUEUE: axtask::WaitQueue = axtask::WaitQueue::new();
+
+/// A set of watches on files and directories, and the queue of their events.
+///
+/// The watches are removed when it's dropped.
+pub struct Watcher {
+    id: u64,
+}
+
+impl Watcher {
+    /// Creates a watcher without watches.
+    pub fn new() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        WATCHERS.lock().insert(id, WatcherState::default());
+        NUM_WATCHERS.fetch_add(1, Ordering::Relaxed);
+        Self { id }
+    }
+
+    /// Watches the file or directory at `path` for the events in `mask`, as
+    /// `inotify_add_watch(2)`. Returns the watch descriptor.
+    ///
+    /// If the path is already watched, the events of the watch are replaced,
+    /// or extended if `mask` has [`MASK_ADD`](WatchMask::MASK_ADD), and its
+    /// descriptor is returned.
+    pub fn add_watch(&self, path: &str, mask: WatchMask) -> AxResult<i32> {
+        if !mask.intersects(WatchMask::ALL_EVENTS) {
+            return ax_err!(InvalidInput);
+        }
+        let follow = !mask.contains(WatchMask::DONT_FOLLOW);
+        let (node, path) = crate::root::lookup_with_path(None, path, follow)?;
+        if mask.contains(WatchMask::ONLYDIR) && !node.get_attr()?.is_dir() {
+            return ax_err!(NotADirectory);
+        }
+        // paths relative to the root are always known
+        let path = path.unwrap();
+
+        let mut watchers = WATCHERS.lock();
+        let state = watchers.get_mut(&self.id).unwrap();
+        let new_mask = mask & (WatchMask::ALL_EVENTS | WatchMask::ONESHOT);
+        if let Some(watch) = state.watches.iter_mut().find(|w| w.path == path) {
+            if mask.contains(WatchMask::MASK_ADD) {
+                watch.mask |= new_mask;
+            } else {
+                watch.mask = new_mask;
+            }
+            return Ok(watch.wd);
+        }
+        state.last_wd += 1;
+        let wd = state.last_wd;
+        state.watches.push(Watch {
+            wd,
+            path,
+            mask: new_mask,
+        });
+        Ok(wd)
+    }
+
+    /// Removes the watch `wd`, as `inotify_rm_watch(2)`. An
+    /// [`IGNORED`](WatchMask::IGNORED) event is queued for it.
+    pub fn remove_watch(&self, wd: i32) -> AxResult {
+        let removed = WATCHERS.lock().get_mut(&self.id).unwrap().remove(wd);
+        if !removed {
+            return ax_err!(InvalidInput);
+        }
+        notify_waiters();
+        Ok(())
+    }
+
+    /// Returns whether there are events to read.
+    pub fn has_events(&self) -> bool {
+        !WATCHERS.lock()[&self.id].events.is_empty()
+    }
+
+    /// Reads the queued events in order, passing each to `take` until it
+    /// returns `false`, which leaves the event in the queue. Returns the
+    /// number of events read.
+    ///
+    /// If there are no events, blocks until some are queued if `wait` is
+    /// true, otherwise returns [`WouldBlock`](axerrno::AxError::WouldBlock).
+    pub fn read_events(
+        &self,
+        wait: bool,
+        mut take: impl FnMut(&FsEvent) -> bool,
+    ) -> AxResult<usize> {
+        loop {
+            let mut watchers = WATCHERS.lock();
+            let events = &mut watchers.get_mut(&self.id).unwrap().events;
+            if !events.is_empty() {
+                let mut n = 0;
+                while events.front().is_some_and(&mut take) {
+                    events.pop_front();
+                    n += 1;
+                }
+                return Ok(n);
+            }
+            let generation = GENERATION.load(Ordering::Acquire);
+            drop(watchers);
+            if !wait {
+                return ax_err!(WouldBlock);
+            }
+            wait_for_events(generation)?;
+        }
+    }
+}
+
+impl Default for Watcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        WATCHERS.lock().remove(&self.id);
+        NUM_WATCHERS.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Splits the absolute `path` into its parent and its last component, or
+/// returns `None` if it's the root.
+fn split_path(path: &str) -> Option<(&str, &str)> {
+    match path.rfind('/')? {
+        0 if path.len() == 1 => None,
+        0 => Some(("/", &path[1..])),
+        idx => Some((&path[..idx], &path[idx + 1..])),
+    }
+}
+
+/// Returns whether there are any watchers, so that events should be
+/// reported.
+pub(crate) fn is_watching() -> bool {
+    NUM_WATCHERS.load(Ordering::Relaxed) > 0
+}
+
+/// Reports an event of `mask` on the file or directory at the absolute
+/// `path`, which is one of the events in [`ALL_EVENTS`](WatchMask::ALL_EVENTS)
+/// except the ones of renames and `*_SELF`, with [`ISDIR`](WatchMask::ISDIR)
+/// if it's a directory.
+pub(crate) fn notify(path: &str, mask: WatchMask) {
+    if !is_watching() {
+        return;
+    }
+    let mut watchers = WATCHERS.lock();
+    for state in watchers.values_mut() {
+        state.report_entry(path, mask, 0);
+        if mask.contains(WatchMask::DELETE) {
+            state.remove_path(path);
+        }
+    }
+    drop(watchers);
+    notify_waiters();
+}
+
+/// Reports that the file or directory at the absolute path `old` was renamed
+/// to `new`, replacing the file there if `replaced` is true.
+pub(crate) fn notify_rename(old: &str, new: &str, is_dir: bool, replaced: bool) {
+    if !is_watching() {
+        return;
+    }
+    static NEXT_COOKIE: AtomicU32 = AtomicU32::new(1);
+    let cookie = NEXT_COOKIE.fetch_add(1, Ordering::Relaxed);
+    let isdir = if is_dir {
+        WatchMask::ISDIR
+    } else {
+        WatchMask::empty()
+    };
+    let mut watchers = WATCHERS.lock();
+    for state in watchers.values_mut() {
+        if replaced {
+            state.remove_path(new);
+        }
+        state.report_entry(old, WatchMask::MOVED_FROM | isdir, cookie);
+        state.report_entry(new, WatchMask::MOVED_TO | isdir, cookie);
+        state.move_path(old, new);
+    }
+    drop(watchers);
+    notify_waiters();
+}
+
+fn notify_waiters() {
+    GENERATION.fetch_add(1, Ordering::Release);
+    #[cfg(feature = "multitask")]
+    WAIT_QUEUE.notify_all(false);
+}
+
+/// Blocks the current task until some events are queued after
+/// `generation`.
+fn wait_for_events(generation: u64) -> AxResult {
+    #[cfg(feature = "multitask")]
+    {
+        WAIT_QUEUE.wait_until(|| GENERATION.load(Ordering::Acquire) != generation);
+        Ok(())
+    }
+    #[cfg(not(feature = "multitask"))]
+    {
+        // no other task can make changes
+        let _ = generation;
+        ax_err!(WouldBlock)
+    }
+}
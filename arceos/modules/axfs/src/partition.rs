@@ -0,0 +1,215 @@
+//! Partition table parsing.
+//!
+//! Both the MBR (including logical partitions in an extended partition) and
+//! the GPT partition schemes are supported. Partitions are numbered the same
+//! way as Linux: primary MBR partitions are `1`-`4`, logical partitions start
+//! from `5`, and GPT partitions are numbered by their slots in the partition
+//! entry array.
+
+use alloc::{vec, vec::Vec};
+
+use crate::dev::BlockDevice;
+
+/// Size of the MBR and EBRs, the block size must be at least this large.
+const SECTOR_SIZE: usize = 512;
+
+/// Maximum number of logical partitions to follow in an extended partition,
+/// in case the EBR chain is looped.
+const MAX_LOGICAL_PARTITIONS: usize = 128;
+/// Maximum number of GPT partition entries to scan.
+const MAX_GPT_ENTRIES: u32 = 256;
+
+const MBR_TYPE_GPT_PROTECTIVE: u8 = 0xee;
+const MBR_TYPES_EXTENDED: [u8; 3] = [0x05, 0x0f, 0x85];
+
+/// A partition found in the partition table of a disk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Partition {
+    /// The partition number, starting from 1.
+    pub number: usize,
+    /// The first block of the partition.
+    pub start_block: u64,
+    /// The number of blocks in the partition.
+    pub num_blocks: u64,
+}
+
+struct MbrEntry {
+    ty: u8,
+    start_block: u64,
+    num_blocks: u64,
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(buf: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(buf[offset..offset + 8].try_into().unwrap())
+}
+
+/// Parses the four partition entries of an MBR or EBR, returns `None` if the
+/// block does not contain a valid partition table.
+fn mbr_entries(block: &[u8]) -> Option<[MbrEntry; 4]> {
+    if block[510] != 0x55 || block[511] != 0xaa {
+        return None;
+    }
+    let entries = core::array::from_fn(|i| {
+        let entry = &block[0x1be + i * 16..0x1be + (i + 1) * 16];
+        (
+            entry[0],
+            MbrEntry {
+                ty: entry[4],
+                start_block: read_u32(entry, 8) as u64,
+                num_blocks: read_u32(entry, 12) as u64,
+            },
+        )
+    });
+    // the boot indicator must be 0x00 or 0x80
+    if entries.iter().any(|(status, _)| *status & 0x7f != 0) {
+        return None;
+    }
+    Some(entries.map(|(_, entry)| entry))
+}
+
+/// Returns `true` if the block is the boot sector of a FAT filesystem that
+/// occupies the whole disk.
+///
+/// A FAT boot sector also ends with `0x55AA`, and its boot code may look like
+/// a partition table.
+fn is_fat_boot_sector(block: &[u8]) -> bool {
+    &block[0x36..0x39] == b"FAT" || &block[0x52..0x55] == b"FAT"
+}
+
+/// Checks that the partition lies within the disk, and adds it to `parts`.
+fn push_partition(parts: &mut Vec<Partition>, disk_blocks: u64, part: Partition) {
+    match part.start_block.checked_add(part.num_blocks) {
+        Some(end) if part.num_blocks > 0 && part.start_block > 0 && end <= disk_blocks => {
+            parts.push(part)
+        }
+        _ => warn!("partition {:?} is out of the disk, ignored", part),
+    }
+}
+
+/// Parses the partition table of `dev`.
+///
+/// Returns an empty list if the device is not partitioned.
+pub(crate) fn parse_partitions(dev: &dyn BlockDevice) -> Vec<Partition> {
+    let disk_blocks = dev.num_blocks();
+    if dev.block_size() < SECTOR_SIZE {
+        return Vec::new();
+    }
+    let mut block = vec![0u8; dev.block_size()];
+    if dev.read_blocks(0, &mut block).is_err() || is_fat_boot_sector(&block) {
+        return Vec::new();
+    }
+    let Some(entries) = mbr_entries(&block) else {
+        return Vec::new();
+    };
+    if entries.iter().any(|e| e.ty == MBR_TYPE_GPT_PROTECTIVE) {
+        return parse_gpt(dev, disk_blocks).unwrap_or_default();
+    }
+
+    let mut parts = Vec::new();
+    let mut extended = None;
+    for (i, entry) in entries.iter().enumerate() {
+        if entry.ty == 0 {
+            continue;
+        }
+        if MBR_TYPES_EXTENDED.contains(&entry.ty) {
+            extended.get_or_insert(entry.start_block);
+            continue;
+        }
+        let part = Partition {
+            number: i + 1,
+            start_block: entry.start_block,
+            num_blocks: entry.num_blocks,
+        };
+        push_partition(&mut parts, disk_blocks, part);
+    }
+    if let Some(ext_start) = extended {
+        parse_logical_partitions(dev, disk_blocks, ext_start, &mut parts);
+    }
+    parts
+}
+
+/// Follows the EBR chain of the extended partition starting at `ext_start`.
+fn parse_logical_partitions(
+    dev: &dyn BlockDevice,
+    disk_blocks: u64,
+    ext_start: u64,
+    parts: &mut Vec<Partition>,
+) {
+    let mut block = vec![0u8; dev.block_size()];
+    let mut ebr = ext_start;
+    for i in 0..MAX_LOGICAL_PARTITIONS {
+        if ebr >= disk_blocks || dev.read_blocks(ebr, &mut block).is_err() {
+            warn!("failed to read EBR at block {}", ebr);
+            return;
+        }
+        let Some([logical, next, ..]) = mbr_entries(&block) else {
+            warn!("invalid EBR at block {}", ebr);
+            return;
+        };
+        if logical.ty != 0 {
+            // the logical partition is relative to this EBR
+            let part = Partition {
+                number: 5 + i,
+                start_block: ebr + logical.start_block,
+                num_blocks: logical.num_blocks,
+            };
+            push_partition(parts, disk_blocks, part);
+        }
+        if next.ty == 0 || next.start_block == 0 {
+            return;
+        }
+        // the next EBR is relative to the extended partition
+        ebr = ext_start + next.start_block;
+    }
+    warn!("too many logical partitions, the rest are ignored");
+}
+
+/// Parses the GPT header at LBA 1 and its partition entries.
+fn parse_gpt(dev: &dyn BlockDevice, disk_blocks: u64) -> Option<Vec<Partition>> {
+    let block_size = dev.block_size();
+    let mut block = vec![0u8; block_size];
+    dev.read_blocks(1, &mut block).ok()?;
+    if &block[0..8] != b"EFI PART" {
+        warn!("protective MBR found but the GPT header is invalid");
+        return None;
+    }
+    let entries_lba = read_u64(&block, 72);
+    let num_entries = read_u32(&block, 80);
+    let entry_size = read_u32(&block, 84) as usize;
+    if entry_size < 128 || entry_size > block_size || block_size % entry_size != 0 {
+        warn!("unsupported GPT partition entry size {}", entry_size);
+        return None;
+    }
+
+    let entries_per_block = block_size / entry_size;
+    let mut parts = Vec::new();
+    for i in 0..num_entries.min(MAX_GPT_ENTRIES) as usize {
+        if i % entries_per_block == 0 {
+            let lba = entries_lba + (i / entries_per_block) as u64;
+            if lba >= disk_blocks {
+                return None;
+            }
+            dev.read_blocks(lba, &mut block).ok()?;
+        }
+        let entry = &block[(i % entries_per_block) * entry_size..][..entry_size];
+        if entry[0..16].iter().all(|&b| b == 0) {
+            continue; // unused entry
+        }
+        let (first_lba, last_lba) = (read_u64(entry, 32), read_u64(entry, 40));
+        if last_lba < first_lba {
+            warn!("invalid GPT partition entry {}", i);
+            continue;
+        }
+        let part = Partition {
+            number: i + 1,
+            start_block: first_lba,
+            num_blocks: last_lba - first_lba + 1,
+        };
+        push_partition(&mut parts, disk_blocks, part);
+    }
+    Some(parts)
+}
@@ -1,74 +1,237 @@
 //! Root directory of the filesystem
 //!
-//! TODO: it doesn't work very well if the mount points have containment relationships.
+//! Mount points are matched component-wise, and may be nested inside other
+//! mounted filesystems (e.g. a ramfs at `/tmp/cache` on top of the ramfs at
+//! `/tmp`). Paths are walked component by component, following symlinks
+//! (which may lead to other filesystems). `..` is resolved lexically on the
+//! path walked so far, so it crosses mount boundaries as expected.
 
-use alloc::{string::String, sync::Arc, vec::Vec};
+use crate::alloc::string::ToString;
+use alloc::{borrow::Cow, string::String, sync::Arc, vec, vec::Vec};
 use axerrno::{ax_err, AxError, AxResult};
 use axfs_vfs::{VfsNodeAttr, VfsNodeOps, VfsNodeRef, VfsNodeType, VfsOps, VfsResult};
 use axsync::Mutex;
+use core::time::Duration;
 use lazyinit::LazyInit;
-use crate::alloc::string::ToString;
 
-use crate::{api::FileType, fs, mounts};
+#[cfg(feature = "devfs")]
+use crate::dev::BlockDevNode;
+#[cfg(not(feature = "myfs"))]
+use crate::dev::Disk;
+use crate::mounts::{self, MountOptions};
+use crate::notify::{self, WatchMask};
+use crate::{api::FileType, dev::NamedDisk, fops::FileTimes, fops::FsStat, fs};
+
+/// The context of the task that has not set one, or of all tasks if
+/// `multitask` is disabled.
+#[cfg(not(feature = "multitask"))]
+static FS_CONTEXT: Mutex<Option<Arc<FsContext>>> = Mutex::new(None);
+
+/// The root directory and the current directory of a task.
+///
+/// With `multitask`, each task has its own context, which is inherited from
+/// the task that creates it.
+#[derive(Clone)]
+struct FsContext {
+    /// The absolute path of the root directory, which ends with `/` only if
+    /// it's `/`.
+    root: String,
+    /// The path of the current directory seen from the root, which always
+    /// ends with `/`.
+    cwd: String,
+}
 
-static CURRENT_DIR_PATH: Mutex<String> = Mutex::new(String::new());
-static CURRENT_DIR: LazyInit<Mutex<VfsNodeRef>> = LazyInit::new();
+impl Default for FsContext {
+    fn default() -> Self {
+        Self {
+            root: "/".into(),
+            cwd: "/".into(),
+        }
+    }
+}
 
-struct MountPoint {
-    path: &'static str,
+/// A filesystem mounted on a directory.
+///
+/// Open files and directories on the filesystem hold a reference to its mount
+/// point, so that it can't be unmounted while it's still in use.
+pub(crate) struct MountPoint {
+    path: String,
     fs: Arc<dyn VfsOps>,
+    /// The device or the name the filesystem is mounted from.
+    #[cfg_attr(not(feature = "procfs"), allow(dead_code))]
+    source: String,
+    fstype: String,
+    options: MountOptions,
 }
 
 struct RootDirectory {
     main_fs: Arc<dyn VfsOps>,
-    mounts: Vec<MountPoint>,
+    #[cfg_attr(not(feature = "procfs"), allow(dead_code))]
+    main_source: String,
+    main_fstype: String,
+    main_options: MountOptions,
+    mounts: Mutex<Vec<Arc<MountPoint>>>,
+}
+
+/// A mounted filesystem, as listed in `/proc/mounts`.
+#[cfg(feature = "procfs")]
+pub(crate) struct MountInfo {
+    pub source: String,
+    pub path: String,
+    pub fstype: String,
+    pub options: MountOptions,
 }
 
 static ROOT_DIR: LazyInit<Arc<RootDirectory>> = LazyInit::new();
 
 impl MountPoint {
-    pub fn new(path: &'static str, fs: Arc<dyn VfsOps>) -> Self {
-        Self { path, fs }
+    pub fn new(
+        path: String,
+        fs: Arc<dyn VfsOps>,
+        source: String,
+        fstype: String,
+        options: MountOptions,
+    ) -> Self {
+        Self {
+            path,
+            fs,
+            source,
+            fstype,
+            options,
+        }
+    }
+
+    /// Returns the number of components of the mount path if `components`
+    /// is inside this mount point.
+    fn match_components(&self, components: &[&str]) -> Option<usize> {
+        let mut depth = 0;
+        for name in self.path.split('/').filter(|s| !s.is_empty()) {
+            if components.get(depth) != Some(&name) {
+                return None;
+            }
+            depth += 1;
+        }
+        Some(depth)
     }
-}
 
-impl Drop for MountPoint {
-    fn drop(&mut self) {
-        self.fs.umount().ok();
+    /// Whether `path` (in canonical form) is strictly inside this mount point.
+    fn is_ancestor_of(&self, path: &str) -> bool {
+        path.strip_prefix(self.path.as_str())
+            .is_some_and(|rest| rest.starts_with('/') && rest.len() > 1)
     }
 }
 
 impl RootDirectory {
-    pub const fn new(main_fs: Arc<dyn VfsOps>) -> Self {
+    pub const fn new(main_fs: Arc<dyn VfsOps>, main_source: String, main_fstype: String) -> Self {
         Self {
             main_fs,
-            mounts: Vec::new(),
+            main_source,
+            main_fstype,
+            main_options: MountOptions::new(),
+            mounts: Mutex::new(Vec::new()),
         }
     }
 
-    pub fn mount(&mut self, path: &'static str, fs: Arc<dyn VfsOps>) -> AxResult {
+    pub fn mount(
+        &self,
+        path: &str,
+        fs: Arc<dyn VfsOps>,
+        source: &str,
+        fstype: &str,
+        options: MountOptions,
+    ) -> AxResult {
+        if !path.starts_with('/') {
+            return ax_err!(InvalidInput, "mount path must start with '/'");
+        }
+        let path = axfs_vfs::path::canonicalize(path);
         if path == "/" {
             return ax_err!(InvalidInput, "cannot mount root filesystem");
         }
-        if !path.starts_with('/') {
-            return ax_err!(InvalidInput, "mount path must start with '/'");
+        if self.contains(&path) {
+            return ax_err!(InvalidInput, "mount point already exists");
+        }
+        // create the mount point in the filesystem it belongs to if it does not exist
+        let mount_point = self.lookup_mounted_fs(&path, |parent_fs, rest_path| {
+            let parent_root = parent_fs.root_dir();
+            match parent_root.clone().lookup(rest_path) {
+                Err(AxError::NotFound) => {
+                    parent_root.create(rest_path, FileType::Dir)?;
+                    parent_root.lookup(rest_path)
+                }
+                res => res,
+            }
+        })?;
+        if !mount_point.get_attr()?.is_dir() {
+            return ax_err!(NotADirectory, "mount point is not a directory");
         }
-        if self.mounts.iter().any(|mp| mp.path == path) {
+
+        let mut mounts = self.mounts.lock();
+        if mounts.iter().any(|mp| mp.path == path) {
             return ax_err!(InvalidInput, "mount point already exists");
         }
-        // create the mount point in the main filesystem if it does not exist
-        self.main_fs.root_dir().create(path, FileType::Dir)?;
-        fs.mount(path, self.main_fs.root_dir().lookup(path)?)?;
-        self.mounts.push(MountPoint::new(path, fs));
+        fs.mount(&path, mount_point)?;
+        mounts.push(Arc::new(MountPoint::new(
+            path,
+            fs,
+            source.into(),
+            fstype.into(),
+            options,
+        )));
         Ok(())
     }
 
-    pub fn _umount(&mut self, path: &str) {
-        self.mounts.retain(|mp| mp.path != path);
+    pub fn umount(&self, path: &str) -> AxResult {
+        let path = axfs_vfs::path::canonicalize(path);
+        let mut mounts = self.mounts.lock();
+        let idx = match mounts.iter().position(|mp| mp.path == path) {
+            Some(idx) => idx,
+            None => return ax_err!(InvalidInput, "not a mount point"),
+        };
+        let mp = &mounts[idx];
+        if mounts.iter().any(|other| mp.is_ancestor_of(&other.path)) {
+            return ax_err!(
+                ResourceBusy,
+                "filesystem has other filesystems mounted on it"
+            );
+        }
+        // the mount table holds one reference, the others come from open files
+        // and directories, or operations in progress
+        if Arc::strong_count(mp) > 1 {
+            return ax_err!(ResourceBusy, "filesystem is in use");
+        }
+        if is_used_by_tasks(mp) {
+            return ax_err!(
+                ResourceBusy,
+                "root or current directory is on the filesystem"
+            );
+        }
+
+        // flush and detach the filesystem before removing it from the mount table
+        mp.fs.umount()?;
+        mounts.remove(idx);
+        Ok(())
     }
 
     pub fn contains(&self, path: &str) -> bool {
-        self.mounts.iter().any(|mp| mp.path == path)
+        self.mounts.lock().iter().any(|mp| mp.path == path)
+    }
+
+    /// Finds the mount point with the longest match of `components`, and
+    /// returns it with the number of matched components.
+    fn find_mount_point(&self, components: &[&str]) -> (Option<Arc<MountPoint>>, usize) {
+        // TODO: more efficient, e.g. trie
+        let mut matched = None;
+        let mut max_depth = 0;
+        for mp in self.mounts.lock().iter() {
+            match mp.match_components(components) {
+                Some(depth) if depth > max_depth => {
+                    max_depth = depth;
+                    matched = Some(mp.clone());
+                }
+                _ => {}
+            }
+        }
+        (matched, max_depth)
     }
 
     fn lookup_mounted_fs<F, T>(&self, path: &str, f: F) -> AxResult<T>
@@ -76,30 +239,33 @@ impl RootDirectory {
         F: FnOnce(Arc<dyn VfsOps>, &str) -> AxResult<T>,
     {
         debug!("lookup at root: {}", path);
-        let path = path.trim_matches('/');
-        if let Some(rest) = path.strip_prefix("./") {
-            return self.lookup_mounted_fs(rest, f);
-        }
-
-        let mut idx = 0;
-        let mut max_len = 0;
+        let components = path_components(path)?;
 
         // Find the filesystem that has the longest mounted path match
-        // TODO: more efficient, e.g. trie
-        for (i, mp) in self.mounts.iter().enumerate() {
-            // skip the first '/'
-            if path.starts_with(&mp.path[1..]) && mp.path.len() - 1 > max_len {
-                max_len = mp.path.len() - 1;
-                idx = i;
-            }
+        let (matched, depth) = self.find_mount_point(&components);
+        let rest_path = components[depth..].join("/");
+        match matched {
+            Some(mp) => f(mp.fs.clone(), &rest_path),
+            None => f(self.main_fs.clone(), &rest_path), // not matched any mount point
         }
+    }
+}
 
-        if max_len == 0 {
-            f(self.main_fs.clone(), path) // not matched any mount point
-        } else {
-            f(self.mounts[idx].fs.clone(), &path[max_len..]) // matched at `idx`
+/// Splits `path` into its components, resolving `.` and `..` lexically.
+///
+/// Going above the root with `..` results in [`AxError::NotFound`].
+fn path_components(path: &str) -> AxResult<Vec<&str>> {
+    let mut components = Vec::new();
+    for name in path.split('/') {
+        match name {
+            "" | "." => {}
+            ".." => {
+                components.pop().ok_or(AxError::NotFound)?;
+            }
+            _ => components.push(name),
         }
     }
+    Ok(components)
 }
 
 impl VfsNodeOps for RootDirectory {
@@ -134,70 +300,567 @@ impl VfsNodeOps for RootDirectory {
     }
 
     fn rename(&self, src_path: &str, dst_path: &str) -> VfsResult {
-        let src_path = self.lookup_mounted_fs(src_path, |fs, rest_path| {
-            if rest_path.is_empty() {
-                return ax_err!(PermissionDenied); // cannot rename mount points
-            } else {
-                Ok(rest_path.to_string())
-            }
-        });
-
-        self.lookup_mounted_fs(dst_path, |fs, rest_path| {
-            if rest_path.is_empty() {
-                ax_err!(PermissionDenied) // cannot rename mount points
-            } else {
-                fs.root_dir().rename(src_path.unwrap().as_str(), rest_path)
-            }
-        })
+        let (src_fs, src_rest) =
+            self.lookup_mounted_fs(src_path, |fs, rest_path| Ok((fs, rest_path.to_string())))?;
+        let (dst_fs, dst_rest) =
+            self.lookup_mounted_fs(dst_path, |fs, rest_path| Ok((fs, rest_path.to_string())))?;
+        if src_rest.is_empty() || dst_rest.is_empty() {
+            return ax_err!(ResourceBusy, "cannot rename mount points");
+        }
+        if !Arc::ptr_eq(&src_fs, &dst_fs) {
+            return ax_err!(Unsupported, "cannot rename across filesystems");
+        }
+        // mount points are kept by path, so they can't be moved along
+        if self.mounts.lock().iter().any(|mp| {
+            mp.path
+                .strip_prefix(src_path)
+                .is_some_and(|rest| rest.starts_with('/'))
+        }) {
+            return ax_err!(ResourceBusy, "filesystems are mounted inside");
+        }
+        src_fs.root_dir().rename(&src_rest, &dst_rest)
     }
 }
 
-pub(crate) fn init_rootfs(disk: crate::dev::Disk) {
-    cfg_if::cfg_if! {
-        if #[cfg(feature = "myfs")] { // override the default filesystem
-            let main_fs = fs::myfs::new_myfs(disk);
-        } else if #[cfg(feature = "fatfs")] {
-            static FAT_FS: LazyInit<Arc<fs::fatfs::FatFileSystem>> = LazyInit::new();
-            FAT_FS.init_once(Arc::new(fs::fatfs::FatFileSystem::new(disk)));
-            FAT_FS.init();
-            let main_fs = FAT_FS.clone();
-        }
-    }
+pub(crate) fn init_rootfs(disks: Vec<NamedDisk>) {
+    #[cfg(feature = "ramfs")]
+    fs::ramfs::set_clock(axhal::time::wall_time);
 
-    let mut root_dir = RootDirectory::new(main_fs);
+    // the initramfs takes precedence over the root device
+    #[cfg(feature = "initramfs")]
+    let initramfs = crate::initramfs::load().map(|fs| {
+        info!("  use initramfs as the root");
+        let fs: Arc<dyn VfsOps> = fs;
+        (fs, String::from("rootfs"), "rootfs", None)
+    });
+    #[cfg(not(feature = "initramfs"))]
+    let initramfs = None;
+    #[allow(unused_variables)]
+    let (main_fs, source, fstype, root_idx) = initramfs.unwrap_or_else(|| open_root_device(&disks));
+    let root_dir = RootDirectory::new(main_fs, source, fstype.into());
 
     #[cfg(feature = "devfs")]
-    root_dir
-        .mount("/dev", mounts::devfs())
-        .expect("failed to mount devfs at /dev");
+    {
+        fs::devfs::register_builtin();
+        for d in disks.iter() {
+            let dev = Arc::new(BlockDevNode::new(d.disk.clone()));
+            fs::devfs::register(&d.name, dev).expect("duplicate block device name");
+        }
+        root_dir
+            .mount(
+                "/dev",
+                mounts::devfs(),
+                "devfs",
+                "devfs",
+                MountOptions::PSEUDO,
+            )
+            .expect("failed to mount devfs at /dev");
+    }
 
     #[cfg(feature = "ramfs")]
-    root_dir
-        .mount("/tmp", mounts::ramfs())
-        .expect("failed to mount ramfs at /tmp");
+    {
+        let mut options = MountOptions::new();
+        let tmpfs = mounts::tmpfs(&mut options);
+        root_dir
+            .mount("/tmp", tmpfs, "tmpfs", "tmpfs", options)
+            .expect("failed to mount ramfs at /tmp");
+    }
 
-    // Mount another ramfs as procfs
     #[cfg(feature = "procfs")]
     root_dir // should not fail
-        .mount("/proc", mounts::procfs().unwrap())
+        .mount(
+            "/proc",
+            mounts::procfs(),
+            "proc",
+            "proc",
+            MountOptions::PSEUDO,
+        )
         .expect("fail to mount procfs at /proc");
 
-    // Mount another ramfs as sysfs
     #[cfg(feature = "sysfs")]
     root_dir // should not fail
-        .mount("/sys", mounts::sysfs().unwrap())
+        .mount(
+            "/sys",
+            mounts::sysfs(),
+            "sysfs",
+            "sysfs",
+            MountOptions::PSEUDO,
+        )
         .expect("fail to mount sysfs at /sys");
 
+    #[cfg(all(feature = "automount", not(feature = "myfs")))]
+    automount(
+        &root_dir,
+        disks
+            .into_iter()
+            .enumerate()
+            .filter_map(|(i, d)| (Some(i) != root_idx && !d.partitioned).then_some(d)),
+    );
+
     ROOT_DIR.init_once(Arc::new(root_dir));
-    CURRENT_DIR.init_once(Mutex::new(ROOT_DIR.clone()));
-    *CURRENT_DIR_PATH.lock() = "/".into();
 }
 
-fn parent_node_of(dir: Option<&VfsNodeRef>, path: &str) -> VfsNodeRef {
-    if path.starts_with('/') {
-        ROOT_DIR.clone()
+/// Opens the root filesystem on the device named by [`axconfig::ROOT_DEV`],
+/// returns it with its source, type name and the index of the device in
+/// `disks`.
+fn open_root_device(disks: &[NamedDisk]) -> (Arc<dyn VfsOps>, String, &'static str, Option<usize>) {
+    assert!(!disks.is_empty(), "No block device or initramfs found!");
+    let mut root_idx = match disks.iter().position(|d| d.name == axconfig::ROOT_DEV) {
+        Some(idx) => idx,
+        None => {
+            warn!(
+                "root device {:?} not found, use {:?} instead",
+                axconfig::ROOT_DEV,
+                disks[0].name
+            );
+            0
+        }
+    };
+    if disks[root_idx].partitioned {
+        // partitions are listed right after their disk
+        root_idx += 1;
+    }
+    info!("  use {} as the root device", disks[root_idx].name);
+    let disk = disks[root_idx].disk.clone();
+
+    cfg_if::cfg_if! {
+        if #[cfg(feature = "myfs")] { // override the default filesystem
+            let (main_fs, fstype) = (fs::myfs::new_myfs(disk.device().clone()), "myfs");
+        } else {
+            let (main_fs, fstype) = new_main_fs(disk);
+        }
+    }
+    // keep the root device unmodified, with all changes in memory
+    #[cfg(feature = "overlayfs")]
+    let (main_fs, fstype) = {
+        info!("  use an overlay on the {} filesystem as the root", fstype);
+        (mounts::overlayfs(main_fs) as Arc<dyn VfsOps>, "overlay")
+    };
+
+    let source = String::from("/dev/") + &disks[root_idx].name;
+    (main_fs, source, fstype, Some(root_idx))
+}
+
+/// Opens the filesystem on the root device, returns it with its type name.
+/// If both FAT and ext2/3/4 are enabled, FAT is used unless the device holds
+/// an ext2/3/4 filesystem.
+#[cfg(not(feature = "myfs"))]
+fn new_main_fs(disk: Disk) -> (Arc<dyn VfsOps>, &'static str) {
+    #[cfg(feature = "ext4fs")]
+    if !cfg!(feature = "fatfs") || fs::ext4fs::Ext4FileSystem::probe(disk.device().as_ref()) {
+        let fs = fs::ext4fs::Ext4FileSystem::new(disk.device().clone())
+            .expect("failed to initialize ext4 filesystem");
+        return (fs, "ext4");
+    }
+    cfg_if::cfg_if! {
+        if #[cfg(feature = "fatfs")] {
+            (fs::fatfs::FatFileSystem::new(disk), "vfat")
+        } else if #[cfg(feature = "ext4fs")] {
+            unreachable!()
+        }
+    }
+}
+
+/// Opens the filesystem on `disk`, returns it with its type name and volume
+/// label.
+#[cfg(all(feature = "automount", not(feature = "myfs")))]
+fn open_labeled_fs(disk: Disk) -> AxResult<(Arc<dyn VfsOps>, &'static str, Option<String>)> {
+    #[cfg(feature = "ext4fs")]
+    if fs::ext4fs::Ext4FileSystem::probe(disk.device().as_ref()) {
+        let fs = fs::ext4fs::Ext4FileSystem::new(disk.device().clone())?;
+        let label = fs.volume_label();
+        return Ok((fs, "ext4", label));
+    }
+    let fs = fs::fatfs::FatFileSystem::try_new(disk)?;
+    let label = fs.volume_label();
+    Ok((fs, "vfat", label))
+}
+
+/// Mounts the filesystems on `disks` on `/mnt/<label>`, or
+/// `/mnt/<device name>` if the volume is not labeled.
+#[cfg(all(feature = "automount", not(feature = "myfs")))]
+fn automount(root_dir: &RootDirectory, disks: impl Iterator<Item = NamedDisk>) {
+    for NamedDisk { name, disk, .. } in disks {
+        let (fs, fstype, label) = match open_labeled_fs(disk) {
+            Ok(res) => res,
+            Err(e) => {
+                warn!("  skip {}: no supported filesystem found ({:?})", name, e);
+                continue;
+            }
+        };
+        // FAT opens the directory if it already exists, others fail
+        match root_dir.create("/mnt", VfsNodeType::Dir) {
+            Ok(_) | Err(AxError::AlreadyExists) => {}
+            Err(e) => {
+                warn!("  failed to create /mnt: {:?}", e);
+                return;
+            }
+        }
+        let source = String::from("/dev/") + &name;
+        let path = String::from("/mnt/") + &label.unwrap_or(name);
+        match root_dir.mount(&path, fs, &source, fstype, MountOptions::new()) {
+            Ok(_) => info!("  mount filesystem at {}", path),
+            Err(e) => warn!("  failed to mount filesystem at {}: {:?}", path, e),
+        }
+    }
+}
+
+/// The maximum number of symlinks followed in a path lookup, as `MAXSYMLINKS`
+/// on Linux.
+const MAX_SYMLINKS: usize = 40;
+
+/// A path resolved to the directory containing its last component.
+struct ResolvedPath {
+    /// The directory containing the last component.
+    dir: VfsNodeRef,
+    /// The absolute path of `dir`, or `None` if the path is relative to an
+    /// opened directory.
+    dir_path: Option<String>,
+    /// The last component, empty if the path is the root or ends with `.`
+    /// or `..`.
+    name: String,
+    /// The node of the last component, or `None` if it does not exist.
+    node: Option<VfsNodeRef>,
+}
+
+impl ResolvedPath {
+    /// Returns the absolute path without symlinks, or `None` if the path is
+    /// relative to an opened directory.
+    fn path(&self) -> Option<String> {
+        let dir_path = self.dir_path.as_deref()?;
+        Some(join_path(dir_path, &self.name))
+    }
+
+    /// Returns the mount point of the filesystem that the path is on.
+    fn mount_point(&self) -> AxResult<Option<Arc<MountPoint>>> {
+        match self.path() {
+            Some(path) => Ok(ROOT_DIR.find_mount_point(&path_components(&path)?).0),
+            None => Ok(None),
+        }
+    }
+
+    /// Fails if the path is on a read-only filesystem. Paths relative to an
+    /// opened directory are checked by the directory instead, as the
+    /// filesystem is not known here.
+    fn check_writable(&self) -> AxResult {
+        match self.dir_path {
+            Some(_) => check_writable(self.mount_point()?.as_deref()),
+            None => Ok(()),
+        }
+    }
+
+    /// Reports an event of `mask` on the path to the watchers, if the path
+    /// is known.
+    fn notify(&self, mask: WatchMask) {
+        if notify::is_watching() {
+            if let Some(path) = self.path() {
+                notify::notify(&path, mask);
+            }
+        }
+    }
+
+    fn into_node(self) -> AxResult<VfsNodeRef> {
+        self.node.ok_or(AxError::NotFound)
+    }
+}
+
+fn join_path(dir: &str, name: &str) -> String {
+    if name.is_empty() {
+        dir.into()
+    } else if dir.ends_with('/') {
+        String::from(dir) + name
+    } else {
+        String::from(dir) + "/" + name
+    }
+}
+
+/// Resolves `path` component by component from `dir`, or from the current
+/// directory if `dir` is `None`. Symlinks are followed, except for the last
+/// component if `follow` is `false` and the path doesn't end with `/`.
+///
+/// Following more than [`MAX_SYMLINKS`] symlinks fails with
+/// [`SYMLINK_LOOP`](crate::fops::SYMLINK_LOOP).
+///
+/// Paths relative to the current directory are resolved from the root, so
+/// that `..` can leave the filesystem mounted at the current directory.
+fn resolve_path(dir: Option<&VfsNodeRef>, path: &str, follow: bool) -> AxResult<ResolvedPath> {
+    resolve_path_at(dir.map(|dir| (dir, None)), path, follow)
+}
+
+/// Resolves `path` as [`resolve_path`], from the opened directory `dir` at
+/// the absolute path `dir_path` if it's known.
+fn resolve_path_at(
+    dir: Option<(&VfsNodeRef, Option<&str>)>,
+    path: &str,
+    follow: bool,
+) -> AxResult<ResolvedPath> {
+    if path.is_empty() {
+        return ax_err!(NotFound);
+    }
+    let ctx = fs_context();
+    let root = || -> AxResult<_> {
+        if ctx.root == "/" {
+            Ok((ROOT_DIR.main_fs.root_dir(), Some(String::from("/"))))
+        } else {
+            Ok((ROOT_DIR.clone().lookup(&ctx.root)?, Some(ctx.root.clone())))
+        }
+    };
+    let (mut cur, mut cur_path) = match dir {
+        Some((dir, dir_path)) if !path.starts_with('/') => {
+            (dir.clone(), dir_path.map(String::from))
+        }
+        _ => root()?,
+    };
+    let path = if dir.is_none() && !path.starts_with('/') {
+        Cow::Owned(ctx.cwd.clone() + path)
+    } else {
+        Cow::Borrowed(path)
+    };
+
+    // components left to walk, in reverse order
+    let mut pending: Vec<String> = path.split('/').rev().map(String::from).collect();
+    let mut links = 0;
+    while let Some(name) = pending.pop() {
+        match name.as_str() {
+            "" | "." => continue,
+            ".." => {
+                (cur, cur_path) = parent_dir_of(&cur, cur_path, &ctx.root)?;
+                continue;
+            }
+            _ => {}
+        }
+        // only trailing slashes may follow the last component
+        let is_last = pending.iter().all(String::is_empty);
+        let node = match lookup_child(&cur, cur_path.as_deref(), &name) {
+            Ok(node) => node,
+            Err(AxError::NotFound) if is_last => {
+                return Ok(ResolvedPath {
+                    dir: cur,
+                    dir_path: cur_path,
+                    name,
+                    node: None,
+                });
+            }
+            Err(e) => return Err(e),
+        };
+        let attr = node.get_attr()?;
+        if attr.file_type() == VfsNodeType::SymLink && (!is_last || follow || !pending.is_empty()) {
+            links += 1;
+            if links > MAX_SYMLINKS {
+                return ax_err!(BadState, "too many levels of symbolic links");
+            }
+            let target = read_link_node(&node)?;
+            if target.is_empty() {
+                return ax_err!(NotFound);
+            } else if target.starts_with('/') {
+                (cur, cur_path) = root()?;
+            }
+            pending.extend(target.split('/').rev().map(String::from));
+            continue;
+        }
+        if is_last {
+            if !pending.is_empty() && !attr.is_dir() {
+                return ax_err!(NotADirectory);
+            }
+            return Ok(ResolvedPath {
+                dir: cur,
+                dir_path: cur_path,
+                name,
+                node: Some(node),
+            });
+        }
+        cur_path = cur_path.map(|dir_path| join_path(&dir_path, &name));
+        cur = node;
+    }
+    Ok(ResolvedPath {
+        dir: cur.clone(),
+        dir_path: cur_path,
+        name: String::new(),
+        node: Some(cur),
+    })
+}
+
+/// Looks up `name` in the directory `dir` at `dir_path`, crossing into the
+/// filesystem mounted there if any.
+fn lookup_child(dir: &VfsNodeRef, dir_path: Option<&str>, name: &str) -> AxResult<VfsNodeRef> {
+    if let Some(dir_path) = dir_path {
+        let path = join_path(dir_path, name);
+        let mounts = ROOT_DIR.mounts.lock();
+        if let Some(mp) = mounts.iter().find(|mp| mp.path == path) {
+            return Ok(mp.fs.root_dir());
+        }
+    }
+    dir.clone().lookup(name)
+}
+
+/// Returns the parent of the directory `dir` at `dir_path`. The parent of the
+/// root (`root` for the task) is the root itself.
+fn parent_dir_of(
+    dir: &VfsNodeRef,
+    dir_path: Option<String>,
+    root: &str,
+) -> AxResult<(VfsNodeRef, Option<String>)> {
+    match dir_path {
+        Some(path) if path == root => Ok((dir.clone(), Some(path))),
+        Some(path) => {
+            let parent = match path.rfind('/') {
+                Some(0) => "/",
+                Some(idx) => &path[..idx],
+                None => return ax_err!(NotFound),
+            };
+            let node = ROOT_DIR.clone().lookup(parent)?;
+            Ok((node, Some(parent.into())))
+        }
+        None => Ok((dir.parent().ok_or(AxError::NotFound)?, None)),
+    }
+}
+
+/// Reads the target of the symlink `node`.
+fn read_link_node(node: &VfsNodeRef) -> AxResult<String> {
+    let mut buf = vec![0; node.get_attr()?.size() as usize];
+    let len = node.read_at(0, &mut buf)?;
+    buf.truncate(len);
+    String::from_utf8(buf).map_err(|_| AxError::InvalidData)
+}
+
+/// Returns the mount point that `path` is on, or `None` if it's on the root
+/// filesystem.
+pub(crate) fn mount_point_of(path: &str, follow: bool) -> AxResult<Option<Arc<MountPoint>>> {
+    resolve_path(None, path, follow)?.mount_point()
+}
+
+/// Returns the mount point that `path` relative to the opened directory `dir`
+/// at the absolute path `dir_path` is on, as [`mount_point_of`].
+pub(crate) fn mount_point_at(
+    dir: &VfsNodeRef,
+    dir_path: &str,
+    path: &str,
+    follow: bool,
+) -> AxResult<Option<Arc<MountPoint>>> {
+    resolve_path_at(Some((dir, Some(dir_path))), path, follow)?.mount_point()
+}
+
+/// Returns the options of the filesystem mounted on `mount`, or of the root
+/// filesystem if it's `None`.
+pub(crate) fn mount_options(mount: Option<&MountPoint>) -> MountOptions {
+    mount.map_or(ROOT_DIR.main_options, |mp| mp.options)
+}
+
+/// Returns the usage of the filesystem mounted on `mount`, or of the root
+/// filesystem if it's `None`.
+pub(crate) fn fs_stat(mount: Option<&MountPoint>) -> AxResult<FsStat> {
+    let (fs, fstype) = match mount {
+        Some(mp) => (&mp.fs, mp.fstype.as_str()),
+        None => (&ROOT_DIR.main_fs, ROOT_DIR.main_fstype.as_str()),
+    };
+    Ok(FsStat {
+        fs_type: fs_magic(fstype),
+        ..root_fs_stat(&fs.root_dir())?
+    })
+}
+
+/// Returns the magic number of Linux for the filesystem type `fstype`, or 0
+/// if it's unknown.
+fn fs_magic(fstype: &str) -> u64 {
+    match fstype {
+        "ramfs" | "rootfs" => 0x8584_58f6,
+        "tmpfs" => 0x0102_1994,
+        "devfs" => 0x1373,
+        "proc" => 0x9fa0,
+        "sysfs" => 0x6265_6572,
+        "vfat" => 0x4d44,
+        "ext4" => 0xef53,
+        "overlay" => 0x794c_7630,
+        _ => 0,
+    }
+}
+
+/// Returns the usage of the filesystem whose root directory is `root`.
+///
+/// [`VfsOps`] has no operation to report it, so it's read from each
+/// filesystem with storage. The others have no blocks or files.
+fn root_fs_stat(root: &VfsNodeRef) -> AxResult<FsStat> {
+    #[cfg(feature = "overlayfs")]
+    if let Some(root) = root.as_any().downcast_ref::<fs::overlayfs::OverlayNode>() {
+        // files are only created in the upper layer
+        return root_fs_stat(&root.real_node());
+    }
+    #[cfg(feature = "ramfs")]
+    if let Some(dir) = root.as_any().downcast_ref::<fs::ramfs::DirNode>() {
+        return Ok(mounts::ramfs_stat(dir));
+    }
+    #[cfg(all(feature = "fatfs", not(feature = "myfs")))]
+    if let Some(stat) = fs::fatfs::fs_stat(root) {
+        return stat;
+    }
+    #[cfg(all(feature = "ext4fs", not(feature = "myfs")))]
+    if let Some(root) = root.as_any().downcast_ref::<fs::ext4fs::Ext4Node>() {
+        return root.fs_stat();
+    }
+    let stat = FsStat {
+        block_size: 4096,
+        name_max: 255,
+        ..Default::default()
+    };
+    #[cfg(feature = "devfs")]
+    if root.as_any().is::<fs::devfs::DirNode>() {
+        let files = count_nodes(root)?;
+        return Ok(FsStat { files, ..stat });
+    }
+    Ok(stat)
+}
+
+/// Counts the nodes in the directory `dir`, including itself.
+#[cfg(feature = "devfs")]
+fn count_nodes(dir: &VfsNodeRef) -> AxResult<u64> {
+    let mut count = 1;
+    const EMPTY: axfs_vfs::VfsDirEntry = axfs_vfs::VfsDirEntry::default();
+    let mut entries = [EMPTY; 8];
+    let mut idx = 0;
+    loop {
+        let n = dir.read_dir(idx, &mut entries)?;
+        if n == 0 {
+            return Ok(count);
+        }
+        idx += n;
+        for entry in &entries[..n] {
+            let name = core::str::from_utf8(entry.name_as_bytes()).unwrap_or_default();
+            if name == "." || name == ".." {
+                continue;
+            }
+            count += match entry.entry_type() {
+                VfsNodeType::Dir => count_nodes(&dir.clone().lookup(name)?)?,
+                _ => 1,
+            };
+        }
+    }
+}
+
+/// Returns `true` if the filesystem mounted on `mount`, or the root filesystem
+/// if it's `None`, can't be modified, either by its mount options or by
+/// itself.
+pub(crate) fn is_read_only(mount: Option<&MountPoint>) -> bool {
+    if mount_options(mount).read_only {
+        return true;
+    }
+    #[cfg(all(feature = "ext4fs", not(feature = "myfs")))]
+    {
+        let root = mount.map_or(&ROOT_DIR.main_fs, |mp| &mp.fs).root_dir();
+        if let Some(root) = root.as_any().downcast_ref::<fs::ext4fs::Ext4Node>() {
+            return root.is_read_only();
+        }
+    }
+    false
+}
+
+/// Fails with [`AxError::PermissionDenied`] if the filesystem mounted on
+/// `mount` (the root filesystem if `None`) is read-only, as there's no error
+/// kind for `EROFS`.
+pub(crate) fn check_writable(mount: Option<&MountPoint>) -> AxResult {
+    if is_read_only(mount) {
+        ax_err!(PermissionDenied, "read-only filesystem")
     } else {
-        dir.cloned().unwrap_or_else(|| CURRENT_DIR.lock().clone())
+        Ok(())
     }
 }
 
@@ -205,52 +868,265 @@ pub(crate) fn absolute_path(path: &str) -> AxResult<String> {
     if path.starts_with('/') {
         Ok(axfs_vfs::path::canonicalize(path))
     } else {
-        let path = CURRENT_DIR_PATH.lock().clone() + path;
+        let path = fs_context().cwd.clone() + path;
         Ok(axfs_vfs::path::canonicalize(&path))
     }
 }
 
-pub(crate) fn lookup(dir: Option<&VfsNodeRef>, path: &str) -> AxResult<VfsNodeRef> {
-    if path.is_empty() {
-        return ax_err!(NotFound);
+pub(crate) fn lookup(dir: Option<&VfsNodeRef>, path: &str, follow: bool) -> AxResult<VfsNodeRef> {
+    resolve_path(dir, path, follow)?.into_node()
+}
+
+/// Looks up `path` as [`lookup`], also returning its absolute path without
+/// symlinks, or `None` if it's relative to the opened directory `dir`.
+pub(crate) fn lookup_with_path(
+    dir: Option<&VfsNodeRef>,
+    path: &str,
+    follow: bool,
+) -> AxResult<(VfsNodeRef, Option<String>)> {
+    let res = resolve_path(dir, path, follow)?;
+    let path = res.path();
+    Ok((res.into_node()?, path))
+}
+
+/// Looks up `path` as [`lookup_with_path`], relative to the opened directory
+/// `dir` at the absolute path `dir_path`.
+pub(crate) fn lookup_with_path_at(
+    dir: &VfsNodeRef,
+    dir_path: &str,
+    path: &str,
+    follow: bool,
+) -> AxResult<(VfsNodeRef, Option<String>)> {
+    let res = resolve_path_at(Some((dir, Some(dir_path))), path, follow)?;
+    let path = res.path();
+    Ok((res.into_node()?, path))
+}
+
+/// Creates an empty file at `path`. Returns its node and its absolute path
+/// as [`lookup_with_path`].
+pub(crate) fn create_file(
+    dir: Option<&VfsNodeRef>,
+    path: &str,
+) -> AxResult<(VfsNodeRef, Option<String>)> {
+    if path.ends_with('/') {
+        return ax_err!(NotADirectory);
     }
-    let node = parent_node_of(dir, path).lookup(path)?;
-    if path.ends_with('/') && !node.get_attr()?.is_dir() {
-        ax_err!(NotADirectory)
-    } else {
-        Ok(node)
+    // a dangling symlink creates its target
+    let res = resolve_path(dir, path, true)?;
+    res.check_writable()?;
+    res.dir.create(&res.name, VfsNodeType::File)?;
+    res.notify(WatchMask::CREATE);
+    Ok((res.dir.clone().lookup(&res.name)?, res.path()))
+}
+
+pub(crate) fn create_dir(dir: Option<&VfsNodeRef>, path: &str) -> AxResult {
+    let res = resolve_path(dir, path, false)?;
+    if res.node.is_some() {
+        return ax_err!(AlreadyExists);
     }
+    res.check_writable()?;
+    res.dir.create(&res.name, VfsNodeType::Dir)?;
+    res.notify(WatchMask::CREATE | WatchMask::ISDIR);
+    Ok(())
 }
 
-pub(crate) fn create_file(dir: Option<&VfsNodeRef>, path: &str) -> AxResult<VfsNodeRef> {
-    if path.is_empty() {
+pub(crate) fn create_symlink(dir: Option<&VfsNodeRef>, target: &str, path: &str) -> AxResult {
+    if target.is_empty() {
         return ax_err!(NotFound);
-    } else if path.ends_with('/') {
-        return ax_err!(NotADirectory);
     }
-    let parent = parent_node_of(dir, path);
-    parent.create(path, VfsNodeType::File)?;
-    parent.lookup(path)
+    let res = resolve_path(dir, path, false)?;
+    if res.node.is_some() {
+        return ax_err!(AlreadyExists);
+    }
+    res.check_writable()?;
+    res.dir.create(&res.name, VfsNodeType::SymLink)?;
+    let node = res.dir.clone().lookup(&res.name)?;
+    if let Err(e) = node.write_at(0, target.as_bytes()) {
+        res.dir.remove(&res.name).ok();
+        return Err(e);
+    }
+    res.notify(WatchMask::CREATE);
+    Ok(())
 }
 
-pub(crate) fn create_dir(dir: Option<&VfsNodeRef>, path: &str) -> AxResult {
-    match lookup(dir, path) {
-        Ok(_) => ax_err!(AlreadyExists),
-        Err(AxError::NotFound) => parent_node_of(dir, path).create(path, VfsNodeType::Dir),
-        Err(e) => Err(e),
+pub(crate) fn read_link(dir: Option<&VfsNodeRef>, path: &str) -> AxResult<String> {
+    let node = lookup(dir, path, false)?;
+    if node.get_attr()?.file_type() != VfsNodeType::SymLink {
+        return ax_err!(InvalidInput);
     }
+    read_link_node(&node)
+}
+
+pub(crate) fn hard_link(old: &str, new: &str) -> AxResult {
+    let src = resolve_path(None, old, false)?;
+    let src_mount = src.mount_point()?;
+    let node = src.into_node()?;
+    if node.get_attr()?.is_dir() {
+        return ax_err!(PermissionDenied);
+    }
+    let dst = resolve_path(None, new, false)?;
+    if dst.node.is_some() {
+        return ax_err!(AlreadyExists);
+    }
+    dst.check_writable()?;
+    if !is_same_mount(src_mount, dst.mount_point()?) {
+        return ax_err!(Unsupported, "cannot link across filesystems");
+    }
+    link_node(&dst.dir, &dst.name, &node)?;
+    dst.notify(WatchMask::CREATE);
+    Ok(())
+}
+
+/// Returns `true` if `path1` and `path2` are on the same mounted filesystem.
+/// They don't need to exist, and symlinks in the last components are not
+/// followed.
+pub(crate) fn same_filesystem(path1: &str, path2: &str) -> AxResult<bool> {
+    let mount1 = resolve_path(None, path1, false)?.mount_point()?;
+    let mount2 = resolve_path(None, path2, false)?.mount_point()?;
+    Ok(is_same_mount(mount1, mount2))
+}
+
+/// Returns `true` if the mount points are the same, `None` for the root
+/// filesystem.
+fn is_same_mount(a: Option<Arc<MountPoint>>, b: Option<Arc<MountPoint>>) -> bool {
+    match (a, b) {
+        (None, None) => true,
+        (Some(a), Some(b)) => Arc::ptr_eq(&a, &b),
+        _ => false,
+    }
+}
+
+/// Adds a hard link named `name` in the directory `dir` to `node`.
+///
+/// [`VfsNodeOps`] has no operation for it, so it's implemented for each
+/// filesystem that supports hard links.
+pub(crate) fn link_node(dir: &VfsNodeRef, name: &str, node: &VfsNodeRef) -> AxResult {
+    #[cfg(feature = "ramfs")]
+    if let Some(dir) = dir.as_any().downcast_ref::<axfs_ramfs::DirNode>() {
+        return dir.link_node(name, node.clone());
+    }
+    #[cfg(all(feature = "ext4fs", not(feature = "myfs")))]
+    if let Some(dir) = dir.as_any().downcast_ref::<fs::ext4fs::Ext4Node>() {
+        return dir.link(name, node);
+    }
+    let _ = (dir, name, node);
+    ax_err!(PermissionDenied, "filesystem does not support hard links")
+}
+
+/// Returns the timestamps of `node`.
+///
+/// [`VfsNodeOps`] has no operation for them, so they're read from each
+/// filesystem that records them, and are zero for the others.
+pub(crate) fn node_times(node: &VfsNodeRef) -> AxResult<FileTimes> {
+    #[cfg(feature = "overlayfs")]
+    if let Some(node) = node.as_any().downcast_ref::<fs::overlayfs::OverlayNode>() {
+        return node_times(&node.real_node());
+    }
+    #[cfg(feature = "ramfs")]
+    {
+        let any = node.as_any();
+        let times = if let Some(file) = any.downcast_ref::<fs::ramfs::FileNode>() {
+            Some(file.times())
+        } else if let Some(dir) = any.downcast_ref::<fs::ramfs::DirNode>() {
+            Some(dir.times())
+        } else {
+            any.downcast_ref::<fs::ramfs::SymlinkNode>()
+                .map(|link| link.times())
+        };
+        if let Some(times) = times {
+            return Ok(FileTimes {
+                accessed: times.accessed,
+                modified: times.modified,
+                changed: times.changed,
+            });
+        }
+    }
+    #[cfg(all(feature = "fatfs", not(feature = "myfs")))]
+    if let Some(times) = fs::fatfs::node_times(node) {
+        return Ok(times);
+    }
+    #[cfg(all(feature = "ext4fs", not(feature = "myfs")))]
+    if let Some(node) = node.as_any().downcast_ref::<fs::ext4fs::Ext4Node>() {
+        return node.times();
+    }
+    let _ = node;
+    Ok(FileTimes::default())
+}
+
+/// Sets the access and modification times of `node`, the ones of `None` are
+/// left unchanged. Fails with [`AxError::Unsupported`] if the filesystem
+/// doesn't record them.
+pub(crate) fn set_node_times(
+    node: &VfsNodeRef,
+    accessed: Option<Duration>,
+    modified: Option<Duration>,
+) -> AxResult {
+    #[cfg(feature = "overlayfs")]
+    if let Some(node) = node.as_any().downcast_ref::<fs::overlayfs::OverlayNode>() {
+        return set_node_times(&node.copy_up()?, accessed, modified);
+    }
+    #[cfg(feature = "ramfs")]
+    {
+        let any = node.as_any();
+        if let Some(file) = any.downcast_ref::<fs::ramfs::FileNode>() {
+            file.set_times(accessed, modified);
+            return Ok(());
+        } else if let Some(dir) = any.downcast_ref::<fs::ramfs::DirNode>() {
+            dir.set_times(accessed, modified);
+            return Ok(());
+        } else if let Some(link) = any.downcast_ref::<fs::ramfs::SymlinkNode>() {
+            link.set_times(accessed, modified);
+            return Ok(());
+        }
+    }
+    #[cfg(all(feature = "fatfs", not(feature = "myfs")))]
+    if let Some(res) = fs::fatfs::set_node_times(node, accessed, modified) {
+        return res;
+    }
+    #[cfg(all(feature = "ext4fs", not(feature = "myfs")))]
+    if let Some(node) = node.as_any().downcast_ref::<fs::ext4fs::Ext4Node>() {
+        return node.set_times(accessed, modified);
+    }
+    let _ = (node, accessed, modified);
+    ax_err!(Unsupported, "filesystem does not record timestamps")
+}
+
+/// Sets the access and modification times of the file at `path`, see
+/// [`set_node_times`].
+pub(crate) fn set_times(
+    path: &str,
+    follow: bool,
+    accessed: Option<Duration>,
+    modified: Option<Duration>,
+) -> AxResult {
+    let res = resolve_path(None, path, follow)?;
+    res.check_writable()?;
+    let node = res.node.as_ref().ok_or(AxError::NotFound)?;
+    set_node_times(node, accessed, modified)?;
+    if node.get_attr()?.is_dir() {
+        res.notify(WatchMask::ATTRIB | WatchMask::ISDIR);
+    } else {
+        res.notify(WatchMask::ATTRIB);
+    }
+    Ok(())
 }
 
 pub(crate) fn remove_file(dir: Option<&VfsNodeRef>, path: &str) -> AxResult {
-    let node = lookup(dir, path)?;
-    let attr = node.get_attr()?;
+    let res = resolve_path(dir, path, false)?;
+    let attr = match &res.node {
+        Some(node) => node.get_attr()?,
+        None => return ax_err!(NotFound),
+    };
     if attr.is_dir() {
-        ax_err!(IsADirectory)
-    } else if !attr.perm().owner_writable() {
-        ax_err!(PermissionDenied)
-    } else {
-        parent_node_of(dir, path).remove(path)
+        return ax_err!(IsADirectory);
+    }
+    res.check_writable()?;
+    if !attr.perm().owner_writable() {
+        return ax_err!(PermissionDenied);
     }
+    res.dir.remove(&res.name)?;
+    res.notify(WatchMask::DELETE);
+    Ok(())
 }
 
 pub(crate) fn remove_dir(dir: Option<&VfsNodeRef>, path: &str) -> AxResult {
@@ -267,53 +1143,178 @@ pub(crate) fn remove_dir(dir: Option<&VfsNodeRef>, path: &str) -> AxResult {
     {
         return ax_err!(InvalidInput);
     }
-    if ROOT_DIR.contains(&absolute_path(path)?) {
+    let res = resolve_path(dir, path, false)?;
+    if res.path().is_some_and(|path| ROOT_DIR.contains(&path)) {
         return ax_err!(PermissionDenied);
     }
 
-    let node = lookup(dir, path)?;
-    let attr = node.get_attr()?;
+    let attr = match &res.node {
+        Some(node) => node.get_attr()?,
+        None => return ax_err!(NotFound),
+    };
     if !attr.is_dir() {
-        ax_err!(NotADirectory)
-    } else if !attr.perm().owner_writable() {
-        ax_err!(PermissionDenied)
-    } else {
-        parent_node_of(dir, path).remove(path)
+        return ax_err!(NotADirectory);
     }
+    res.check_writable()?;
+    if !attr.perm().owner_writable() {
+        return ax_err!(PermissionDenied);
+    }
+    res.dir.remove(&res.name)?;
+    res.notify(WatchMask::DELETE | WatchMask::ISDIR);
+    Ok(())
 }
 
 pub(crate) fn current_dir() -> AxResult<String> {
-    Ok(CURRENT_DIR_PATH.lock().clone())
+    Ok(fs_context().cwd.clone())
 }
 
 pub(crate) fn set_current_dir(path: &str) -> AxResult {
-    let mut abs_path = absolute_path(path)?;
+    let ctx = fs_context();
+    let res = resolve_path(None, path, true)?;
+    let real_path = res.path().unwrap();
+    check_searchable_dir(res)?;
+    // the path is always inside the root, as `..` can't leave it
+    let mut abs_path = match real_path.strip_prefix(ctx.root.trim_end_matches('/')) {
+        Some("") => "/".into(),
+        Some(path) => String::from(path),
+        None => real_path,
+    };
     if !abs_path.ends_with('/') {
         abs_path += "/";
     }
-    if abs_path == "/" {
-        *CURRENT_DIR.lock() = ROOT_DIR.clone();
-        *CURRENT_DIR_PATH.lock() = "/".into();
-        return Ok(());
-    }
+    set_fs_context(ctx.root.clone(), abs_path);
+    Ok(())
+}
 
-    let node = lookup(None, &abs_path)?;
-    let attr = node.get_attr()?;
+/// Changes the root directory of the current task to `path`, and the current
+/// directory to the new root.
+pub(crate) fn set_root_dir(path: &str) -> AxResult {
+    let res = resolve_path(None, path, true)?;
+    let root = res.path().unwrap();
+    check_searchable_dir(res)?;
+    set_fs_context(root, "/".into());
+    Ok(())
+}
+
+/// Checks that the resolved path is a directory that can be searched, i.e.
+/// can be the root or the current directory.
+fn check_searchable_dir(res: ResolvedPath) -> AxResult {
+    let attr = res.into_node()?.get_attr()?;
     if !attr.is_dir() {
         ax_err!(NotADirectory)
     } else if !attr.perm().owner_executable() {
         ax_err!(PermissionDenied)
     } else {
-        *CURRENT_DIR.lock() = node;
-        *CURRENT_DIR_PATH.lock() = abs_path;
         Ok(())
     }
 }
 
+/// Returns the context of the current task.
+#[cfg(feature = "multitask")]
+fn fs_context() -> Arc<FsContext> {
+    axtask::current_may_uninit()
+        .and_then(|curr| curr.module_ext())
+        .unwrap_or_default()
+}
+
+/// Sets the context of the current task. Tasks created by it later inherit
+/// the new one.
+#[cfg(feature = "multitask")]
+fn set_fs_context(root: String, cwd: String) {
+    axtask::current().set_module_ext(Arc::new(FsContext { root, cwd }));
+}
+
+/// Returns the context of all tasks.
+#[cfg(not(feature = "multitask"))]
+fn fs_context() -> Arc<FsContext> {
+    FS_CONTEXT.lock().clone().unwrap_or_default()
+}
+
+/// Sets the context of all tasks.
+#[cfg(not(feature = "multitask"))]
+fn set_fs_context(root: String, cwd: String) {
+    *FS_CONTEXT.lock() = Some(Arc::new(FsContext { root, cwd }));
+}
+
+/// Returns whether the filesystem mounted on `mp` holds the root or the
+/// current directory of any task.
+fn is_used_by_tasks(mp: &MountPoint) -> bool {
+    let is_on_fs = |path: &str| mp.is_ancestor_of(path) || path.trim_end_matches('/') == mp.path;
+    let is_used = |ctx: &FsContext| {
+        let cwd = ctx.root.trim_end_matches('/').to_string() + &ctx.cwd;
+        is_on_fs(&ctx.root) || is_on_fs(&cwd)
+    };
+    #[cfg(feature = "multitask")]
+    if axtask::all_tasks()
+        .iter()
+        .filter_map(|task| task.module_ext::<FsContext>())
+        .any(|ctx| is_used(&ctx))
+    {
+        return true;
+    }
+    is_used(&fs_context())
+}
+
+/// Renames `old` to `new`, atomically replacing `new` if it exists. Both
+/// must be on the same filesystem, or it fails with [`AxError::Unsupported`].
 pub(crate) fn rename(old: &str, new: &str) -> AxResult {
-    if parent_node_of(None, new).lookup(new).is_ok() {
-        warn!("dst file already exist, now remove it");
-        remove_file(None, new)?;
+    let src = resolve_path(None, old, false)?;
+    let dst = resolve_path(None, new, false)?;
+    let src_attr = match &src.node {
+        Some(node) => node.get_attr()?,
+        None => return ax_err!(NotFound),
+    };
+    if src.name.is_empty() || dst.name.is_empty() {
+        return ax_err!(InvalidInput); // rename '/', '.' or '..'
+    } else if new.ends_with('/') && !src_attr.is_dir() {
+        return ax_err!(NotADirectory);
     }
-    parent_node_of(None, old).rename(old, new)
+    src.check_writable()?;
+    dst.check_writable()?;
+
+    // paths relative to the root are always known
+    let (old, new) = (src.path().unwrap(), dst.path().unwrap());
+    if old == new {
+        return Ok(());
+    } else if src_attr.is_dir() && new.starts_with(&old) && new[old.len()..].starts_with('/') {
+        return ax_err!(InvalidInput, "cannot move a directory into itself");
+    }
+    ROOT_DIR.rename(&old, &new)?;
+    notify::notify_rename(&old, &new, src_attr.is_dir(), dst.node.is_some());
+    Ok(())
+}
+
+pub(crate) fn mount(
+    path: &str,
+    fs: Arc<dyn VfsOps>,
+    source: &str,
+    fstype: &str,
+    options: MountOptions,
+) -> AxResult {
+    let path = resolve_path(None, path, true)?.path().unwrap();
+    ROOT_DIR.mount(&path, fs, source, fstype, options)
+}
+
+pub(crate) fn umount(path: &str) -> AxResult {
+    ROOT_DIR.umount(&resolve_path(None, path, true)?.path().unwrap())
+}
+
+/// Returns all mounted filesystems, the root filesystem first and the others
+/// in the order they were mounted.
+#[cfg(feature = "procfs")]
+pub(crate) fn mounts() -> Vec<MountInfo> {
+    let root = MountInfo {
+        source: ROOT_DIR.main_source.clone(),
+        path: "/".into(),
+        fstype: ROOT_DIR.main_fstype.clone(),
+        options: ROOT_DIR.main_options,
+    };
+    let mounts = ROOT_DIR.mounts.lock();
+    let others = mounts.iter().map(|mp| MountInfo {
+        source: mp.source.clone(),
+        path: mp.path.clone(),
+        fstype: mp.fstype.clone(),
+        options: mp.options,
+    });
+    core::iter::once(root).chain(others).collect()
 }
@@ -198,6 +198,8 @@ fn test_devfs_ramfs() -> Result<()> {
         .collect::<Vec<_>>();
     assert!(dirents.contains(&"null".into()));
     assert!(dirents.contains(&"zero".into()));
+    assert!(dirents.contains(&"vda".into()));
+    assert_eq!(fs::metadata("/dev/vda")?.file_type(), FileType::BlockDevice);
 
     // stat /dev
     let dname = "/dev";
@@ -242,16 +244,556 @@ fn test_devfs_ramfs() -> Result<()> {
     assert_eq!(fs::read_dir("tmp").unwrap().count(), 1);
     assert_eq!(fs::write(".///tmp///dir//.///test.txt", "test"), Ok(()));
     assert_eq!(fs::read("tmp//././/dir//.///test.txt"), Ok("test".into()));
-    // assert_err!(fs::remove_dir("dev/../tmp//dir"), DirectoryNotEmpty); // TODO
+    assert_err!(fs::remove_dir("dev/../tmp//dir"), DirectoryNotEmpty);
     assert_err!(fs::remove_dir("/tmp/dir/../dir"), DirectoryNotEmpty);
     assert_eq!(fs::remove_file("./tmp//dir//test.txt"), Ok(()));
     assert_eq!(fs::remove_dir("tmp/dir/.././dir///"), Ok(()));
     assert_eq!(fs::read_dir("tmp").unwrap().count(), 0);
 
+    // mount points are matched by whole path components
+    assert_eq!(fs::create_dir("/tmpfoo"), Ok(()));
+    assert_eq!(fs::read_dir("tmp").unwrap().count(), 0);
+    assert_eq!(fs::remove_dir("/tmp/../tmpfoo"), Ok(()));
+
     println!("test_devfs_ramfs() OK!");
     Ok(())
 }
 
+fn test_mount_umount() -> Result<()> {
+    let mnt = "/tmp/cache";
+    println!("test mount and umount {:?}:", mnt);
+
+    // mount a ramfs inside the ramfs at /tmp
+    fs::mount_fstype(mnt, "ramfs")?;
+    fs::write("/tmp/cache/test.txt", "test")?;
+    assert_eq!(
+        fs::read("/tmp/./cache/../cache//test.txt"),
+        Ok("test".into())
+    );
+    assert_eq!(fs::read_dir(mnt)?.count(), 1);
+
+    // error cases
+    assert_err!(fs::mount_fstype(mnt, "ramfs"), InvalidInput);
+    assert_err!(fs::mount_fstype("/tmp/unknown", "unknownfs"), Unsupported);
+    assert_err!(fs::mount_fstype("/short.txt", "ramfs"), NotADirectory);
+    assert_err!(fs::remove_dir(mnt), PermissionDenied);
+    assert_err!(fs::umount("/tmp/not-mounted"), InvalidInput);
+
+    // busy filesystems
+    assert_err!(fs::umount("/tmp"), ResourceBusy);
+    let file = File::open("/tmp/cache/test.txt")?;
+    assert_err!(fs::umount(mnt), ResourceBusy);
+    drop(file);
+
+    fs::umount(mnt)?;
+    assert_err!(fs::umount(mnt), InvalidInput);
+    assert!(fs::metadata(mnt)?.is_dir());
+    assert_err!(fs::metadata("/tmp/cache/test.txt"), NotFound);
+    fs::remove_dir(mnt)?;
+    assert_eq!(fs::read_dir("tmp").unwrap().count(), 0);
+
+    println!("test_mount_umount() OK!");
+    Ok(())
+}
+
+fn test_symlinks() -> Result<()> {
+    println!("test symlinks and hard links in /tmp:");
+    fs::create_dir("/tmp/pkg")?;
+    fs::write("/tmp/pkg/data.txt", "data")?;
+
+    // relative and absolute targets, also across mount points
+    fs::symlink("pkg/data.txt", "/tmp/rel")?;
+    fs::symlink("/tmp/pkg", "/tmp/abs")?;
+    fs::symlink("/short.txt", "/tmp/root-link")?;
+    assert_eq!(fs::read_link("/tmp/rel")?, "pkg/data.txt");
+    assert_eq!(fs::read("/tmp/rel"), Ok("data".into()));
+    assert_eq!(fs::read("/tmp/./abs//data.txt"), Ok("data".into()));
+    assert_eq!(fs::read("/tmp/root-link")?, fs::read("/short.txt")?);
+    assert!(fs::symlink_metadata("/tmp/abs")?.is_symlink());
+    assert!(fs::metadata("/tmp/abs")?.is_dir());
+
+    // `..` after a symlink leads to the parent of its target
+    fs::create_dir("/tmp/abs/sub")?;
+    fs::symlink("pkg/sub", "/tmp/deep")?;
+    assert_eq!(fs::read("/tmp/deep/../data.txt"), Ok("data".into()));
+    fs::set_current_dir("/tmp/deep")?;
+    assert_eq!(fs::current_dir()?, "/tmp/pkg/sub/");
+    assert_eq!(fs::read("../data.txt"), Ok("data".into()));
+    fs::set_current_dir("/")?;
+
+    // writing to a dangling symlink creates its target
+    fs::symlink("new.txt", "/tmp/dangling")?;
+    assert_err!(fs::metadata("/tmp/dangling"), NotFound);
+    fs::write("/tmp/dangling", "new")?;
+    assert_eq!(fs::read("/tmp/new.txt"), Ok("new".into()));
+
+    // loops and no-follow opens
+    fs::symlink("loop2", "/tmp/loop1")?;
+    fs::symlink("loop1", "/tmp/loop2")?;
+    assert_err!(fs::metadata("/tmp/loop1"), BadState);
+    assert!(fs::symlink_metadata("/tmp/loop1")?.is_symlink());
+    let mut opts = axfs::fops::OpenOptions::new();
+    opts.read(true);
+    opts.no_follow(true);
+    assert_err!(axfs::fops::File::open("/tmp/rel", &opts), BadState);
+    assert!(axfs::fops::File::open("/tmp/abs/data.txt", &opts).is_ok());
+
+    // error cases
+    assert_err!(fs::symlink("x", "/tmp/rel"), AlreadyExists);
+    assert_err!(fs::symlink("", "/tmp/empty"), NotFound);
+    assert_err!(fs::read_link("/tmp/pkg"), InvalidInput);
+    assert_err!(fs::read("/tmp/rel/"), NotADirectory);
+    assert_err!(fs::remove_dir("/tmp/abs"), NotADirectory);
+
+    // hard links share the file
+    fs::hard_link("/tmp/abs/data.txt", "/tmp/hard.txt")?;
+    fs::write("/tmp/hard.txt", "changed")?;
+    assert_eq!(fs::read("/tmp/pkg/data.txt"), Ok("changed".into()));
+    fs::remove_file("/tmp/pkg/data.txt")?;
+    assert_eq!(fs::read("/tmp/hard.txt"), Ok("changed".into()));
+    assert_err!(fs::hard_link("/tmp/pkg", "/tmp/dir-link"), PermissionDenied);
+    assert_err!(fs::hard_link("/tmp/hard.txt", "/tmp/rel"), AlreadyExists);
+    assert_err!(fs::hard_link("/tmp/hard.txt", "/hard.txt"), Unsupported);
+
+    // symlinks are removed themselves, not their targets
+    for name in [
+        "rel",
+        "abs",
+        "root-link",
+        "deep",
+        "dangling",
+        "loop1",
+        "loop2",
+    ] {
+        fs::remove_file(&format!("/tmp/{}", name))?;
+    }
+    assert!(fs::metadata("/tmp/pkg/sub")?.is_dir());
+    fs::remove_file("/tmp/new.txt")?;
+    fs::remove_file("/tmp/hard.txt")?;
+    fs::remove_dir("/tmp/pkg/sub")?;
+    fs::remove_dir("/tmp/pkg")?;
+    assert_eq!(fs::read_dir("tmp").unwrap().count(), 0);
+
+    println!("test_symlinks() OK!");
+    Ok(())
+}
+
+fn test_rename() -> Result<()> {
+    println!("test rename in /tmp:");
+    fs::create_dir("/tmp/a")?;
+    fs::create_dir("/tmp/a/sub")?;
+    fs::create_dir("/tmp/b")?;
+    fs::write("/tmp/a/f1", "f1")?;
+    fs::write("/tmp/b/f2", "f2")?;
+
+    // move between directories, replacing the target
+    fs::rename("/tmp/a/f1", "/tmp/b/f1")?;
+    assert_err!(fs::metadata("/tmp/a/f1"), NotFound);
+    fs::rename("/tmp/b/f1", "/tmp/b/f2")?;
+    assert_eq!(fs::read("/tmp/b/f2"), Ok("f1".into()));
+    assert_err!(fs::metadata("/tmp/b/f1"), NotFound);
+
+    // move a non-empty directory, relative to the current directory
+    fs::write("/tmp/a/sub/f3", "f3")?;
+    fs::set_current_dir("/tmp/b")?;
+    fs::rename("../a/sub", "sub")?;
+    assert_eq!(fs::read("/tmp/b/sub/f3"), Ok("f3".into()));
+    fs::set_current_dir("/")?;
+
+    // error cases
+    assert_err!(fs::rename("/tmp/b/f2", "/tmp/a"), IsADirectory);
+    assert_err!(fs::rename("/tmp/a", "/tmp/b/f2"), NotADirectory);
+    assert_err!(fs::rename("/tmp/a", "/tmp/b"), DirectoryNotEmpty);
+    assert_err!(fs::rename("/tmp/b", "/tmp/b/sub/b"), InvalidInput);
+    assert_err!(fs::rename("/tmp/b/f2", "/tmp/b/f2/"), NotADirectory);
+    assert_err!(fs::rename("/tmp/b/f2", "/f2"), Unsupported);
+    assert_err!(fs::rename("/tmp", "/tmp2"), ResourceBusy);
+    assert_err!(fs::rename("/tmp/none", "/tmp/a/none"), NotFound);
+    assert!(fs::metadata("/tmp/b/f2")?.is_file());
+
+    // an empty directory can be replaced
+    fs::rename("/tmp/b", "/tmp/a")?;
+    assert_eq!(fs::read("/tmp/a/sub/f3"), Ok("f3".into()));
+    fs::remove_file("/tmp/a/sub/f3")?;
+    fs::remove_dir("/tmp/a/sub")?;
+    fs::remove_file("/tmp/a/f2")?;
+    fs::remove_dir("/tmp/a")?;
+    assert_eq!(fs::read_dir("tmp").unwrap().count(), 0);
+
+    // move on the root filesystem
+    fs::write("/rename.txt", "rename")?;
+    fs::rename("/rename.txt", "/very/rename.txt")?;
+    assert_eq!(fs::read("/very/rename.txt"), Ok("rename".into()));
+    fs::remove_file("/very/rename.txt")?;
+
+    println!("test_rename() OK!");
+    Ok(())
+}
+
+fn test_procfs() -> Result<()> {
+    println!("test procfs:");
+
+    let mounts = fs::read_to_string("/proc/mounts")?;
+    assert!(mounts.contains("\ntmpfs /tmp tmpfs rw,size="));
+    assert!(mounts.contains("\nproc /proc proc rw,nosuid,noexec 0 0\n"));
+    fs::mount_fstype("/tmp/proc-test", "ramfs")?;
+    let mounts = fs::read_to_string("/proc/mounts")?;
+    assert!(mounts.ends_with("ramfs /tmp/proc-test ramfs rw 0 0\n"));
+    fs::umount("/tmp/proc-test")?;
+    fs::remove_dir("/tmp/proc-test")?;
+    let mounts = fs::read_to_string("/proc/mounts")?;
+    assert!(!mounts.contains("/tmp/proc-test"));
+
+    let meminfo = fs::read_to_string("/proc/meminfo")?;
+    assert!(meminfo.starts_with("MemTotal:"));
+    let uptime = fs::read_to_string("/proc/uptime")?;
+    assert!(uptime.split(' ').next().unwrap().parse::<f64>().is_ok());
+    assert_eq!(fs::read_to_string("/proc/sys/vm/overcommit_memory")?, "0\n");
+
+    // the entries can't be changed
+    assert_err!(fs::remove_file("/proc/meminfo"), PermissionDenied);
+    assert_err!(fs::create_dir("/proc/foo"), PermissionDenied);
+
+    println!("test_procfs() OK!");
+    Ok(())
+}
+
+fn test_mount_options() -> Result<()> {
+    use axfs::fops::{File as RawFile, OpenOptions as RawOpenOptions};
+
+    println!("test mount options:");
+    assert_err!("foo".parse::<fs::MountOptions>(), InvalidInput);
+    let options = "size=8k,nr_inodes=3".parse()?;
+    assert_err!(
+        fs::mount_fstype_with_options("/tmp/small", "devfs", options),
+        InvalidInput
+    );
+
+    // a tmpfs with the root and two other nodes, of at most 8 KB
+    fs::mount_fstype_with_options("/tmp/small", "tmpfs", options)?;
+    fs::write("/tmp/small/a", [0; 4096])?;
+    assert_err!(fs::write("/tmp/small/b", [0; 8192]), StorageFull);
+    fs::write("/tmp/small/b", [0; 4096])?;
+    assert_err!(fs::create_dir("/tmp/small/c"), StorageFull);
+    fs::remove_file("/tmp/small/a")?;
+    fs::create_dir("/tmp/small/c")?;
+
+    fs::mount_fstype_with_options("/tmp/ro", "ramfs", "ro".parse()?)?;
+    assert_err!(fs::write("/tmp/ro/f", "ro"), PermissionDenied);
+    assert_err!(fs::create_dir("/tmp/ro/d"), PermissionDenied);
+    assert_eq!(fs::read_dir("/tmp/ro")?.count(), 0);
+
+    let mut opts = RawOpenOptions::new();
+    opts.read(true);
+    opts.execute(true);
+    fs::mount_fstype_with_options("/tmp/noexec", "ramfs", "noexec".parse()?)?;
+    fs::write("/tmp/noexec/prog", "prog")?;
+    assert_err!(RawFile::open("/tmp/noexec/prog", &opts), PermissionDenied);
+    fs::write("/tmp/prog", "prog")?;
+    assert!(RawFile::open("/tmp/prog", &opts).is_ok());
+    assert_err!(RawFile::open("/tmp", &opts), PermissionDenied);
+    fs::remove_file("/tmp/prog")?;
+
+    let mounts = fs::read_to_string("/proc/mounts")?;
+    assert!(mounts.contains("tmpfs /tmp/small tmpfs rw,size=8k,nr_inodes=3 0 0\n"));
+    assert!(mounts.contains("ramfs /tmp/ro ramfs ro 0 0\n"));
+    assert!(mounts.contains("ramfs /tmp/noexec ramfs rw,noexec 0 0\n"));
+
+    for mnt in ["/tmp/small", "/tmp/ro", "/tmp/noexec"] {
+        fs::umount(mnt)?;
+        fs::remove_dir(mnt)?;
+    }
+
+    println!("test_mount_options() OK!");
+    Ok(())
+}
+
+fn test_sysfs() -> Result<()> {
+    println!("test sysfs:");
+
+    let level = fs::read_to_string("/sys/kernel/log_level")?;
+    fs::write("/sys/kernel/log_level", "trace\n")?;
+    assert_eq!(log::max_level(), log::LevelFilter::Trace);
+    assert_eq!(fs::read_to_string("/sys/kernel/log_level")?, "trace\n");
+    assert_err!(fs::write("/sys/kernel/log_level", "bogus"), InvalidInput);
+    fs::write("/sys/kernel/log_level", level)?;
+
+    let path = "/sys/devices/system/clocksource/clocksource0/current_clocksource";
+    assert!(!fs::read_to_string(path)?.is_empty());
+    assert_err!(fs::write(path, "tsc"), PermissionDenied);
+    assert_err!(fs::create_dir("/sys/kernel/foo"), PermissionDenied);
+
+    println!("test_sysfs() OK!");
+    Ok(())
+}
+
+fn test_device_registry() -> Result<()> {
+    use axfs::fops::{self, DeviceOps};
+    use axfs_vfs::{VfsNodeAttr, VfsNodeOps, VfsNodePerm, VfsResult};
+    use std::sync::Arc;
+
+    struct TestDev;
+
+    impl VfsNodeOps for TestDev {
+        fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
+            let perm = VfsNodePerm::from_bits_truncate(0o600);
+            Ok(VfsNodeAttr::new(perm, FileType::CharDevice, 0, 0))
+        }
+        axfs_vfs::impl_vfs_non_dir_default! {}
+    }
+
+    impl DeviceOps for TestDev {
+        unsafe fn ioctl(&self, cmd: u32, arg: usize) -> VfsResult<usize> {
+            match cmd {
+                0x1234 => Ok(arg + 1),
+                _ => Err(Error::Unsupported),
+            }
+        }
+    }
+
+    let ioctl = |path: &str, cmd: u32, arg: usize| {
+        let mut opts = fops::OpenOptions::new();
+        opts.read(true);
+        let file = fops::File::open(path, &opts)?;
+        unsafe { file.ioctl(cmd, arg) }
+    };
+
+    // built-in devices
+    assert_eq!(
+        fs::metadata("/dev/console")?.file_type(),
+        FileType::CharDevice
+    );
+    let mut buf = [0; 100];
+    assert_eq!(File::open("/dev/urandom")?.read(&mut buf)?, 100);
+
+    // block devices
+    let mut size = 0u64;
+    const BLKGETSIZE64: u32 = 0x8008_1272;
+    assert_eq!(
+        ioctl("/dev/vda", BLKGETSIZE64, &mut size as *mut _ as _),
+        Ok(0)
+    );
+    assert_eq!(size, fs::metadata("/dev/vda")?.len());
+
+    // devices registered after /dev is mounted appear in it
+    fs::register_device("testdev", Arc::new(TestDev))?;
+    let dirents = fs::read_dir("/dev")?
+        .map(|e| e.unwrap().file_name())
+        .collect::<Vec<_>>();
+    assert!(dirents.contains(&"testdev".into()));
+    assert_eq!(ioctl("/dev/testdev", 0x1234, 41), Ok(42));
+    assert_err!(ioctl("/dev/testdev", 0x4321, 0), Unsupported);
+    assert_err!(
+        fs::register_device("testdev", Arc::new(TestDev)),
+        AlreadyExists
+    );
+    assert_err!(
+        fs::register_device("test/dev", Arc::new(TestDev)),
+        InvalidInput
+    );
+
+    // not supported by other files
+    assert_err!(ioctl("/dev/null", 0x1234, 0), Unsupported);
+    fs::write("/tmp/regular.txt", "test")?;
+    assert_err!(ioctl("/tmp/regular.txt", 0x1234, 0), Unsupported);
+    fs::remove_file("/tmp/regular.txt")?;
+
+    println!("test_device_registry() OK!");
+    Ok(())
+}
+
+fn test_file_times() -> Result<()> {
+    use std::time::Duration;
+
+    println!("test file timestamps:");
+    // FAT only records the date of the last access, and the modification
+    // time in 2 seconds
+    let day = Duration::from_secs(1_200_009_600);
+    let time = Duration::from_secs(1_200_012_346);
+
+    for fname in ["/tmp/times.txt", "/times.txt"] {
+        fs::write(fname, "time")?;
+        fs::set_times(fname, Some(day), Some(time))?;
+        let times = fs::metadata(fname)?.times();
+        assert_eq!((times.accessed, times.modified), (day, time));
+        fs::set_times(fname, None, Some(day))?;
+        let times = fs::metadata(fname)?.times();
+        assert_eq!((times.accessed, times.modified), (day, day));
+        fs::remove_file(fname)?;
+    }
+
+    fs::write("/tmp/times.txt", "time")?;
+    fs::symlink("times.txt", "/tmp/times.lnk")?;
+    fs::set_symlink_times("/tmp/times.lnk", None, Some(time))?;
+    assert_eq!(
+        fs::symlink_metadata("/tmp/times.lnk")?.times().modified,
+        time
+    );
+    assert_ne!(fs::metadata("/tmp/times.lnk")?.times().modified, time);
+    fs::set_times("/tmp/times.lnk", None, Some(day))?;
+    assert_eq!(fs::metadata("/tmp/times.txt")?.times().modified, day);
+    fs::remove_file("/tmp/times.lnk")?;
+    fs::remove_file("/tmp/times.txt")?;
+
+    assert_err!(fs::set_times("/dev/null", None, Some(time)), Unsupported);
+
+    println!("test_file_times() OK!");
+    Ok(())
+}
+
+fn test_file_locks() -> Result<()> {
+    use axfs::fops::{self, FileLock, LockType};
+
+    println!("test file locks:");
+    for fname in ["/tmp/locks.txt", "/locks.txt"] {
+        fs::write(fname, "lock")?;
+        let (file1, file2) = (File::open(fname)?, File::open(fname)?);
+        file1.lock_shared()?;
+        assert!(file2.try_lock_shared()?);
+        assert!(!file2.try_lock()?);
+        assert_err!(file1.lock(), WouldBlock); // no other task to unlock it
+        file2.unlock()?;
+        file1.lock()?;
+        assert!(!file2.try_lock_shared()?);
+        drop(file1); // released on close
+        assert!(file2.try_lock()?);
+        drop(file2);
+
+        // byte-range locks of the same task don't conflict
+        let mut opts = fops::OpenOptions::new();
+        opts.write(true);
+        let file = fops::File::open(fname, &opts)?;
+        let lock = FileLock::new(LockType::Exclusive, 1, 2);
+        file.set_lock(&lock, false)?;
+        file.set_lock(&FileLock::new(LockType::Exclusive, 0, 0), false)?;
+        assert_eq!(file.get_lock(&lock)?, None);
+        let shared = FileLock::new(LockType::Shared, 0, 0);
+        assert_err!(file.set_lock(&shared, false), PermissionDenied);
+        file.set_lock(&FileLock::new(LockType::Unlock, 0, 0), false)?;
+        drop(file);
+        fs::remove_file(fname)?;
+    }
+
+    println!("test_file_locks() OK!");
+    Ok(())
+}
+
+fn test_statfs() -> Result<()> {
+    println!("test statfs:");
+    let root = fs::statfs("/")?;
+    assert!(root.blocks_free <= root.blocks && root.name_max > 0);
+
+    let tmp = fs::statfs("/tmp")?;
+    assert_eq!(tmp.fs_type, 0x0102_1994); // TMPFS_MAGIC
+    assert!(tmp.blocks > 0 && tmp.files > 0);
+    fs::write("/tmp/statfs.txt", "statfs")?;
+    let file = File::open("/tmp/statfs.txt")?;
+    assert_eq!(file.fs_stat()?.files_free, tmp.files_free - 1);
+    drop(file);
+    fs::remove_file("/tmp/statfs.txt")?;
+    assert_eq!(fs::statfs("/tmp")?.files_free, tmp.files_free);
+
+    let proc = fs::statfs("/proc/mounts")?;
+    assert_eq!((proc.fs_type, proc.blocks), (0x9fa0, 0));
+    assert_err!(fs::statfs("/tmp/not-exist"), NotFound);
+
+    println!("test_statfs() OK!");
+    Ok(())
+}
+
+fn test_notify() -> Result<()> {
+    use fs::{FsEvent, WatchMask, Watcher};
+    println!("test notify:");
+    fs::create_dir("/tmp/watched")?;
+    let watcher = Watcher::new();
+    let wd = watcher.add_watch("/tmp/watched", WatchMask::ALL_EVENTS)?;
+    assert_err!(
+        watcher.add_watch("/tmp/not-exist", WatchMask::CREATE),
+        NotFound
+    );
+
+    fs::write("/tmp/watched/a.txt", "notify")?;
+    fs::rename("/tmp/watched/a.txt", "/tmp/watched/b.txt")?;
+    fs::create_dir("/tmp/watched/dir")?;
+    fs::remove_file("/tmp/watched/b.txt")?;
+    fs::write("/tmp/unwatched.txt", "notify")?;
+    fs::remove_file("/tmp/unwatched.txt")?;
+
+    let mut events = Vec::new();
+    watcher.read_events(false, |ev: &FsEvent| {
+        events.push(ev.clone());
+        true
+    })?;
+    let kinds: Vec<_> = events
+        .iter()
+        .map(|ev| (ev.mask, ev.name.as_str()))
+        .collect();
+    assert_eq!(
+        kinds,
+        [
+            (WatchMask::CREATE, "a.txt"),
+            (WatchMask::OPEN, "a.txt"),
+            (WatchMask::MODIFY, "a.txt"),
+            (WatchMask::CLOSE_WRITE, "a.txt"),
+            (WatchMask::MOVED_FROM, "a.txt"),
+            (WatchMask::MOVED_TO, "b.txt"),
+            (WatchMask::CREATE | WatchMask::ISDIR, "dir"),
+            (WatchMask::DELETE, "b.txt"),
+        ]
+    );
+    assert!(events.iter().all(|ev| ev.wd == wd));
+    assert!(events[4].cookie != 0 && events[4].cookie == events[5].cookie);
+    assert_err!(watcher.read_events(false, |_| true), WouldBlock);
+
+    // removing the watched directory removes the watch
+    fs::remove_dir("/tmp/watched/dir")?;
+    fs::remove_dir("/tmp/watched")?;
+    let mut masks = Vec::new();
+    watcher.read_events(false, |ev| {
+        masks.push(ev.mask);
+        true
+    })?;
+    assert_eq!(
+        masks,
+        [
+            WatchMask::DELETE | WatchMask::ISDIR,
+            WatchMask::DELETE_SELF,
+            WatchMask::IGNORED
+        ]
+    );
+    assert_err!(watcher.remove_watch(wd), InvalidInput);
+
+    println!("test_notify() OK!");
+    Ok(())
+}
+
+fn test_root_dir() -> Result<()> {
+    println!("test root directory in /tmp:");
+    fs::create_dir_all("/tmp/jail/sub")?;
+    fs::write("/tmp/jail/f", "jailed")?;
+    fs::set_current_dir("/tmp/jail/sub")?;
+
+    // there's no way out, so run this test last
+    fs::set_root_dir("/tmp/jail")?;
+    assert_eq!(fs::current_dir()?, "/");
+    assert_eq!(fs::read("/f"), Ok("jailed".into()));
+    assert_eq!(fs::read("/../../f"), Ok("jailed".into()));
+    assert_err!(fs::metadata("/tmp"), NotFound);
+
+    fs::set_current_dir("sub")?;
+    assert_eq!(fs::current_dir()?, "/sub/");
+    assert_eq!(fs::read("../../f"), Ok("jailed".into()));
+    fs::set_current_dir("/")?;
+    assert_eq!(fs::current_dir()?, "/");
+    assert_err!(fs::set_current_dir("/f"), NotADirectory);
+    assert_err!(fs::set_current_dir("nonexistent/.."), NotFound);
+    fs::set_current_dir("sub/..")?;
+    assert_eq!(fs::current_dir()?, "/");
+
+    println!("test_root_dir() OK!");
+    Ok(())
+}
+
 pub fn test_all() {
     test_read_write_file().expect("test_read_write_file() failed");
     test_read_dir().expect("test_read_dir() failed");
@@ -259,4 +801,16 @@ pub fn test_all() {
     test_create_file_dir().expect("test_create_file_dir() failed");
     test_remove_file_dir().expect("test_remove_file_dir() failed");
     test_devfs_ramfs().expect("test_devfs_ramfs() failed");
+    test_device_registry().expect("test_device_registry() failed");
+    test_mount_umount().expect("test_mount_umount() failed");
+    test_symlinks().expect("test_symlinks() failed");
+    test_rename().expect("test_rename() failed");
+    test_procfs().expect("test_procfs() failed");
+    test_mount_options().expect("test_mount_options() failed");
+    test_sysfs().expect("test_sysfs() failed");
+    test_file_times().expect("test_file_times() failed");
+    test_file_locks().expect("test_file_locks() failed");
+    test_statfs().expect("test_statfs() failed");
+    test_notify().expect("test_notify() failed");
+    test_root_dir().expect("test_root_dir() failed");
 }
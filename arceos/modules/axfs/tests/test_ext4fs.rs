@@ -0,0 +1,54 @@
+#![cfg(all(feature = "ext4fs", not(feature = "myfs")))]
+
+mod test_common;
+
+use axdriver::AxDeviceContainer;
+use axdriver_block::ramdisk::RamDisk;
+use axfs::api::{self as fs, File, FileType};
+use axio::Error;
+
+const IMG_PATH: &str = "resources/ext4.img";
+
+fn make_disk() -> std::io::Result<RamDisk> {
+    let path = std::env::current_dir()?.join(IMG_PATH);
+    println!("Loading disk image from {:?} ...", path);
+    let data = std::fs::read(path)?;
+    println!("size = {} bytes", data.len());
+    Ok(RamDisk::from(&data))
+}
+
+#[test]
+fn test_ext4fs() {
+    println!("Testing ext4fs with ramdisk ...");
+
+    let disk = make_disk().expect("failed to load disk image");
+    axtask::init_scheduler(); // call this to use `axsync::Mutex`.
+    axfs::init_filesystems(AxDeviceContainer::from_one(disk));
+
+    // metadata from the image
+    assert!(fs::metadata("/lost+found").unwrap().is_dir());
+    let md = fs::metadata("/readonly.txt").unwrap();
+    assert_eq!(md.permissions().bits(), 0o444);
+    assert_eq!(
+        File::options().write(true).open("/readonly.txt").err(),
+        Some(Error::PermissionDenied)
+    );
+    let md = fs::symlink_metadata("/link.txt").unwrap();
+    assert_eq!(md.file_type(), FileType::SymLink);
+    assert_eq!(fs::read_link("/link.txt").unwrap(), "short.txt");
+    assert_eq!(fs::read_to_string("/link.txt").unwrap(), "Rust is cool!\n");
+
+    // new links on disk
+    fs::symlink("very/long/path", "/path-link").unwrap();
+    assert_eq!(
+        fs::read_to_string("/path-link/test.txt").unwrap(),
+        "Rust is cool!\n"
+    );
+    fs::hard_link("/path-link/test.txt", "/hard.txt").unwrap();
+    fs::remove_file("/very/long/path/test.txt").unwrap();
+    assert_eq!(fs::read_to_string("/hard.txt").unwrap(), "Rust is cool!\n");
+    fs::rename("/hard.txt", "/path-link/test.txt").unwrap();
+    fs::remove_file("/path-link").unwrap();
+
+    test_common::test_all();
+}
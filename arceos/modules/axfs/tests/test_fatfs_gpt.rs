@@ -0,0 +1,80 @@
+#![cfg(not(feature = "myfs"))]
+
+mod test_common;
+
+use axdriver::AxDeviceContainer;
+use axdriver_block::ramdisk::RamDisk;
+use axfs::api::{self as fs, FileType};
+
+const IMG_PATH: &str = "resources/fat16.img";
+const BLOCK_SIZE: usize = 512;
+const ENTRY_SIZE: usize = 128;
+
+fn gpt_entry(first_lba: u64, last_lba: u64) -> [u8; ENTRY_SIZE] {
+    // "Microsoft basic data" partition type GUID
+    const BASIC_DATA: [u8; 16] = [
+        0xa2, 0xa0, 0xd0, 0xeb, 0xe5, 0xb9, 0x33, 0x44, 0x87, 0xc0, 0x68, 0xb6, 0xb7, 0x26, 0x99,
+        0xc7,
+    ];
+    let mut entry = [0; ENTRY_SIZE];
+    entry[0..16].copy_from_slice(&BASIC_DATA);
+    entry[16] = 1; // unique partition GUID
+    entry[32..40].copy_from_slice(&first_lba.to_le_bytes());
+    entry[40..48].copy_from_slice(&last_lba.to_le_bytes());
+    entry
+}
+
+/// Builds a disk with the FAT image as GPT partition 1, and an empty
+/// partition in slot 3, leaving slot 2 unused.
+fn make_disk() -> std::io::Result<(RamDisk, usize)> {
+    let path = std::env::current_dir()?.join(IMG_PATH);
+    println!("Loading disk image from {:?} ...", path);
+    let fat = std::fs::read(path)?;
+    let fat_blocks = (fat.len() / BLOCK_SIZE) as u64;
+
+    let part1_start = 2048;
+    let part3_start = part1_start + fat_blocks.next_multiple_of(2048);
+    let total_blocks = part3_start + 2048 + 34; // leave space for the backup GPT
+    let mut data = vec![0; total_blocks as usize * BLOCK_SIZE];
+
+    // protective MBR
+    let mbr = &mut data[..BLOCK_SIZE];
+    mbr[0x1be + 4] = 0xee;
+    mbr[0x1be + 8..0x1be + 12].copy_from_slice(&1u32.to_le_bytes());
+    mbr[0x1be + 12..0x1be + 16].copy_from_slice(&((total_blocks - 1) as u32).to_le_bytes());
+    mbr[510] = 0x55;
+    mbr[511] = 0xaa;
+
+    // GPT header, with the partition entry array at LBA 2
+    let header = &mut data[BLOCK_SIZE..2 * BLOCK_SIZE];
+    header[0..8].copy_from_slice(b"EFI PART");
+    header[72..80].copy_from_slice(&2u64.to_le_bytes());
+    header[80..84].copy_from_slice(&128u32.to_le_bytes());
+    header[84..88].copy_from_slice(&(ENTRY_SIZE as u32).to_le_bytes());
+
+    let entries = &mut data[2 * BLOCK_SIZE..];
+    entries[..ENTRY_SIZE].copy_from_slice(&gpt_entry(part1_start, part1_start + fat_blocks - 1));
+    entries[2 * ENTRY_SIZE..3 * ENTRY_SIZE]
+        .copy_from_slice(&gpt_entry(part3_start, part3_start + 2047));
+
+    data[part1_start as usize * BLOCK_SIZE..][..fat.len()].copy_from_slice(&fat);
+    println!("size = {} bytes", data.len());
+    Ok((RamDisk::from(&data), fat.len()))
+}
+
+#[test]
+fn test_fatfs_gpt() {
+    println!("Testing fatfs on a GPT partition with ramdisk ...");
+
+    let (disk, fat_size) = make_disk().expect("failed to load disk image");
+    axtask::init_scheduler(); // call this to use `axsync::Mutex`.
+    axfs::init_filesystems(AxDeviceContainer::from_one(disk));
+
+    let md = fs::metadata("/dev/vda1").unwrap();
+    assert_eq!(md.file_type(), FileType::BlockDevice);
+    assert_eq!(md.len(), fat_size as u64);
+    assert!(fs::metadata("/dev/vda2").is_err());
+    assert_eq!(fs::metadata("/dev/vda3").unwrap().len(), 2048 * 512);
+
+    test_common::test_all();
+}
@@ -0,0 +1,70 @@
+#![cfg(not(feature = "myfs"))]
+
+mod test_common;
+
+use axdriver::AxDeviceContainer;
+use axdriver_block::ramdisk::RamDisk;
+use axfs::api::{self as fs, FileType};
+
+const IMG_PATH: &str = "resources/fat16.img";
+const BLOCK_SIZE: usize = 512;
+
+fn mbr_entry(ty: u8, start_block: u32, num_blocks: u32) -> [u8; 16] {
+    let mut entry = [0; 16];
+    entry[4] = ty;
+    entry[8..12].copy_from_slice(&start_block.to_le_bytes());
+    entry[12..16].copy_from_slice(&num_blocks.to_le_bytes());
+    entry
+}
+
+fn write_mbr(block: &mut [u8], entries: &[[u8; 16]]) {
+    for (i, entry) in entries.iter().enumerate() {
+        block[0x1be + i * 16..0x1be + (i + 1) * 16].copy_from_slice(entry);
+    }
+    block[510] = 0x55;
+    block[511] = 0xaa;
+}
+
+/// Builds a disk with the FAT image as the primary partition 1, and an empty
+/// logical partition 5 in an extended partition.
+fn make_disk() -> std::io::Result<(RamDisk, usize)> {
+    let path = std::env::current_dir()?.join(IMG_PATH);
+    println!("Loading disk image from {:?} ...", path);
+    let fat = std::fs::read(path)?;
+    let fat_blocks = fat.len() / BLOCK_SIZE;
+
+    let part1_start = 2048;
+    let (ext_start, ext_blocks) = (part1_start + fat_blocks.next_multiple_of(2048), 4096);
+    let mut data = vec![0; (ext_start + ext_blocks) * BLOCK_SIZE];
+    write_mbr(
+        &mut data[..BLOCK_SIZE],
+        &[
+            mbr_entry(0x0c, part1_start as u32, fat_blocks as u32),
+            mbr_entry(0x05, ext_start as u32, ext_blocks as u32),
+        ],
+    );
+    write_mbr(
+        &mut data[ext_start * BLOCK_SIZE..][..BLOCK_SIZE],
+        &[mbr_entry(0x83, 1, 2048)],
+    );
+    data[part1_start * BLOCK_SIZE..][..fat.len()].copy_from_slice(&fat);
+    println!("size = {} bytes", data.len());
+    Ok((RamDisk::from(&data), fat.len()))
+}
+
+#[test]
+fn test_fatfs_mbr() {
+    println!("Testing fatfs on an MBR partition with ramdisk ...");
+
+    let (disk, fat_size) = make_disk().expect("failed to load disk image");
+    axtask::init_scheduler(); // call this to use `axsync::Mutex`.
+    axfs::init_filesystems(AxDeviceContainer::from_one(disk));
+
+    let md = fs::metadata("/dev/vda1").unwrap();
+    assert_eq!(md.file_type(), FileType::BlockDevice);
+    assert_eq!(md.len(), fat_size as u64);
+    assert_eq!(fs::metadata("/dev/vda5").unwrap().len(), 2048 * 512);
+    assert!(fs::metadata("/dev/vda2").is_err()); // the extended partition
+
+    test_common::test_all();
+}
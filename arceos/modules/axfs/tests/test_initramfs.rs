@@ -0,0 +1,37 @@
+#![cfg(all(feature = "initramfs", initramfs_path))]
+
+// Run with `AX_INITRAMFS=resources/initramfs.cpio cargo test --features initramfs`.
+
+mod test_common;
+
+use axdriver::AxDeviceContainer;
+use axdriver_block::ramdisk::RamDisk;
+use axfs::api as fs;
+use axio::Result;
+
+fn test_initramfs_root() -> Result<()> {
+    println!("test initramfs root:");
+    let mounts = fs::read_to_string("/proc/mounts")?;
+    assert!(mounts.starts_with("rootfs / rootfs rw 0 0\n"));
+
+    // symlinks are unpacked as well
+    assert_eq!(fs::read_link("/link.txt")?, "short.txt");
+    assert_eq!(fs::read_to_string("/link.txt")?, "Rust is cool!\n");
+    fs::remove_file("/link.txt")?;
+
+    println!("test_initramfs_root() OK!");
+    Ok(())
+}
+
+#[test]
+fn test_initramfs() {
+    println!("Testing initramfs ...");
+
+    axtask::init_scheduler(); // call this to use `axsync::Mutex`.
+
+    // the initramfs takes precedence over the disk, which is only in devfs
+    axfs::init_filesystems(AxDeviceContainer::from_one(RamDisk::default()));
+
+    test_initramfs_root().expect("test_initramfs_root() failed");
+    test_common::test_all();
+}
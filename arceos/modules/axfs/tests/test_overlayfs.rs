@@ -0,0 +1,81 @@
+#![cfg(all(feature = "overlayfs", not(feature = "myfs")))]
+
+mod test_common;
+
+use axdriver::AxDeviceContainer;
+use axdriver_block::ramdisk::RamDisk;
+use axfs::api::{self as fs, OpenOptions};
+use axio::{Error, Result, Write};
+
+macro_rules! assert_err {
+    ($expr: expr, $err: ident) => {
+        assert_eq!(($expr).err(), Some(Error::$err))
+    };
+}
+
+const IMG_PATH: &str = "resources/fat16.img";
+
+fn make_disk() -> std::io::Result<RamDisk> {
+    let path = std::env::current_dir()?.join(IMG_PATH);
+    println!("Loading disk image from {:?} ...", path);
+    let data = std::fs::read(path)?;
+    println!("size = {} bytes", data.len());
+    Ok(RamDisk::from(&data))
+}
+
+fn file_names(path: &str) -> Result<Vec<String>> {
+    let mut names = fs::read_dir(path)?
+        .map(|entry| entry.map(|e| e.file_name()))
+        .collect::<Result<Vec<_>>>()?;
+    names.sort();
+    Ok(names)
+}
+
+fn test_overlay() -> Result<()> {
+    println!("test overlay on FAT:");
+    let mounts = fs::read_to_string("/proc/mounts")?;
+    assert!(mounts.contains(" / overlay rw 0 0\n"));
+
+    // files are copied up when they're modified
+    let fname = "/very-long-dir-name/very-long-file-name.txt";
+    let mut file = OpenOptions::new().append(true).open(fname)?;
+    file.write_all(b"Overlay too!\n")?;
+    drop(file);
+    assert_eq!(fs::read_to_string(fname)?, "Rust is cool!\nOverlay too!\n");
+
+    // removed files are hidden, and recreated directories are empty
+    fs::remove_file(fname)?;
+    assert_err!(fs::metadata(fname), NotFound);
+    assert_eq!(fs::read_dir("/very-long-dir-name")?.count(), 0);
+    fs::remove_dir("/very-long-dir-name")?;
+    assert!(!file_names("/")?.iter().any(|n| n == "very-long-dir-name"));
+    fs::create_dir("/very-long-dir-name")?;
+    assert_eq!(fs::read_dir("/very-long-dir-name")?.count(), 0);
+
+    // directories in both layers are merged
+    fs::write("/very/upper.txt", "upper")?;
+    assert_eq!(file_names("/very")?, ["long", "upper.txt"]);
+    fs::rename("/very/upper.txt", "/upper.txt")?;
+    assert_eq!(file_names("/very")?, ["long"]);
+    fs::remove_file("/upper.txt")?;
+    assert_err!(fs::rename("/very", "/very2"), Unsupported);
+
+    // whiteouts are not visible
+    assert_err!(fs::write("/.wh.foo", "foo"), InvalidInput);
+    assert_err!(fs::metadata("/.wh.very-long-dir-name"), NotFound);
+
+    println!("test_overlay() OK!");
+    Ok(())
+}
+
+#[test]
+fn test_overlayfs() {
+    println!("Testing overlayfs with ramdisk ...");
+
+    let disk = make_disk().expect("failed to load disk image");
+    axtask::init_scheduler(); // call this to use `axsync::Mutex`.
+    axfs::init_filesystems(AxDeviceContainer::from_one(disk));
+
+    test_overlay().expect("test_overlay() failed");
+    test_common::test_all();
+}
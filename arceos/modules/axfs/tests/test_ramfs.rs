@@ -7,7 +7,7 @@ use std::sync::Arc;
 use axdriver::AxDeviceContainer;
 use axdriver_block::ramdisk::RamDisk;
 use axfs::api::{self as fs, File};
-use axfs::fops::{Disk, MyFileSystemIf};
+use axfs::fops::{BlockDevice, MyFileSystemIf};
 use axfs_ramfs::RamFileSystem;
 use axfs_vfs::VfsOps;
 use axio::{Result, Write};
@@ -16,7 +16,7 @@ struct MyFileSystemIfImpl;
 
 #[crate_interface::impl_interface]
 impl MyFileSystemIf for MyFileSystemIfImpl {
-    fn new_myfs(_disk: Disk) -> Arc<dyn VfsOps> {
+    fn new_myfs(_dev: Arc<dyn BlockDevice>) -> Arc<dyn VfsOps> {
         Arc::new(RamFileSystem::new())
     }
 }
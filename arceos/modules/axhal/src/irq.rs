@@ -1,5 +1,7 @@
 //! Interrupt management.
 
+use core::sync::atomic::{AtomicUsize, Ordering};
+
 use handler_table::HandlerTable;
 
 use crate::platform::irq::{dispatch_irq, MAX_IRQ_COUNT};
@@ -12,10 +14,32 @@ pub type IrqHandler = handler_table::Handler;
 
 static IRQ_HANDLER_TABLE: HandlerTable<MAX_IRQ_COUNT> = HandlerTable::new();
 
+/// The number of times each IRQ has occurred.
+static IRQ_COUNTS: [AtomicUsize; MAX_IRQ_COUNT] = [const { AtomicUsize::new(0) }; MAX_IRQ_COUNT];
+
+/// Returns the number of times each IRQ has occurred, as pairs of the IRQ
+/// number and the count. IRQs that never occurred are skipped.
+pub fn irq_counts() -> impl Iterator<Item = (usize, usize)> {
+    IRQ_COUNTS.iter().enumerate().filter_map(|(irq_num, count)| {
+        let count = count.load(Ordering::Relaxed);
+        (count > 0).then_some((irq_num, count))
+    })
+}
+
+/// Counts an occurrence of the IRQ, for IRQs not dispatched by
+/// [`dispatch_irq_common`].
+#[allow(dead_code)]
+pub(crate) fn count_irq(irq_num: usize) {
+    if let Some(count) = IRQ_COUNTS.get(irq_num) {
+        count.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
 /// Platform-independent IRQ dispatching.
 #[allow(dead_code)]
 pub(crate) fn dispatch_irq_common(irq_num: usize) {
     trace!("IRQ {}", irq_num);
+    count_irq(irq_num);
     if !IRQ_HANDLER_TABLE.handle(irq_num) {
         warn!("Unhandled IRQ {}", irq_num);
     }
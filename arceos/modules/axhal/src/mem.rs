@@ -1,6 +1,7 @@
 //! Physical memory management.
 
 use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 #[doc(no_inline)]
 pub use memory_addr::{MemoryAddr, PhysAddr, VirtAddr, PAGE_SIZE_4K};
@@ -70,9 +71,52 @@ pub const fn phys_to_virt(paddr: PhysAddr) -> VirtAddr {
     va!(paddr.as_usize() + axconfig::PHYS_VIRT_OFFSET)
 }
 
+/// The physical address range of the initial ramdisk loaded by the
+/// bootloader, empty if there is none.
+static INITRD_START: AtomicUsize = AtomicUsize::new(0);
+static INITRD_END: AtomicUsize = AtomicUsize::new(0);
+
 /// Returns an iterator over all physical memory regions.
 pub fn memory_regions() -> impl Iterator<Item = MemRegion> {
-    kernel_image_regions().chain(crate::platform::mem::platform_regions())
+    kernel_image_regions()
+        .chain(initrd_region())
+        .chain(crate::platform::mem::platform_regions())
+}
+
+/// Returns the initial ramdisk (e.g., a cpio archive) loaded by the
+/// bootloader, if any.
+///
+/// Its memory is reserved, so it's never used for allocation.
+pub fn initrd() -> Option<&'static [u8]> {
+    let (start, end) = initrd_range()?;
+    let ptr = phys_to_virt(start).as_ptr();
+    Some(unsafe { core::slice::from_raw_parts(ptr, end.as_usize() - start.as_usize()) })
+}
+
+/// Records the physical address range of the initial ramdisk, before the
+/// memory regions are used.
+#[allow(dead_code)]
+pub(crate) fn set_initrd(start: PhysAddr, end: PhysAddr) {
+    INITRD_START.store(start.as_usize(), Ordering::Relaxed);
+    INITRD_END.store(end.as_usize(), Ordering::Relaxed);
+}
+
+fn initrd_range() -> Option<(PhysAddr, PhysAddr)> {
+    let start = INITRD_START.load(Ordering::Relaxed);
+    let end = INITRD_END.load(Ordering::Relaxed);
+    (start < end).then(|| (pa!(start), pa!(end)))
+}
+
+/// Returns the memory region of the initial ramdisk, if any.
+fn initrd_region() -> Option<MemRegion> {
+    let (start, end) = initrd_range()?;
+    let (start, end) = (start.align_down_4k(), end.align_up_4k());
+    Some(MemRegion {
+        paddr: start,
+        size: end.as_usize() - start.as_usize(),
+        flags: MemRegionFlags::RESERVED | MemRegionFlags::READ,
+        name: "initrd",
+    })
 }
 
 /// Returns the memory regions of the kernel image (code and data sections).
@@ -126,17 +170,26 @@ pub(crate) fn default_mmio_regions() -> impl Iterator<Item = MemRegion> {
     })
 }
 
-/// Returns the default free memory regions (kernel image end to physical memory end).
+/// Returns the default free memory regions (kernel image end to physical memory end),
+/// without the initial ramdisk.
 #[allow(dead_code)]
 pub(crate) fn default_free_regions() -> impl Iterator<Item = MemRegion> {
     let start = virt_to_phys((_ekernel as usize).into()).align_up_4k();
     let end = pa!(axconfig::PHYS_MEMORY_END).align_down_4k();
-    core::iter::once(MemRegion {
+    let free = |start: PhysAddr, end: PhysAddr| MemRegion {
         paddr: start,
-        size: end.as_usize() - start.as_usize(),
+        size: end.as_usize().saturating_sub(start.as_usize()),
         flags: MemRegionFlags::FREE | MemRegionFlags::READ | MemRegionFlags::WRITE,
         name: "free memory",
-    })
+    };
+    let regions = match initrd_region() {
+        Some(rd) if rd.paddr < end && rd.paddr + rd.size > start => [
+            free(start, rd.paddr),
+            free((rd.paddr + rd.size).max(start), end),
+        ],
+        _ => [free(start, end), free(end, end)],
+    };
+    regions.into_iter().filter(|r| r.size > 0)
 }
 
 /// Fills the `.bss` section with zeros.
@@ -0,0 +1,119 @@
+//! A minimal reader of the flattened device tree (FDT) passed by the
+//! bootloader, used before any memory allocator is available.
+
+use crate::mem::{phys_to_virt, PhysAddr};
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+const FDT_END: u32 = 9;
+
+struct Fdt {
+    data: &'static [u8],
+    structs: usize,
+    strings: usize,
+}
+
+impl Fdt {
+    /// Checks the header of the FDT at the physical address `dtb`.
+    unsafe fn from_paddr(dtb: usize) -> Option<Self> {
+        if dtb == 0 {
+            return None;
+        }
+        let ptr = phys_to_virt(dtb.into()).as_ptr();
+        let header = core::slice::from_raw_parts(ptr, 40);
+        let be32 = |off: usize| u32::from_be_bytes(header[off..off + 4].try_into().unwrap());
+        if be32(0) != FDT_MAGIC {
+            return None;
+        }
+        Some(Self {
+            data: core::slice::from_raw_parts(ptr, be32(4) as usize),
+            structs: be32(8) as usize,
+            strings: be32(12) as usize,
+        })
+    }
+
+    fn be32(&self, off: usize) -> Option<u32> {
+        let bytes = self.data.get(off..off + 4)?;
+        Some(u32::from_be_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn cstr(&self, off: usize) -> Option<&'static [u8]> {
+        let rest = self.data.get(off..)?;
+        let len = rest.iter().position(|&b| b == 0)?;
+        Some(&rest[..len])
+    }
+
+    /// Calls `f` with the name and value of each property of the top-level
+    /// node `node`.
+    fn for_each_prop(&self, node: &[u8], mut f: impl FnMut(&[u8], &[u8])) -> Option<()> {
+        let mut off = self.structs;
+        let mut depth = 0;
+        let mut found = false;
+        loop {
+            let token = self.be32(off)?;
+            off += 4;
+            match token {
+                FDT_BEGIN_NODE => {
+                    let name = self.cstr(off)?;
+                    off = (off + name.len() + 4) & !3;
+                    depth += 1;
+                    // unit addresses (`@...`) are not used for matching
+                    if depth == 2 && name.split(|&b| b == b'@').next() == Some(node) {
+                        found = true;
+                    }
+                }
+                FDT_END_NODE => {
+                    if found && depth == 2 {
+                        return Some(());
+                    }
+                    depth -= 1;
+                }
+                FDT_PROP => {
+                    let len = self.be32(off)? as usize;
+                    let name = self.cstr(self.strings + self.be32(off + 4)? as usize)?;
+                    let value = self.data.get(off + 8..off + 8 + len)?;
+                    off = (off + 8 + len + 3) & !3;
+                    if found && depth == 2 {
+                        f(name, value);
+                    }
+                }
+                FDT_NOP => {}
+                FDT_END => return Some(()),
+                _ => return None,
+            }
+        }
+    }
+}
+
+/// Parses a 32-bit or 64-bit big-endian address.
+fn parse_addr(value: &[u8]) -> Option<usize> {
+    match value.len() {
+        4 => Some(u32::from_be_bytes(value.try_into().unwrap()) as usize),
+        8 => Some(u64::from_be_bytes(value.try_into().unwrap()) as usize),
+        _ => None,
+    }
+}
+
+/// Returns the physical address range of the initial ramdisk, given by the
+/// `linux,initrd-start` and `linux,initrd-end` properties of `/chosen`.
+///
+/// # Safety
+///
+/// `dtb` must be 0 or the physical address of a valid FDT, which is mapped
+/// at [`phys_to_virt`].
+pub(crate) unsafe fn initrd_range(dtb: usize) -> Option<(PhysAddr, PhysAddr)> {
+    let fdt = Fdt::from_paddr(dtb)?;
+    let (mut start, mut end) = (None, None);
+    fdt.for_each_prop(b"chosen", |name, value| match name {
+        b"linux,initrd-start" => start = parse_addr(value),
+        b"linux,initrd-end" => end = parse_addr(value),
+        _ => {}
+    })?;
+    match (start, end) {
+        (Some(start), Some(end)) if start < end => Some((start.into(), end.into())),
+        _ => None,
+    }
+}
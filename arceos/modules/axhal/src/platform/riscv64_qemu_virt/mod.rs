@@ -23,6 +23,9 @@ unsafe extern "C" fn rust_entry(cpu_id: usize, dtb: usize) {
     crate::cpu::init_primary(cpu_id);
     crate::arch::set_trap_vector_base(trap_vector_base as usize);
     self::time::init_early();
+    if let Some((start, end)) = super::fdt::initrd_range(dtb) {
+        crate::mem::set_initrd(start, end);
+    }
     rust_main(cpu_id, dtb);
 }
 
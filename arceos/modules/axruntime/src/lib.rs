@@ -22,6 +22,9 @@
 #[macro_use]
 extern crate axlog;
 
+#[cfg(feature = "fs")]
+extern crate alloc;
+
 #[cfg(all(target_os = "none", not(test)))]
 mod lang_items;
 
@@ -190,6 +193,10 @@ pub extern "C" fn rust_main(cpu_id: usize, dtb: usize) -> ! {
         core::hint::spin_loop();
     }
 
+    // the system shuts down when the main task exits
+    #[cfg(all(feature = "multitask", feature = "fs"))]
+    axtask::current().on_exit(|_| sync_filesystems());
+
     unsafe { main() };
 
     #[cfg(feature = "multitask")]
@@ -197,7 +204,23 @@ pub extern "C" fn rust_main(cpu_id: usize, dtb: usize) -> ! {
     #[cfg(not(feature = "multitask"))]
     {
         debug!("main task exited: exit_code={}", 0);
-        axhal::misc::terminate();
+        terminate();
+    }
+}
+
+/// Shuts down the system, after writing the cached data of filesystems back
+/// to the devices.
+pub fn terminate() -> ! {
+    #[cfg(feature = "fs")]
+    sync_filesystems();
+    axhal::misc::terminate()
+}
+
+#[cfg(feature = "fs")]
+fn sync_filesystems() {
+    info!("Syncing filesystems...");
+    if axfs::api::sync().is_err() {
+        warn!("failed to sync filesystems");
     }
 }
 
@@ -262,25 +285,46 @@ fn init_allocator() {
 #[cfg(feature = "irq")]
 fn init_interrupt() {
     use axhal::time::TIMER_IRQ_NUM;
+    use core::sync::atomic::AtomicU64;
 
-    // Setup timer interrupt handler
-    const PERIODIC_INTERVAL_NANOS: u64 =
-        axhal::time::NANOS_PER_SEC / axconfig::TICKS_PER_SEC as u64;
+    // Setup timer interrupt handler, the interval can be changed at runtime
+    static PERIODIC_INTERVAL_NANOS: AtomicU64 =
+        AtomicU64::new(axhal::time::NANOS_PER_SEC / axconfig::TICKS_PER_SEC as u64);
 
     #[percpu::def_percpu]
     static NEXT_DEADLINE: u64 = 0;
 
     fn update_timer() {
+        let interval = PERIODIC_INTERVAL_NANOS.load(Ordering::Relaxed);
         let now_ns = axhal::time::monotonic_time_nanos();
         // Safety: we have disabled preemption in IRQ handler.
         let mut deadline = unsafe { NEXT_DEADLINE.read_current_raw() };
         if now_ns >= deadline {
-            deadline = now_ns + PERIODIC_INTERVAL_NANOS;
+            deadline = now_ns + interval;
         }
-        unsafe { NEXT_DEADLINE.write_current_raw(deadline + PERIODIC_INTERVAL_NANOS) };
+        unsafe { NEXT_DEADLINE.write_current_raw(deadline + interval) };
         axhal::time::set_oneshot_timer(deadline);
     }
 
+    // Time slices of the scheduler are counted in timer ticks, so this also
+    // scales them.
+    #[cfg(feature = "fs")]
+    axfs::api::add_tunable(
+        "sched_tick_us",
+        || alloc::format!("{}", PERIODIC_INTERVAL_NANOS.load(Ordering::Relaxed) / 1000),
+        |value| {
+            let us: u64 = value
+                .trim()
+                .parse()
+                .map_err(|_| axerrno::AxError::InvalidInput)?;
+            if !(1..=axhal::time::MICROS_PER_SEC).contains(&us) {
+                return Err(axerrno::AxError::InvalidInput);
+            }
+            PERIODIC_INTERVAL_NANOS.store(us * 1000, Ordering::Relaxed);
+            Ok(())
+        },
+    );
+
     axhal::irq::register_handler(TIMER_IRQ_NUM, || {
         update_timer();
         #[cfg(feature = "multitask")]
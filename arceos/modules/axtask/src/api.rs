@@ -1,11 +1,15 @@
 //! Task APIs for multi-task configuration.
 
-use alloc::{string::String, sync::Arc};
+use alloc::{string::String, sync::Arc, vec::Vec};
 
-pub(crate) use crate::run_queue::{AxRunQueue, RUN_QUEUE};
+pub(crate) use crate::run_queue::current_run_queue;
 
 #[doc(cfg(feature = "multitask"))]
-pub use crate::task::{CurrentTask, TaskId, TaskInner};
+pub use crate::cpumask::CpuMask;
+#[doc(cfg(feature = "multitask"))]
+pub use crate::task::{
+    CurrentTask, DeadlineParams, Interrupted, SchedClass, TaskId, TaskInner, TaskState,
+};
 #[doc(cfg(feature = "multitask"))]
 pub use crate::task_ext::{TaskExtMut, TaskExtRef};
 #[doc(cfg(feature = "multitask"))]
@@ -15,7 +19,11 @@ pub use crate::wait_queue::WaitQueue;
 pub type AxTaskRef = Arc<AxTask>;
 
 cfg_if::cfg_if! {
-    if #[cfg(feature = "sched_rr")] {
+    if #[cfg(feature = "sched_class")] {
+        const MAX_TIME_SLICE: usize = 5;
+        pub(crate) type AxTask = crate::sched_class::ClassTask<TaskInner, MAX_TIME_SLICE>;
+        pub(crate) type Scheduler = crate::sched_class::ClassScheduler<TaskInner, MAX_TIME_SLICE>;
+    } else if #[cfg(feature = "sched_rr")] {
         const MAX_TIME_SLICE: usize = 5;
         pub(crate) type AxTask = scheduler::RRTask<TaskInner, MAX_TIME_SLICE>;
         pub(crate) type Scheduler = scheduler::RRScheduler<TaskInner, MAX_TIME_SLICE>;
@@ -63,6 +71,19 @@ pub fn current() -> CurrentTask {
     CurrentTask::get()
 }
 
+/// Returns all tasks that are not dropped yet, in the order of their IDs.
+///
+/// Exited tasks are included until they are dropped, i.e. they are still
+/// referenced, for example by their joiners.
+pub fn all_tasks() -> Vec<AxTaskRef> {
+    crate::task::all_tasks()
+}
+
+/// Returns the set of CPUs that have started scheduling tasks.
+pub fn online_cpus() -> CpuMask {
+    crate::run_queue::online_cpus()
+}
+
 /// Initializes the task scheduler (for the primary CPU).
 pub fn init_scheduler() {
     info!("Initialize scheduling...");
@@ -81,21 +102,40 @@ pub fn init_scheduler_secondary() {
 
 /// Handles periodic timer ticks for the task manager.
 ///
-/// For example, advance scheduler states, checks timed events, etc.
+/// For example, advance scheduler states, checks timed events, balances the
+/// loads of CPUs periodically, etc.
 #[cfg(feature = "irq")]
 #[doc(cfg(feature = "irq"))]
 pub fn on_timer_tick() {
     crate::timers::check_events();
-    RUN_QUEUE.lock().scheduler_timer_tick();
+    current_run_queue().scheduler_timer_tick();
 }
 
-/// Adds the given task to the run queue, returns the task reference.
+/// Adds the given task to the run queue with the fewest ready tasks among the
+/// CPUs that it's allowed to run on, returns the task reference.
 pub fn spawn_task(task: TaskInner) -> AxTaskRef {
     let task_ref = task.into_arc();
-    RUN_QUEUE.lock().add_task(task_ref.clone());
+    crate::run_queue::select_run_queue(&task_ref).add_task(task_ref.clone());
     task_ref
 }
 
+/// Adds the given task to the run queue like [`spawn_task`], in the
+/// scheduling class `class`.
+///
+/// Returns [`None`] if `class` is invalid, or not supported by the scheduler,
+/// i.e. other than the default `SchedClass::Normal(0)` without the
+/// `sched_class` feature.
+pub fn spawn_task_with_class(task: TaskInner, class: SchedClass) -> Option<AxTaskRef> {
+    if !class.is_valid() || (cfg!(not(feature = "sched_class")) && class != SchedClass::default()) {
+        return None;
+    }
+    let task_ref = task.into_arc();
+    #[cfg(feature = "sched_class")]
+    task_ref.set_sched_class(class);
+    crate::run_queue::select_run_queue(&task_ref).add_task(task_ref.clone());
+    Some(task_ref)
+}
+
 /// Spawns a new task with the given parameters.
 ///
 /// Returns the task reference.
@@ -119,23 +159,99 @@ where
     spawn_raw(f, "".into(), axconfig::TASK_STACK_SIZE)
 }
 
+/// Spawns a new task with the default parameters, which only runs on the CPU
+/// `cpu_id`.
+///
+/// Returns the task reference.
+///
+/// # Panics
+///
+/// Panics if `cpu_id` is not less than [`axconfig::SMP`].
+pub fn spawn_on_cpu<F>(f: F, cpu_id: usize) -> AxTaskRef
+where
+    F: FnOnce() + Send + 'static,
+{
+    let task = TaskInner::new(f, "".into(), axconfig::TASK_STACK_SIZE);
+    task.set_cpumask(CpuMask::single(cpu_id));
+    spawn_task(task)
+}
+
+/// Set the CPUs that the current task is allowed to run on.
+///
+/// If the current CPU is not in `cpumask`, the current task is migrated to one
+/// of the CPUs in it immediately.
+///
+/// Returns `false` if none of the CPUs in `cpumask` is online, and the
+/// affinity is not changed.
+pub fn set_affinity(cpumask: CpuMask) -> bool {
+    current_run_queue().set_current_affinity(cpumask)
+}
+
 /// Set the priority for current task.
 ///
 /// The range of the priority is dependent on the underlying scheduler. For
 /// example, in the [CFS] scheduler, the priority is the nice value, ranging from
-/// -20 to 19.
+/// -20 to 19. With the `sched_class` feature, it's the nice value of a
+/// [`SchedClass::Normal`] task, and can't be set for tasks of other classes.
 ///
 /// Returns `true` if the priority is set successfully.
 ///
 /// [CFS]: https://en.wikipedia.org/wiki/Completely_Fair_Scheduler
 pub fn set_priority(prio: isize) -> bool {
-    RUN_QUEUE.lock().set_current_priority(prio)
+    current_run_queue().set_current_priority(prio)
+}
+
+/// Moves the current task to the scheduling class `class`.
+///
+/// A real-time task starts a new period at once. Without the `sched_class`
+/// feature, only [`SchedClass::Normal`] is supported, where the nice value is
+/// set as the priority by [`set_priority`].
+///
+/// Returns `false` if `class` is invalid or not supported by the scheduler.
+pub fn set_sched_class(class: SchedClass) -> bool {
+    current_run_queue().set_current_sched_class(class)
+}
+
+/// Returns the scheduling class of the current task.
+///
+/// Without the `sched_class` feature, it's always the default
+/// `SchedClass::Normal(0)`.
+pub fn get_sched_class() -> SchedClass {
+    #[cfg(feature = "sched_class")]
+    return current().as_task_ref().sched_class();
+    #[cfg(not(feature = "sched_class"))]
+    SchedClass::default()
+}
+
+/// Makes the current task a real-time task with the given deadline
+/// scheduling parameters, or a normal task if `params` is [`None`].
+///
+/// It's a shorthand of [`set_sched_class`], a real-time task that becomes a
+/// normal one has the nice value 0.
+///
+/// Returns `false` if `params` is invalid, or real-time tasks are not
+/// supported by the scheduler.
+pub fn set_deadline(params: Option<DeadlineParams>) -> bool {
+    match params {
+        Some(params) => set_sched_class(SchedClass::RealTime(params)),
+        None if get_deadline().is_some() => set_sched_class(SchedClass::default()),
+        None => true,
+    }
+}
+
+/// Returns the deadline scheduling parameters of the current task, or
+/// [`None`] if it's not a real-time task.
+pub fn get_deadline() -> Option<DeadlineParams> {
+    match get_sched_class() {
+        SchedClass::RealTime(params) => Some(params),
+        _ => None,
+    }
 }
 
 /// Current task gives up the CPU time voluntarily, and switches to another
 /// ready task.
 pub fn yield_now() {
-    RUN_QUEUE.lock().yield_current();
+    current_run_queue().yield_current();
 }
 
 /// Current task is going to sleep for the given duration.
@@ -150,14 +266,43 @@ pub fn sleep(dur: core::time::Duration) {
 /// If the feature `irq` is not enabled, it uses busy-wait instead.
 pub fn sleep_until(deadline: axhal::time::TimeValue) {
     #[cfg(feature = "irq")]
-    RUN_QUEUE.lock().sleep_until(deadline);
+    current_run_queue().sleep_until(deadline, false);
     #[cfg(not(feature = "irq"))]
     axhal::time::busy_wait_until(deadline);
 }
 
+/// Current task is going to sleep like [`sleep`], but it's woken up at once
+/// if it's cancelled.
+///
+/// Returns [`Interrupted`] if the current task is cancelled.
+pub fn sleep_interruptible(dur: core::time::Duration) -> Result<(), Interrupted> {
+    sleep_until_interruptible(axhal::time::wall_time() + dur)
+}
+
+/// Current task is going to sleep like [`sleep_until`], but it's woken up at
+/// once if it's cancelled.
+///
+/// Returns [`Interrupted`] if the current task is cancelled.
+pub fn sleep_until_interruptible(deadline: axhal::time::TimeValue) -> Result<(), Interrupted> {
+    #[cfg(feature = "irq")]
+    current_run_queue().sleep_until(deadline, true);
+    #[cfg(not(feature = "irq"))]
+    while axhal::time::wall_time() < deadline && !current().is_cancelled() {
+        core::hint::spin_loop();
+    }
+    if current().is_cancelled() {
+        Err(Interrupted)
+    } else {
+        Ok(())
+    }
+}
+
 /// Exits the current task.
+///
+/// The exit callbacks registered by [`TaskInner::on_exit`] are called first.
 pub fn exit(exit_code: i32) -> ! {
-    RUN_QUEUE.lock().exit_current(exit_code)
+    current().run_exit_callbacks(exit_code);
+    current_run_queue().exit_current(exit_code)
 }
 
 /// The idle task routine.
@@ -0,0 +1,81 @@
+use core::fmt;
+
+const _: () = assert!(axconfig::SMP <= usize::BITS as usize);
+
+/// A set of CPUs, e.g. the CPUs that a task is allowed to run on.
+///
+/// CPU IDs that are not less than [`axconfig::SMP`] are never in the set.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct CpuMask(usize);
+
+impl CpuMask {
+    const VALID_BITS: usize = usize::MAX >> (usize::BITS as usize - axconfig::SMP);
+
+    /// Creates an empty set.
+    pub const fn new() -> Self {
+        Self(0)
+    }
+
+    /// Creates a set of all CPUs.
+    pub const fn full() -> Self {
+        Self(Self::VALID_BITS)
+    }
+
+    /// Creates a set of only one CPU.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `cpu_id` is not less than [`axconfig::SMP`].
+    pub const fn single(cpu_id: usize) -> Self {
+        assert!(cpu_id < axconfig::SMP);
+        Self(1 << cpu_id)
+    }
+
+    /// Creates a set from a bitmap, where bit `i` is set if CPU `i` is in the
+    /// set. Bits of nonexistent CPUs are ignored.
+    pub const fn from_bits(bits: usize) -> Self {
+        Self(bits & Self::VALID_BITS)
+    }
+
+    /// Returns the bitmap of the set.
+    pub const fn bits(&self) -> usize {
+        self.0
+    }
+
+    /// Returns `true` if the set contains no CPUs.
+    pub const fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// Returns `true` if CPU `cpu_id` is in the set.
+    pub const fn get(&self, cpu_id: usize) -> bool {
+        cpu_id < axconfig::SMP && self.0 & (1 << cpu_id) != 0
+    }
+
+    /// Adds CPU `cpu_id` to the set if `value` is `true`, or removes it
+    /// otherwise.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `cpu_id` is not less than [`axconfig::SMP`].
+    pub fn set(&mut self, cpu_id: usize, value: bool) {
+        assert!(cpu_id < axconfig::SMP);
+        if value {
+            self.0 |= 1 << cpu_id;
+        } else {
+            self.0 &= !(1 << cpu_id);
+        }
+    }
+
+    /// Iterates over the IDs of the CPUs in the set, in ascending order.
+    pub fn iter(&self) -> impl Iterator<Item = usize> {
+        let bits = self.0;
+        (0..axconfig::SMP).filter(move |&i| bits & (1 << i) != 0)
+    }
+}
+
+impl fmt::Debug for CpuMask {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
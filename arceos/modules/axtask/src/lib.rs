@@ -4,6 +4,11 @@
 //! creation, scheduling, sleeping, termination, etc. The scheduler algorithm
 //! is configurable by cargo features.
 //!
+//! Each CPU has its own run queue. Idle CPUs steal ready tasks from the
+//! busiest ones, and if the `irq` feature is enabled, the loads of CPUs are
+//! also balanced periodically on timer ticks. Tasks can be restricted to a
+//! set of CPUs with a [`CpuMask`], which stealing and balancing respect.
+//!
 //! # Cargo Features
 //!
 //! - `multitask`: Enable multi-task support. If it's enabled, complex task
@@ -20,6 +25,13 @@
 //!   the `multitask` and `preempt` features if it is enabled.
 //! - `sched_cfs`: Use the [Completely Fair Scheduler][3]. It also enables the
 //!   the `multitask` and `preempt` features if it is enabled.
+//! - `sched_class`: Use the hierarchical scheduler, where tasks of different
+//!   [`SchedClass`]es are mixed: real-time tasks with [`DeadlineParams`] run
+//!   first, by the Earliest Deadline First algorithm, then normal tasks by CFS,
+//!   and finally idle tasks. It also enables the `multitask` and `preempt`
+//!   features if it is enabled, and overrides other scheduler features.
+//! - `sched_edf`: An alias of `sched_class`, where tasks with
+//!   [`DeadlineParams`] set by [`set_deadline`] run before others.
 //!
 //! [1]: scheduler::FifoScheduler
 //! [2]: scheduler::RRScheduler
@@ -42,6 +54,7 @@ cfg_if::cfg_if! {
         extern crate log;
         extern crate alloc;
 
+        mod cpumask;
         mod run_queue;
         mod task;
         mod task_ext;
@@ -50,6 +63,8 @@ cfg_if::cfg_if! {
 
         #[cfg(feature = "irq")]
         mod timers;
+        #[cfg(feature = "sched_class")]
+        mod sched_class;
 
         #[doc(cfg(feature = "multitask"))]
         pub use self::api::*;
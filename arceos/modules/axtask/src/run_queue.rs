@@ -1,72 +1,313 @@
 use alloc::collections::VecDeque;
 use alloc::sync::Arc;
+use core::ops::Deref;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use kernel_guard::NoPreemptIrqSave;
 use kspin::SpinNoIrq;
 use lazyinit::LazyInit;
 use scheduler::BaseScheduler;
 
 use crate::task::{CurrentTask, TaskState};
-use crate::{AxTaskRef, Scheduler, TaskInner, WaitQueue};
-
-// TODO: per-CPU
-pub(crate) static RUN_QUEUE: LazyInit<SpinNoIrq<AxRunQueue>> = LazyInit::new();
+use crate::{AxTaskRef, CpuMask, SchedClass, Scheduler, TaskInner, WaitQueue};
 
-// TODO: per-CPU
-static EXITED_TASKS: SpinNoIrq<VecDeque<AxTaskRef>> = SpinNoIrq::new(VecDeque::new());
-
-static WAIT_FOR_EXIT: WaitQueue = WaitQueue::new();
+/// The run queues of all CPUs, indexed by the CPU IDs. Each one is initialized
+/// when the scheduler is initialized on its CPU.
+static RUN_QUEUES: [LazyInit<AxRunQueue>; axconfig::SMP] =
+    [const { LazyInit::new() }; axconfig::SMP];
 
 #[percpu::def_percpu]
 static IDLE_TASK: LazyInit<AxTaskRef> = LazyInit::new();
 
+/// The task switched out by the last context switch on this CPU. It's held
+/// until the switch is finished, so other CPUs can't run it before its context
+/// is saved.
+#[percpu::def_percpu]
+static PREV_TASK: Option<PrevTask> = None;
+
+/// The load balancer runs every so many timer ticks on each CPU.
+#[cfg(feature = "irq")]
+const BALANCE_INTERVAL: usize = 10;
+
 pub(crate) struct AxRunQueue {
+    cpu_id: usize,
+    scheduler: SpinNoIrq<ReadyQueue>,
+    /// The number of ready tasks in `scheduler`, which can be read without
+    /// locking it.
+    nr_ready: AtomicUsize,
+    exited_tasks: SpinNoIrq<VecDeque<AxTaskRef>>,
+    wait_for_exit: WaitQueue,
+    #[cfg(feature = "irq")]
+    ticks: AtomicUsize,
+}
+
+/// The scheduler of a run queue, which also lets other CPUs see the next task
+/// without taking it.
+struct ReadyQueue {
     scheduler: Scheduler,
+    /// The next task, taken out of the scheduler by
+    /// [`ReadyQueue::peek_next_task`] until it's picked.
+    ///
+    /// It can't be put back, the schedulers may reorder it (e.g. charge the
+    /// time since picked to its virtual runtime).
+    next: Option<AxTaskRef>,
+}
+
+impl ReadyQueue {
+    fn new() -> Self {
+        Self {
+            scheduler: Scheduler::new(),
+            next: None,
+        }
+    }
+
+    fn add_task(&mut self, task: AxTaskRef) {
+        self.scheduler.add_task(task)
+    }
+
+    fn pick_next_task(&mut self) -> Option<AxTaskRef> {
+        self.take_next().or_else(|| self.scheduler.pick_next_task())
+    }
+
+    /// Returns the task that [`ReadyQueue::pick_next_task`] will return,
+    /// leaving it in the queue.
+    fn peek_next_task(&mut self) -> Option<&AxTaskRef> {
+        if self.next.is_none() {
+            self.next = self.scheduler.pick_next_task();
+        }
+        self.next.as_ref()
+    }
+
+    /// Takes the task returned by [`ReadyQueue::peek_next_task`].
+    fn take_next(&mut self) -> Option<AxTaskRef> {
+        let task = self.next.take()?;
+        // it starts running now, not when it was peeked
+        #[cfg(feature = "sched_class")]
+        task.start_running();
+        Some(task)
+    }
+
+    fn put_prev_task(&mut self, prev: AxTaskRef, preempt: bool) {
+        self.scheduler.put_prev_task(prev, preempt)
+    }
+
+    /// Tells the scheduler that `task` stops running on this CPU without being
+    /// put back, because it's blocked or migrating to another CPU.
+    fn detach_task(&mut self, task: &AxTaskRef) {
+        #[cfg(feature = "sched_class")]
+        self.scheduler.detach_task(task);
+        #[cfg(not(feature = "sched_class"))]
+        let _ = task;
+    }
+
+    fn task_tick(&mut self, current: &AxTaskRef) -> bool {
+        self.scheduler.task_tick(current)
+    }
+
+    fn set_priority(&mut self, task: &AxTaskRef, prio: isize) -> bool {
+        self.scheduler.set_priority(task, prio)
+    }
+}
+
+struct PrevTask {
+    task: AxTaskRef,
+    /// The run queue of another CPU that the task is migrating to.
+    migrate_to: Option<&'static AxRunQueue>,
+}
+
+/// A reference to the run queue of the current CPU.
+///
+/// IRQs and preemption are disabled while it's held, so the current task
+/// stays on the CPU.
+pub(crate) struct CurrentRunQueueRef {
+    inner: &'static AxRunQueue,
+    _guard: NoPreemptIrqSave,
+}
+
+impl Deref for CurrentRunQueueRef {
+    type Target = AxRunQueue;
+
+    fn deref(&self) -> &Self::Target {
+        self.inner
+    }
+}
+
+/// Gets the run queue of the current CPU.
+pub(crate) fn current_run_queue() -> CurrentRunQueueRef {
+    let guard = NoPreemptIrqSave::new();
+    CurrentRunQueueRef {
+        inner: &RUN_QUEUES[axhal::cpu::this_cpu_id()],
+        _guard: guard,
+    }
+}
+
+/// Selects the run queue for `task` among the CPUs that it's allowed to run
+/// on, which is the one with the fewest ready tasks, preferring the current
+/// CPU.
+///
+/// Returns the run queue of the current CPU if none of the allowed CPUs is
+/// online.
+pub(crate) fn select_run_queue(task: &AxTaskRef) -> &'static AxRunQueue {
+    let cpumask = task.cpumask();
+    let curr_cpu_id = axhal::cpu::this_cpu_id();
+    run_queues()
+        .filter(|rq| cpumask.get(rq.cpu_id))
+        .min_by_key(|rq| (rq.nr_ready(), rq.cpu_id != curr_cpu_id))
+        .unwrap_or(&RUN_QUEUES[curr_cpu_id])
+}
+
+/// Returns the set of CPUs whose run queues are initialized.
+pub(crate) fn online_cpus() -> CpuMask {
+    let mut cpumask = CpuMask::new();
+    for rq in run_queues() {
+        cpumask.set(rq.cpu_id, true);
+    }
+    cpumask
+}
+
+/// Wakes up the blocked `task` on the CPU it ran on last, or another CPU if
+/// it's not allowed to run there any more.
+///
+/// Returns `false` if it's not blocked, e.g. already woken up otherwise.
+pub(crate) fn unblock_task(task: AxTaskRef, resched: bool) -> bool {
+    // the task may be woken up by a timer and a wait queue at the same time,
+    // only one of them can put it into a run queue.
+    if task.transition_state(TaskState::Blocked, TaskState::Ready) {
+        wakeup_run_queue(&task).unblock_task(task, resched);
+        true
+    } else {
+        false
+    }
+}
+
+/// Wakes up `task` like [`unblock_task`] if it's blocked in an interruptible
+/// wait.
+pub(crate) fn interrupt_task(task: AxTaskRef) {
+    let _guard = NoPreemptIrqSave::new();
+    if task.interrupt() {
+        wakeup_run_queue(&task).unblock_task(task, true);
+    }
+}
+
+/// Selects the run queue for the woken up `task`.
+fn wakeup_run_queue(task: &AxTaskRef) -> &'static AxRunQueue {
+    let last = &RUN_QUEUES[task.cpu_id()];
+    // a task that is still switching out must stay on its CPU.
+    if task.on_cpu() || task.cpumask().get(last.cpu_id) {
+        last
+    } else {
+        select_run_queue(task)
+    }
+}
+
+/// Iterates over the initialized run queues.
+fn run_queues() -> impl Iterator<Item = &'static AxRunQueue> {
+    RUN_QUEUES.iter().filter_map(LazyInit::get)
 }
 
 impl AxRunQueue {
-    pub fn new() -> SpinNoIrq<Self> {
-        let gc_task = TaskInner::new(gc_entry, "gc".into(), axconfig::TASK_STACK_SIZE).into_arc();
-        let mut scheduler = Scheduler::new();
-        scheduler.add_task(gc_task);
-        SpinNoIrq::new(Self { scheduler })
+    pub fn new(cpu_id: usize) -> Self {
+        let gc_task = TaskInner::new(
+            move || gc_entry(cpu_id),
+            "gc".into(),
+            axconfig::TASK_STACK_SIZE,
+        )
+        .into_arc();
+        gc_task.set_cpumask(CpuMask::single(cpu_id));
+        let rq = Self {
+            cpu_id,
+            scheduler: SpinNoIrq::new(ReadyQueue::new()),
+            nr_ready: AtomicUsize::new(0),
+            exited_tasks: SpinNoIrq::new(VecDeque::new()),
+            wait_for_exit: WaitQueue::new(),
+            #[cfg(feature = "irq")]
+            ticks: AtomicUsize::new(0),
+        };
+        rq.enqueue(gc_task);
+        rq
+    }
+
+    /// Returns the number of tasks that are ready to run on this CPU.
+    pub fn nr_ready(&self) -> usize {
+        self.nr_ready.load(Ordering::Relaxed)
     }
 
-    pub fn add_task(&mut self, task: AxTaskRef) {
-        debug!("task spawn: {}", task.id_name());
+    pub fn add_task(&self, task: AxTaskRef) {
+        debug!("task spawn: {} on CPU {}", task.id_name(), self.cpu_id);
         assert!(task.is_ready());
-        self.scheduler.add_task(task);
+        self.enqueue(task);
     }
 
     #[cfg(feature = "irq")]
-    pub fn scheduler_timer_tick(&mut self) {
+    pub fn scheduler_timer_tick(&self) {
         let curr = crate::current();
-        if !curr.is_idle() && self.scheduler.task_tick(curr.as_task_ref()) {
+        if !curr.is_idle() && self.scheduler.lock().task_tick(curr.as_task_ref()) {
             #[cfg(feature = "preempt")]
             curr.set_preempt_pending(true);
         }
+        if self.ticks.fetch_add(1, Ordering::Relaxed) % BALANCE_INTERVAL == 0 {
+            self.load_balance();
+        }
     }
 
-    pub fn yield_current(&mut self) {
+    pub fn yield_current(&self) {
         let curr = crate::current();
         trace!("task yield: {}", curr.id_name());
         assert!(curr.is_running());
         self.resched(false);
     }
 
-    pub fn set_current_priority(&mut self, prio: isize) -> bool {
+    pub fn set_current_affinity(&self, cpumask: CpuMask) -> bool {
+        if online_cpus().bits() & cpumask.bits() == 0 {
+            return false;
+        }
+        let curr = crate::current();
+        curr.set_cpumask(cpumask);
+        if !cpumask.get(self.cpu_id) {
+            debug!("task set affinity: {}, {:?}", curr.id_name(), cpumask);
+            // the current task will be migrated in `resched()`.
+            self.resched(false);
+        }
+        true
+    }
+
+    pub fn set_current_priority(&self, prio: isize) -> bool {
         self.scheduler
+            .lock()
             .set_priority(crate::current().as_task_ref(), prio)
     }
 
+    pub fn set_current_sched_class(&self, class: SchedClass) -> bool {
+        if !class.is_valid() {
+            return false;
+        }
+        #[cfg(feature = "sched_class")]
+        {
+            let curr = crate::current();
+            debug!("task set sched class: {}, {:?}", curr.id_name(), class);
+            // the current task is not in the run queue, and can't be ticked
+            // since IRQs are disabled.
+            curr.as_task_ref().set_sched_class(class);
+            // let the task of the highest class run
+            self.resched(false);
+            true
+        }
+        #[cfg(not(feature = "sched_class"))]
+        match class {
+            SchedClass::Normal(nice) => self.set_current_priority(nice) || nice == 0,
+            _ => false,
+        }
+    }
+
     #[cfg(feature = "preempt")]
-    pub fn preempt_resched(&mut self) {
+    pub fn preempt_resched(&self) {
         let curr = crate::current();
         assert!(curr.is_running());
 
-        // When we get the mutable reference of the run queue, we must
-        // have held the `SpinNoIrq` lock with both IRQs and preemption
-        // disabled. So we need to set `current_disable_count` to 1 in
-        // `can_preempt()` to obtain the preemption permission before
-        //  locking the run queue.
+        // When we get the reference of the run queue, we must have held
+        // the guard with both IRQs and preemption disabled. So we need to
+        // set `current_disable_count` to 1 in `can_preempt()` to obtain the
+        // preemption permission before getting the run queue.
         let can_preempt = curr.can_preempt(1);
 
         debug!(
@@ -81,25 +322,28 @@ impl AxRunQueue {
         }
     }
 
-    pub fn exit_current(&mut self, exit_code: i32) -> ! {
+    pub fn exit_current(&self, exit_code: i32) -> ! {
         let curr = crate::current();
         debug!("task exit: {}, exit_code={}", curr.id_name(), exit_code);
         assert!(curr.is_running());
         assert!(!curr.is_idle());
         if curr.is_init() {
-            EXITED_TASKS.lock().clear();
+            self.exited_tasks.lock().clear();
             axhal::misc::terminate();
         } else {
-            curr.set_state(TaskState::Exited);
-            curr.notify_exit(exit_code, self);
-            EXITED_TASKS.lock().push_back(curr.clone());
-            WAIT_FOR_EXIT.notify_one_locked(false, self);
+            curr.notify_exit(exit_code);
+            self.exited_tasks.lock().push_back(curr.clone());
+            self.wait_for_exit.notify_one(false);
             self.resched(false);
         }
         unreachable!("task exited!");
     }
 
-    pub fn block_current<F>(&mut self, wait_queue_push: F)
+    /// Blocks the current task, `wait_queue_push` is called to put it into a
+    /// wait queue (or a timer list) after it's marked as blocked.
+    ///
+    /// If `interruptible`, the task is also woken up when it's cancelled.
+    pub fn block_current<F>(&self, interruptible: bool, wait_queue_push: F)
     where
         F: FnOnce(AxTaskRef),
     {
@@ -112,25 +356,30 @@ impl AxRunQueue {
         #[cfg(feature = "preempt")]
         assert!(curr.can_preempt(1));
 
-        curr.set_state(TaskState::Blocked);
+        // charge the time it has run before it can be woken up, maybe on
+        // another CPU.
+        self.scheduler.lock().detach_task(curr.as_task_ref());
+        curr.set_blocked(interruptible);
         wait_queue_push(curr.clone());
+        // the canceller can't wake it up if it's cancelled before marked as
+        // blocked, do it by itself.
+        if interruptible && curr.is_cancelled() {
+            unblock_task(curr.clone(), false);
+        }
         self.resched(false);
     }
 
-    pub fn unblock_task(&mut self, task: AxTaskRef, resched: bool) {
-        debug!("task unblock: {}", task.id_name());
-        if task.is_blocked() {
-            task.set_state(TaskState::Ready);
-            self.scheduler.add_task(task); // TODO: priority
-            if resched {
-                #[cfg(feature = "preempt")]
-                crate::current().set_preempt_pending(true);
-            }
+    fn unblock_task(&self, task: AxTaskRef, resched: bool) {
+        debug!("task unblock: {} on CPU {}", task.id_name(), self.cpu_id);
+        self.enqueue(task); // TODO: priority
+        if resched && self.cpu_id == axhal::cpu::this_cpu_id() {
+            #[cfg(feature = "preempt")]
+            crate::current().set_preempt_pending(true);
         }
     }
 
     #[cfg(feature = "irq")]
-    pub fn sleep_until(&mut self, deadline: axhal::time::TimeValue) {
+    pub fn sleep_until(&self, deadline: axhal::time::TimeValue, interruptible: bool) {
         let curr = crate::current();
         debug!("task sleep: {}, deadline={:?}", curr.id_name(), deadline);
         assert!(curr.is_running());
@@ -138,32 +387,151 @@ impl AxRunQueue {
 
         let now = axhal::time::wall_time();
         if now < deadline {
-            crate::timers::set_alarm_wakeup(deadline, curr.clone());
-            curr.set_state(TaskState::Blocked);
-            self.resched(false);
+            self.block_current(interruptible, |task| {
+                crate::timers::set_alarm_wakeup(deadline, task)
+            });
+            if curr.in_timer_list() {
+                // interrupted before the deadline
+                crate::timers::cancel_alarm(curr.as_task_ref());
+            }
         }
     }
 }
 
 impl AxRunQueue {
+    /// Puts a ready task into the scheduler of this CPU.
+    fn enqueue(&self, task: AxTaskRef) {
+        task.set_cpu_id(self.cpu_id);
+        self.scheduler.lock().add_task(task);
+        self.nr_ready.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Picks the next task to run on this CPU. Tasks that are no longer
+    /// allowed to run on this CPU are moved to other CPUs.
+    fn pick_next_task(&self) -> Option<AxTaskRef> {
+        loop {
+            let task = self.scheduler.lock().pick_next_task()?;
+            self.nr_ready.fetch_sub(1, Ordering::Relaxed);
+            // a task that is still switching out can't be moved, let it run
+            // and it will be migrated in the next `resched()`.
+            if task.on_cpu() || task.cpumask().get(self.cpu_id) {
+                return Some(task);
+            }
+            let rq = select_run_queue(&task);
+            if core::ptr::eq(rq, self) {
+                return Some(task);
+            }
+            debug!(
+                "task migrate: {} from CPU {} to CPU {}",
+                task.id_name(),
+                self.cpu_id,
+                rq.cpu_id
+            );
+            self.scheduler.lock().detach_task(&task);
+            rq.enqueue(task);
+        }
+    }
+
+    /// Takes a ready task out of this run queue to move it to CPU `cpu_id`.
+    ///
+    /// Returns [`None`] if there are no tasks, or the next task is still
+    /// switching out on this CPU, or is not allowed to run on CPU `cpu_id`.
+    /// The next task is left where it is then.
+    fn take_task(&self, cpu_id: usize) -> Option<AxTaskRef> {
+        let mut scheduler = self.scheduler.lock();
+        let task = scheduler.peek_next_task()?;
+        if task.on_cpu() || !task.cpumask().get(cpu_id) {
+            return None;
+        }
+        self.nr_ready.fetch_sub(1, Ordering::Relaxed);
+        let task = scheduler.take_next()?;
+        scheduler.detach_task(&task);
+        Some(task)
+    }
+
+    /// Returns the run queue of another CPU with the most ready tasks, if it
+    /// has more than `min_ready` ones.
+    fn busiest_run_queue(&self, min_ready: usize) -> Option<&'static AxRunQueue> {
+        run_queues()
+            .filter(|rq| rq.cpu_id != self.cpu_id)
+            .max_by_key(|rq| rq.nr_ready())
+            .filter(|rq| rq.nr_ready() > min_ready)
+    }
+
+    /// Steals a ready task from the busiest CPU when this one becomes idle.
+    fn steal_task(&self) -> Option<AxTaskRef> {
+        let task = self.busiest_run_queue(0)?.take_task(self.cpu_id)?;
+        debug!("task steal: {} to CPU {}", task.id_name(), self.cpu_id);
+        // it's added to the scheduler of this CPU before it runs.
+        self.enqueue(task);
+        self.pick_next_task()
+    }
+
+    /// Pulls ready tasks from the busiest CPU, until both CPUs have about
+    /// the same number of ready tasks.
+    #[cfg(feature = "irq")]
+    fn load_balance(&self) {
+        let Some(busiest) = self.busiest_run_queue(self.nr_ready() + 1) else {
+            return;
+        };
+        let nr_migrate = busiest.nr_ready().saturating_sub(self.nr_ready()) / 2;
+        for _ in 0..nr_migrate {
+            let Some(task) = busiest.take_task(self.cpu_id) else {
+                break;
+            };
+            debug!(
+                "task migrate: {} from CPU {} to CPU {}",
+                task.id_name(),
+                busiest.cpu_id,
+                self.cpu_id
+            );
+            self.enqueue(task);
+        }
+    }
+
     /// Common reschedule subroutine. If `preempt`, keep current task's time
     /// slice, otherwise reset it.
-    fn resched(&mut self, preempt: bool) {
+    ///
+    /// If the current task is not allowed to run on this CPU, it's migrated
+    /// to another CPU.
+    fn resched(&self, preempt: bool) {
         let prev = crate::current();
+        let mut migrate_to = None;
         if prev.is_running() {
             prev.set_state(TaskState::Ready);
             if !prev.is_idle() {
-                self.scheduler.put_prev_task(prev.clone(), preempt);
+                if !prev.cpumask().get(self.cpu_id) {
+                    let rq = select_run_queue(prev.as_task_ref());
+                    if !core::ptr::eq(rq, self) {
+                        // it can only be put into another run queue after
+                        // it's switched out.
+                        migrate_to = Some(rq);
+                    }
+                }
+                if migrate_to.is_none() {
+                    self.scheduler.lock().put_prev_task(prev.clone(), preempt);
+                    self.nr_ready.fetch_add(1, Ordering::Relaxed);
+                } else {
+                    self.scheduler.lock().detach_task(prev.as_task_ref());
+                }
             }
         }
-        let next = self.scheduler.pick_next_task().unwrap_or_else(|| unsafe {
-            // Safety: IRQs must be disabled at this time.
-            IDLE_TASK.current_ref_raw().get_unchecked().clone()
-        });
-        self.switch_to(prev, next);
+        let next = self
+            .pick_next_task()
+            .or_else(|| self.steal_task())
+            .unwrap_or_else(|| unsafe {
+                // Safety: IRQs must be disabled at this time.
+                IDLE_TASK.current_ref_raw().get_unchecked().clone()
+            });
+        self.switch_to(prev, next, migrate_to);
     }
 
-    fn switch_to(&mut self, prev_task: CurrentTask, next_task: AxTaskRef) {
+    fn switch_to(
+        &self,
+        prev_task: CurrentTask,
+        next_task: AxTaskRef,
+        migrate_to: Option<&'static AxRunQueue>,
+    ) {
         trace!(
             "context switch: {} -> {}",
             prev_task.id_name(),
@@ -175,6 +543,8 @@ impl AxRunQueue {
         if prev_task.ptr_eq(&next_task) {
             return;
         }
+        next_task.set_cpu_id(self.cpu_id);
+        next_task.set_on_cpu(true);
 
         unsafe {
             let prev_ctx_ptr = prev_task.ctx_mut_ptr();
@@ -185,19 +555,41 @@ impl AxRunQueue {
             assert!(Arc::strong_count(prev_task.as_task_ref()) > 1);
             assert!(Arc::strong_count(&next_task) >= 1);
 
+            *PREV_TASK.current_ref_mut_raw() = Some(PrevTask {
+                task: prev_task.clone(),
+                migrate_to,
+            });
             CurrentTask::set_current(prev_task, next_task);
             (*prev_ctx_ptr).switch_to(&*next_ctx_ptr);
+            finish_switch();
+        }
+    }
+}
+
+/// Finishes the context switch on this CPU after switching to the new task,
+/// the previous one can be run on other CPUs from now on.
+///
+/// # Safety
+///
+/// IRQs must be disabled.
+pub(crate) unsafe fn finish_switch() {
+    if let Some(PrevTask { task, migrate_to }) = PREV_TASK.current_ref_mut_raw().take() {
+        task.set_on_cpu(false);
+        if let Some(rq) = migrate_to {
+            debug!("task migrate: {} to CPU {}", task.id_name(), rq.cpu_id);
+            rq.enqueue(task);
         }
     }
 }
 
-fn gc_entry() {
+fn gc_entry(cpu_id: usize) {
+    let rq = &RUN_QUEUES[cpu_id];
     loop {
         // Drop all exited tasks and recycle resources.
-        let n = EXITED_TASKS.lock().len();
+        let n = rq.exited_tasks.lock().len();
         for _ in 0..n {
             // Do not do the slow drops in the critical section.
-            let task = EXITED_TASKS.lock().pop_front();
+            let task = rq.exited_tasks.lock().pop_front();
             if let Some(task) = task {
                 if Arc::strong_count(&task) == 1 {
                     // If I'm the last holder of the task, drop it immediately.
@@ -205,11 +597,11 @@ fn gc_entry() {
                 } else {
                     // Otherwise (e.g, `switch_to` is not compeleted, held by the
                     // joiner, etc), push it back and wait for them to drop first.
-                    EXITED_TASKS.lock().push_back(task);
+                    rq.exited_tasks.lock().push_back(task);
                 }
             }
         }
-        WAIT_FOR_EXIT.wait();
+        rq.wait_for_exit.wait();
     }
 }
 
@@ -226,7 +618,8 @@ pub(crate) fn init() {
     main_task.set_state(TaskState::Running);
     unsafe { CurrentTask::init_current(main_task) };
 
-    RUN_QUEUE.init_once(AxRunQueue::new());
+    let cpu_id = axhal::cpu::this_cpu_id();
+    RUN_QUEUES[cpu_id].init_once(AxRunQueue::new(cpu_id));
 }
 
 pub(crate) fn init_secondary() {
@@ -237,4 +630,7 @@ pub(crate) fn init_secondary() {
         i.init_once(idle_task.clone());
     });
     unsafe { CurrentTask::init_current(idle_task) }
+
+    let cpu_id = axhal::cpu::this_cpu_id();
+    RUN_QUEUES[cpu_id].init_once(AxRunQueue::new(cpu_id));
 }
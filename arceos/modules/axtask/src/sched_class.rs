@@ -0,0 +1,422 @@
+//! Hierarchical scheduler with multiple scheduling classes.
+//!
+//! Every task belongs to one of the [`SchedClass`]es, which are strictly
+//! ordered: a task runs only if no task of a higher class is ready.
+//!
+//! - Real-time tasks have a [`DeadlineParams`], and a budget of `runtime` in
+//!   every `period`, which must be consumed before the deadline. The task with
+//!   the earliest absolute deadline runs first (EDF). Budgets are enforced with
+//!   the Constant Bandwidth Server (CBS) rules: a task that exhausts its budget
+//!   has its deadline postponed by one period and its budget replenished, so it
+//!   can't take more than its bandwidth from others.
+//! - Normal tasks share the CPU fairly by their nice values, the task with the
+//!   smallest virtual runtime runs first (CFS).
+//! - Idle tasks are scheduled round-robin when no other task is ready.
+
+use alloc::{collections::BTreeMap, collections::VecDeque, sync::Arc};
+use core::ops::Deref;
+use core::sync::atomic::{AtomicI64, AtomicIsize, AtomicU64, AtomicU8, Ordering};
+use core::time::Duration;
+
+use scheduler::BaseScheduler;
+
+use crate::{DeadlineParams, SchedClass};
+
+const CLASS_REALTIME: u8 = 0;
+const CLASS_NORMAL: u8 = 1;
+const CLASS_IDLE: u8 = 2;
+
+/// The weight of a normal task of nice 0.
+const NICE_0_WEIGHT: u64 = 1024;
+
+/// The weights of normal tasks of nice -20 to 19, each level is about 10%
+/// more or less CPU time.
+const NICE_TO_WEIGHT: [u64; 40] = [
+    88761, 71755, 56483, 46273, 36291, 29154, 23254, 18705, 14949, 11916, 9548, 7620, 6100, 4904,
+    3906, 3121, 2501, 1991, 1586, 1277, 1024, 820, 655, 526, 423, 335, 272, 215, 172, 137, 110, 87,
+    70, 56, 45, 36, 29, 23, 18, 15,
+];
+
+/// How far behind the others a waking normal task may be, in nanoseconds of
+/// virtual runtime.
+const WAKEUP_LATENCY: u64 = 6_000_000;
+
+fn now_nanos() -> u64 {
+    axhal::time::monotonic_time_nanos()
+}
+
+fn nanos(dur: Duration) -> u64 {
+    dur.as_nanos() as u64
+}
+
+/// A task wrapper for the [`ClassScheduler`].
+pub struct ClassTask<T, const S: usize> {
+    inner: T,
+    class: AtomicU8,
+    /// The time when the task starts to run, or is accounted last time.
+    exec_start: AtomicU64,
+
+    /// The relative deadline of a real-time task in nanoseconds.
+    rel_deadline: AtomicU64,
+    /// The runtime in every period in nanoseconds.
+    runtime: AtomicU64,
+    /// The period in nanoseconds.
+    period: AtomicU64,
+    /// The absolute deadline of the current period.
+    abs_deadline: AtomicU64,
+    /// The remaining runtime of the current period, may be negative if the
+    /// task overruns.
+    budget: AtomicI64,
+
+    /// The nice value of a normal task.
+    nice: AtomicIsize,
+    /// The virtual runtime of a normal task in nanoseconds.
+    vruntime: AtomicU64,
+
+    /// The remaining time slice of an idle task.
+    time_slice: AtomicIsize,
+}
+
+impl<T, const S: usize> ClassTask<T, S> {
+    /// Creates a new normal task with nice 0.
+    pub const fn new(inner: T) -> Self {
+        Self {
+            inner,
+            class: AtomicU8::new(CLASS_NORMAL),
+            exec_start: AtomicU64::new(0),
+            rel_deadline: AtomicU64::new(0),
+            runtime: AtomicU64::new(0),
+            period: AtomicU64::new(0),
+            abs_deadline: AtomicU64::new(0),
+            budget: AtomicI64::new(0),
+            nice: AtomicIsize::new(0),
+            vruntime: AtomicU64::new(0),
+            time_slice: AtomicIsize::new(S as isize),
+        }
+    }
+
+    /// Returns a reference to the inner task struct.
+    pub const fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    /// Returns the scheduling class of the task.
+    pub fn sched_class(&self) -> SchedClass {
+        match self.class() {
+            CLASS_REALTIME => SchedClass::RealTime(DeadlineParams {
+                runtime: Duration::from_nanos(self.runtime.load(Ordering::Acquire)),
+                deadline: Duration::from_nanos(self.rel_deadline.load(Ordering::Acquire)),
+                period: Duration::from_nanos(self.period.load(Ordering::Acquire)),
+            }),
+            CLASS_NORMAL => SchedClass::Normal(self.nice.load(Ordering::Acquire)),
+            _ => SchedClass::Idle,
+        }
+    }
+
+    /// Moves the task to the scheduling class `class`. A real-time task
+    /// starts a new period at once.
+    ///
+    /// The task must not be in the ready queues, and `class` must be valid.
+    pub fn set_sched_class(&self, class: SchedClass) {
+        let now = now_nanos();
+        self.exec_start.store(now, Ordering::Release);
+        match class {
+            SchedClass::RealTime(params) => {
+                self.runtime.store(nanos(params.runtime), Ordering::Release);
+                self.rel_deadline
+                    .store(nanos(params.deadline), Ordering::Release);
+                self.period.store(nanos(params.period), Ordering::Release);
+                self.replenish(now);
+                self.class.store(CLASS_REALTIME, Ordering::Release);
+            }
+            SchedClass::Normal(nice) => {
+                self.nice.store(nice, Ordering::Release);
+                self.class.store(CLASS_NORMAL, Ordering::Release);
+            }
+            SchedClass::Idle => {
+                self.time_slice.store(S as isize, Ordering::Release);
+                self.class.store(CLASS_IDLE, Ordering::Release);
+            }
+        }
+    }
+
+    /// Starts charging the time it runs from now.
+    pub(crate) fn start_running(&self) {
+        self.exec_start.store(now_nanos(), Ordering::Release);
+    }
+
+    fn class(&self) -> u8 {
+        self.class.load(Ordering::Acquire)
+    }
+
+    fn abs_deadline(&self) -> u64 {
+        self.abs_deadline.load(Ordering::Acquire)
+    }
+
+    fn vruntime(&self) -> u64 {
+        self.vruntime.load(Ordering::Acquire)
+    }
+
+    /// Charges the time it has run since the last accounting, to the budget
+    /// of a real-time task, or the virtual runtime of a normal task.
+    fn account(&self, now: u64) {
+        let start = self.exec_start.swap(now, Ordering::AcqRel);
+        let elapsed = now.saturating_sub(start);
+        match self.class() {
+            CLASS_REALTIME => {
+                self.budget.fetch_sub(elapsed as i64, Ordering::AcqRel);
+            }
+            CLASS_NORMAL => {
+                let nice = self.nice.load(Ordering::Acquire);
+                let weight = NICE_TO_WEIGHT[(nice + 20) as usize];
+                let delta = elapsed as u128 * NICE_0_WEIGHT as u128 / weight as u128;
+                self.vruntime.fetch_add(delta as u64, Ordering::AcqRel);
+            }
+            _ => {}
+        }
+    }
+
+    /// Starts a new period with a full budget from `now`.
+    fn replenish(&self, now: u64) {
+        self.abs_deadline.store(
+            now + self.rel_deadline.load(Ordering::Acquire),
+            Ordering::Release,
+        );
+        self.budget.store(
+            self.runtime.load(Ordering::Acquire) as i64,
+            Ordering::Release,
+        );
+    }
+
+    fn budget_exhausted(&self) -> bool {
+        self.budget.load(Ordering::Acquire) <= 0
+    }
+
+    /// Postpones the deadline by periods until the budget is positive again.
+    fn postpone_if_exhausted(&self) {
+        let runtime = self.runtime.load(Ordering::Acquire);
+        let period = self.period.load(Ordering::Acquire);
+        while self.budget_exhausted() {
+            self.abs_deadline.fetch_add(period, Ordering::AcqRel);
+            self.budget.fetch_add(runtime as i64, Ordering::AcqRel);
+        }
+    }
+
+    /// Updates the deadline when a real-time task becomes ready. If the
+    /// remaining budget can't be used up before the deadline without
+    /// exceeding its bandwidth, a new period is started.
+    fn update_deadline_on_ready(&self, now: u64) {
+        let abs_deadline = self.abs_deadline();
+        let budget = self.budget.load(Ordering::Acquire);
+        let runtime = self.runtime.load(Ordering::Acquire) as u128;
+        let period = self.period.load(Ordering::Acquire) as u128;
+        // budget / (abs_deadline - now) > runtime / period
+        if now >= abs_deadline
+            || budget.max(0) as u128 * period > (abs_deadline - now) as u128 * runtime
+        {
+            self.replenish(now);
+        }
+        self.postpone_if_exhausted();
+    }
+
+    fn time_slice(&self) -> isize {
+        self.time_slice.load(Ordering::Acquire)
+    }
+
+    fn reset_time_slice(&self) {
+        self.time_slice.store(S as isize, Ordering::Release);
+    }
+}
+
+impl<T, const S: usize> Deref for ClassTask<T, S> {
+    type Target = T;
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+/// A hierarchical scheduler with real-time (EDF), normal (CFS) and idle
+/// (round-robin) classes.
+///
+/// `S` is the time slice of idle tasks in ticks.
+pub struct ClassScheduler<T, const S: usize> {
+    /// Ready real-time tasks, ordered by their absolute deadlines.
+    rt_queue: BTreeMap<(u64, usize), Arc<ClassTask<T, S>>>,
+    /// Ready normal tasks, ordered by their virtual runtime.
+    normal_queue: BTreeMap<(u64, usize), Arc<ClassTask<T, S>>>,
+    /// Ready idle tasks.
+    idle_queue: VecDeque<Arc<ClassTask<T, S>>>,
+    /// The smallest virtual runtime of normal tasks, never decreases.
+    min_vruntime: u64,
+}
+
+impl<T, const S: usize> ClassScheduler<T, S> {
+    /// Creates a new empty scheduler.
+    pub const fn new() -> Self {
+        Self {
+            rt_queue: BTreeMap::new(),
+            normal_queue: BTreeMap::new(),
+            idle_queue: VecDeque::new(),
+            min_vruntime: 0,
+        }
+    }
+
+    /// Returns the name of the scheduler.
+    pub fn scheduler_name() -> &'static str {
+        "Hierarchical (real-time, normal, idle)"
+    }
+
+    fn rt_key(task: &Arc<ClassTask<T, S>>) -> (u64, usize) {
+        (task.abs_deadline(), Arc::as_ptr(task) as usize)
+    }
+
+    fn normal_key(task: &Arc<ClassTask<T, S>>) -> (u64, usize) {
+        (task.vruntime(), Arc::as_ptr(task) as usize)
+    }
+
+    /// Puts a normal task into the queue, its virtual runtime is at least
+    /// `min_vruntime - max_lag`, so it can't monopolize the CPU after sleeping
+    /// long or migrated from another CPU.
+    fn insert_normal(&mut self, task: Arc<ClassTask<T, S>>, max_lag: u64) {
+        let vruntime = task
+            .vruntime()
+            .max(self.min_vruntime.saturating_sub(max_lag));
+        task.vruntime.store(vruntime, Ordering::Release);
+        self.normal_queue.insert(Self::normal_key(&task), task);
+    }
+
+    /// Moves `min_vruntime` forward to the smaller one of the running normal
+    /// task and the first ready one.
+    fn update_min_vruntime(&mut self, curr: &Arc<ClassTask<T, S>>) {
+        let vruntime = match self.normal_queue.first_key_value() {
+            Some((&(first, _), _)) => first.min(curr.vruntime()),
+            None => curr.vruntime(),
+        };
+        self.min_vruntime = self.min_vruntime.max(vruntime);
+    }
+
+    /// Charges the time it has run to a task that stops running without
+    /// being put back, because it's blocked or migrating to another CPU.
+    ///
+    /// The virtual runtime of a normal task becomes relative to this queue,
+    /// and [`BaseScheduler::add_task`] adds it to the `min_vruntime` of the
+    /// queue it's added to, which may be on another CPU.
+    pub fn detach_task(&mut self, task: &Arc<ClassTask<T, S>>) {
+        task.account(now_nanos());
+        if task.class() == CLASS_NORMAL {
+            self.update_min_vruntime(task);
+            let lag = task.vruntime().wrapping_sub(self.min_vruntime);
+            task.vruntime.store(lag, Ordering::Release);
+        }
+    }
+
+    fn has_higher_class_ready(&self, class: u8) -> bool {
+        match class {
+            CLASS_REALTIME => false,
+            CLASS_NORMAL => !self.rt_queue.is_empty(),
+            _ => !self.rt_queue.is_empty() || !self.normal_queue.is_empty(),
+        }
+    }
+}
+
+impl<T, const S: usize> BaseScheduler for ClassScheduler<T, S> {
+    type SchedItem = Arc<ClassTask<T, S>>;
+
+    fn init(&mut self) {}
+
+    fn add_task(&mut self, task: Self::SchedItem) {
+        match task.class() {
+            CLASS_REALTIME => {
+                task.update_deadline_on_ready(now_nanos());
+                self.rt_queue.insert(Self::rt_key(&task), task);
+            }
+            CLASS_NORMAL => {
+                // detached from a queue, or new with no lag
+                let lag = task.vruntime() as i64;
+                let vruntime = self.min_vruntime.saturating_add_signed(lag);
+                task.vruntime.store(vruntime, Ordering::Release);
+                self.insert_normal(task, WAKEUP_LATENCY);
+            }
+            _ => self.idle_queue.push_back(task),
+        }
+    }
+
+    fn remove_task(&mut self, task: &Self::SchedItem) -> Option<Self::SchedItem> {
+        match task.class() {
+            CLASS_REALTIME => self.rt_queue.remove(&Self::rt_key(task)),
+            CLASS_NORMAL => self.normal_queue.remove(&Self::normal_key(task)),
+            _ => self
+                .idle_queue
+                .iter()
+                .position(|t| Arc::ptr_eq(t, task))
+                .and_then(|idx| self.idle_queue.remove(idx)),
+        }
+    }
+
+    fn pick_next_task(&mut self) -> Option<Self::SchedItem> {
+        let task = if let Some((_, task)) = self.rt_queue.pop_first() {
+            task
+        } else if let Some((_, task)) = self.normal_queue.pop_first() {
+            self.min_vruntime = self.min_vruntime.max(task.vruntime());
+            task
+        } else {
+            self.idle_queue.pop_front()?
+        };
+        task.start_running();
+        Some(task)
+    }
+
+    fn put_prev_task(&mut self, prev: Self::SchedItem, preempt: bool) {
+        prev.account(now_nanos());
+        match prev.class() {
+            CLASS_REALTIME => {
+                prev.postpone_if_exhausted();
+                self.rt_queue.insert(Self::rt_key(&prev), prev);
+            }
+            CLASS_NORMAL => self.insert_normal(prev, 0),
+            _ => {
+                if prev.time_slice() > 0 && preempt {
+                    self.idle_queue.push_front(prev);
+                } else {
+                    prev.reset_time_slice();
+                    self.idle_queue.push_back(prev);
+                }
+            }
+        }
+    }
+
+    fn task_tick(&mut self, current: &Self::SchedItem) -> bool {
+        current.account(now_nanos());
+        let class = current.class();
+        if self.has_higher_class_ready(class) {
+            return true;
+        }
+        match class {
+            CLASS_REALTIME => {
+                current.budget_exhausted()
+                    || self
+                        .rt_queue
+                        .first_key_value()
+                        .is_some_and(|(&(deadline, _), _)| deadline < current.abs_deadline())
+            }
+            CLASS_NORMAL => {
+                self.update_min_vruntime(current);
+                self.normal_queue
+                    .first_key_value()
+                    .is_some_and(|(&(vruntime, _), _)| vruntime < current.vruntime())
+            }
+            _ => current.time_slice.fetch_sub(1, Ordering::Release) <= 1,
+        }
+    }
+
+    fn set_priority(&mut self, task: &Self::SchedItem, prio: isize) -> bool {
+        // only normal tasks have a nice value
+        if task.class() == CLASS_NORMAL && (-20..20).contains(&prio) {
+            task.nice.store(prio, Ordering::Release);
+            true
+        } else {
+            false
+        }
+    }
+}
@@ -1,19 +1,20 @@
-use alloc::{boxed::Box, string::String, sync::Arc};
+use alloc::collections::BTreeMap;
+use alloc::sync::{Arc, Weak};
+use alloc::{boxed::Box, string::String, vec::Vec};
+use core::any::Any;
 use core::ops::Deref;
-use core::sync::atomic::{AtomicBool, AtomicI32, AtomicU64, AtomicU8, Ordering};
-use core::{alloc::Layout, cell::UnsafeCell, fmt, ptr::NonNull};
-
-#[cfg(feature = "preempt")]
-use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::{AtomicBool, AtomicI32, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use core::{alloc::Layout, cell::UnsafeCell, fmt, ptr::NonNull, time::Duration};
 
 #[cfg(feature = "tls")]
 use axhal::tls::TlsArea;
 
 use axhal::arch::TaskContext;
+use kspin::SpinNoIrq;
 use memory_addr::{align_up_4k, VirtAddr};
 
-use crate::task_ext::AxTaskExt;
-use crate::{AxRunQueue, AxTask, AxTaskRef, WaitQueue};
+use crate::task_ext::{AxTaskExt, ModuleExts};
+use crate::{AxTask, AxTaskRef, CpuMask, WaitQueue};
 
 /// A unique identifier for a thread.
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
@@ -22,13 +23,86 @@ pub struct TaskId(u64);
 /// The possible states of a task.
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
-pub(crate) enum TaskState {
+pub enum TaskState {
+    /// Running on a CPU.
     Running = 1,
+    /// Ready to run, waiting in the run queue.
     Ready = 2,
+    /// Blocked in a wait queue or sleeping.
     Blocked = 3,
+    /// Exited, but not dropped yet.
     Exited = 4,
 }
 
+/// Set in the state of a blocked task if the wait can be interrupted by
+/// [`TaskInner::cancel`].
+const STATE_INTERRUPTIBLE: u8 = 0x80;
+
+/// The error returned by interruptible waits if the current task is
+/// cancelled by [`TaskInner::cancel`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Interrupted;
+
+/// A callback called with the exit code when a task exits.
+type ExitCallback = Box<dyn FnOnce(i32) + Send>;
+
+/// The parameters of a real-time task, scheduled by the earliest deadline.
+///
+/// The task is allowed to run for `runtime` in every `period`, and the
+/// scheduler tries to finish it before `deadline` after the period starts.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct DeadlineParams {
+    /// The execution time in every period.
+    pub runtime: Duration,
+    /// The deadline relative to the start of every period.
+    pub deadline: Duration,
+    /// The length of the period.
+    pub period: Duration,
+}
+
+impl DeadlineParams {
+    /// Returns `true` if `0 < runtime <= deadline <= period`.
+    pub fn is_valid(&self) -> bool {
+        !self.runtime.is_zero() && self.runtime <= self.deadline && self.deadline <= self.period
+    }
+}
+
+/// The scheduling class of a task.
+///
+/// Classes are strictly ordered: a task runs only if no task of a higher
+/// class is ready.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SchedClass {
+    /// Real-time tasks with deadline scheduling parameters, the one with the
+    /// earliest deadline runs first.
+    RealTime(DeadlineParams),
+    /// Normal tasks with a nice value from -20 to 19, which share the CPU
+    /// fairly by their nice values.
+    Normal(isize),
+    /// Background tasks that only run when no other task is ready.
+    Idle,
+}
+
+impl SchedClass {
+    /// Returns `true` if the deadline parameters or the nice value is valid.
+    pub fn is_valid(&self) -> bool {
+        match self {
+            Self::RealTime(params) => params.is_valid(),
+            Self::Normal(nice) => (-20..20).contains(nice),
+            Self::Idle => true,
+        }
+    }
+}
+
+impl Default for SchedClass {
+    fn default() -> Self {
+        Self::Normal(0)
+    }
+}
+
+/// All tasks that are not dropped yet, indexed by their IDs.
+static TASK_TABLE: SpinNoIrq<BTreeMap<u64, Weak<AxTask>>> = SpinNoIrq::new(BTreeMap::new());
+
 /// The inner task structure.
 pub struct TaskInner {
     id: TaskId,
@@ -39,6 +113,13 @@ pub struct TaskInner {
     entry: Option<*mut dyn FnOnce()>,
     state: AtomicU8,
 
+    /// The CPU that the task is running on, or whose run queue it's in.
+    cpu_id: AtomicUsize,
+    /// Whether the task is running on a CPU, or still being switched out.
+    on_cpu: AtomicBool,
+    /// The bitmap of [`CpuMask`] that the task is allowed to run on.
+    cpumask: AtomicUsize,
+
     in_wait_queue: AtomicBool,
     #[cfg(feature = "irq")]
     in_timer_list: AtomicBool,
@@ -48,12 +129,18 @@ pub struct TaskInner {
     #[cfg(feature = "preempt")]
     preempt_disable_count: AtomicUsize,
 
+    /// Whether the task is requested to be cancelled.
+    cancelled: AtomicBool,
+    /// The callbacks to call when the task exits, or [`None`] if it's
+    /// exiting.
+    exit_callbacks: SpinNoIrq<Option<Vec<ExitCallback>>>,
     exit_code: AtomicI32,
     wait_for_exit: WaitQueue,
 
     kstack: Option<TaskStack>,
     ctx: UnsafeCell<TaskContext>,
     task_ext: AxTaskExt,
+    module_exts: ModuleExts,
 
     #[cfg(feature = "tls")]
     tls: TlsArea,
@@ -102,6 +189,10 @@ impl TaskInner {
         #[cfg(not(feature = "tls"))]
         let tls = VirtAddr::from(0);
 
+        if let Some(curr) = crate::current_may_uninit() {
+            t.module_exts = ModuleExts::inherit(&curr.module_exts);
+            t.set_cpumask(curr.cpumask());
+        }
         t.entry = Some(Box::into_raw(Box::new(entry)));
         t.ctx_mut().init(task_entry as usize, kstack.top(), tls);
         t.kstack = Some(kstack);
@@ -126,6 +217,38 @@ impl TaskInner {
         alloc::format!("Task({}, {:?})", self.id.as_u64(), self.name)
     }
 
+    /// Gets the state of the task.
+    #[inline]
+    pub fn state(&self) -> TaskState {
+        (self.state.load(Ordering::Acquire) & !STATE_INTERRUPTIBLE).into()
+    }
+
+    /// Gets the ID of the CPU that the task is running on, or is going to run
+    /// on if it's ready. For a blocked task, it's the CPU that it ran on last.
+    #[inline]
+    pub fn cpu_id(&self) -> usize {
+        self.cpu_id.load(Ordering::Acquire)
+    }
+
+    /// Gets the set of CPUs that the task is allowed to run on.
+    ///
+    /// Tasks inherit it from the task that creates them, all CPUs are allowed
+    /// by default.
+    #[inline]
+    pub fn cpumask(&self) -> CpuMask {
+        CpuMask::from_bits(self.cpumask.load(Ordering::Acquire))
+    }
+
+    /// Sets the set of CPUs that the task is allowed to run on.
+    ///
+    /// If the task is running or ready to run on a CPU not in `cpumask`, it's
+    /// moved to another CPU the next time it's scheduled. Use
+    /// [`set_affinity`](crate::set_affinity) to move the current task at once.
+    #[inline]
+    pub fn set_cpumask(&self, cpumask: CpuMask) {
+        self.cpumask.store(cpumask.bits(), Ordering::Release)
+    }
+
     /// Wait for the task to exit, and return the exit code.
     ///
     /// It will return immediately if the task has already exited (but not dropped).
@@ -135,6 +258,61 @@ impl TaskInner {
         Some(self.exit_code.load(Ordering::Acquire))
     }
 
+    /// Wait for the task to exit like [`TaskInner::join`], but the wait is
+    /// interrupted if the current task is cancelled.
+    pub fn join_interruptible(&self) -> Result<Option<i32>, Interrupted> {
+        self.wait_for_exit
+            .wait_until_interruptible(|| self.state() == TaskState::Exited)?;
+        Ok(Some(self.exit_code.load(Ordering::Acquire)))
+    }
+
+    /// Requests the task to be cancelled.
+    ///
+    /// The cancellation is deferred: the task is not stopped at once, but its
+    /// current and later interruptible waits (e.g.
+    /// [`WaitQueue::wait_interruptible`]) return [`Interrupted`], and the task
+    /// is expected to exit then. Uninterruptible waits are not affected.
+    pub fn cancel(&self) {
+        if self.cancelled.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        debug!("task cancel: {}", self.id_name());
+        let task = TASK_TABLE
+            .lock()
+            .get(&self.id.as_u64())
+            .and_then(Weak::upgrade);
+        if let Some(task) = task {
+            crate::run_queue::interrupt_task(task);
+        }
+    }
+
+    /// Returns `true` if the task is requested to be cancelled by
+    /// [`TaskInner::cancel`].
+    #[inline]
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Registers a callback to clean up resources when the task exits.
+    ///
+    /// Callbacks are called with the exit code in the context of the exiting
+    /// task, in the reverse order of registration, before its joiners are
+    /// woken up.
+    ///
+    /// Returns `false` if the task is already exiting, and `f` is not called.
+    pub fn on_exit<F>(&self, f: F) -> bool
+    where
+        F: FnOnce(i32) + Send + 'static,
+    {
+        match self.exit_callbacks.lock().as_mut() {
+            Some(callbacks) => {
+                callbacks.push(Box::new(f));
+                true
+            }
+            None => false,
+        }
+    }
+
     /// Returns the pointer to the user-defined task extended data.
     ///
     /// # Safety
@@ -159,6 +337,20 @@ impl TaskInner {
             None
         }
     }
+
+    /// Gets the extended data of type `T` attached by a kernel module.
+    ///
+    /// Tasks inherit the data of the task that creates them, until it's
+    /// replaced by [`TaskInner::set_module_ext`].
+    pub fn module_ext<T: Any + Send + Sync>(&self) -> Option<Arc<T>> {
+        self.module_exts.get()
+    }
+
+    /// Attaches the extended data of type `T` to the task, replacing the old
+    /// one. It doesn't affect the tasks that have inherited the old one.
+    pub fn set_module_ext<T: Any + Send + Sync>(&self, data: Arc<T>) {
+        self.module_exts.set(data)
+    }
 }
 
 // private methods
@@ -171,6 +363,9 @@ impl TaskInner {
             is_init: false,
             entry: None,
             state: AtomicU8::new(TaskState::Ready as u8),
+            cpu_id: AtomicUsize::new(0),
+            on_cpu: AtomicBool::new(false),
+            cpumask: AtomicUsize::new(CpuMask::full().bits()),
             in_wait_queue: AtomicBool::new(false),
             #[cfg(feature = "irq")]
             in_timer_list: AtomicBool::new(false),
@@ -178,11 +373,14 @@ impl TaskInner {
             need_resched: AtomicBool::new(false),
             #[cfg(feature = "preempt")]
             preempt_disable_count: AtomicUsize::new(0),
+            cancelled: AtomicBool::new(false),
+            exit_callbacks: SpinNoIrq::new(Some(Vec::new())),
             exit_code: AtomicI32::new(0),
             wait_for_exit: WaitQueue::new(),
             kstack: None,
             ctx: UnsafeCell::new(TaskContext::new()),
             task_ext: AxTaskExt::empty(),
+            module_exts: ModuleExts::new(),
             #[cfg(feature = "tls")]
             tls: TlsArea::alloc(),
         }
@@ -199,6 +397,9 @@ impl TaskInner {
     pub(crate) fn new_init(name: String) -> Self {
         let mut t = Self::new_common(TaskId::new(), name);
         t.is_init = true;
+        // it's running on the current CPU
+        *t.cpu_id.get_mut() = axhal::cpu::this_cpu_id();
+        *t.on_cpu.get_mut() = true;
         if t.name == "idle" {
             t.is_idle = true;
         }
@@ -206,17 +407,68 @@ impl TaskInner {
     }
 
     pub(crate) fn into_arc(self) -> AxTaskRef {
-        Arc::new(AxTask::new(self))
+        let id = self.id.as_u64();
+        let task = Arc::new(AxTask::new(self));
+        TASK_TABLE.lock().insert(id, Arc::downgrade(&task));
+        task
     }
 
     #[inline]
-    pub(crate) fn state(&self) -> TaskState {
-        self.state.load(Ordering::Acquire).into()
+    pub(crate) fn set_state(&self, state: TaskState) {
+        self.state.store(state as u8, Ordering::Release)
     }
 
+    /// Marks the task as blocked. An `interruptible` one can also be woken up
+    /// by [`TaskInner::cancel`].
     #[inline]
-    pub(crate) fn set_state(&self, state: TaskState) {
-        self.state.store(state as u8, Ordering::Release)
+    pub(crate) fn set_blocked(&self, interruptible: bool) {
+        let mut state = TaskState::Blocked as u8;
+        if interruptible {
+            state |= STATE_INTERRUPTIBLE;
+        }
+        // pairs with `cancel()`, so either the canceller sees the task is
+        // blocked, or the task sees it's cancelled.
+        self.state.store(state, Ordering::SeqCst)
+    }
+
+    /// Changes the state from `from` to `to` atomically, returns `false` if
+    /// the state is not `from`.
+    #[inline]
+    pub(crate) fn transition_state(&self, from: TaskState, to: TaskState) -> bool {
+        self.state
+            .fetch_update(Ordering::SeqCst, Ordering::Acquire, |state| {
+                (state & !STATE_INTERRUPTIBLE == from as u8).then_some(to as u8)
+            })
+            .is_ok()
+    }
+
+    /// Changes the state from blocked to ready atomically if the task is in
+    /// an interruptible wait, returns `false` otherwise.
+    #[inline]
+    pub(crate) fn interrupt(&self) -> bool {
+        self.state
+            .compare_exchange(
+                TaskState::Blocked as u8 | STATE_INTERRUPTIBLE,
+                TaskState::Ready as u8,
+                Ordering::SeqCst,
+                Ordering::Acquire,
+            )
+            .is_ok()
+    }
+
+    #[inline]
+    pub(crate) fn set_cpu_id(&self, cpu_id: usize) {
+        self.cpu_id.store(cpu_id, Ordering::Release)
+    }
+
+    #[inline]
+    pub(crate) fn on_cpu(&self) -> bool {
+        self.on_cpu.load(Ordering::Acquire)
+    }
+
+    #[inline]
+    pub(crate) fn set_on_cpu(&self, on_cpu: bool) {
+        self.on_cpu.store(on_cpu, Ordering::Release)
     }
 
     #[inline]
@@ -297,16 +549,28 @@ impl TaskInner {
     fn current_check_preempt_pending() {
         let curr = crate::current();
         if curr.need_resched.load(Ordering::Acquire) && curr.can_preempt(0) {
-            let mut rq = crate::RUN_QUEUE.lock();
+            let rq = crate::run_queue::current_run_queue();
             if curr.need_resched.load(Ordering::Acquire) {
                 rq.preempt_resched();
             }
         }
     }
 
-    pub(crate) fn notify_exit(&self, exit_code: i32, rq: &mut AxRunQueue) {
+    /// Calls the exit callbacks, no more callbacks can be registered after
+    /// that.
+    pub(crate) fn run_exit_callbacks(&self, exit_code: i32) {
+        let callbacks = self.exit_callbacks.lock().take();
+        for f in callbacks.into_iter().flatten().rev() {
+            f(exit_code);
+        }
+    }
+
+    pub(crate) fn notify_exit(&self, exit_code: i32) {
+        // the exit code must be visible before the state, as joiners may
+        // check the state on other CPUs.
         self.exit_code.store(exit_code, Ordering::Release);
-        self.wait_for_exit.notify_all_locked(false, rq);
+        self.set_state(TaskState::Exited);
+        self.wait_for_exit.notify_all(false);
     }
 
     #[inline]
@@ -343,9 +607,19 @@ impl fmt::Debug for TaskInner {
 impl Drop for TaskInner {
     fn drop(&mut self) {
         debug!("task drop: {}", self.id_name());
+        TASK_TABLE.lock().remove(&self.id.as_u64());
     }
 }
 
+/// Returns all tasks that are not dropped yet, in the order of their IDs.
+pub(crate) fn all_tasks() -> Vec<AxTaskRef> {
+    TASK_TABLE
+        .lock()
+        .values()
+        .filter_map(Weak::upgrade)
+        .collect()
+}
+
 struct TaskStack {
     ptr: NonNull<u8>,
     layout: Layout,
@@ -429,8 +703,8 @@ impl Deref for CurrentTask {
 }
 
 extern "C" fn task_entry() -> ! {
-    // release the lock that was implicitly held across the reschedule
-    unsafe { crate::RUN_QUEUE.force_unlock() };
+    // finish the context switch from the previous task
+    unsafe { crate::run_queue::finish_switch() };
     #[cfg(feature = "irq")]
     axhal::arch::enable_irqs();
     let task = crate::current();
@@ -1,8 +1,12 @@
 //! User-defined task extended data.
 
+use alloc::{sync::Arc, vec::Vec};
 use core::alloc::Layout;
+use core::any::{Any, TypeId};
 use core::mem::{align_of, size_of};
 
+use kspin::SpinNoIrq;
+
 #[no_mangle]
 #[linkage = "weak"]
 static __AX_TASK_EXT_SIZE: usize = 0;
@@ -108,6 +112,46 @@ impl Drop for AxTaskExt {
     }
 }
 
+/// Task extended data defined by kernel modules, keyed by their types.
+///
+/// Unlike the one defined by [`def_task_ext!`], which is left to the
+/// application, any number of modules can attach their own data to tasks.
+pub(crate) struct ModuleExts {
+    exts: SpinNoIrq<Vec<(TypeId, Arc<dyn Any + Send + Sync>)>>,
+}
+
+impl ModuleExts {
+    /// Creates an empty set of extended data.
+    pub const fn new() -> Self {
+        Self {
+            exts: SpinNoIrq::new(Vec::new()),
+        }
+    }
+
+    /// Creates a set of extended data that shares all data with `other`.
+    pub fn inherit(other: &Self) -> Self {
+        Self {
+            exts: SpinNoIrq::new(other.exts.lock().clone()),
+        }
+    }
+
+    /// Gets the data of type `T`.
+    pub fn get<T: Any + Send + Sync>(&self) -> Option<Arc<T>> {
+        let exts = self.exts.lock();
+        let (_, data) = exts.iter().find(|(id, _)| *id == TypeId::of::<T>())?;
+        data.clone().downcast().ok()
+    }
+
+    /// Sets the data of type `T`, replacing the old one.
+    pub fn set<T: Any + Send + Sync>(&self, data: Arc<T>) {
+        let mut exts = self.exts.lock();
+        match exts.iter_mut().find(|(id, _)| *id == TypeId::of::<T>()) {
+            Some((_, old)) => *old = data,
+            None => exts.push((TypeId::of::<T>(), data)),
+        }
+    }
+}
+
 /// A trait to convert [`TaskInner::task_ext_ptr`] to the reference of the
 /// concrete type.
 ///
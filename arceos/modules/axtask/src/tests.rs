@@ -1,7 +1,9 @@
 use core::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Mutex, Once};
 
-use crate::{api as axtask, current, WaitQueue};
+use crate::{
+    api as axtask, current, CpuMask, DeadlineParams, Interrupted, SchedClass, TaskInner, WaitQueue,
+};
 
 static INIT: Once = Once::new();
 static SERIAL: Mutex<()> = Mutex::new(());
@@ -128,3 +130,249 @@ fn test_task_join() {
         assert_eq!(tasks[i].join(), Some(i as _));
     }
 }
+
+#[test]
+fn test_all_tasks() {
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    let task = axtask::spawn_raw(axtask::yield_now, "listed".into(), 0x1000);
+    let id = task.id();
+    let tasks = axtask::all_tasks();
+    assert!(tasks
+        .windows(2)
+        .all(|w| w[0].id().as_u64() < w[1].id().as_u64()));
+    assert!(tasks.iter().any(|t| t.id() == id && t.name() == "listed"));
+    assert!(tasks.iter().any(|t| t.name() == "main"));
+    drop(tasks);
+
+    assert_eq!(task.join(), Some(0));
+    assert_eq!(task.state(), axtask::TaskState::Exited);
+}
+
+#[test]
+fn test_module_ext() {
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    struct Cwd(&'static str);
+
+    let curr = current();
+    curr.set_module_ext(std::sync::Arc::new(Cwd("/parent")));
+    assert_eq!(curr.module_ext::<Cwd>().unwrap().0, "/parent");
+    assert!(curr.module_ext::<u32>().is_none());
+
+    // inherited by the child, which can replace it without affecting the parent
+    let child = axtask::spawn(|| {
+        let curr = current();
+        assert_eq!(curr.module_ext::<Cwd>().unwrap().0, "/parent");
+        curr.set_module_ext(std::sync::Arc::new(Cwd("/child")));
+        assert_eq!(curr.module_ext::<Cwd>().unwrap().0, "/child");
+    });
+    assert_eq!(child.join(), Some(0));
+    assert_eq!(curr.module_ext::<Cwd>().unwrap().0, "/parent");
+}
+
+#[test]
+fn test_task_cpu_id() {
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    let cpu_id = axhal::cpu::this_cpu_id();
+    assert_eq!(current().cpu_id(), cpu_id);
+
+    let task = axtask::spawn(move || {
+        assert_eq!(current().cpu_id(), cpu_id);
+        axtask::yield_now();
+        assert_eq!(current().cpu_id(), cpu_id);
+    });
+    assert_eq!(task.join(), Some(0));
+    assert_eq!(task.cpu_id(), cpu_id);
+}
+
+#[test]
+fn test_task_affinity() {
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    let cpu_id = axhal::cpu::this_cpu_id();
+    assert_eq!(current().cpumask(), CpuMask::full());
+
+    let task = axtask::spawn_on_cpu(
+        move || {
+            assert_eq!(current().cpumask(), CpuMask::single(cpu_id));
+            assert!(!axtask::set_affinity(CpuMask::new()));
+            assert_eq!(current().cpumask(), CpuMask::single(cpu_id));
+            assert!(axtask::set_affinity(CpuMask::full()));
+            // child tasks inherit the affinity
+            let child = axtask::spawn(|| assert_eq!(current().cpumask(), CpuMask::full()));
+            child.join();
+        },
+        cpu_id,
+    );
+    assert_eq!(task.join(), Some(0));
+    assert_eq!(task.cpumask(), CpuMask::full());
+}
+
+#[test]
+fn test_deadline() {
+    use core::time::Duration;
+
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    let invalid = DeadlineParams {
+        runtime: Duration::from_millis(2),
+        deadline: Duration::from_millis(1),
+        period: Duration::from_millis(10),
+    };
+    assert!(!axtask::set_deadline(Some(invalid)));
+    assert!(axtask::set_deadline(None));
+    assert_eq!(axtask::get_deadline(), None);
+
+    #[cfg(feature = "sched_class")]
+    {
+        const NUM_TASKS: usize = 5;
+        static READY: AtomicUsize = AtomicUsize::new(0);
+        static GO: AtomicUsize = AtomicUsize::new(0);
+        static WQ: WaitQueue = WaitQueue::new();
+        static ORDER: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+
+        let tasks: Vec<_> = (0..NUM_TASKS)
+            .map(|i| {
+                axtask::spawn(move || {
+                    let params = DeadlineParams {
+                        runtime: Duration::from_secs(1),
+                        deadline: Duration::from_secs((10 - i) as u64),
+                        period: Duration::from_secs(10),
+                    };
+                    assert!(axtask::set_deadline(Some(params)));
+                    assert_eq!(axtask::get_deadline(), Some(params));
+                    READY.fetch_add(1, Ordering::Relaxed);
+                    WQ.wait_until(|| GO.load(Ordering::Relaxed) != 0);
+                    ORDER.lock().unwrap().push(i);
+                })
+            })
+            .collect();
+        while READY.load(Ordering::Relaxed) < NUM_TASKS {
+            axtask::yield_now();
+        }
+        GO.store(1, Ordering::Relaxed);
+        WQ.notify_all(false);
+        for task in tasks {
+            task.join();
+        }
+        // the task with the earliest deadline runs first
+        assert_eq!(*ORDER.lock().unwrap(), [4, 3, 2, 1, 0]);
+    }
+}
+
+#[test]
+fn test_sched_class() {
+    use core::time::Duration;
+
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    let invalid = DeadlineParams {
+        runtime: Duration::from_millis(2),
+        deadline: Duration::from_millis(1),
+        period: Duration::from_millis(10),
+    };
+    assert!(!axtask::set_sched_class(SchedClass::RealTime(invalid)));
+    assert!(!axtask::set_sched_class(SchedClass::Normal(20)));
+    assert!(axtask::set_sched_class(SchedClass::Normal(0)));
+    assert_eq!(axtask::get_sched_class(), SchedClass::Normal(0));
+
+    #[cfg(feature = "sched_class")]
+    {
+        static READY: AtomicUsize = AtomicUsize::new(0);
+        static GO: AtomicUsize = AtomicUsize::new(0);
+        static WQ: WaitQueue = WaitQueue::new();
+        static ORDER: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+
+        let rt = |deadline| {
+            SchedClass::RealTime(DeadlineParams {
+                runtime: Duration::from_secs(1),
+                deadline: Duration::from_secs(deadline),
+                period: Duration::from_secs(10),
+            })
+        };
+        // the classes are strictly ordered, and the real-time task with the
+        // earliest deadline runs first.
+        let classes = [SchedClass::Idle, SchedClass::Normal(0), rt(9), rt(8)];
+        let tasks: Vec<_> = classes
+            .iter()
+            .enumerate()
+            .map(|(i, &class)| {
+                let task = TaskInner::new(
+                    move || {
+                        assert_eq!(axtask::get_sched_class(), class);
+                        // only normal tasks have a priority
+                        let is_normal = matches!(class, SchedClass::Normal(_));
+                        assert_eq!(axtask::set_priority(0), is_normal);
+                        READY.fetch_add(1, Ordering::Relaxed);
+                        WQ.notify_all(false);
+                        WQ.wait_until(|| GO.load(Ordering::Relaxed) != 0);
+                        ORDER.lock().unwrap().push(i);
+                    },
+                    format!("T{}", i),
+                    0x1000,
+                );
+                axtask::spawn_task_with_class(task, class).unwrap()
+            })
+            .collect();
+        // block rather than yield, or the idle task would never run
+        WQ.wait_until(|| READY.load(Ordering::Relaxed) == classes.len());
+        GO.store(1, Ordering::Relaxed);
+        WQ.notify_all(false);
+        for task in tasks {
+            task.join();
+        }
+        assert_eq!(*ORDER.lock().unwrap(), [3, 2, 1, 0]);
+    }
+}
+
+#[test]
+fn test_task_cancel() {
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    static WQ: WaitQueue = WaitQueue::new();
+    static EXIT_CODE: AtomicUsize = AtomicUsize::new(0);
+
+    // cancelled before it blocks
+    let task = axtask::spawn(|| assert_eq!(WQ.wait_interruptible(), Err(Interrupted)));
+    task.cancel();
+    assert_eq!(task.join(), Some(0));
+
+    // cancelled while it's blocked
+    let task = axtask::spawn(|| {
+        assert!(current().on_exit(|code| EXIT_CODE.store(code as usize, Ordering::Relaxed)));
+        assert_eq!(WQ.wait_until_interruptible(|| false), Err(Interrupted));
+        assert!(current().is_cancelled());
+        // and later interruptible waits return at once
+        assert_eq!(WQ.wait_interruptible(), Err(Interrupted));
+        axtask::exit(2);
+    });
+    while task.state() != axtask::TaskState::Blocked {
+        axtask::yield_now();
+    }
+    task.cancel();
+    assert_eq!(task.join(), Some(2));
+    assert_eq!(EXIT_CODE.load(Ordering::Relaxed), 2);
+    assert!(!task.on_exit(|_| {}));
+
+    // a cancelled task that is still in the queue doesn't take the wakeup
+    let cancelled = axtask::spawn(|| assert_eq!(WQ.wait_interruptible(), Err(Interrupted)));
+    let waiter = axtask::spawn(|| WQ.wait());
+    while cancelled.state() != axtask::TaskState::Blocked
+        || waiter.state() != axtask::TaskState::Blocked
+    {
+        axtask::yield_now();
+    }
+    cancelled.cancel();
+    assert!(WQ.notify_one(false));
+    assert_eq!(waiter.join(), Some(0));
+    assert_eq!(cancelled.join(), Some(0));
+}
@@ -1,8 +1,10 @@
 use alloc::collections::VecDeque;
 use alloc::sync::Arc;
+use kernel_guard::NoPreemptIrqSave;
 use kspin::SpinRaw;
 
-use crate::{AxRunQueue, AxTaskRef, CurrentTask, RUN_QUEUE};
+use crate::run_queue::{current_run_queue, unblock_task};
+use crate::{AxTaskRef, CurrentTask, Interrupted};
 
 /// A queue to store sleeping tasks.
 ///
@@ -27,7 +29,7 @@ use crate::{AxRunQueue, AxTaskRef, CurrentTask, RUN_QUEUE};
 /// assert_eq!(VALUE.load(Ordering::Relaxed), 1);
 /// ```
 pub struct WaitQueue {
-    queue: SpinRaw<VecDeque<AxTaskRef>>, // we already disabled IRQs when lock the `RUN_QUEUE`
+    queue: SpinRaw<VecDeque<AxTaskRef>>, // we always disable IRQs before locking it
 }
 
 impl WaitQueue {
@@ -50,7 +52,7 @@ impl WaitQueue {
         // the event from another queue.
         if curr.in_wait_queue() {
             // wake up by timer (timeout).
-            // `RUN_QUEUE` is not locked here, so disable IRQs.
+            // the run queue is not held here, so disable IRQs.
             let _guard = kernel_guard::IrqSave::new();
             self.queue.lock().retain(|t| !curr.ptr_eq(t));
             curr.set_in_wait_queue(false);
@@ -65,11 +67,16 @@ impl WaitQueue {
     /// Blocks the current task and put it into the wait queue, until other task
     /// notifies it.
     pub fn wait(&self) {
-        RUN_QUEUE.lock().block_current(|task| {
-            task.set_in_wait_queue(true);
-            self.queue.lock().push_back(task)
-        });
-        self.cancel_events(crate::current());
+        self.wait_common(false);
+    }
+
+    /// Blocks the current task like [`WaitQueue::wait`], but it also wakes up
+    /// if it's cancelled.
+    ///
+    /// Returns [`Interrupted`] if the current task is cancelled.
+    pub fn wait_interruptible(&self) -> Result<(), Interrupted> {
+        self.wait_common(true);
+        check_cancelled()
     }
 
     /// Blocks the current task and put it into the wait queue, until the given
@@ -81,48 +88,149 @@ impl WaitQueue {
     where
         F: Fn() -> bool,
     {
+        self.wait_until_common(false, condition);
+    }
+
+    /// Blocks the current task like [`WaitQueue::wait_until`], but it also
+    /// wakes up if it's cancelled.
+    ///
+    /// Returns [`Interrupted`] if the current task is cancelled before the
+    /// condition becomes true.
+    pub fn wait_until_interruptible<F>(&self, condition: F) -> Result<(), Interrupted>
+    where
+        F: Fn() -> bool,
+    {
+        if self.wait_until_common(true, condition) {
+            Ok(())
+        } else {
+            Err(Interrupted)
+        }
+    }
+
+    /// Blocks the current task and put it into the wait queue, until other tasks
+    /// notify it, or the given duration has elapsed.
+    #[cfg(feature = "irq")]
+    pub fn wait_timeout(&self, dur: core::time::Duration) -> bool {
+        self.wait_timeout_common(false, dur)
+    }
+
+    /// Blocks the current task like [`WaitQueue::wait_timeout`], but it also
+    /// wakes up if it's cancelled.
+    ///
+    /// Returns [`Interrupted`] if the current task is cancelled.
+    #[cfg(feature = "irq")]
+    pub fn wait_timeout_interruptible(
+        &self,
+        dur: core::time::Duration,
+    ) -> Result<bool, Interrupted> {
+        let timeout = self.wait_timeout_common(true, dur);
+        check_cancelled().map(|_| timeout)
+    }
+
+    /// Blocks the current task and put it into the wait queue, until the given
+    /// `condition` becomes true, or the given duration has elapsed.
+    ///
+    /// Note that even other tasks notify this task, it will not wake up until
+    /// the above conditions are met.
+    #[cfg(feature = "irq")]
+    pub fn wait_timeout_until<F>(&self, dur: core::time::Duration, condition: F) -> bool
+    where
+        F: Fn() -> bool,
+    {
+        self.wait_timeout_until_common(false, dur, condition)
+    }
+
+    /// Blocks the current task like [`WaitQueue::wait_timeout_until`], but it
+    /// also wakes up if it's cancelled.
+    ///
+    /// Returns [`Interrupted`] if the current task is cancelled before the
+    /// condition becomes true.
+    #[cfg(feature = "irq")]
+    pub fn wait_timeout_until_interruptible<F>(
+        &self,
+        dur: core::time::Duration,
+        condition: F,
+    ) -> Result<bool, Interrupted>
+    where
+        F: Fn() -> bool,
+    {
+        let timeout = self.wait_timeout_until_common(true, dur, condition);
+        if timeout {
+            check_cancelled()?;
+        }
+        Ok(timeout)
+    }
+
+    fn wait_common(&self, interruptible: bool) {
+        if interruptible && crate::current().is_cancelled() {
+            return;
+        }
+        current_run_queue().block_current(interruptible, |task| {
+            task.set_in_wait_queue(true);
+            self.queue.lock().push_back(task)
+        });
+        self.cancel_events(crate::current());
+    }
+
+    /// Returns `false` if it's interrupted before the condition becomes true.
+    fn wait_until_common<F>(&self, interruptible: bool, condition: F) -> bool
+    where
+        F: Fn() -> bool,
+    {
+        let mut satisfied = true;
         loop {
-            let mut rq = RUN_QUEUE.lock();
+            let rq = current_run_queue();
+            // hold the wait queue until the task is in it, so notifications
+            // after `condition` is checked will not be missed.
+            let mut wq = self.queue.lock();
             if condition() {
                 break;
             }
-            rq.block_current(|task| {
+            if interruptible && crate::current().is_cancelled() {
+                satisfied = false;
+                break;
+            }
+            rq.block_current(interruptible, move |task| {
                 task.set_in_wait_queue(true);
-                self.queue.lock().push_back(task);
+                wq.push_back(task);
             });
         }
         self.cancel_events(crate::current());
+        satisfied
     }
 
-    /// Blocks the current task and put it into the wait queue, until other tasks
-    /// notify it, or the given duration has elapsed.
     #[cfg(feature = "irq")]
-    pub fn wait_timeout(&self, dur: core::time::Duration) -> bool {
+    fn wait_timeout_common(&self, interruptible: bool, dur: core::time::Duration) -> bool {
         let curr = crate::current();
+        if interruptible && curr.is_cancelled() {
+            return false;
+        }
         let deadline = axhal::time::wall_time() + dur;
         debug!(
             "task wait_timeout: {} deadline={:?}",
             curr.id_name(),
             deadline
         );
-        crate::timers::set_alarm_wakeup(deadline, curr.clone());
 
-        RUN_QUEUE.lock().block_current(|task| {
+        current_run_queue().block_current(interruptible, |task| {
             task.set_in_wait_queue(true);
-            self.queue.lock().push_back(task)
+            self.queue.lock().push_back(task.clone());
+            crate::timers::set_alarm_wakeup(deadline, task);
         });
         let timeout = curr.in_wait_queue(); // still in the wait queue, must have timed out
         self.cancel_events(curr);
         timeout
     }
 
-    /// Blocks the current task and put it into the wait queue, until the given
-    /// `condition` becomes true, or the given duration has elapsed.
-    ///
-    /// Note that even other tasks notify this task, it will not wake up until
-    /// the above conditions are met.
+    /// Also returns `true` if it's interrupted before the condition becomes
+    /// true.
     #[cfg(feature = "irq")]
-    pub fn wait_timeout_until<F>(&self, dur: core::time::Duration, condition: F) -> bool
+    fn wait_timeout_until_common<F>(
+        &self,
+        interruptible: bool,
+        dur: core::time::Duration,
+        condition: F,
+    ) -> bool
     where
         F: Fn() -> bool,
     {
@@ -133,18 +241,24 @@ impl WaitQueue {
             curr.id_name(),
             deadline
         );
-        crate::timers::set_alarm_wakeup(deadline, curr.clone());
 
         let mut timeout = true;
         while axhal::time::wall_time() < deadline {
-            let mut rq = RUN_QUEUE.lock();
+            let rq = current_run_queue();
+            let mut wq = self.queue.lock();
             if condition() {
                 timeout = false;
                 break;
             }
-            rq.block_current(|task| {
+            if interruptible && curr.is_cancelled() {
+                break;
+            }
+            rq.block_current(interruptible, move |task| {
                 task.set_in_wait_queue(true);
-                self.queue.lock().push_back(task);
+                wq.push_back(task.clone());
+                if !task.in_timer_list() {
+                    crate::timers::set_alarm_wakeup(deadline, task);
+                }
             });
         }
         self.cancel_events(curr);
@@ -153,15 +267,22 @@ impl WaitQueue {
 
     /// Wakes up one task in the wait queue, usually the first one.
     ///
+    /// Tasks that are woken up otherwise (interrupted or timed out), but not
+    /// yet removed by themselves, are removed and skipped. Returns `false` if
+    /// no task is woken up.
+    ///
     /// If `resched` is true, the current task will be preempted when the
     /// preemption is enabled.
     pub fn notify_one(&self, resched: bool) -> bool {
-        let mut rq = RUN_QUEUE.lock();
-        if !self.queue.lock().is_empty() {
-            self.notify_one_locked(resched, &mut rq)
-        } else {
-            false
+        let _guard = NoPreemptIrqSave::new();
+        let mut wq = self.queue.lock();
+        while let Some(task) = wq.pop_front() {
+            task.set_in_wait_queue(false);
+            if unblock_task(task, resched) {
+                return true;
+            }
         }
+        false
     }
 
     /// Wakes all tasks in the wait queue.
@@ -169,15 +290,10 @@ impl WaitQueue {
     /// If `resched` is true, the current task will be preempted when the
     /// preemption is enabled.
     pub fn notify_all(&self, resched: bool) {
-        loop {
-            let mut rq = RUN_QUEUE.lock();
-            if let Some(task) = self.queue.lock().pop_front() {
-                task.set_in_wait_queue(false);
-                rq.unblock_task(task, resched);
-            } else {
-                break;
-            }
-            drop(rq); // we must unlock `RUN_QUEUE` after unlocking `self.queue`.
+        let _guard = NoPreemptIrqSave::new();
+        while let Some(task) = self.queue.lock().pop_front() {
+            task.set_in_wait_queue(false);
+            unblock_task(task, resched);
         }
     }
 
@@ -186,31 +302,22 @@ impl WaitQueue {
     /// If `resched` is true, the current task will be preempted when the
     /// preemption is enabled.
     pub fn notify_task(&mut self, resched: bool, task: &AxTaskRef) -> bool {
-        let mut rq = RUN_QUEUE.lock();
+        let _guard = NoPreemptIrqSave::new();
         let mut wq = self.queue.lock();
         if let Some(index) = wq.iter().position(|t| Arc::ptr_eq(t, task)) {
             task.set_in_wait_queue(false);
-            rq.unblock_task(wq.remove(index).unwrap(), resched);
-            true
-        } else {
-            false
-        }
-    }
-
-    pub(crate) fn notify_one_locked(&self, resched: bool, rq: &mut AxRunQueue) -> bool {
-        if let Some(task) = self.queue.lock().pop_front() {
-            task.set_in_wait_queue(false);
-            rq.unblock_task(task, resched);
-            true
+            unblock_task(wq.remove(index).unwrap(), resched)
         } else {
             false
         }
     }
+}
 
-    pub(crate) fn notify_all_locked(&self, resched: bool, rq: &mut AxRunQueue) {
-        while let Some(task) = self.queue.lock().pop_front() {
-            task.set_in_wait_queue(false);
-            rq.unblock_task(task, resched);
-        }
+/// Returns [`Interrupted`] if the current task is cancelled.
+fn check_cancelled() -> Result<(), Interrupted> {
+    if crate::current().is_cancelled() {
+        Err(Interrupted)
+    } else {
+        Ok(())
     }
 }
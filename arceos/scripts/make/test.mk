@@ -2,5 +2,7 @@
 
 define unit_test
   $(call run_cmd,cargo test,-p axfs $(1) --features "myfs" -- --nocapture)
+  $(call run_cmd,cargo test,-p axfs $(1) --features "ext4fs" -- --nocapture)
+  $(call run_cmd,AX_INITRAMFS=$(CURDIR)/modules/axfs/resources/initramfs.cpio cargo test,-p axfs $(1) --features "initramfs" -- --nocapture)
   $(call run_cmd,cargo test,--workspace $(1) -- --nocapture)
 endef
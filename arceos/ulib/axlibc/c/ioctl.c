@@ -1,9 +1,21 @@
+#include <stdarg.h>
 #include <stdio.h>
 #include <sys/ioctl.h>
 
-// TODO
+#ifdef AX_CONFIG_FD
+
+// TODO: remove this function in future work
+int ax_ioctl(int fd, unsigned long request, unsigned long arg);
+
 int ioctl(int __fd, int __request, ...)
 {
-    unimplemented();
-    return 0;
+    unsigned long arg;
+    va_list ap;
+    va_start(ap, __request);
+    arg = va_arg(ap, unsigned long);
+    va_end(ap);
+
+    return ax_ioctl(__fd, (unsigned int)__request, arg);
 }
+
+#endif // AX_CONFIG_FD
@@ -61,13 +61,6 @@ int access(const char *pathname, int mode)
     return 0;
 }
 
-// TODO:
-ssize_t readlink(const char *path, char *buf, size_t bufsiz)
-{
-    unimplemented();
-    return 0;
-}
-
 // TODO:
 int unlink(const char *pathname)
 {
@@ -110,13 +103,6 @@ int ftruncate(int fd, off_t length)
     return 0;
 }
 
-// TODO
-int chdir(const char *__path)
-{
-    unimplemented();
-    return 0;
-}
-
 // TODO
 int truncate(const char *path, off_t length)
 {
@@ -97,8 +97,9 @@
 #define POSIX_FADV_NOREUSE  5
 #endif
 
-#define AT_FDCWD      (-100)
-#define AT_EMPTY_PATH 0x1000
+#define AT_FDCWD            (-100)
+#define AT_SYMLINK_NOFOLLOW 0x100
+#define AT_EMPTY_PATH       0x1000
 
 #define SYNC_FILE_RANGE_WAIT_BEFORE 1
 #define SYNC_FILE_RANGE_WRITE       2
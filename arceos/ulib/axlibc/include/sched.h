@@ -2,6 +2,27 @@
 #define _SCHED_H
 
 #include <stddef.h>
+#include <stdint.h>
+#include <string.h>
+#include <sys/types.h>
+
+#define SCHED_OTHER    0
+#define SCHED_FIFO     1
+#define SCHED_RR       2
+#define SCHED_BATCH    3
+#define SCHED_IDLE     5
+#define SCHED_DEADLINE 6
+
+struct sched_attr {
+    uint32_t size;
+    uint32_t sched_policy;
+    uint64_t sched_flags;
+    int32_t sched_nice;
+    uint32_t sched_priority;
+    uint64_t sched_runtime;
+    uint64_t sched_deadline;
+    uint64_t sched_period;
+};
 
 typedef struct cpu_set_t {
     unsigned long __bits[128 / sizeof(long)];
@@ -12,12 +33,20 @@ typedef struct cpu_set_t {
                         : (((unsigned long *)(set))[(i) / 8 / sizeof(long)] op( \
                               1UL << ((i) % (8 * sizeof(long))))))
 
-#define CPU_SET_S(i, size, set) __CPU_op_S(i, size, set, |=)
-#define CPU_ZERO_S(size, set)   memset(set, 0, size)
+#define CPU_SET_S(i, size, set)   __CPU_op_S(i, size, set, |=)
+#define CPU_CLR_S(i, size, set)   __CPU_op_S(i, size, set, &= ~)
+#define CPU_ISSET_S(i, size, set) __CPU_op_S(i, size, set, &)
+#define CPU_ZERO_S(size, set)     memset(set, 0, size)
 
-#define CPU_SET(i, set) CPU_SET_S(i, sizeof(cpu_set_t), set);
-#define CPU_ZERO(set)   CPU_ZERO_S(sizeof(cpu_set_t), set)
+#define CPU_SET(i, set)   CPU_SET_S(i, sizeof(cpu_set_t), set)
+#define CPU_CLR(i, set)   CPU_CLR_S(i, sizeof(cpu_set_t), set)
+#define CPU_ISSET(i, set) CPU_ISSET_S(i, sizeof(cpu_set_t), set)
+#define CPU_ZERO(set)     CPU_ZERO_S(sizeof(cpu_set_t), set)
 
 int sched_setaffinity(pid_t, size_t, const cpu_set_t *);
+int sched_getaffinity(pid_t, size_t, cpu_set_t *);
+
+int sched_setattr(pid_t, struct sched_attr *, unsigned int);
+int sched_getattr(pid_t, struct sched_attr *, unsigned int, unsigned int);
 
 #endif // _SCHED_H
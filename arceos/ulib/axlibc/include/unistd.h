@@ -58,6 +58,7 @@ int faccessat(int, const char *, int, int);
 
 int chdir(const char *);
 int fchdir(int);
+int chroot(const char *);
 char *getcwd(char *, size_t);
 
 unsigned alarm(unsigned);
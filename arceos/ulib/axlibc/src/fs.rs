@@ -1,7 +1,9 @@
-use core::ffi::{c_char, c_int};
+use core::ffi::{c_char, c_int, c_ulong, c_void};
 
 use arceos_posix_api::{
-    sys_fstat, sys_getcwd, sys_lseek, sys_lstat, sys_open, sys_rename, sys_stat,
+    sys_chdir, sys_chroot, sys_flock, sys_fstat, sys_fstatfs, sys_getcwd, sys_link, sys_lseek,
+    sys_lstat, sys_mount, sys_open, sys_readlink, sys_rename, sys_stat, sys_statfs, sys_symlink,
+    sys_umount2, sys_utimensat,
 };
 
 use crate::{ctypes, utils::e};
@@ -51,12 +53,94 @@ pub unsafe extern "C" fn lstat(path: *const c_char, buf: *mut ctypes::stat) -> c
     e(sys_lstat(path, buf) as _)
 }
 
+/// Get the usage of the filesystem containing `path`.
+///
+/// Return 0 if success.
+#[no_mangle]
+pub unsafe extern "C" fn statfs(path: *const c_char, buf: *mut ctypes::statfs) -> c_int {
+    e(sys_statfs(path, buf))
+}
+
+/// Get the usage of the filesystem containing the file `fd`.
+///
+/// Return 0 if success.
+#[no_mangle]
+pub unsafe extern "C" fn fstatfs(fd: c_int, buf: *mut ctypes::statfs) -> c_int {
+    e(sys_fstatfs(fd, buf))
+}
+
+/// Set the access and modification times of the file `path`.
+///
+/// Return 0 if success.
+#[no_mangle]
+pub unsafe extern "C" fn utimensat(
+    dirfd: c_int,
+    path: *const c_char,
+    times: *const ctypes::timespec,
+    flags: c_int,
+) -> c_int {
+    e(sys_utimensat(dirfd, path, times, flags))
+}
+
+/// Set the access and modification times of the file `fd`.
+///
+/// Return 0 if success.
+#[no_mangle]
+pub unsafe extern "C" fn futimens(fd: c_int, times: *const ctypes::timespec) -> c_int {
+    e(sys_utimensat(fd, core::ptr::null(), times, 0))
+}
+
+/// Create a symbolic link `linkpath` pointing to `target`.
+///
+/// Return 0 if success.
+#[no_mangle]
+pub unsafe extern "C" fn symlink(target: *const c_char, linkpath: *const c_char) -> c_int {
+    e(sys_symlink(target, linkpath))
+}
+
+/// Read the target of the symbolic link `path` into `buf`.
+///
+/// Return the number of bytes placed in `buf`.
+#[no_mangle]
+pub unsafe extern "C" fn readlink(
+    path: *const c_char,
+    buf: *mut c_char,
+    bufsize: usize,
+) -> ctypes::ssize_t {
+    e(sys_readlink(path, buf, bufsize) as _) as _
+}
+
+/// Create a new hard link `new` to the file `old`.
+///
+/// Return 0 if success.
+#[no_mangle]
+pub unsafe extern "C" fn link(old: *const c_char, new: *const c_char) -> c_int {
+    e(sys_link(old, new))
+}
+
 /// Get the path of the current directory.
 #[no_mangle]
 pub unsafe extern "C" fn getcwd(buf: *mut c_char, size: usize) -> *mut c_char {
     sys_getcwd(buf, size)
 }
 
+/// Change the current directory of the calling thread to `path`.
+///
+/// Return 0 if success.
+#[no_mangle]
+pub unsafe extern "C" fn chdir(path: *const c_char) -> c_int {
+    e(sys_chdir(path))
+}
+
+/// Change the root directory of the calling thread to `path`, and the current
+/// directory to the new root.
+///
+/// Return 0 if success.
+#[no_mangle]
+pub unsafe extern "C" fn chroot(path: *const c_char) -> c_int {
+    e(sys_chroot(path))
+}
+
 /// Rename `old` to `new`
 /// If new exists, it is first removed.
 ///
@@ -65,3 +149,41 @@ pub unsafe extern "C" fn getcwd(buf: *mut c_char, size: usize) -> *mut c_char {
 pub unsafe extern "C" fn rename(old: *const c_char, new: *const c_char) -> c_int {
     e(sys_rename(old, new))
 }
+
+/// Mount a new filesystem of type `fstype` on the directory `target`.
+///
+/// Return 0 if the operation succeeds, otherwise return -1.
+#[no_mangle]
+pub unsafe extern "C" fn mount(
+    source: *const c_char,
+    target: *const c_char,
+    fstype: *const c_char,
+    flags: c_ulong,
+    data: *const c_void,
+) -> c_int {
+    e(sys_mount(source, target, fstype, flags, data))
+}
+
+/// Unmount the filesystem mounted on `target`.
+///
+/// Return 0 if the operation succeeds, otherwise return -1.
+#[no_mangle]
+pub unsafe extern "C" fn umount(target: *const c_char) -> c_int {
+    e(sys_umount2(target, 0))
+}
+
+/// Unmount the filesystem mounted on `target` with `flags`.
+///
+/// Return 0 if the operation succeeds, otherwise return -1.
+#[no_mangle]
+pub unsafe extern "C" fn umount2(target: *const c_char, flags: c_int) -> c_int {
+    e(sys_umount2(target, flags))
+}
+
+/// Apply or remove an advisory lock on the whole file `fd`.
+///
+/// Return 0 if the operation succeeds, otherwise return -1.
+#[no_mangle]
+pub unsafe extern "C" fn flock(fd: c_int, operation: c_int) -> c_int {
+    e(sys_flock(fd, operation))
+}
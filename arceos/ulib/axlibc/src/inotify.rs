@@ -0,0 +1,37 @@
+use core::ffi::{c_char, c_int};
+
+use arceos_posix_api::{sys_inotify_add_watch, sys_inotify_init1, sys_inotify_rm_watch};
+
+use crate::utils::e;
+
+/// Create an inotify instance.
+///
+/// Return a file descriptor referring to it.
+#[no_mangle]
+pub unsafe extern "C" fn inotify_init() -> c_int {
+    e(sys_inotify_init1(0))
+}
+
+/// Create an inotify instance with `flags` (`IN_NONBLOCK` and `IN_CLOEXEC`).
+///
+/// Return a file descriptor referring to it.
+#[no_mangle]
+pub unsafe extern "C" fn inotify_init1(flags: c_int) -> c_int {
+    e(sys_inotify_init1(flags))
+}
+
+/// Watch the file or directory at `path` for the events in `mask`.
+///
+/// Return the watch descriptor.
+#[no_mangle]
+pub unsafe extern "C" fn inotify_add_watch(fd: c_int, path: *const c_char, mask: u32) -> c_int {
+    e(sys_inotify_add_watch(fd, path, mask))
+}
+
+/// Remove the watch `wd` from the inotify instance `fd`.
+///
+/// Return 0 if success.
+#[no_mangle]
+pub unsafe extern "C" fn inotify_rm_watch(fd: c_int, wd: c_int) -> c_int {
+    e(sys_inotify_rm_watch(fd, wd))
+}
@@ -53,6 +53,8 @@ mod utils;
 mod fd_ops;
 #[cfg(feature = "fs")]
 mod fs;
+#[cfg(feature = "fs")]
+mod inotify;
 #[cfg(any(feature = "select", feature = "epoll"))]
 mod io_mpx;
 #[cfg(feature = "alloc")]
@@ -63,6 +65,8 @@ mod net;
 mod pipe;
 #[cfg(feature = "multitask")]
 mod pthread;
+#[cfg(feature = "multitask")]
+mod sched;
 #[cfg(feature = "alloc")]
 mod strftime;
 #[cfg(feature = "fp_simd")]
@@ -97,10 +101,15 @@ pub use self::malloc::{free, malloc};
 pub use self::strftime::strftime;
 
 #[cfg(feature = "fd")]
-pub use self::fd_ops::{ax_fcntl, close, dup, dup2, dup3};
+pub use self::fd_ops::{ax_fcntl, ax_ioctl, close, dup, dup2, dup3};
 
 #[cfg(feature = "fs")]
-pub use self::fs::{ax_open, fstat, getcwd, lseek, lstat, rename, stat};
+pub use self::fs::{
+    ax_open, chdir, chroot, flock, fstat, fstatfs, futimens, getcwd, lseek, lstat, rename, stat,
+    statfs, utimensat,
+};
+#[cfg(feature = "fs")]
+pub use self::inotify::{inotify_add_watch, inotify_init, inotify_init1, inotify_rm_watch};
 
 #[cfg(feature = "net")]
 pub use self::net::{
@@ -37,6 +37,38 @@ pub unsafe extern "C" fn pthread_join(
     e(api::sys_pthread_join(thread, retval))
 }
 
+/// Requests the given thread to be cancelled.
+#[no_mangle]
+pub unsafe extern "C" fn pthread_cancel(thread: ctypes::pthread_t) -> c_int {
+    e(api::sys_pthread_cancel(thread))
+}
+
+/// Exits the current thread if it's requested to be cancelled.
+#[no_mangle]
+pub unsafe extern "C" fn pthread_testcancel() {
+    api::sys_pthread_testcancel()
+}
+
+/// Set the CPUs that the given thread is allowed to run on.
+#[no_mangle]
+pub unsafe extern "C" fn pthread_setaffinity_np(
+    thread: ctypes::pthread_t,
+    cpusetsize: usize,
+    cpuset: *const ctypes::cpu_set_t,
+) -> c_int {
+    e(api::sys_pthread_setaffinity_np(thread, cpusetsize, cpuset))
+}
+
+/// Get the CPUs that the given thread is allowed to run on.
+#[no_mangle]
+pub unsafe extern "C" fn pthread_getaffinity_np(
+    thread: ctypes::pthread_t,
+    cpusetsize: usize,
+    cpuset: *mut ctypes::cpu_set_t,
+) -> c_int {
+    e(api::sys_pthread_getaffinity_np(thread, cpusetsize, cpuset))
+}
+
 /// Initialize a mutex.
 #[no_mangle]
 pub unsafe extern "C" fn pthread_mutex_init(
@@ -0,0 +1,46 @@
+use crate::{ctypes, utils::e};
+use arceos_posix_api::{
+    sys_sched_getaffinity, sys_sched_getattr, sys_sched_setaffinity, sys_sched_setattr,
+};
+use core::ffi::{c_int, c_uint};
+
+/// Set the CPUs that the thread `pid` is allowed to run on.
+#[no_mangle]
+pub unsafe extern "C" fn sched_setaffinity(
+    pid: c_int,
+    cpusetsize: usize,
+    cpuset: *const ctypes::cpu_set_t,
+) -> c_int {
+    e(sys_sched_setaffinity(pid, cpusetsize, cpuset))
+}
+
+/// Get the CPUs that the thread `pid` is allowed to run on.
+#[no_mangle]
+pub unsafe extern "C" fn sched_getaffinity(
+    pid: c_int,
+    cpusetsize: usize,
+    cpuset: *mut ctypes::cpu_set_t,
+) -> c_int {
+    e(sys_sched_getaffinity(pid, cpusetsize, cpuset))
+}
+
+/// Set the scheduling policy and attributes of the thread `pid`.
+#[no_mangle]
+pub unsafe extern "C" fn sched_setattr(
+    pid: c_int,
+    attr: *const ctypes::sched_attr,
+    flags: c_uint,
+) -> c_int {
+    e(sys_sched_setattr(pid, attr, flags))
+}
+
+/// Get the scheduling policy and attributes of the thread `pid`.
+#[no_mangle]
+pub unsafe extern "C" fn sched_getattr(
+    pid: c_int,
+    attr: *mut ctypes::sched_attr,
+    size: c_uint,
+    flags: c_uint,
+) -> c_int {
+    e(sys_sched_getattr(pid, attr, size, flags))
+}
@@ -1,4 +1,5 @@
 use crate::io::{prelude::*, Result, SeekFrom};
+use crate::time::{SystemTime, UNIX_EPOCH};
 use core::fmt;
 
 use arceos_api::fs as api;
@@ -16,7 +17,7 @@ pub struct File {
 }
 
 /// Metadata information about a file.
-pub struct Metadata(api::AxFileAttr);
+pub struct Metadata(pub(super) api::AxFileAttr);
 
 /// Options and flags which can be used to configure how a file is opened.
 #[derive(Clone, Debug)]
@@ -90,6 +91,12 @@ impl Metadata {
         self.0.is_file()
     }
 
+    /// Returns `true` if this metadata is for a symbolic link. It's only
+    /// possible for metadata from [`symlink_metadata`](super::symlink_metadata).
+    pub const fn is_symlink(&self) -> bool {
+        matches!(self.0.file_type(), FileType::SymLink)
+    }
+
     /// Returns the size of the file, in bytes, this metadata is for.
     #[allow(clippy::len_without_is_empty)]
     pub const fn len(&self) -> u64 {
@@ -110,6 +117,21 @@ impl Metadata {
     pub const fn blocks(&self) -> u64 {
         self.0.blocks()
     }
+
+    /// Returns the last modification time listed in this metadata.
+    ///
+    /// It's [`UNIX_EPOCH`] if the filesystem doesn't record timestamps.
+    pub fn modified(&self) -> Result<SystemTime> {
+        Ok(UNIX_EPOCH + self.0.times().modified)
+    }
+
+    /// Returns the last access time of this metadata.
+    ///
+    /// It's [`UNIX_EPOCH`] if the filesystem doesn't record timestamps. Some
+    /// filesystems, like FAT, only record the date of the last access.
+    pub fn accessed(&self) -> Result<SystemTime> {
+        Ok(UNIX_EPOCH + self.0.times().accessed)
+    }
 }
 
 impl fmt::Debug for Metadata {
@@ -119,6 +141,7 @@ impl fmt::Debug for Metadata {
             .field("is_dir", &self.is_dir())
             .field("is_file", &self.is_file())
             .field("permissions", &self.permissions())
+            .field("modified", &self.modified().ok())
             .finish_non_exhaustive()
     }
 }
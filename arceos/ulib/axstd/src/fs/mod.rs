@@ -11,6 +11,9 @@ use alloc::{string::String, vec::Vec};
 pub use self::dir::{DirBuilder, DirEntry, ReadDir};
 pub use self::file::{File, FileType, Metadata, OpenOptions, Permissions};
 
+/// Usage information of a filesystem, returned by [`statfs`].
+pub type FsStat = arceos_api::fs::AxFsStat;
+
 /// Read the entire contents of a file into a bytes vector.
 #[cfg(feature = "alloc")]
 pub fn read(path: &str) -> io::Result<Vec<u8>> {
@@ -37,11 +40,22 @@ pub fn write<C: AsRef<[u8]>>(path: &str, contents: C) -> io::Result<()> {
 }
 
 /// Given a path, query the file system to get information about a file,
-/// directory, etc.
+/// directory, etc. Symlinks are followed.
 pub fn metadata(path: &str) -> io::Result<Metadata> {
     File::open(path)?.metadata()
 }
 
+/// Queries the metadata about a file without following symlinks.
+pub fn symlink_metadata(path: &str) -> io::Result<Metadata> {
+    arceos_api::fs::ax_symlink_attr(path).map(Metadata)
+}
+
+/// Returns the usage of the filesystem containing the path, such as its size
+/// and free space.
+pub fn statfs(path: &str) -> io::Result<FsStat> {
+    arceos_api::fs::ax_statfs(path)
+}
+
 /// Returns an iterator over the entries within a directory.
 pub fn read_dir(path: &str) -> io::Result<ReadDir> {
     ReadDir::new(path)
@@ -68,10 +82,33 @@ pub fn remove_file(path: &str) -> io::Result<()> {
     arceos_api::fs::ax_remove_file(path)
 }
 
-/// Rename a file or directory to a new name.
-/// Delete the original file if `old` already exists.
+/// Rename a file or directory to a new name, possibly in another directory.
+/// Replace the file at `new` atomically if it already exists.
 ///
-/// This only works then the new path is in the same mounted fs.
+/// This only works then the new path is in the same mounted fs, otherwise it
+/// fails with `Unsupported`.
 pub fn rename(old: &str, new: &str) -> io::Result<()> {
     arceos_api::fs::ax_rename(old, new)
 }
+
+/// Creates a new symbolic link at `link` pointing to `original`.
+///
+/// `original` is not checked, and is resolved relative to the directory of
+/// the link when the link is followed.
+pub fn symlink(original: &str, link: &str) -> io::Result<()> {
+    arceos_api::fs::ax_symlink(original, link)
+}
+
+/// Reads the target of a symbolic link.
+#[cfg(feature = "alloc")]
+pub fn read_link(path: &str) -> io::Result<String> {
+    arceos_api::fs::ax_read_link(path)
+}
+
+/// Creates a new hard link at `link` to the file at `original`.
+///
+/// Fails with [`Unsupported`](io::Error::Unsupported) if the paths are on
+/// different filesystems.
+pub fn hard_link(original: &str, link: &str) -> io::Result<()> {
+    arceos_api::fs::ax_hard_link(original, link)
+}
@@ -9,6 +9,10 @@ use core::{cell::UnsafeCell, num::NonZeroU64};
 use arceos_api::task::{self as api, AxTaskHandle};
 use axerrno::ax_err_type;
 
+pub use arceos_api::task::AxCpuMask as CpuMask;
+pub use arceos_api::task::AxDeadlineParams as DeadlineParams;
+pub use arceos_api::task::AxSchedClass as SchedClass;
+
 /// A unique identifier for a running thread.
 #[derive(Eq, PartialEq, Clone, Copy, Debug)]
 pub struct ThreadId(NonZeroU64);
@@ -49,6 +53,10 @@ pub struct Builder {
     name: Option<String>,
     // The size of the stack for the spawned thread in bytes
     stack_size: Option<usize>,
+    // The CPUs that the spawned thread is allowed to run on
+    affinity: Option<CpuMask>,
+    // The scheduling class of the spawned thread
+    sched_class: Option<SchedClass>,
 }
 
 impl Builder {
@@ -58,6 +66,8 @@ impl Builder {
         Builder {
             name: None,
             stack_size: None,
+            affinity: None,
+            sched_class: None,
         }
     }
 
@@ -73,6 +83,24 @@ impl Builder {
         self
     }
 
+    /// Restricts the new thread to run only on the CPUs in `cpumask`.
+    ///
+    /// By default, the new thread inherits the affinity of the current one.
+    pub fn affinity(mut self, cpumask: CpuMask) -> Builder {
+        self.affinity = Some(cpumask);
+        self
+    }
+
+    /// Sets the scheduling class of the new thread.
+    ///
+    /// By default, the new thread is a normal thread with the nice value 0.
+    /// Classes other than the default are only supported with the
+    /// `sched_class` feature, or spawning fails.
+    pub fn sched_class(mut self, class: SchedClass) -> Builder {
+        self.sched_class = Some(class);
+        self
+    }
+
     /// Spawns a new thread by taking ownership of the `Builder`, and returns an
     /// [`io::Result`] to its [`JoinHandle`].
     ///
@@ -95,6 +123,9 @@ impl Builder {
         F: Send + 'static,
         T: Send + 'static,
     {
+        if self.affinity.is_some_and(|cpumask| cpumask.is_empty()) {
+            return Err(ax_err_type!(InvalidInput));
+        }
         let name = self.name.unwrap_or_default();
         let stack_size = self
             .stack_size
@@ -115,7 +146,12 @@ impl Builder {
             drop(their_packet);
         };
 
-        let task = api::ax_spawn(main, name, stack_size);
+        let task = if self.affinity.is_some() || self.sched_class.is_some() {
+            let class = self.sched_class.unwrap_or_default();
+            api::ax_spawn_with(main, name, stack_size, self.affinity, class)?
+        } else {
+            api::ax_spawn(main, name, stack_size)
+        };
         Ok(JoinHandle {
             thread: Thread::from_id(task.id()),
             native: task,
@@ -130,6 +166,14 @@ pub fn current() -> Thread {
     Thread::from_id(id)
 }
 
+/// Sets the scheduling class of the current thread.
+///
+/// Classes other than the default `SchedClass::Normal(0)` are only supported
+/// with the `sched_class` feature.
+pub fn set_sched_class(class: SchedClass) -> io::Result<()> {
+    api::ax_set_current_sched_class(class)
+}
+
 /// Spawns a new thread, returning a [`JoinHandle`] for it.
 ///
 /// The join handle provides a [`join`] method that can be used to join the